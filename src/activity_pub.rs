@@ -0,0 +1,356 @@
+use itertools::Itertools;
+use log::{debug, info};
+use serde_json::Value;
+use url::Url;
+use crabo_model::Snapshot;
+use crate::inflight::set_stage;
+use crate::quality::{DataSource, with_quality};
+use crate::snapper::{bare_snapshot, Clients};
+use crate::util::{normalize_language_tag, parse_datetime, sanitize_url};
+
+/// Accept header ActivityPub servers respond to with objects rather
+/// than HTML pages.
+const ACTIVITY_ACCEPT: &str = "application/activity+json, \
+    application/ld+json; profile=\"https://www.w3.org/ns/activitystreams\"";
+
+/// Addresses of public collection, object addressed to none of them is
+/// meant for followers or mentioned people only.
+const PUBLIC_COLLECTIONS: [&str; 3] = [
+    "https://www.w3.org/ns/activitystreams#Public",
+    "as:Public",
+    "Public",
+];
+
+/// Types of objects snapshot could be made of, unlike actors or
+/// collections.
+const OBJECT_TYPES: [&str; 8] = [
+    "Note",
+    "Article",
+    "Page",
+    "Video",
+    "Image",
+    "Audio",
+    "Event",
+    "Question",
+];
+
+/// Returns the first URL in `value`, which is either URL itself, Link
+/// object or array of these, e.g. `attachment[].url` or `attributedTo`.
+fn first_url(value: &Value) -> Option<&str> {
+    match value {
+        Value::String(url) => Some(url),
+
+        Value::Object(link) => link.get("href")
+            .or_else(|| link.get("url"))
+            .or_else(|| link.get("id"))
+            .and_then(first_url),
+
+        Value::Array(values) => values.iter().find_map(first_url),
+
+        _ => None,
+    }
+}
+
+/// Returns non-blank string `key` of `object`.
+fn text(object: &Value, key: &str) -> Option<String> {
+    object.get(key)
+        .and_then(Value::as_str)
+        .filter(|text| !text.trim().is_empty())
+        .map(|text| text.to_string())
+}
+
+/// Returns text of `html` content, e.g. `<p>Hi &amp; bye</p>` becomes
+/// `Hi & bye`. Content of objects is sanitized by servers already,
+/// so markup is simple enough to drop tags without parsing it.
+fn plain_text(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+
+    for c in html.chars() {
+        match (c, in_tag) {
+            ('<', _) => {
+                in_tag = true;
+                text.push(' ');
+            }
+
+            ('>', true) => in_tag = false,
+            (_, true) => {}
+            (c, false) => text.push(c),
+        }
+    }
+
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+        .split_whitespace()
+        .join(" ")
+}
+
+/// Returns true if `object` is addressed to public collection.
+fn is_public(object: &Value) -> bool {
+    ["to", "cc"].into_iter()
+        .filter_map(|key| object.get(key))
+        .flat_map(|audience| match audience {
+            Value::Array(values) => values.iter().collect(),
+            value => vec![value],
+        })
+        .filter_map(Value::as_str)
+        .any(|address| PUBLIC_COLLECTIONS.contains(&address))
+}
+
+/// Returns true if `actor` agreed to be found by strangers. Mastodon
+/// 4.2+ has explicit `indexable` flag for search, older servers and
+/// other software have `discoverable` only.
+fn has_consent(actor: &Value) -> bool {
+    let flag = |key| actor.get(key).and_then(Value::as_bool);
+
+    flag("indexable")
+        .or_else(|| flag("discoverable"))
+        .unwrap_or(false)
+}
+
+/// Returns true if hosts of `a` and `b` are the same, so one could
+/// not claim objects or actors of another server.
+fn same_host(a: &Url, b: &Url) -> bool {
+    a.host_str().is_some() && a.host_str() == b.host_str()
+}
+
+/// This function makes snapshot of `url` from ActivityPub `object`
+/// written by `actor`. Returns None if object has neither text nor
+/// image attached.
+fn object_to_snapshot(url: Url, object: &Value, actor: &Value) -> Option<Snapshot> {
+    let name = text(object, "name");
+
+    // summary of notes is content warning, which hides the rest
+    let summary = text(object, "summary");
+    let is_note = object.get("type").and_then(Value::as_str) == Some("Note");
+
+    let sensitive = object.get("sensitive").and_then(Value::as_bool).unwrap_or(false) ||
+        (is_note && summary.is_some());
+
+    let description = summary
+        .or_else(|| text(object, "content"))
+        .map(|html| plain_text(&html))
+        .filter(|text| !text.is_empty());
+
+    let attachments = match object.get("attachment") {
+        Some(Value::Array(attachments)) => attachments.iter().collect(),
+        Some(attachment) => vec![attachment],
+        None => vec![],
+    };
+
+    let image = attachments.into_iter().find(|attachment| {
+        attachment.get("mediaType")
+            .and_then(Value::as_str)
+            .is_some_and(|media_type| media_type.starts_with("image/"))
+    });
+
+    let preview_url = image
+        .and_then(|image| image.get("url"))
+        .and_then(first_url)
+        .and_then(|image_url| url.join(image_url).ok())
+        .and_then(sanitize_url);
+
+    if preview_url.is_none() && name.is_none() && description.is_none() {
+        return None;
+    }
+
+    let image = image.filter(|_| preview_url.is_some());
+    let dimension = |key| image
+        .and_then(|image| image.get(key))
+        .and_then(Value::as_u64)
+        .and_then(|value| u32::try_from(value).ok());
+
+    let author = text(actor, "name")
+        .or_else(|| text(actor, "preferredUsername"));
+
+    let fediverse_creator = text(actor, "preferredUsername")
+        .zip(url.host_str())
+        .map(|(username, host)| format!("@{username}@{host}"));
+
+    let tags = match object.get("tag") {
+        Some(Value::Array(tags)) => tags.iter()
+            .filter(|tag| tag.get("type").and_then(Value::as_str) == Some("Hashtag"))
+            .filter_map(|tag| text(tag, "name"))
+            .map(|tag| match tag.starts_with('#') {
+                true => tag,
+                false => format!("#{tag}"),
+            })
+            .collect(),
+
+        _ => vec![],
+    };
+
+    let language = object.get("contentMap")
+        .and_then(Value::as_object)
+        .and_then(|content_map| content_map.keys().next())
+        .and_then(|language| normalize_language_tag(language));
+
+    let canonical_url = object.get("url")
+        .and_then(first_url)
+        .or_else(|| object.get("id").and_then(Value::as_str))
+        .and_then(|canonical_url| Url::parse(canonical_url).ok())
+        .and_then(sanitize_url);
+
+    let snapshot = Snapshot {
+        // notes have no name, author is the next best thing
+        title: name.or_else(|| author.clone()),
+        description,
+        preview_mime_type: image.and_then(|image| text(image, "mediaType")),
+        preview_alt: image.and_then(|image| text(image, "name")),
+        preview_width: dimension("width"),
+        preview_height: dimension("height"),
+        preview_url,
+        published_at: text(object, "published").and_then(|x| parse_datetime(&x)),
+        updated_at: text(object, "updated").and_then(|x| parse_datetime(&x)),
+        author,
+        fediverse_creator,
+        tags,
+        language,
+        canonical_url,
+        sensitive,
+        ..bare_snapshot(url)
+    };
+
+    Some(with_quality(snapshot, DataSource::Api))
+}
+
+/// This function asks server of `url` for ActivityPub object rather than
+/// HTML page and makes snapshot of it, so posts of servers that put
+/// nothing but author into meta tags still get meaningful snapshots.
+/// `clients` provide HTTP clients, which sign requests if configured.
+///
+/// Returns None if there is no public object at `url` or its author did
+/// not agree to be found, snapshot of HTML page is all there is then.
+pub async fn snap_activity(url: &Url, clients: &Clients) -> Option<Snapshot> {
+    let accept = [("Accept".to_string(), ACTIVITY_ACCEPT.to_string())];
+
+    set_stage("fetching ActivityPub object");
+
+    let object: Value = match clients.page_client.get_json(url, &accept).await {
+        Ok(object) => object,
+
+        Err(err) => {
+            debug!("{url}: no ActivityPub object: {err:?}");
+            return None;
+        }
+    };
+
+    let is_object = object.get("type")
+        .and_then(Value::as_str)
+        .is_some_and(|object_type| OBJECT_TYPES.contains(&object_type));
+
+    let id = object.get("id")
+        .and_then(Value::as_str)
+        .and_then(|id| Url::parse(id).ok());
+
+    // object served by one server could claim to be from another one
+    if !is_object || !id.as_ref().is_some_and(|id| same_host(id, url)) {
+        debug!("{url}: response is not ActivityPub object of this server");
+        return None;
+    }
+
+    if !is_public(&object) {
+        info!("{url}: ActivityPub object is not public");
+        return None;
+    }
+
+    let actor_url = object.get("attributedTo")
+        .and_then(first_url)
+        .and_then(|actor_url| Url::parse(actor_url).ok())
+        .filter(|actor_url| same_host(actor_url, url))?;
+
+    set_stage("fetching ActivityPub actor");
+
+    let actor: Value = match clients.page_client.get_json(&actor_url, &accept).await {
+        Ok(actor) => actor,
+
+        Err(err) => {
+            debug!("{url}: failed to get author {actor_url}: {err:?}");
+            return None;
+        }
+    };
+
+    if !has_consent(&actor) {
+        info!("{url}: author {actor_url} is not discoverable");
+        return None;
+    }
+
+    object_to_snapshot(url.clone(), &object, &actor)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use url::Url;
+    use crate::activity_pub::{has_consent, is_public, object_to_snapshot, plain_text};
+
+    #[test]
+    fn test_consent() {
+        assert!(has_consent(&json!({"discoverable": true})));
+        assert!(!has_consent(&json!({"discoverable": true, "indexable": false})));
+        assert!(!has_consent(&json!({"preferredUsername": "jane"})));
+
+        assert!(is_public(&json!({"to": ["https://www.w3.org/ns/activitystreams#Public"]})));
+        assert!(is_public(&json!({"to": [], "cc": "as:Public"})));
+        assert!(!is_public(&json!({"to": ["https://social.example/users/jane/followers"]})));
+    }
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(plain_text("<p>Tom &amp; Jerry</p><p>are<br>back</p>"), "Tom & Jerry are back");
+        assert_eq!(plain_text("<p></p>"), "");
+    }
+
+    #[test]
+    fn test_object_to_snapshot() {
+        let url = Url::parse("https://social.example/@jane/1").unwrap();
+
+        let object = json!({
+            "id": "https://social.example/users/jane/statuses/1",
+            "type": "Note",
+            "url": "https://social.example/@jane/1",
+            "summary": "spoilers",
+            "content": "<p>The butler did it</p>",
+            "contentMap": {"en": "<p>The butler did it</p>"},
+            "published": "2024-03-01T12:00:00Z",
+            "attachment": [
+                {"type": "Document", "mediaType": "video/mp4", "url": "/a.mp4"},
+                {"type": "Document", "mediaType": "image/png",
+                 "url": "https://files.social.example/b.png",
+                 "name": "butler", "width": 640, "height": 480}
+            ],
+            "tag": [
+                {"type": "Mention", "name": "@john"},
+                {"type": "Hashtag", "name": "#mystery"}
+            ]
+        });
+
+        let actor = json!({"preferredUsername": "jane", "name": "Jane"});
+
+        let snapshot = object_to_snapshot(url, &object, &actor).unwrap();
+
+        assert_eq!(snapshot.title.as_deref(), Some("Jane"));
+        assert_eq!(snapshot.description.as_deref(), Some("spoilers"));
+        assert!(snapshot.sensitive);
+
+        assert_eq!(
+            snapshot.preview_url.as_ref().map(|x| x.as_str()),
+            Some("https://files.social.example/b.png")
+        );
+
+        assert_eq!(snapshot.preview_alt.as_deref(), Some("butler"));
+        assert_eq!((snapshot.preview_width, snapshot.preview_height), (Some(640), Some(480)));
+        assert_eq!(snapshot.fediverse_creator.as_deref(), Some("@jane@social.example"));
+        assert_eq!(snapshot.tags, vec!["#mystery"]);
+        assert_eq!(snapshot.language.as_deref(), Some("en"));
+
+        assert!(object_to_snapshot(
+            Url::parse("https://social.example/").unwrap(),
+            &json!({"type": "Note"}),
+            &actor,
+        ).is_none());
+    }
+}
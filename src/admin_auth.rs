@@ -0,0 +1,108 @@
+use std::future::{ready, Ready};
+use std::sync::Arc;
+use actix_web::{FromRequest, HttpRequest, HttpResponse, web};
+use actix_web::dev::Payload;
+use actix_web::error::InternalError;
+use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use sha2::{Digest, Sha256};
+
+/// Token `/admin` endpoints require in `Authorization: Bearer` header.
+/// Without token admin endpoints are disabled, as they share listener
+/// with public endpoints such as `/snap`.
+#[derive(Clone, Default)]
+pub(crate) struct AdminToken {
+    /// Digest of token, so comparison takes the same time regardless of
+    /// how much of presented token matches.
+    digest: Option<Arc<[u8]>>,
+}
+
+impl AdminToken {
+    /// Constructs new instance of [AdminToken] that accepts `token`.
+    /// Empty token disables admin endpoints.
+    pub(crate) fn new(token: Option<&str>) -> Self {
+        Self {
+            digest: token
+                .map(|token| token.trim())
+                .filter(|token| !token.is_empty())
+                .map(|token| Sha256::digest(token.as_bytes()).to_vec().into()),
+        }
+    }
+
+    /// Returns true if admin endpoints are enabled.
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.digest.is_some()
+    }
+
+    /// Returns true if `authorization` header value carries valid token.
+    fn accepts(&self, authorization: &str) -> bool {
+        let Some(digest) = &self.digest else {
+            return false;
+        };
+
+        let Some(token) = authorization.strip_prefix("Bearer ") else {
+            return false;
+        };
+
+        Sha256::digest(token.trim().as_bytes()).as_slice() == digest.as_ref()
+    }
+}
+
+/// Proof that request carries valid [AdminToken]. Admin endpoints take
+/// it as argument, so unauthorized requests never reach them.
+pub(crate) struct AdminAuth;
+
+impl FromRequest for AdminAuth {
+    type Error = InternalError<&'static str>;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let token = request.app_data::<web::Data<AdminToken>>()
+            .map(|token| token.get_ref().clone())
+            .unwrap_or_default();
+
+        if !token.is_enabled() {
+            return ready(Err(InternalError::from_response(
+                "admin endpoints are disabled",
+                HttpResponse::Forbidden().body("Admin endpoints are disabled"),
+            )));
+        }
+
+        let is_authorized = request.headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|authorization| token.accepts(authorization));
+
+        match is_authorized {
+            true => ready(Ok(AdminAuth)),
+
+            false => ready(Err(InternalError::from_response(
+                "admin token is missing or invalid",
+                HttpResponse::Unauthorized()
+                    .insert_header((WWW_AUTHENTICATE, "Bearer"))
+                    .finish(),
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::admin_auth::AdminToken;
+
+    #[test]
+    fn test_admin_token() {
+        let token = AdminToken::new(Some(" secret "));
+
+        assert!(token.is_enabled());
+        assert!(token.accepts("Bearer secret"));
+        assert!(!token.accepts("Bearer secret2"));
+        assert!(!token.accepts("Basic secret"));
+        assert!(!token.accepts("secret"));
+
+        let disabled = AdminToken::new(Some(""));
+
+        assert!(!disabled.is_enabled());
+        assert!(!disabled.accepts("Bearer "));
+        assert!(!AdminToken::new(None).is_enabled());
+    }
+}
@@ -0,0 +1,493 @@
+use actix_web::http::header::ACCEPT_LANGUAGE;
+use futures::future::LocalBoxFuture;
+use log::{debug, warn};
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::Value;
+use url::Url;
+use crabo_model::{SnapError, Snapshot};
+use crate::inflight::set_stage;
+use crate::log_throttle::warn_for_host;
+use crate::provider_headers::RequestHeaders;
+use crate::quality::{DataSource, with_quality};
+use crate::snapper::{
+    bare_snapshot,
+    CacheHints,
+    Clients,
+    Snapper,
+    SnapshotAndHints,
+};
+use crate::snapper_registry::{
+    CONFIGURED_API_PRIORITY,
+    SITE_API_PRIORITY,
+    UrlMatcher,
+};
+use crate::util::{
+    guess_mime_from_url,
+    normalize_language_tag,
+    parse_datetime,
+    sanitize_url,
+};
+
+/// Paths to values of snapshot fields in API response, e.g.
+/// `$.items[0].snippet.title`. Only dot-separated keys and array indexes
+/// are supported, which is enough for most simple JSON APIs.
+#[derive(Clone, Debug, Default, Deserialize)]
+struct FieldPaths {
+    title: Option<String>,
+    description: Option<String>,
+    preview_url: Option<String>,
+    tags: Option<String>,
+    published_at: Option<String>,
+    language: Option<String>,
+}
+
+/// Definition of API provider as written in configuration file.
+#[derive(Clone, Debug, Deserialize)]
+struct ApiProviderConfig {
+    /// Unique name of provider, used in cache hints.
+    name: String,
+
+    /// Regular expression URLs handled by provider should match.
+    /// Named groups could be referenced in `endpoint`.
+    url_pattern: String,
+
+    /// API endpoint template, e.g. `https://api.example/v1/items/{id}`.
+    /// `{url}` is replaced with encoded address of page.
+    endpoint: String,
+
+    /// Value of `source` field of snapshots.
+    source: Option<String>,
+
+    /// Name of publisher shown next to snapshots, e.g. `Example News`.
+    site_name: Option<String>,
+
+    /// Icon of publisher shown next to snapshots.
+    icon_url: Option<Url>,
+
+    /// Where to find snapshot fields in API response.
+    #[serde(default)]
+    fields: FieldPaths,
+
+    /// Query parameter API takes preferred language in, e.g. `hl` or
+    /// `locale`. Language is sent in Accept-Language header only if not set.
+    language_parameter: Option<String>,
+
+    /// Priority against other snappers, [CONFIGURED_API_PRIORITY] by
+    /// default. Provider with priority above [SITE_API_PRIORITY] takes
+    /// over URLs built-in snappers would handle otherwise.
+    priority: Option<i32>,
+}
+
+/// Snapper for JSON API defined by operator in configuration file,
+/// so simple APIs could be supported without writing Rust code.
+pub struct ApiSnapper {
+    config: ApiProviderConfig,
+    url_pattern: Regex,
+
+    /// Headers sent to API along with requests.
+    request_headers: RequestHeaders,
+}
+
+/// Segment of path to JSON value.
+#[derive(Debug, PartialEq)]
+enum PathSegment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+/// This function splits `path` such as `$.items[0].title` into segments.
+/// Returns None if path is malformed.
+fn parse_json_path(path: &str) -> Option<Vec<PathSegment<'_>>> {
+    let path = path.trim()
+        .strip_prefix('$')
+        .unwrap_or(path.trim());
+
+    let mut segments = vec![];
+
+    for part in path.split('.').filter(|part| !part.is_empty()) {
+        let (key, mut indexes) = match part.split_once('[') {
+            Some((key, indexes)) => (key, Some(indexes)),
+            None => (part, None),
+        };
+
+        if !key.is_empty() {
+            segments.push(PathSegment::Key(key));
+        }
+
+        // indexes look like `0][1]` at this point
+        while let Some(rest) = indexes {
+            let (index, rest) = rest.split_once(']')?;
+            segments.push(PathSegment::Index(index.trim().parse().ok()?));
+
+            indexes = match rest.strip_prefix('[') {
+                Some(rest) => Some(rest),
+                None if rest.is_empty() => None,
+                None => return None,
+            };
+        }
+    }
+
+    Some(segments)
+}
+
+/// This function looks up value at `path` in `value`.
+fn find_json_value<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    parse_json_path(path)?
+        .into_iter()
+        .try_fold(value, |value, segment| match segment {
+            PathSegment::Key(key) => value.get(key),
+            PathSegment::Index(index) => value.get(index),
+        })
+}
+
+/// Returns `value` as text if it is a string or a number.
+fn json_value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        _ => None,
+    }
+}
+
+impl ApiSnapper {
+    /// This function constructs [ApiSnapper] instances from `json` which
+    /// is array of provider definitions, e.g.
+    /// ```json
+    /// [{
+    ///     "name": "example",
+    ///     "url_pattern": "^https://example\\.com/items/(?<id>\\d+)",
+    ///     "endpoint": "https://api.example.com/v1/items/{id}",
+    ///     "source": "Example",
+    ///     "fields": {
+    ///         "title": "$.item.name",
+    ///         "preview_url": "$.item.images[0].url",
+    ///         "tags": "$.item.tags"
+    ///     }
+    /// }]
+    /// ```
+    /// Returns error if `json` is malformed or has invalid patterns.
+    pub fn from_json_many(json: &str) -> Result<Vec<Self>, String> {
+        let configs: Vec<ApiProviderConfig> = serde_json::from_str(json)
+            .map_err(|err| format!("Malformed API providers: {err:?}"))?;
+
+        configs.into_iter()
+            .map(|config| {
+                let url_pattern = Regex::new(&config.url_pattern)
+                    .map_err(|err| format!(
+                        "Invalid URL pattern of API provider {}: {err:?}",
+                        config.name,
+                    ))?;
+
+                Ok(
+                    Self {
+                        config,
+                        url_pattern,
+                        request_headers: RequestHeaders::default(),
+                    }
+                )
+            })
+            .collect()
+    }
+
+    /// This function loads [ApiSnapper] instances from JSON file at `path`.
+    pub fn load_many(path: &str) -> Result<Vec<Self>, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {path}: {err:?}"))?;
+
+        Self::from_json_many(&json)
+    }
+
+    /// Returns this snapper sending `request_headers` along with API
+    /// requests.
+    pub fn with_request_headers(self, request_headers: RequestHeaders) -> Self {
+        Self { request_headers, ..self }
+    }
+
+    /// Returns priority of this snapper against other snappers.
+    pub fn priority(&self) -> i32 {
+        self.config.priority.unwrap_or(CONFIGURED_API_PRIORITY)
+    }
+
+    /// Returns matcher of URLs handled by this provider.
+    pub fn url_matcher(&self) -> UrlMatcher {
+        UrlMatcher::Pattern(self.url_pattern.clone())
+    }
+
+    /// This method builds API endpoint address for page `url`, which asks
+    /// for response in `language` if it is set and provider takes it.
+    /// Returns None if `url` is not handled by this provider.
+    fn endpoint_url(&self, url: &Url, language: Option<&str>) -> Option<Url> {
+        let captures = self.url_pattern.captures(url.as_str())?;
+
+        let encoded_url: String = url::form_urlencoded::byte_serialize(
+            url.as_str().as_bytes()
+        ).collect();
+
+        let endpoint = self.url_pattern.capture_names()
+            .flatten()
+            .fold(
+                self.config.endpoint.replace("{url}", &encoded_url),
+                |endpoint, name| {
+                    let value: String = url::form_urlencoded::byte_serialize(
+                        captures.name(name)
+                            .map_or("", |x| x.as_str())
+                            .as_bytes()
+                    ).collect();
+
+                    endpoint.replace(&format!("{{{name}}}"), &value)
+                }
+            );
+
+        match Url::parse(&endpoint) {
+            Ok(mut endpoint_url) => {
+                if let (Some(parameter), Some(language)) = (
+                    &self.config.language_parameter,
+                    language,
+                ) {
+                    endpoint_url.query_pairs_mut().append_pair(parameter, language);
+                }
+
+                Some(endpoint_url)
+            }
+
+            Err(err) => {
+                warn!(
+                    "API provider {} produced invalid endpoint \
+                    '{endpoint}': {err:?}",
+                    self.config.name,
+                );
+
+                clients.provider_metrics.record_api_error(&cache_hints.provider);
+                None
+            }
+        }
+    }
+
+    /// This method converts API `response` to [Snapshot] of `url`.
+    /// Returns None if response has neither title nor description.
+    async fn response_to_snapshot(
+        &self,
+        url: Url,
+        response: &Value,
+        clients: &Clients,
+    ) -> Option<Snapshot> {
+        let fields = &self.config.fields;
+
+        let text = |path: &Option<String>| path.as_ref()
+            .and_then(|path| find_json_value(response, path))
+            .and_then(json_value_to_string)
+            .filter(|text| !text.trim().is_empty());
+
+        let title = text(&fields.title);
+        let description = text(&fields.description);
+
+        if title.is_none() && description.is_none() {
+            debug!(
+                "API provider {} returned nothing usable for {url}",
+                self.config.name,
+            );
+
+            return None;
+        }
+
+        let preview_url = text(&fields.preview_url)
+            .and_then(|x| url.join(&x).ok())
+            .and_then(sanitize_url);
+
+        let preview_mime_type = guess_mime_from_url(
+            preview_url.as_ref(),
+            clients,
+        ).await;
+
+        let tags_value = fields.tags.as_ref()
+            .and_then(|path| find_json_value(response, path));
+
+        // tags are either array of strings or a comma-separated string
+        let raw_tags: Vec<String> = match tags_value {
+            Some(Value::Array(values)) => values.iter()
+                .filter_map(json_value_to_string)
+                .collect(),
+
+            Some(value) => json_value_to_string(value)
+                .map(|x| x.split(',').map(|tag| tag.to_string()).collect())
+                .unwrap_or_default(),
+
+            None => vec![],
+        };
+
+        let tags = raw_tags.into_iter()
+            .map(|tag| tag.trim().to_string())
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| format!("#{tag}"))
+            .collect();
+
+        // dates are either text or UNIX timestamp
+        let published_at = fields.published_at.as_ref()
+            .and_then(|path| find_json_value(response, path))
+            .and_then(|value| match value {
+                Value::Number(number) => number.as_i64()
+                    .and_then(|x| chrono::DateTime::from_timestamp(x, 0)),
+
+                _ => value.as_str().and_then(parse_datetime),
+            });
+
+        let language = text(&fields.language)
+            .and_then(|language| normalize_language_tag(&language));
+
+        let snapshot = Snapshot {
+            preview_url,
+            title,
+            description,
+            source: self.config.source.clone(),
+            site_name: self.config.site_name.clone(),
+            icon_url: self.config.icon_url.clone(),
+            preview_mime_type,
+            tags,
+            published_at,
+            language,
+            ..bare_snapshot(url)
+        };
+
+        Some(with_quality(snapshot, DataSource::Api))
+    }
+}
+
+impl Snapper for ApiSnapper {
+    fn provider(&self) -> String {
+        format!("api:{}", self.config.name)
+    }
+
+    /// Language is passed to API either as parameter or as
+    /// Accept-Language header, there is no telling whether API honors it.
+    fn is_localized(&self, _url: &Url) -> bool {
+        true
+    }
+
+    fn cache_hints(&self, url: &Url) -> Option<CacheHints> {
+        match self.url_pattern.is_match(url.as_str()) {
+            true => Some(
+                CacheHints {
+                    provider: self.provider(),
+                    id: format!("{}:{url}", self.provider()),
+                    language: None,
+                }
+            ),
+
+            false => None,
+        }
+    }
+
+    fn snap<'a>(
+        &'a self,
+        url: Url,
+        cache_hints: CacheHints,
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, SnapshotAndHints> {
+        Box::pin(async move {
+            let language = cache_hints.language.as_deref();
+
+            let endpoint_url = match self.endpoint_url(&url, language) {
+                Some(endpoint_url) => endpoint_url,
+
+                None => return SnapshotAndHints {
+                    snapshot: Err(SnapError::ProviderError),
+                    hints: cache_hints,
+                }
+            };
+
+            let mut extra_headers = self.request_headers.merged_with(&[]);
+
+            if let (None, Some(language)) = (&self.config.language_parameter, language) {
+                extra_headers.push((ACCEPT_LANGUAGE.to_string(), language.to_string()));
+            }
+
+            set_stage("calling API");
+
+            let snapshot = match clients.page_client.get_json::<Value>(
+                &endpoint_url,
+                &extra_headers,
+            ).await {
+                Ok(response) => self.response_to_snapshot(url, &response, clients)
+                    .await
+                    .ok_or(SnapError::ParseFailed),
+
+                Err(err) => {
+                    warn_for_host(endpoint_url.host_str().unwrap_or_default(), format_args!(
+                        "Failed to get details for {url} from API provider {}, \
+                        API call result is: {err:?}",
+                        self.config.name,
+                    ));
+
+                    Err(err.snap_error())
+                }
+            };
+
+            SnapshotAndHints {
+                snapshot,
+                hints: cache_hints,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use url::Url;
+    use crate::api_snapper::{ApiSnapper, find_json_value, parse_json_path};
+    use crate::snapper::Snapper;
+
+    #[test]
+    fn test_json_path() {
+        let value = json!({"items": [{"title": "a"}, {"title": "b", "n": 1}]});
+
+        assert_eq!(find_json_value(&value, "$.items[1].title"), Some(&json!("b")));
+        assert_eq!(find_json_value(&value, "items[0].title"), Some(&json!("a")));
+        assert_eq!(find_json_value(&value, "$.items[2].title"), None);
+        assert_eq!(find_json_value(&value, "$"), Some(&value));
+        assert!(parse_json_path("$.items[x]").is_none());
+        assert!(parse_json_path("$.items[0]x").is_none());
+    }
+
+    #[test]
+    fn test_api_provider() {
+        let snappers = ApiSnapper::from_json_many(r#"[{
+            "name": "example",
+            "url_pattern": "^https://example\\.com/items/(?<id>\\d+)",
+            "endpoint": "https://api.example.com/v1/items/{id}?page={url}"
+        }]"#).unwrap();
+
+        let snapper = &snappers[0];
+        let url = Url::parse("https://example.com/items/42").unwrap();
+
+        assert_eq!(snapper.cache_hints(&url).unwrap().provider, "api:example");
+
+        assert_eq!(
+            snapper.endpoint_url(&url, Some("de")).unwrap().as_str(),
+            "https://api.example.com/v1/items/42\
+            ?page=https%3A%2F%2Fexample.com%2Fitems%2F42"
+        );
+
+        let snapper = ApiSnapper::from_json_many(r#"[{
+            "name": "example",
+            "url_pattern": "^https://example\\.com/items/(?<id>\\d+)",
+            "endpoint": "https://api.example.com/v1/items/{id}",
+            "language_parameter": "hl"
+        }]"#).unwrap().pop().unwrap();
+
+        assert_eq!(
+            snapper.endpoint_url(&url, Some("pt-BR")).unwrap().as_str(),
+            "https://api.example.com/v1/items/42?hl=pt-BR"
+        );
+
+        let url = Url::parse("https://example.com/other/42").unwrap();
+        assert!(snapper.cache_hints(&url).is_none());
+
+        assert!(
+            ApiSnapper::from_json_many(
+                r#"[{"name": "x", "url_pattern": "(", "endpoint": "x"}]"#
+            ).is_err()
+        );
+    }
+}
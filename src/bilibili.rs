@@ -1,30 +1,115 @@
-use actix_web::dev::ResourcePath;
+use std::fmt::{Display, Formatter};
+use futures::future::LocalBoxFuture;
 use log::{debug, warn};
 use serde::Deserialize;
+use url::Url;
 
-use crabo_model::Snapshot;
-use fedineko_http_client::GenericClient;
+use crabo_model::{SnapError, Snapshot};
 
-use crate::snapper::{CacheHints, Clients, Snapper, SnapshotAndHints};
+use crate::snapper::{
+    bare_snapshot,
+    CacheHints,
+    Clients,
+    Snapper,
+    SnapshotAndHints,
+};
+use crate::inflight::set_stage;
+use crate::provider_headers::RequestHeaders;
+use crate::quality::{DataSource, with_quality};
+use crate::short_links::{hints_id, parse_hints_id, ShortLinks};
+use crate::util::{format_compact_count, is_host_of};
+
+/// Icon of BiliBili shown next to site name.
+const BILIBILI_ICON_URL: &str = "https://www.bilibili.com/favicon.ico";
+
+/// Endpoint that describes video by its ID, along with its tags.
+const BILIBILI_VIEW_API_URL: &str = "https://api.bilibili.com/x/web-interface/view/detail";
+
+/// Alphabet of BV IDs, see [av_to_bv].
+const BV_ALPHABET: &[u8; 58] = b"FcwAPNKTMug3GV5Lj7EJnHpWsx4tb8haYeviqBz6rkCy12mUSDQX9RdoZf";
+
+/// Positions of BV ID characters, least significant digit first.
+const BV_POSITIONS: [usize; 9] = [11, 10, 3, 8, 4, 6, 5, 7, 9];
+
+/// Numeric IDs are below this and mixed with [BV_XOR_CODE] when encoded.
+const MAX_AID: u64 = 1 << 51;
+
+/// Constant numeric IDs are XOR-ed with when encoded.
+const BV_XOR_CODE: u64 = 23442827791579;
 
 /// This is barebones implementation of API to get video information from
 /// BiliBili.
 ///
 /// API endpoint was taken from <https://github.com/Nemo2011/bilibili-api>
-pub(crate) struct BiliBiliSnapper {}
+pub struct BiliBiliSnapper {
+    /// Headers sent to API along with requests.
+    request_headers: RequestHeaders,
+
+    /// Video IDs resolved short links lead to.
+    short_links: ShortLinks<VideoId>,
+}
 
 /// A very simplified version of BiliBili's video data.
 #[derive(Deserialize)]
 #[derive(Clone)]
 struct VideoData {
+    /// Video ID, e.g. `BV1a2b3c`.
+    bvid: Option<String>,
+
     /// Thumbnail image reference.
-    pic: Option<url::Url>,
+    pic: Option<Url>,
 
     /// Video title.
     title: Option<String>,
 
     /// Video description.
     desc: Option<String>,
+
+    /// UNIX timestamp of publication.
+    pubdate: Option<i64>,
+
+    /// Uploader of video.
+    owner: Option<Owner>,
+
+    /// Duration of video in seconds.
+    duration: Option<u64>,
+
+    /// Counters of video.
+    stat: Option<Stat>,
+}
+
+/// Counters of BiliBili video.
+#[derive(Deserialize)]
+#[derive(Clone)]
+struct Stat {
+    /// Number of views.
+    view: Option<u64>,
+}
+
+/// Tag of BiliBili video.
+#[derive(Deserialize)]
+#[derive(Clone)]
+struct Tag {
+    /// Name of tag.
+    tag_name: Option<String>,
+}
+
+/// Uploader of BiliBili video.
+#[derive(Deserialize)]
+#[derive(Clone)]
+struct Owner {
+    /// Name of uploader.
+    name: Option<String>,
+}
+
+/// Video data along with its tags.
+#[derive(Deserialize)]
+struct VideoDetail {
+    #[serde(rename = "View")]
+    view: VideoData,
+
+    #[serde(default, rename = "Tags")]
+    tags: Vec<Tag>,
 }
 
 /// Example response
@@ -32,164 +117,336 @@ struct VideoData {
 ///   "code": 0,
 ///   "message": "0",
 ///   "data": {
-///     "bvid": "BVxxxxxxxx",
-///     "aid": 3xxxxxxxxx,
-///     "pic": "https://domain/path/image",
-///     "title": "...",
-///     "pubdate": 1234567890,
-///     "desc": "...",
-///    ...
-///    }
+///     "View": {
+///       "bvid": "BVxxxxxxxx",
+///       "aid": 3xxxxxxxxx,
+///       "pic": "https://domain/path/image",
+///       "title": "...",
+///       "pubdate": 1234567890,
+///       "duration": 213,
+///       "desc": "...",
+///       "owner": {"mid": 1234, "name": "...", "face": "..."},
+///       "stat": {"view": 12345, ...},
+///       ...
+///     },
+///     "Tags": [{"tag_id": 1234, "tag_name": "..."}, ...],
+///     ...
+///   }
+/// }
 /// ```
 #[derive(Deserialize)]
 struct BiliBiliResponse {
-    data: VideoData,
+    data: VideoDetail,
+}
+
+/// Video ID as it is found in URL.
+#[derive(Clone, Debug, PartialEq)]
+enum VideoId {
+    /// Current ID of video, e.g. `BV1a2b3c`.
+    Bv(String),
+
+    /// Legacy numeric ID of video, e.g. `av170001`.
+    Av(u64),
+
+    /// Short link, e.g. `https://b23.tv/abcdEFG`, which needs to be
+    /// resolved to one of the above.
+    Short(Url),
+}
+
+impl VideoId {
+    /// Parses video ID from `id` found in path of video page.
+    fn from_path_segment(id: &str) -> Option<Self> {
+        if id.starts_with("BV") && id.len() > 2 {
+            return Some(Self::Bv(id.to_string()));
+        }
+
+        id.strip_prefix("av")
+            .or_else(|| id.strip_prefix("AV"))
+            .and_then(|aid| aid.parse().ok())
+            .map(Self::Av)
+    }
+
+    /// Parses video ID from cache hints `id` made by [VideoId::to_string].
+    fn parse(id: &str) -> Option<Self> {
+        match parse_hints_id(id) {
+            Some(url) => Some(Self::Short(url)),
+            None => Self::from_path_segment(id),
+        }
+    }
+
+    /// Returns address of API endpoint that describes this video,
+    /// short links have to be resolved first.
+    fn api_url(&self) -> Option<Url> {
+        let mut url = Url::parse(BILIBILI_VIEW_API_URL).ok()?;
+
+        match self {
+            Self::Bv(bvid) => url.query_pairs_mut().append_pair("bvid", bvid),
+            Self::Av(aid) => url.query_pairs_mut().append_pair("aid", &aid.to_string()),
+            Self::Short(_) => return None,
+        };
+
+        Some(url)
+    }
+}
+
+/// This function encodes legacy numeric video ID `aid` as BV ID, the
+/// same way BiliBili does, so both lead to the same cache entry.
+/// Returns None if `aid` is out of range.
+fn av_to_bv(aid: u64) -> Option<String> {
+    if aid == 0 || aid >= MAX_AID {
+        return None;
+    }
+
+    let mut bvid = *b"BV1000000000";
+    let mut value = (MAX_AID | aid) ^ BV_XOR_CODE;
+
+    for position in BV_POSITIONS {
+        bvid[position] = BV_ALPHABET[(value % 58) as usize];
+        value /= 58;
+    }
+
+    String::from_utf8(bvid.to_vec()).ok()
+}
+
+impl VideoId {
+    /// Returns the form of this ID video page uses, legacy numeric IDs
+    /// are encoded as BV IDs.
+    fn canonical(self) -> Self {
+        match self {
+            Self::Av(aid) => av_to_bv(aid).map_or(Self::Av(aid), Self::Bv),
+            video_id => video_id,
+        }
+    }
+}
+
+impl Display for VideoId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Bv(bvid) => write!(f, "{bvid}"),
+            Self::Av(aid) => write!(f, "av{aid}"),
+            Self::Short(url) => write!(f, "{}", hints_id(url)),
+        }
+    }
+}
+
+/// Returns address of short link with `code`.
+fn short_link_url(code: &str) -> Option<Url> {
+    Url::parse("https://b23.tv/")
+        .and_then(|base| base.join(code))
+        .ok()
 }
 
 /// This function extracts BiliBili video ID from `url`,
 /// and returns either that ID or None.
-fn extract_video_id(url: &url::Url) -> Option<String> {
-    let host = url.host()?.to_string();
+fn extract_video_id(url: &Url) -> Option<VideoId> {
+    let host = url.host_str()?;
+
+    if is_host_of(host, "b23.tv") {
+        let code = url.path().trim_matches('/');
 
-    if host.ends_with("b23.tv") {
-        // that's short URL that needs to be resolved
-        return Some(host.path().to_string());
+        return match code.is_empty() || code.contains('/') {
+            true => None,
+            false => short_link_url(code).map(VideoId::Short),
+        };
     }
 
-    if !host.ends_with("bilibili.com") {
+    if !is_host_of(host, "bilibili.com") {
         debug!("Could not extract BiliBili video ID from URL {}", url);
         return None;
     }
 
     url.path()
         .strip_prefix("/video/")
-        .map(|s| s.trim_matches('/').to_string())
+        .and_then(|s| s.split('/').next())
+        .and_then(VideoId::from_path_segment)
 }
 
 impl BiliBiliSnapper {
-    /// This method converts `video` data to Crabo [Snapshot].
+    /// Constructs new instance of [BiliBiliSnapper] that sends
+    /// `request_headers` along with API requests.
+    pub fn new(request_headers: RequestHeaders) -> Self {
+        Self {
+            request_headers,
+            short_links: ShortLinks::default(),
+        }
+    }
+
+    /// This method converts `video` data and its `tags` to Crabo
+    /// [Snapshot]. Number of views goes before description, as there is
+    /// no better place for it.
     /// On success returns instance of [Snapshot], otherwise None is returned.
     fn videodata_to_snapshot(
         &self,
-        url: url::Url,
+        url: Url,
         video: VideoData,
+        tags: Vec<Tag>,
     ) -> Option<Snapshot> {
+        let views = video.stat
+            .and_then(|stat| stat.view)
+            .map(|views| format!("{} views", format_compact_count(views)));
+
+        let description = match (views, video.desc) {
+            (Some(views), Some(desc)) if !desc.trim().is_empty() => {
+                Some(format!("{views}\n\n{desc}"))
+            }
+
+            (Some(views), _) => Some(views),
+            (None, desc) => desc,
+        };
+
         let preview_mime_type = video.pic.as_ref()
             .map(|x| mime_guess::from_path(x.path()))
             .and_then(|m| m.first())
             .map(|m| m.to_string());
 
-        Some(Snapshot {
-            url,
+        let snapshot = Snapshot {
             preview_url: video.pic,
             title: video.title,
-            description: video.desc,
+            description,
             source: Option::from("BiliBili".to_string()),
-            tags: Vec::default(),
-            preview_mime_type,
-            application_name: None,
-        })
-    }
+            site_name: Option::from("BiliBili".to_string()),
+            icon_url: Url::parse(BILIBILI_ICON_URL).ok(),
+            published_at: video.pubdate
+                .and_then(|x| chrono::DateTime::from_timestamp(x, 0)),
+            author: video.owner.and_then(|owner| owner.name),
+            duration_seconds: video.duration.filter(|seconds| *seconds > 0),
 
-    /// This method attempts to resolve shortened URL represented by `id`
-    /// to actual video ID. `client` is used to make requests.
-    /// Returns either resolved video ID or None.
-    async fn resolve_short_url(
-        id: &str,
-        client: &GenericClient,
-    ) -> Option<String> {
-        let url = url::Url::parse("https://b23.tv")
-            .and_then(|u| u.join(id))
-            .unwrap();
+            tags: tags.into_iter()
+                .filter_map(|tag| tag.tag_name)
+                .filter(|name| !name.trim().is_empty())
+                .map(|name| format!("#{name}"))
+                .collect(),
 
-        let headers = match client.head(&url).await {
-            Ok(headers) => headers,
+            canonical_url: video.bvid
+                .and_then(|id| Url::parse("https://www.bilibili.com/video/")
+                    .and_then(|base| base.join(&format!("{id}/")))
+                    .ok()
+                ),
 
-            Err(err) => {
-                warn!("Failed to resolve short URL {url}: {err:?}");
-                return None;
-            }
+            preview_mime_type,
+            ..bare_snapshot(url)
         };
 
-        headers.get("location")
-            .map(|value| url::Url::parse(value.to_str().unwrap()).unwrap())
-            .and_then(|url| extract_video_id(&url))
+        Some(with_quality(snapshot, DataSource::Api))
     }
 }
 
 impl Snapper for BiliBiliSnapper {
-    fn cache_hints(&self, video_url: &url::Url) -> Option<CacheHints> {
+    fn provider(&self) -> String {
+        "bilibili".into()
+    }
+
+    /// Legacy numeric IDs are encoded as BV IDs, so both forms of video
+    /// page share cache hints.
+    fn cache_hints(&self, video_url: &Url) -> Option<CacheHints> {
         extract_video_id(video_url)
+            .map(|id| match id {
+                VideoId::Short(url) => self.short_links.get(&url)
+                    .unwrap_or(VideoId::Short(url)),
+
+                id => id.canonical(),
+            })
             .map(|id| CacheHints {
-                provider: "bilibili".into(),
-                id,
+                provider: self.provider(),
+                id: id.to_string(),
+                language: None,
             })
     }
 
-    async fn snap(
-        &self,
-        url: url::Url,
+    fn snap<'a>(
+        &'a self,
+        url: Url,
         cache_hints: CacheHints,
-        clients: &Clients,
-    ) -> SnapshotAndHints {
-        // if ID in hints does not look like video ID, then earlier that ID
-        // was extracted from short URL and needs to be resolved to a proper
-        // video address from which video ID will be extracted, so in turn it
-        // could be fed to API endpoint.
-        // ...
-        // Yes, it is a very unnatural way of doing things.
-        //
-        // Maybe it is better to resolve in cache_hints() instead and revamp
-        // synchronous code there.
-        let video_id = if !cache_hints.id.starts_with("BV") {
-            Self::resolve_short_url(&cache_hints.id, &clients.no_follow_client)
-                .await
-                .unwrap_or(cache_hints.id.clone())
-        } else {
-            cache_hints.id.clone()
-        };
-
-        // TODO: this URL construction is flawed,
-        //       needs a proper URL parameters join.
-        let query_url_str = format!(
-            "https://api.bilibili.com/x/web-interface/view?bvid={video_id}"
-        );
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, SnapshotAndHints> {
+        Box::pin(async move {
+            let video_id = match VideoId::parse(&cache_hints.id) {
+                Some(VideoId::Short(short_url)) => {
+                    set_stage("resolving short URL");
 
-        let query_url = url::Url::parse(&query_url_str).unwrap();
+                    self.short_links.resolve(
+                        &short_url,
+                        clients,
+                        |location| extract_video_id(location)
+                            .filter(|video_id| !matches!(video_id, VideoId::Short(_)))
+                            .map(VideoId::canonical),
+                    ).await
+                }
 
-        match clients.generic_client.get_json::<BiliBiliResponse>(
-            &query_url,
-            None,
-        ).await {
-            Ok(response) => {
-                let snapshot = self.videodata_to_snapshot(url, response.data);
+                video_id => video_id,
+            };
 
-                SnapshotAndHints {
-                    snapshot,
+            let Some(query_url) = video_id.as_ref().and_then(VideoId::api_url) else {
+                return SnapshotAndHints {
+                    snapshot: Err(SnapError::NotFound),
                     hints: cache_hints,
+                };
+            };
+
+            set_stage("calling BiliBili API");
+
+            match clients.page_client.get_json::<BiliBiliResponse>(
+                &query_url,
+                &self.request_headers.merged_with(&[]),
+            ).await {
+                Ok(response) => {
+                    // short and legacy links share entry with video page
+                    let hints = match &response.data.view.bvid {
+                        Some(bvid) => CacheHints {
+                            id: bvid.clone(),
+                            ..cache_hints
+                        },
+
+                        None => cache_hints,
+                    };
+
+                    let snapshot = self.videodata_to_snapshot(
+                        url,
+                        response.data.view,
+                        response.data.tags,
+                    )
+                        .ok_or(SnapError::ParseFailed);
+
+                    SnapshotAndHints {
+                        snapshot,
+                        hints,
+                    }
                 }
-            }
 
-            Err(err) => {
-                warn!(
-                    "Failed to get details for BiliBili video '{video_id}', \
-                    API call result is: {err:?}"
-                );
+                Err(err) => {
+                    warn!(
+                        "Failed to get details for BiliBili video '{}', \
+                        API call result is: {err:?}",
+                        cache_hints.id,
+                    );
 
-                SnapshotAndHints {
-                    snapshot: None,
-                    hints: cache_hints,
+                    clients.provider_metrics.record_api_error("bilibili");
+
+                    SnapshotAndHints {
+                        snapshot: Err(err.snap_error()),
+                        hints: cache_hints,
+                    }
                 }
             }
-        }
+        })
     }
 }
 
 #[cfg(test)]
 mod test {
+    use actix_web::http::{Method, StatusCode};
     use url::Url;
 
-    use crate::bilibili::extract_video_id;
+    use crate::bilibili::{
+        av_to_bv,
+        BiliBiliResponse,
+        BiliBiliSnapper,
+        extract_video_id,
+        VideoId,
+    };
+    use crate::page_client::{CannedResponse, CannedTransport};
+    use crate::provider_headers::RequestHeaders;
+    use crate::snapper::{Snapper, test_clients};
 
     #[test]
     fn test_bilibili_video_id_extraction() {
@@ -198,6 +455,110 @@ mod test {
             ?share_source=copy_web&vd_source=abcxyz"
         ).unwrap();
 
-        assert_eq!(extract_video_id(&url), Some("BV1a2b3c".to_string()));
+        assert_eq!(extract_video_id(&url), Some(VideoId::Bv("BV1a2b3c".to_string())));
+
+        let extract = |url: &str| extract_video_id(&Url::parse(url).unwrap());
+
+        assert_eq!(extract("https://m.bilibili.com/video/av170001"), Some(VideoId::Av(170001)));
+        assert_eq!(
+            extract("https://b23.tv/abcdEFG?share_source=copy"),
+            Some(VideoId::Short(Url::parse("https://b23.tv/abcdEFG").unwrap()))
+        );
+
+        assert_eq!(extract("https://b23.tv/"), None);
+        assert_eq!(extract("https://www.bilibili.com/"), None);
+        assert_eq!(extract("https://notbilibili.com/video/BV1a2b3c"), None);
+    }
+
+    #[test]
+    fn test_bilibili_video_id_round_trip() {
+        for video_id in [
+            VideoId::Bv("BV1a2b3c".to_string()),
+            VideoId::Av(170001),
+            VideoId::Short(Url::parse("https://b23.tv/abcdEFG").unwrap()),
+        ] {
+            assert_eq!(VideoId::parse(&video_id.to_string()), Some(video_id));
+        }
+
+        assert_eq!(
+            VideoId::Bv("BV1a2b3c&x=1".to_string()).api_url().unwrap().as_str(),
+            "https://api.bilibili.com/x/web-interface/view/detail?bvid=BV1a2b3c%26x%3D1"
+        );
+
+        assert_eq!(
+            VideoId::Av(170001).api_url().unwrap().as_str(),
+            "https://api.bilibili.com/x/web-interface/view/detail?aid=170001"
+        );
+    }
+
+    #[actix_rt::test]
+    async fn test_canonical_cache_hints() {
+        assert_eq!(av_to_bv(170001).as_deref(), Some("BV17x411w7KC"));
+        assert_eq!(av_to_bv(1054803170).as_deref(), Some("BV1mH4y1u7UA"));
+        assert_eq!(av_to_bv(0), None);
+
+        let snapper = BiliBiliSnapper::new(RequestHeaders::default());
+        let id = |url: &str| snapper.cache_hints(&Url::parse(url).unwrap()).unwrap().id;
+
+        assert_eq!(id("https://m.bilibili.com/video/av170001"), "BV17x411w7KC");
+        assert_eq!(id("https://www.bilibili.com/video/BV17x411w7KC/?p=1"), "BV17x411w7KC");
+        assert_eq!(id("https://b23.tv/abcdEFG"), "short:https://b23.tv/abcdEFG");
+
+        let redirect = CannedResponse {
+            headers: vec![(
+                "Location".to_string(),
+                "https://m.bilibili.com/video/av170001".to_string(),
+            )],
+
+            ..CannedResponse::status(StatusCode::FOUND)
+        };
+
+        let clients = test_clients(
+            CannedTransport::default().respond(Method::HEAD, "https://b23.tv/abcdEFG", redirect)
+        );
+
+        let short_url = Url::parse("https://b23.tv/abcdEFG").unwrap();
+
+        snapper.short_links.resolve(&short_url, &clients, |location| {
+            extract_video_id(location).map(VideoId::canonical)
+        }).await;
+
+        assert_eq!(id("https://b23.tv/abcdEFG"), "BV17x411w7KC");
+    }
+
+    #[test]
+    fn test_videodata_to_snapshot() {
+        let response: BiliBiliResponse = serde_json::from_str(r#"{
+            "code": 0,
+            "data": {
+                "View": {
+                    "bvid": "BV1a2b3c",
+                    "title": "Title",
+                    "desc": "Description",
+                    "duration": 213,
+                    "owner": {"mid": 1, "name": "Uploader"},
+                    "stat": {"view": 123456}
+                },
+                "Tags": [{"tag_id": 1, "tag_name": "cats"}, {"tag_id": 2, "tag_name": " "}]
+            }
+        }"#).unwrap();
+
+        let snapper = BiliBiliSnapper::new(RequestHeaders::default());
+
+        let snapshot = snapper.videodata_to_snapshot(
+            Url::parse("https://b23.tv/abcdEFG").unwrap(),
+            response.data.view,
+            response.data.tags,
+        ).unwrap();
+
+        assert_eq!(snapshot.author.as_deref(), Some("Uploader"));
+        assert_eq!(snapshot.duration_seconds, Some(213));
+        assert_eq!(snapshot.description.as_deref(), Some("123.4K views\n\nDescription"));
+        assert_eq!(snapshot.tags, vec!["#cats"]);
+
+        assert_eq!(
+            snapshot.canonical_url.unwrap().as_str(),
+            "https://www.bilibili.com/video/BV1a2b3c/"
+        );
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,439 @@
+use std::sync::Arc;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use log::debug;
+use resvg::tiny_skia::{Pixmap, Transform};
+use resvg::usvg::{fontdb, Options, Tree};
+use unicode_segmentation::UnicodeSegmentation;
+use url::Url;
+use crabo_model::Snapshot;
+use crate::snapper::Clients;
+use crate::util::sniff_mime_type;
+
+/// Width of card, the size social networks use for link previews.
+pub const CARD_WIDTH: u32 = 1200;
+
+/// Height of card.
+pub const CARD_HEIGHT: u32 = 630;
+
+/// Images larger than this are not embedded into card.
+const MAX_EMBEDDED_IMAGE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Outer margin of card content.
+const MARGIN: u32 = 60;
+
+/// Side of square thumbnail on the right of card.
+const THUMBNAIL_SIDE: u32 = 400;
+
+/// Side of site icon.
+const ICON_SIDE: u32 = 48;
+
+/// Fonts cards are rendered with, first available one is used.
+const FONT_FAMILY: &str = "'Noto Sans', 'DejaVu Sans', 'Liberation Sans', sans-serif";
+
+/// Image embedded into card as data URL, so card is self-contained and
+/// renderers never fetch anything on their own.
+pub struct EmbeddedImage {
+    data_url: String,
+}
+
+impl EmbeddedImage {
+    /// Constructs image from `data` if it is in format renderers
+    /// support.
+    pub fn new(data: &[u8]) -> Option<Self> {
+        let mime_type = sniff_mime_type(data)
+            .filter(|mime_type| {
+                ["image/png", "image/jpeg", "image/gif", "image/webp"].contains(mime_type)
+            })?;
+
+        Some(
+            Self {
+                data_url: format!("data:{mime_type};base64,{}", BASE64.encode(data)),
+            }
+        )
+    }
+
+    /// This function downloads image at `url` with `clients`, so it
+    /// could be embedded. Returns None if image could not be downloaded
+    /// or is not supported.
+    pub async fn fetch(url: &Url, clients: &Clients) -> Option<Self> {
+        let data = match clients.page_client.get_bytes(url).await {
+            Ok(data) => data,
+
+            Err(err) => {
+                debug!("Failed to get card image {url}: {err:?}");
+                return None;
+            }
+        };
+
+        match data.len() <= MAX_EMBEDDED_IMAGE_BYTES {
+            true => Self::new(&data),
+            false => None,
+        }
+    }
+}
+
+/// Returns `text` escaped for XML.
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Returns plain text of snapshot `text` that went through content
+/// cleaner, e.g. `a &amp; b<br />c` becomes `a & b` and `c` on separate
+/// lines, so it is not escaped twice when drawn.
+fn html_to_text(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find(['<', '&']) {
+        plain.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if rest.starts_with('<') {
+            let tag = rest.find('>')
+                .map(|end| &rest[..=end])
+                .filter(|tag| {
+                    let name = tag[1..tag.len() - 1].trim_end_matches('/').trim();
+                    name.eq_ignore_ascii_case("br")
+                });
+
+            if let Some(tag) = tag {
+                plain.push('\n');
+                rest = &rest[tag.len()..];
+                continue;
+            }
+        } else if let Some(end) = rest.find(';').filter(|end| *end <= 10) {
+            let decoded = match &rest[1..end] {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                entity => entity.strip_prefix("#x")
+                    .or(entity.strip_prefix("#X"))
+                    .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+                    .or(entity.strip_prefix('#').and_then(|dec| dec.parse().ok()))
+                    .and_then(char::from_u32),
+            };
+
+            if let Some(decoded) = decoded {
+                plain.push(decoded);
+                rest = &rest[end + 1..];
+                continue;
+            }
+        }
+
+        // not a line break or entity, kept as is
+        plain.push_str(&rest[..1]);
+        rest = &rest[1..];
+    }
+
+    plain.push_str(rest);
+    plain
+}
+
+/// Splits `text` into at most `max_lines` lines of at most `max_chars`
+/// graphemes, breaking at whitespace where possible and at line breaks
+/// of `text`. Text that does not fit is cut with ellipsis.
+fn wrap_text(text: &str, max_chars: usize, max_lines: usize) -> Vec<String> {
+    let mut lines: Vec<String> = vec![];
+    let mut line = String::new();
+    let mut line_chars = 0;
+
+    for paragraph in text.lines() {
+        if line_chars > 0 {
+            lines.push(std::mem::take(&mut line));
+            line_chars = 0;
+        }
+
+        wrap_paragraph(paragraph, max_chars, &mut lines, &mut line, &mut line_chars);
+    }
+
+    if line_chars > 0 {
+        lines.push(line);
+    }
+
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+
+        let last = &mut lines[max_lines - 1];
+        let kept: String = last.graphemes(true).take(max_chars.saturating_sub(1)).collect();
+        *last = format!("{}…", kept.trim_end());
+    }
+
+    lines
+}
+
+/// Helper function to wrap words of `paragraph` into `lines`, `line` is
+/// the one being filled and `line_chars` is number of its graphemes.
+fn wrap_paragraph(
+    paragraph: &str,
+    max_chars: usize,
+    lines: &mut Vec<String>,
+    line: &mut String,
+    line_chars: &mut usize,
+) {
+    for word in paragraph.split_whitespace() {
+        let mut word = word;
+
+        loop {
+            let word_chars = word.graphemes(true).count();
+            let space = usize::from(*line_chars > 0);
+
+            if *line_chars + space + word_chars <= max_chars {
+                if space > 0 {
+                    line.push(' ');
+                }
+
+                line.push_str(word);
+                *line_chars += space + word_chars;
+                break;
+            }
+
+            if *line_chars > 0 {
+                lines.push(std::mem::take(line));
+                *line_chars = 0;
+                continue;
+            }
+
+            // word longer than line is broken
+            let split = word.grapheme_indices(true)
+                .nth(max_chars)
+                .map(|(index, _)| index)
+                .unwrap_or(word.len());
+
+            lines.push(word[..split].to_string());
+            word = &word[split..];
+        }
+    }
+}
+
+/// Returns `<text>` element with `lines` starting at `y`.
+fn text_element(lines: &[String], x: u32, y: u32, line_height: u32, style: &str) -> String {
+    let spans: String = lines.iter()
+        .enumerate()
+        .map(|(i, line)| format!(
+            "<tspan x=\"{x}\" y=\"{}\">{}</tspan>",
+            y + i as u32 * line_height,
+            escape_xml(line),
+        ))
+        .collect();
+
+    format!("<text font-family=\"{FONT_FAMILY}\" {style}>{spans}</text>")
+}
+
+/// This function renders `snapshot` into SVG social card with title,
+/// description, site and `thumbnail` and `icon` if there are such.
+/// Thumbnail of sensitive snapshot is blurred.
+pub fn card_svg(
+    snapshot: &Snapshot,
+    thumbnail: Option<&EmbeddedImage>,
+    icon: Option<&EmbeddedImage>,
+) -> String {
+    let text_width = match thumbnail {
+        Some(_) => CARD_WIDTH - 3 * MARGIN - THUMBNAIL_SIDE,
+        None => CARD_WIDTH - 2 * MARGIN,
+    };
+
+    // average glyph is about half as wide as font size
+    let title_lines = wrap_text(
+        &html_to_text(snapshot.title.as_deref().unwrap_or(snapshot.url.as_str())),
+        (text_width / 28) as usize,
+        3,
+    );
+
+    let description_lines = wrap_text(
+        &html_to_text(snapshot.description.as_deref().unwrap_or_default()),
+        (text_width / 16) as usize,
+        5 - title_lines.len().min(3),
+    );
+
+    let site = snapshot.site_name.as_deref()
+        .or(snapshot.source.as_deref())
+        .or(snapshot.url.host_str())
+        .unwrap_or_default();
+
+    let site_x = match icon {
+        Some(_) => MARGIN + ICON_SIDE + 16,
+        None => MARGIN,
+    };
+
+    let title_y = MARGIN + ICON_SIDE + 80;
+    let description_y = title_y + title_lines.len() as u32 * 62 + 20;
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{CARD_WIDTH}\" \
+        height=\"{CARD_HEIGHT}\" viewBox=\"0 0 {CARD_WIDTH} {CARD_HEIGHT}\">\
+        <defs>\
+        <clipPath id=\"thumbnail\"><rect x=\"{}\" y=\"{}\" width=\"{THUMBNAIL_SIDE}\" \
+        height=\"{THUMBNAIL_SIDE}\" rx=\"24\"/></clipPath>\
+        <filter id=\"blur\"><feGaussianBlur stdDeviation=\"40\"/></filter>\
+        </defs>\
+        <rect width=\"100%\" height=\"100%\" fill=\"#ffffff\"/>\
+        <rect width=\"100%\" height=\"12\" fill=\"{}\"/>",
+        CARD_WIDTH - MARGIN - THUMBNAIL_SIDE,
+        (CARD_HEIGHT - THUMBNAIL_SIDE) / 2,
+        escape_xml(snapshot.accent_color.as_deref().unwrap_or("#6364ff")),
+    );
+
+    if let Some(icon) = icon {
+        svg.push_str(&format!(
+            "<image href=\"{}\" x=\"{MARGIN}\" y=\"{MARGIN}\" width=\"{ICON_SIDE}\" \
+            height=\"{ICON_SIDE}\"/>",
+            icon.data_url,
+        ));
+    }
+
+    svg.push_str(&text_element(
+        &wrap_text(&html_to_text(site), (text_width / 16) as usize, 1),
+        site_x,
+        MARGIN + 36,
+        0,
+        "font-size=\"30\" fill=\"#555555\"",
+    ));
+
+    svg.push_str(&text_element(
+        &title_lines,
+        MARGIN,
+        title_y,
+        62,
+        "font-size=\"52\" font-weight=\"bold\" fill=\"#111111\"",
+    ));
+
+    svg.push_str(&text_element(
+        &description_lines,
+        MARGIN,
+        description_y,
+        42,
+        "font-size=\"30\" fill=\"#333333\"",
+    ));
+
+    if let Some(thumbnail) = thumbnail {
+        let filter = match snapshot.sensitive {
+            true => " filter=\"url(#blur)\"",
+            false => "",
+        };
+
+        svg.push_str(&format!(
+            "<g clip-path=\"url(#thumbnail)\"><image href=\"{}\" x=\"{}\" y=\"{}\" \
+            width=\"{THUMBNAIL_SIDE}\" height=\"{THUMBNAIL_SIDE}\" \
+            preserveAspectRatio=\"xMidYMid slice\"{filter}/></g>",
+            thumbnail.data_url,
+            CARD_WIDTH - MARGIN - THUMBNAIL_SIDE,
+            (CARD_HEIGHT - THUMBNAIL_SIDE) / 2,
+        ));
+    }
+
+    svg.push_str("</svg>");
+    svg
+}
+
+/// This struct rasterizes SVG cards into PNG images. System fonts are
+/// loaded once, when renderer is constructed, and shared by workers.
+#[derive(Clone)]
+pub struct CardRenderer {
+    fontdb: Arc<fontdb::Database>,
+}
+
+impl Default for CardRenderer {
+    fn default() -> Self {
+        let mut fontdb = fontdb::Database::new();
+        fontdb.load_system_fonts();
+
+        Self {
+            fontdb: Arc::new(fontdb),
+        }
+    }
+}
+
+impl CardRenderer {
+    /// Returns number of font faces cards could be rendered with.
+    pub fn font_count(&self) -> usize {
+        self.fontdb.len()
+    }
+
+    /// Rasterizes `svg` made by [card_svg] into PNG image. Rendering
+    /// takes a while, so it is expected to run on blocking thread.
+    pub fn render_png(&self, svg: &str) -> Result<Vec<u8>, String> {
+        let tree = Tree::from_str(svg, &Options::default(), &self.fontdb)
+            .map_err(|err| format!("Malformed card: {err:?}"))?;
+
+        let mut pixmap = Pixmap::new(CARD_WIDTH, CARD_HEIGHT)
+            .ok_or("Failed to allocate card image")?;
+
+        resvg::render(&tree, Transform::default(), &mut pixmap.as_mut());
+
+        pixmap.encode_png()
+            .map_err(|err| format!("Failed to encode card: {err:?}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crabo_model::Snapshot;
+    use crate::card::{card_svg, CardRenderer, EmbeddedImage, html_to_text, wrap_text};
+    use crate::snapper::bare_snapshot;
+
+    #[test]
+    fn test_wrap_text() {
+        assert_eq!(wrap_text("one two three", 7, 3), vec!["one two", "three"]);
+        assert_eq!(wrap_text("abcdefghij", 4, 3), vec!["abcd", "efgh", "ij"]);
+        assert_eq!(wrap_text("one two three four", 7, 1), vec!["one…"]);
+        assert!(wrap_text("   ", 7, 1).is_empty());
+        assert_eq!(wrap_text("one\ntwo three", 9, 3), vec!["one", "two three"]);
+        assert_eq!(wrap_text("one\n\ntwo", 9, 3), vec!["one", "two"]);
+    }
+
+    #[test]
+    fn test_html_to_text() {
+        assert_eq!(html_to_text("a &amp; b<br />c<BR>d"), "a & b\nc\nd");
+        assert_eq!(html_to_text("&lt;p&gt; &#39;x&#x27; &quot;"), "<p> 'x' \"");
+        assert_eq!(html_to_text("fish & chips <3 &unknown;"), "fish & chips <3 &unknown;");
+        assert_eq!(html_to_text("&amp;amp;"), "&amp;");
+    }
+
+    #[test]
+    fn test_card() {
+        let snapshot = Snapshot {
+            title: Some("Cats & <dogs>".to_string()),
+            description: Some("Who is the best?".to_string()),
+            ..bare_snapshot(Url::parse("https://pets.example/a").unwrap())
+        };
+
+        let svg = card_svg(&snapshot, None, None);
+        assert!(svg.contains("Cats &amp; &lt;dogs&gt;"));
+        assert!(svg.contains("pets.example"));
+        assert!(!svg.contains("<image"));
+
+        let png = b"\x89PNG\r\n\x1a\n";
+        let thumbnail = EmbeddedImage::new(png).unwrap();
+        assert!(EmbeddedImage::new(b"<svg/>").is_none());
+
+        let svg = card_svg(&snapshot, Some(&thumbnail), None);
+        assert!(svg.contains("href=\"data:image/png;base64,iVBORw0KGgo=\""));
+
+        let rendered = CardRenderer::default().render_png(&card_svg(&snapshot, None, None)).unwrap();
+        assert!(rendered.starts_with(b"\x89PNG"));
+    }
+
+    #[test]
+    fn test_card_cleaned_description() {
+        let snapshot = Snapshot {
+            title: Some("Fish &amp; chips".to_string()),
+            description: Some("Line one &amp; more<br />Line two".to_string()),
+            ..bare_snapshot(Url::parse("https://food.example/a").unwrap())
+        };
+
+        let svg = card_svg(&snapshot, None, None);
+        assert!(svg.contains("Fish &amp; chips</tspan>"));
+        assert!(svg.contains(">Line one &amp; more</tspan>"));
+        assert!(svg.contains(">Line two</tspan>"));
+        assert!(!svg.contains("&amp;amp;"));
+        assert!(!svg.contains("&lt;br"));
+    }
+}
@@ -0,0 +1,236 @@
+use encoding_rs::{Decoder, Encoding, UTF_8};
+
+/// Number of bytes examined for `<meta charset>`, as HTML spec suggests.
+const PRESCAN_BYTES: usize = 1024;
+
+/// Returns encoding from `charset` parameter of `content_type` value,
+/// e.g. `text/html; charset=Shift_JIS`.
+fn charset_from_content_type(content_type: &str) -> Option<&'static Encoding> {
+    content_type.split(';')
+        .skip(1)
+        .filter_map(|parameter| parameter.split_once('='))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+        .and_then(|(_, value)| Encoding::for_label(
+            value.trim().trim_matches(['"', '\'']).as_bytes()
+        ))
+}
+
+/// Looks for charset declared by `<meta charset>` or
+/// `<meta http-equiv="Content-Type">` in the beginning of HTML `bytes`.
+///
+/// This is a simplified version of prescan described in HTML spec,
+/// it just looks for the first `charset=` substring.
+fn prescan_meta_charset(bytes: &[u8]) -> Option<&'static Encoding> {
+    let head = &bytes[..bytes.len().min(PRESCAN_BYTES)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
+    let position = head.find("charset=")?;
+
+    let label: String = head[position + "charset=".len()..]
+        .trim_start_matches(['"', '\''])
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || "-_:.".contains(*c))
+        .collect();
+
+    // document that was decoded to find meta tag is ASCII compatible,
+    // so UTF-16 declaration is a lie that spec says to treat as UTF-8.
+    Encoding::for_label(label.as_bytes())
+        .map(|encoding| encoding.output_encoding())
+}
+
+/// Looks for encoding declared by XML declaration such as
+/// `<?xml version="1.0" encoding="ISO-8859-1"?>` XHTML pages start with.
+fn prescan_xml_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    let head = &bytes[..bytes.len().min(PRESCAN_BYTES)];
+    let head = String::from_utf8_lossy(head);
+
+    let declaration = head.trim_start_matches('\u{feff}')
+        .trim_start()
+        .strip_prefix("<?xml")?;
+
+    let declaration = &declaration[..declaration.find("?>")?];
+    let position = declaration.find("encoding=")?;
+
+    let label: String = declaration[position + "encoding=".len()..]
+        .trim_start_matches(['"', '\''])
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || "-_:.".contains(*c))
+        .collect();
+
+    Encoding::for_label(label.as_bytes())
+        .map(|encoding| encoding.output_encoding())
+}
+
+/// Selects encoding of HTML document which starts with `bytes` and is
+/// served with `content_type`. Byte order mark takes precedence over
+/// Content-Type header which in turn takes precedence over XML declaration
+/// and meta tags. If nothing is declared, UTF-8 is assumed.
+fn select_encoding(
+    bytes: &[u8],
+    content_type: Option<&str>,
+) -> &'static Encoding {
+    Encoding::for_bom(bytes)
+        .map(|(encoding, _)| encoding)
+        .or_else(|| content_type.and_then(charset_from_content_type))
+        .or_else(|| prescan_xml_encoding(bytes))
+        .or_else(|| prescan_meta_charset(bytes))
+        .unwrap_or(UTF_8)
+}
+
+/// Streaming transcoder of HTML documents to UTF-8.
+///
+/// The first [PRESCAN_BYTES] of document are buffered to figure out its
+/// encoding, the rest is transcoded chunk by chunk as it arrives.
+pub struct Utf8Transcoder {
+    /// Content-Type header value document was served with.
+    content_type: Option<String>,
+
+    /// Bytes buffered until encoding is known.
+    pending: Vec<u8>,
+
+    /// Decoder for selected encoding.
+    decoder: Option<Decoder>,
+}
+
+impl Utf8Transcoder {
+    /// Constructs new instance of [Utf8Transcoder] for document served
+    /// with `content_type`.
+    pub fn new(content_type: Option<&str>) -> Self {
+        Self {
+            content_type: content_type.map(|x| x.to_string()),
+            pending: vec![],
+            decoder: None,
+        }
+    }
+
+    /// Helper method to decode `bytes` with `decoder`.
+    /// `last` is set for the last portion of document.
+    fn decode(decoder: &mut Decoder, bytes: &[u8], last: bool) -> String {
+        let capacity = decoder.max_utf8_buffer_length(bytes.len())
+            .unwrap_or(bytes.len() * 3);
+
+        let mut output = String::with_capacity(capacity);
+        let _ = decoder.decode_to_string(bytes, &mut output, last);
+
+        output
+    }
+
+    /// Helper method to select decoder once enough bytes are buffered,
+    /// or document has ended as indicated by `last`.
+    /// Returns transcoded buffered bytes if decoder was selected.
+    fn flush_pending(&mut self, last: bool) -> Option<String> {
+        if self.pending.len() < PRESCAN_BYTES && !last {
+            return None;
+        }
+
+        let encoding = select_encoding(
+            &self.pending,
+            self.content_type.as_deref(),
+        );
+
+        // new_decoder() deals with byte order mark itself
+        let mut decoder = encoding.new_decoder();
+        let pending = std::mem::take(&mut self.pending);
+        let output = Self::decode(&mut decoder, &pending, last);
+
+        self.decoder = Some(decoder);
+
+        Some(output)
+    }
+
+    /// Transcodes next `chunk` of document.
+    /// Returns UTF-8 text ready for parsing, it could be empty while
+    /// encoding is not known yet.
+    pub fn push(&mut self, chunk: &[u8]) -> String {
+        match self.decoder.as_mut() {
+            Some(decoder) => Self::decode(decoder, chunk, false),
+
+            None => {
+                self.pending.extend_from_slice(chunk);
+                self.flush_pending(false).unwrap_or_default()
+            }
+        }
+    }
+
+    /// Transcodes whatever is left after document has ended.
+    pub fn finish(&mut self) -> String {
+        match self.decoder.as_mut() {
+            Some(decoder) => Self::decode(decoder, &[], true),
+            None => self.flush_pending(true).unwrap_or_default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding_rs::{EUC_KR, SHIFT_JIS, UTF_8, WINDOWS_1251};
+    use crate::charset::{
+        charset_from_content_type,
+        prescan_meta_charset,
+        prescan_xml_encoding,
+        select_encoding,
+        Utf8Transcoder,
+    };
+
+    #[test]
+    fn test_charset_sniffing() {
+        assert_eq!(
+            prescan_meta_charset(b"<html><head><meta charset=\"Shift_JIS\">"),
+            Some(SHIFT_JIS)
+        );
+
+        assert_eq!(
+            prescan_meta_charset(
+                b"<meta http-equiv=\"Content-Type\" \
+                content=\"text/html; charset=euc-kr\">"
+            ),
+            Some(EUC_KR)
+        );
+
+        assert_eq!(prescan_meta_charset(b"<meta charset=utf-16>"), Some(UTF_8));
+        assert_eq!(prescan_meta_charset(b"<html><head><title>"), None);
+
+        assert_eq!(
+            charset_from_content_type("text/html; Charset=\"windows-1251\""),
+            Some(WINDOWS_1251)
+        );
+
+        assert_eq!(charset_from_content_type("text/html"), None);
+
+        assert_eq!(
+            prescan_xml_encoding(
+                b"<?xml version=\"1.0\" encoding=\"Windows-1251\"?>\n<html>"
+            ),
+            Some(WINDOWS_1251)
+        );
+
+        assert_eq!(prescan_xml_encoding(b"<?xml version=\"1.0\"?>"), None);
+
+        // header wins over meta tag
+        assert_eq!(
+            select_encoding(
+                b"<meta charset=euc-kr>",
+                Some("text/html; charset=shift_jis"),
+            ),
+            SHIFT_JIS
+        );
+    }
+
+    #[test]
+    fn test_transcoding() {
+        let html = format!(
+            "<meta charset=shift_jis>{}<title>日本語</title>",
+            " ".repeat(2000)
+        );
+
+        let (encoded, _, _) = SHIFT_JIS.encode(&html);
+        let mut transcoder = Utf8Transcoder::new(Some("text/html"));
+
+        // split in the middle of multibyte sequence
+        let (first, second) = encoded.split_at(encoded.len() - "</title>".len() - 1);
+        let mut decoded = transcoder.push(first);
+        decoded.push_str(&transcoder.push(second));
+        decoded.push_str(&transcoder.finish());
+
+        assert_eq!(decoded, html);
+    }
+}
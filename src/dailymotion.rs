@@ -0,0 +1,385 @@
+use std::fmt::{Display, Formatter};
+use futures::future::LocalBoxFuture;
+use log::{debug, warn};
+use serde::Deserialize;
+use url::Url;
+
+use crabo_model::{SnapError, Snapshot};
+
+use crate::inflight::set_stage;
+use crate::provider_headers::RequestHeaders;
+use crate::quality::{DataSource, with_quality};
+use crate::short_links::{hints_id, parse_hints_id, ShortLinks};
+use crate::snapper::{
+    bare_snapshot,
+    CacheHints,
+    Clients,
+    Snapper,
+    SnapshotAndHints,
+};
+use crate::util::is_host_of;
+
+/// Icon of Dailymotion shown next to site name.
+const DAILYMOTION_ICON_URL: &str = "https://www.dailymotion.com/favicon.ico";
+
+/// Endpoint that describes video by its ID.
+const DAILYMOTION_VIDEO_API_URL: &str = "https://api.dailymotion.com/video/";
+
+/// Fields of video requested from API.
+const VIDEO_FIELDS: &str = "id,title,description,thumbnail_url,owner.screenname,created_time,duration";
+
+/// Dailymotion video as Data API describes it, only fields snapshot is
+/// made of.
+///
+/// Example response
+/// ```{
+///   "id": "x8abcde",
+///   "title": "...",
+///   "description": "...",
+///   "thumbnail_url": "https://s1.dmcdn.net/v/.../x1080",
+///   "owner.screenname": "...",
+///   "created_time": 1234567890,
+///   "duration": 213
+/// }
+/// ```
+#[derive(Deserialize)]
+struct VideoData {
+    /// Video ID, e.g. `x8abcde`.
+    id: Option<String>,
+
+    title: Option<String>,
+    description: Option<String>,
+
+    /// Thumbnail image reference.
+    thumbnail_url: Option<Url>,
+
+    /// Name of uploader.
+    #[serde(rename = "owner.screenname")]
+    owner_screenname: Option<String>,
+
+    /// UNIX timestamp of publication.
+    created_time: Option<i64>,
+
+    /// Duration of video in seconds.
+    duration: Option<u64>,
+}
+
+/// Video ID as it is found in URL.
+#[derive(Clone, Debug, PartialEq)]
+enum VideoId {
+    /// ID of video, e.g. `x8abcde`.
+    Video(String),
+
+    /// Short link, e.g. `https://dai.ly/x8abcde`, which needs to be
+    /// resolved to video ID.
+    Short(Url),
+}
+
+impl VideoId {
+    /// Parses video ID from cache hints `id` made by [VideoId::to_string].
+    fn parse(id: &str) -> Self {
+        match parse_hints_id(id) {
+            Some(url) => Self::Short(url),
+            None => Self::Video(id.to_string()),
+        }
+    }
+
+    /// Returns address of API endpoint that describes this video,
+    /// short links have to be resolved first.
+    fn api_url(&self) -> Option<Url> {
+        let Self::Video(id) = self else {
+            return None;
+        };
+
+        let mut url = Url::parse(DAILYMOTION_VIDEO_API_URL)
+            .and_then(|base| base.join(id))
+            .ok()?;
+
+        url.query_pairs_mut().append_pair("fields", VIDEO_FIELDS);
+        Some(url)
+    }
+}
+
+impl Display for VideoId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Video(id) => write!(f, "{id}"),
+            Self::Short(url) => write!(f, "{}", hints_id(url)),
+        }
+    }
+}
+
+/// Returns true if `id` looks like ID of video or code of short link.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// Returns address of short link with `code`.
+fn short_link_url(code: &str) -> Option<Url> {
+    Url::parse("https://dai.ly/")
+        .and_then(|base| base.join(code))
+        .ok()
+}
+
+/// This function extracts Dailymotion video ID from `url`,
+/// and returns either that ID or None.
+fn extract_video_id(url: &Url) -> Option<VideoId> {
+    let host = url.host_str()?;
+
+    if is_host_of(host, "dai.ly") {
+        let code = url.path().trim_matches('/');
+
+        return match is_valid_id(code) {
+            true => short_link_url(code).map(VideoId::Short),
+            false => None,
+        };
+    }
+
+    if !is_host_of(host, "dailymotion.com") {
+        debug!("Could not extract Dailymotion video ID from URL {}", url);
+        return None;
+    }
+
+    let path = url.path();
+
+    // legacy pages have title after ID, e.g. `/video/x7abcd_title`
+    path.strip_prefix("/video/")
+        .or_else(|| path.strip_prefix("/embed/video/"))
+        .and_then(|s| s.split(['/', '_']).next())
+        .filter(|id| is_valid_id(id))
+        .map(|id| VideoId::Video(id.to_string()))
+}
+
+/// This function converts `video` data to Crabo [Snapshot] of `url`.
+fn videodata_to_snapshot(url: Url, video: VideoData) -> Snapshot {
+    let preview_mime_type = video.thumbnail_url.as_ref()
+        .and_then(|x| mime_guess::from_path(x.path()).first())
+        .map(|m| m.to_string());
+
+    let snapshot = Snapshot {
+        title: video.title,
+        description: video.description.filter(|description| !description.trim().is_empty()),
+        author: video.owner_screenname,
+        preview_url: video.thumbnail_url,
+        preview_mime_type,
+        source: Option::from("Dailymotion".to_string()),
+        site_name: Option::from("Dailymotion".to_string()),
+        icon_url: Url::parse(DAILYMOTION_ICON_URL).ok(),
+        published_at: video.created_time
+            .and_then(|x| chrono::DateTime::from_timestamp(x, 0)),
+        duration_seconds: video.duration.filter(|seconds| *seconds > 0),
+
+        canonical_url: video.id
+            .and_then(|id| Url::parse("https://www.dailymotion.com/video/")
+                .and_then(|base| base.join(&id))
+                .ok()
+            ),
+
+        ..bare_snapshot(url)
+    };
+
+    with_quality(snapshot, DataSource::Api)
+}
+
+/// This snapper describes Dailymotion videos with public Data API, which
+/// needs no credentials for public videos.
+pub struct DailymotionSnapper {
+    /// Headers sent to API along with requests.
+    request_headers: RequestHeaders,
+
+    /// Video IDs resolved short links lead to.
+    short_links: ShortLinks<VideoId>,
+}
+
+impl DailymotionSnapper {
+    /// Constructs new instance of [DailymotionSnapper] that sends
+    /// `request_headers` along with API requests.
+    pub fn new(request_headers: RequestHeaders) -> Self {
+        Self {
+            request_headers,
+            short_links: ShortLinks::default(),
+        }
+    }
+}
+
+impl Snapper for DailymotionSnapper {
+    fn provider(&self) -> String {
+        "dailymotion".into()
+    }
+
+    /// Video pages, legacy pages with title and embedded players share
+    /// cache hints with ID of video.
+    fn cache_hints(&self, video_url: &Url) -> Option<CacheHints> {
+        extract_video_id(video_url)
+            .map(|id| match id {
+                VideoId::Short(url) => self.short_links.get(&url)
+                    .unwrap_or(VideoId::Short(url)),
+
+                id => id,
+            })
+            .map(|id| CacheHints {
+                provider: self.provider(),
+                id: id.to_string(),
+                language: None,
+            })
+    }
+
+    fn snap<'a>(
+        &'a self,
+        url: Url,
+        cache_hints: CacheHints,
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, SnapshotAndHints> {
+        Box::pin(async move {
+            let video_id = match VideoId::parse(&cache_hints.id) {
+                VideoId::Short(short_url) => {
+                    set_stage("resolving short URL");
+
+                    self.short_links.resolve(
+                        &short_url,
+                        clients,
+                        |location| extract_video_id(location)
+                            .filter(|video_id| !matches!(video_id, VideoId::Short(_))),
+                    ).await
+                }
+
+                video_id => Some(video_id),
+            };
+
+            let Some(video_id) = video_id else {
+                return SnapshotAndHints {
+                    snapshot: Err(SnapError::NotFound),
+                    hints: cache_hints,
+                };
+            };
+
+            let Some(query_url) = video_id.api_url() else {
+                return SnapshotAndHints {
+                    snapshot: Err(SnapError::ParseFailed),
+                    hints: cache_hints,
+                };
+            };
+
+            set_stage("calling Dailymotion API");
+
+            match clients.page_client.get_json::<VideoData>(
+                &query_url,
+                &self.request_headers.merged_with(&[]),
+            ).await {
+                Ok(video) => SnapshotAndHints {
+                    snapshot: Ok(videodata_to_snapshot(url, video)),
+
+                    hints: CacheHints {
+                        id: video_id.to_string(),
+                        ..cache_hints
+                    },
+                },
+
+                Err(err) => {
+                    warn!(
+                        "Failed to get details for Dailymotion video '{video_id}', \
+                        API call result is: {err:?}",
+                    );
+
+                    clients.provider_metrics.record_api_error("dailymotion");
+
+                    SnapshotAndHints {
+                        snapshot: Err(err.snap_error()),
+                        hints: cache_hints,
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{Method, StatusCode};
+    use url::Url;
+
+    use crate::dailymotion::{
+        DailymotionSnapper,
+        extract_video_id,
+        VideoData,
+        videodata_to_snapshot,
+        VideoId,
+    };
+    use crate::page_client::{CannedResponse, CannedTransport};
+    use crate::provider_headers::RequestHeaders;
+    use crate::snapper::{Snapper, test_clients};
+
+    #[test]
+    fn test_extract_video_id() {
+        let extract = |url: &str| extract_video_id(&Url::parse(url).unwrap());
+        let video = |id: &str| Some(VideoId::Video(id.to_string()));
+
+        assert_eq!(extract("https://www.dailymotion.com/video/x8abcde?playlist=x6"), video("x8abcde"));
+        assert_eq!(extract("https://www.dailymotion.com/video/x7abcd_some-title"), video("x7abcd"));
+        assert_eq!(extract("https://geo.dailymotion.com/embed/video/x8abcde"), video("x8abcde"));
+        assert_eq!(
+            extract("https://dai.ly/x8abcde"),
+            Some(VideoId::Short(Url::parse("https://dai.ly/x8abcde").unwrap()))
+        );
+
+        assert_eq!(extract("https://dai.ly/"), None);
+        assert_eq!(extract("https://www.dailymotion.com/user"), None);
+        assert_eq!(extract("https://notdailymotion.com/video/x8abcde"), None);
+    }
+
+    #[actix_rt::test]
+    async fn test_cache_hints() {
+        let snapper = DailymotionSnapper::new(RequestHeaders::default());
+        let id = |url: &str| snapper.cache_hints(&Url::parse(url).unwrap()).unwrap().id;
+        let short_url = Url::parse("https://dai.ly/abcdEFG").unwrap();
+
+        assert_eq!(id("https://dai.ly/abcdEFG"), "short:https://dai.ly/abcdEFG");
+
+        let redirect = CannedResponse {
+            headers: vec![(
+                "Location".to_string(),
+                "https://www.dailymotion.com/video/x8abcde".to_string(),
+            )],
+
+            ..CannedResponse::status(StatusCode::MOVED_PERMANENTLY)
+        };
+
+        let clients = test_clients(
+            CannedTransport::default().respond(Method::HEAD, "https://dai.ly/abcdEFG", redirect)
+        );
+
+        snapper.short_links.resolve(&short_url, &clients, extract_video_id).await;
+
+        assert_eq!(id("https://dai.ly/abcdEFG"), "x8abcde");
+        assert_eq!(VideoId::parse("short:https://dai.ly/abcdEFG"), VideoId::Short(short_url.clone()));
+        assert_eq!(VideoId::Short(short_url).api_url(), None);
+
+        assert_eq!(
+            VideoId::parse("x8abcde").api_url().unwrap().as_str(),
+            "https://api.dailymotion.com/video/x8abcde?fields=id%2Ctitle%2Cdescription%2C\
+            thumbnail_url%2Cowner.screenname%2Ccreated_time%2Cduration"
+        );
+    }
+
+    #[test]
+    fn test_videodata_to_snapshot() {
+        let video: VideoData = serde_json::from_str(r#"{
+            "id": "x8abcde",
+            "title": "Title",
+            "description": "",
+            "thumbnail_url": "https://s1.dmcdn.net/v/ABC/x1080.jpg",
+            "owner.screenname": "Uploader",
+            "created_time": 1700000000,
+            "duration": 0
+        }"#).unwrap();
+
+        let snapshot = videodata_to_snapshot(Url::parse("https://dai.ly/abcdEFG").unwrap(), video);
+
+        assert_eq!(snapshot.title.as_deref(), Some("Title"));
+        assert_eq!(snapshot.author.as_deref(), Some("Uploader"));
+        assert_eq!(snapshot.description, None);
+        assert_eq!(snapshot.duration_seconds, None);
+        assert_eq!(snapshot.preview_mime_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(snapshot.canonical_url.unwrap().as_str(), "https://www.dailymotion.com/video/x8abcde");
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt::{Debug, Formatter};
+use std::net::SocketAddr;
+use std::time::Duration;
+use hickory_resolver::config::{ResolverConfig, ResolverOpts};
+use hickory_resolver::TokioAsyncResolver;
+use log::warn;
+
+/// Default number of DNS records kept in cache.
+const DEFAULT_CACHE_SIZE: usize = 4096;
+
+/// Default minimum time record is cached for, even if its TTL is shorter.
+const DEFAULT_MIN_TTL: Duration = Duration::from_secs(60);
+
+/// Default maximum time record is cached for, even if its TTL is longer.
+const DEFAULT_MAX_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Helper function to parse number of seconds from `text`.
+fn parse_ttl(text: &str) -> Option<Duration> {
+    let ttl = text.trim()
+        .parse()
+        .ok()
+        .map(Duration::from_secs);
+
+    if ttl.is_none() {
+        warn!("Ignoring invalid DNS TTL '{text}'");
+    }
+
+    ttl
+}
+
+/// Helper function to construct resolver options with cache of
+/// `cache_size` records and TTLs clamped to `min_ttl..=max_ttl`.
+fn resolver_options(
+    mut options: ResolverOpts,
+    cache_size: usize,
+    min_ttl: Duration,
+    max_ttl: Duration,
+) -> ResolverOpts {
+    // maximum wins if limits contradict each other
+    let min_ttl = min_ttl.min(max_ttl);
+
+    options.cache_size = cache_size;
+    options.positive_min_ttl = Some(min_ttl);
+    options.positive_max_ttl = Some(max_ttl);
+
+    // host that failed to resolve might be fixed soon, so failures are
+    // not remembered for long
+    options.negative_max_ttl = Some(min_ttl);
+
+    options
+}
+
+/// Caching DNS resolver.
+///
+/// Batch of links usually points to a handful of hosts, and every one of
+/// them is resolved before fetch, on every redirect hop and for every
+/// image mime guess. Asking system resolver each time is slow and
+/// floods it, so answers are cached in process.
+///
+/// TTLs are clamped: records with tiny TTL are still cached for a while,
+/// records with huge TTL are refreshed eventually.
+///
+/// Clones share the same cache.
+#[derive(Clone)]
+pub struct DnsCache {
+    resolver: TokioAsyncResolver,
+}
+
+impl Debug for DnsCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DnsCache")
+    }
+}
+
+impl DnsCache {
+    /// Constructs new instance of [DnsCache] that keeps up to
+    /// `cache_size` records for at least `min_ttl` and at most `max_ttl`
+    /// seconds, defaults are used for values that are not set.
+    /// Name servers are taken from system configuration.
+    pub fn new(
+        cache_size: Option<&str>,
+        min_ttl: Option<&str>,
+        max_ttl: Option<&str>,
+    ) -> Self {
+        let (config, options) = hickory_resolver::system_conf::read_system_conf()
+            .unwrap_or_else(|err| {
+                warn!("Failed to read system DNS configuration, using defaults: {err}");
+                (ResolverConfig::default(), ResolverOpts::default())
+            });
+
+        let cache_size = cache_size
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(DEFAULT_CACHE_SIZE);
+
+        let options = resolver_options(
+            options,
+            cache_size,
+            min_ttl.and_then(parse_ttl).unwrap_or(DEFAULT_MIN_TTL),
+            max_ttl.and_then(parse_ttl).unwrap_or(DEFAULT_MAX_TTL),
+        );
+
+        Self {
+            resolver: TokioAsyncResolver::tokio(config, options),
+        }
+    }
+
+    /// This method resolves `host` to addresses to connect to on `port`.
+    pub async fn lookup(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<Vec<SocketAddr>, String> {
+        self.resolver.lookup_ip(host)
+            .await
+            .map(|lookup| lookup.iter()
+                .map(|ip| SocketAddr::new(ip, port))
+                .collect()
+            )
+            .map_err(|err| err.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use hickory_resolver::config::ResolverOpts;
+    use crate::dns_cache::{parse_ttl, resolver_options};
+
+    #[test]
+    fn test_resolver_options() {
+        let options = resolver_options(
+            ResolverOpts::default(),
+            16,
+            Duration::from_secs(30),
+            Duration::from_secs(600),
+        );
+
+        assert_eq!(options.cache_size, 16);
+        assert_eq!(options.positive_min_ttl, Some(Duration::from_secs(30)));
+        assert_eq!(options.positive_max_ttl, Some(Duration::from_secs(600)));
+        assert_eq!(options.negative_max_ttl, Some(Duration::from_secs(30)));
+
+        let options = resolver_options(
+            ResolverOpts::default(),
+            16,
+            Duration::from_secs(600),
+            Duration::from_secs(30),
+        );
+
+        assert_eq!(options.positive_min_ttl, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_parse_ttl() {
+        assert_eq!(parse_ttl(" 300 "), Some(Duration::from_secs(300)));
+        assert_eq!(parse_ttl("-1"), None);
+        assert_eq!(parse_ttl("soon"), None);
+    }
+}
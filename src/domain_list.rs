@@ -0,0 +1,125 @@
+use crate::idn::normalize_host;
+
+/// This function matches `text` against glob `pattern`, where `*`
+/// matches any number of characters, e.g. `cdn*.example.com`.
+fn matches_glob(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+
+    let (mut p, mut t) = (0, 0);
+
+    // position of the last `*` in pattern and of text it matched up to
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else {
+            match backtrack {
+                Some((star, matched)) => {
+                    p = star + 1;
+                    t = matched + 1;
+                    backtrack = Some((star, matched + 1));
+                }
+
+                None => return false,
+            }
+        }
+    }
+
+    pattern[p..].iter().all(|x| *x == b'*')
+}
+
+/// List of domain patterns operator configures, e.g. sites owners of
+/// which asked not to be fetched.
+///
+/// Pattern without `*` matches domain itself and all its subdomains,
+/// so `example.com` matches `www.example.com` too. Pattern with `*` is
+/// matched as glob against the whole host, e.g. `*.example.com` matches
+/// subdomains only.
+#[derive(Clone, Debug, Default)]
+pub struct DomainList {
+    patterns: Vec<String>,
+}
+
+impl DomainList {
+    /// This function constructs new instance of [DomainList] from `text`
+    /// with one pattern per line. Empty lines and lines starting with `#`
+    /// are ignored.
+    pub fn parse(text: &str) -> Self {
+        let patterns = text.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(normalize_host)
+            .collect();
+
+        Self {
+            patterns,
+        }
+    }
+
+    /// This function loads [DomainList] from file at `path`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        std::fs::read_to_string(path)
+            .map(|text| Self::parse(&text))
+            .map_err(|err| format!("Failed to read {path}: {err:?}"))
+    }
+
+    /// Returns number of patterns.
+    pub fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Returns true if there are no patterns.
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Returns true if `host` matches any pattern.
+    pub fn matches(&self, host: &str) -> bool {
+        let host = normalize_host(host);
+
+        self.patterns.iter().any(|pattern| match pattern.contains('*') {
+            true => matches_glob(pattern, &host),
+
+            false => host == *pattern || host.strip_suffix(pattern.as_str())
+                .is_some_and(|prefix| prefix.ends_with('.')),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::domain_list::{DomainList, matches_glob};
+
+    #[test]
+    fn test_glob() {
+        assert!(matches_glob("*.example.com", "a.b.example.com"));
+        assert!(!matches_glob("*.example.com", "example.com"));
+        assert!(matches_glob("cdn*.example.com", "cdn12.example.com"));
+        assert!(matches_glob("a*b*c", "aXbYbZc"));
+        assert!(!matches_glob("a*b*c", "aXbYbZ"));
+    }
+
+    #[test]
+    fn test_domain_list() {
+        let list = DomainList::parse("
+            # removal requests
+            Example.com
+            *.cdn.example.org
+            bücher.de
+        ");
+
+        assert_eq!(list.len(), 3);
+        assert!(list.matches("www.xn--bcher-kva.de"));
+        assert!(list.matches("example.com"));
+        assert!(list.matches("www.EXAMPLE.com."));
+        assert!(!list.matches("notexample.com"));
+        assert!(list.matches("a.cdn.example.org"));
+        assert!(!list.matches("cdn.example.org"));
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Duration;
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use url::Url;
+use crate::util::CRABO_VERSION;
+
+/// Number of reports waiting to be sent, the rest are dropped.
+const MAX_PENDING_REPORTS: usize = 256;
+
+/// Maximum number of reports sent per [REPORT_WINDOW], so a storm of
+/// errors does not turn into a storm of webhook calls.
+const MAX_REPORTS_PER_WINDOW: usize = 30;
+
+/// See [MAX_REPORTS_PER_WINDOW].
+const REPORT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Time webhook has to accept report.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Queue of reports, set only if webhook is configured.
+static REPORTS: OnceLock<mpsc::Sender<ErrorReport>> = OnceLock::new();
+
+/// Error report as posted to webhook.
+#[derive(Debug, Serialize)]
+struct ErrorReport {
+    /// Always `crabo`, so webhook could tell components apart.
+    service: &'static str,
+
+    /// Version of Crabo.
+    version: &'static str,
+
+    /// Kind of error, e.g. `panic` or `snapper`.
+    kind: &'static str,
+
+    /// What happened.
+    message: String,
+
+    /// Details such as provider or host.
+    context: HashMap<&'static str, String>,
+
+    /// When error happened.
+    reported_at: DateTime<Utc>,
+}
+
+/// Reports error of `kind` with `message` and `context` to webhook.
+/// Does nothing if webhook is not configured.
+/// Reports are sent in background, if too many of them are waiting,
+/// new ones are dropped.
+pub fn report_error(
+    kind: &'static str,
+    message: String,
+    context: &[(&'static str, &str)],
+) {
+    let Some(reports) = REPORTS.get() else {
+        return;
+    };
+
+    let report = ErrorReport {
+        service: "crabo",
+        version: CRABO_VERSION,
+        kind,
+        message,
+        context: context.iter()
+            .map(|(key, value)| (*key, value.to_string()))
+            .collect(),
+        reported_at: Utc::now(),
+    };
+
+    if reports.try_send(report).is_err() {
+        warn!("Too many error reports are waiting, dropping one");
+    }
+}
+
+/// This function makes errors reported by [report_error] and panics
+/// go to `webhook`, e.g. Sentry-compatible relay or chat integration.
+/// Returns future that sends reports, it must be spawned.
+pub fn install(webhook: Url) -> impl Future<Output = ()> {
+    let (sender, receiver) = mpsc::channel(MAX_PENDING_REPORTS);
+
+    if REPORTS.set(sender).is_err() {
+        warn!("Error reporter is installed already");
+    }
+
+    let default_hook = std::panic::take_hook();
+
+    std::panic::set_hook(Box::new(move |info| {
+        let location = info.location()
+            .map(|location| location.to_string())
+            .unwrap_or_default();
+
+        report_error("panic", info.to_string(), &[("location", &location)]);
+        default_hook(info);
+    }));
+
+    send_reports(webhook, receiver)
+}
+
+/// Helper function to post reports from `receiver` to `webhook`.
+async fn send_reports(webhook: Url, mut receiver: mpsc::Receiver<ErrorReport>) {
+    let client = awc::Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .finish();
+
+    let mut window_started = Instant::now();
+    let mut sent_in_window = 0;
+
+    while let Some(report) = receiver.recv().await {
+        if window_started.elapsed() >= REPORT_WINDOW {
+            window_started = Instant::now();
+            sent_in_window = 0;
+        }
+
+        if sent_in_window >= MAX_REPORTS_PER_WINDOW {
+            warn!("Too many errors reported, dropping report: {}", report.message);
+            continue;
+        }
+
+        sent_in_window += 1;
+
+        match client.post(webhook.as_str()).send_json(&report).await {
+            Ok(response) if response.status().is_success() => {}
+
+            Ok(response) => warn!(
+                "Error webhook responded with {} to report",
+                response.status(),
+            ),
+
+            Err(err) => warn!("Failed to send error report: {err}"),
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use lol_html::Selector;
+use serde::Deserialize;
+use crate::idn::normalize_host;
+
+/// Snapshot field value of which could be extracted by [ExtractionRule].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum RuleField {
+    Title,
+    Description,
+    Image,
+    Tags,
+}
+
+/// Describes where value of a single field is found on page.
+#[derive(Clone, Debug, Deserialize)]
+pub struct FieldRule {
+    /// CSS selector of element, e.g. `div.cover > img`.
+    pub selector: String,
+
+    /// Attribute of element value is taken from, e.g. `src`.
+    /// If not set, text of element is taken.
+    pub attribute: Option<String>,
+}
+
+/// Set of rules to extract snapshot fields from pages of `domain`
+/// that have broken or no OpenGraph meta tags at all.
+/// Values found by rules take precedence over meta tags.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ExtractionRule {
+    /// Domain rule is applied to, including its subdomains.
+    pub domain: String,
+
+    pub title: Option<FieldRule>,
+    pub description: Option<FieldRule>,
+    pub image: Option<FieldRule>,
+    pub tags: Option<FieldRule>,
+}
+
+impl ExtractionRule {
+    /// Returns all fields this rule defines along with their rules.
+    pub fn fields(&self) -> Vec<(RuleField, &FieldRule)> {
+        [
+            (RuleField::Title, self.title.as_ref()),
+            (RuleField::Description, self.description.as_ref()),
+            (RuleField::Image, self.image.as_ref()),
+            (RuleField::Tags, self.tags.as_ref()),
+        ].into_iter()
+            .filter_map(|(field, rule)| rule.map(|rule| (field, rule)))
+            .collect()
+    }
+
+    /// Returns true if rule should be applied to page on `host`.
+    fn matches_host(&self, host: &str) -> bool {
+        let domain = self.domain.trim_start_matches("*.");
+
+        host.eq_ignore_ascii_case(domain) || host.to_lowercase()
+            .strip_suffix(&domain.to_lowercase())
+            .is_some_and(|prefix| prefix.ends_with('.'))
+    }
+}
+
+/// Operator-defined extraction rules, see [ExtractionRule].
+#[derive(Clone, Debug, Default)]
+pub struct ExtractionRules {
+    rules: Vec<ExtractionRule>,
+}
+
+impl ExtractionRules {
+    /// This function constructs new instance of [ExtractionRules] from
+    /// `json` which is array of [ExtractionRule] definitions, e.g.
+    /// ```json
+    /// [{
+    ///     "domain": "example.com",
+    ///     "title": {"selector": "h2.headline"},
+    ///     "image": {"selector": "div.cover img", "attribute": "src"}
+    /// }]
+    /// ```
+    /// Returns error if `json` is malformed or has invalid selectors.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let mut rules: Vec<ExtractionRule> = serde_json::from_str(json)
+            .map_err(|err| format!("Malformed extraction rules: {err:?}"))?;
+
+        for rule in &mut rules {
+            // hosts of URLs are punycode, so should be domains of rules
+            rule.domain = match rule.domain.strip_prefix("*.") {
+                Some(domain) => format!("*.{}", normalize_host(domain)),
+                None => normalize_host(&rule.domain),
+            };
+        }
+
+        for rule in &rules {
+            for (field, field_rule) in rule.fields() {
+                if let Err(err) = field_rule.selector.parse::<Selector>() {
+                    return Err(
+                        format!(
+                            "Invalid selector '{}' of {field:?} rule for {}: \
+                            {err:?}",
+                            field_rule.selector,
+                            rule.domain,
+                        )
+                    );
+                }
+            }
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// This function loads [ExtractionRules] from JSON file at `path`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {path}: {err:?}"))?;
+
+        Self::from_json(&json)
+    }
+
+    /// Returns number of rules.
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    /// Returns true if there are no rules.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Returns rule for pages on `host` if there is any.
+    /// The most specific domain wins if several rules match.
+    pub fn for_host(&self, host: &str) -> Option<&ExtractionRule> {
+        self.rules.iter()
+            .filter(|rule| rule.matches_host(host))
+            .max_by_key(|rule| rule.domain.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extraction_rules::{ExtractionRules, RuleField};
+
+    #[test]
+    fn test_rules_loading() {
+        let rules = ExtractionRules::from_json(r#"[
+            {"domain": "example.com", "title": {"selector": "h2.headline"}},
+            {
+                "domain": "blog.example.com",
+                "image": {"selector": "div.cover img", "attribute": "src"}
+            }
+        ]"#).unwrap();
+
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules.for_host("www.example.com").unwrap().domain, "example.com");
+        assert_eq!(rules.for_host("Blog.Example.com").unwrap().domain, "blog.example.com");
+        assert!(rules.for_host("notexample.com").is_none());
+
+        let fields: Vec<_> = rules.for_host("example.com")
+            .unwrap()
+            .fields()
+            .into_iter()
+            .map(|(field, _)| field)
+            .collect();
+
+        assert_eq!(fields, vec![RuleField::Title]);
+
+        assert!(
+            ExtractionRules::from_json(
+                r#"[{"domain": "example.com", "tags": {"selector": "a:::b"}}]"#
+            ).is_err()
+        );
+    }
+}
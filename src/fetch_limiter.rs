@@ -0,0 +1,166 @@
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use lru::LruCache;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+/// Default maximum number of requests to origin servers in flight.
+pub const DEFAULT_MAX_CONCURRENT_FETCHES: usize = 32;
+
+/// Default maximum number of requests to a single server in flight.
+pub const DEFAULT_MAX_FETCHES_PER_HOST: usize = 1;
+
+/// Default minimum time between starts of requests to the same server.
+pub const DEFAULT_HOST_DELAY: Duration = Duration::from_millis(250);
+
+/// Number of servers scheduling state is kept for.
+const TRACKED_HOSTS: usize = 4096;
+
+/// Scheduling state of a single server.
+struct HostSlot {
+    /// Limits requests to server in flight.
+    semaphore: Arc<Semaphore>,
+
+    /// When the latest request to server was allowed to start.
+    last_start: Mutex<Option<Instant>>,
+}
+
+/// Permission to make request, request slots are released once it is
+/// dropped.
+pub struct FetchPermit {
+    _host_permit: OwnedSemaphorePermit,
+    _permit: OwnedSemaphorePermit,
+}
+
+/// This struct limits number of concurrent requests to origin servers
+/// across all `/snap` requests and workers, so a burst of large batches
+/// does not open hundreds of connections at once.
+///
+/// It is polite to every single server too: only a few requests to it
+/// are in flight, and they start with a delay after each other, so batch
+/// of links to the same blog does not hit it all at once.
+///
+/// Clones share the same limits.
+#[derive(Clone)]
+pub struct FetchLimiter {
+    semaphore: Arc<Semaphore>,
+    hosts: Arc<Mutex<LruCache<String, Arc<HostSlot>>>>,
+    max_fetches_per_host: usize,
+    host_delay: Duration,
+}
+
+impl FetchLimiter {
+    /// Constructs new instance of [FetchLimiter] that allows up to
+    /// `max_concurrent_fetches` requests in flight, up to
+    /// `max_fetches_per_host` of them to the same server, each starting
+    /// at least `host_delay` after the previous one to that server.
+    pub fn new(
+        max_concurrent_fetches: usize,
+        max_fetches_per_host: usize,
+        host_delay: Duration,
+    ) -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent_fetches.max(1))),
+            hosts: Arc::new(Mutex::new(
+                LruCache::new(NonZeroUsize::new(TRACKED_HOSTS).unwrap())
+            )),
+            max_fetches_per_host: max_fetches_per_host.max(1),
+            host_delay,
+        }
+    }
+
+    /// Helper method to get scheduling state of `host`.
+    fn host_slot(&self, host: &str) -> Arc<HostSlot> {
+        self.hosts.lock()
+            .unwrap()
+            .get_or_insert(host.to_string(), || Arc::new(
+                HostSlot {
+                    semaphore: Arc::new(Semaphore::new(self.max_fetches_per_host)),
+                    last_start: Mutex::new(None),
+                }
+            ))
+            .clone()
+    }
+
+    /// This method waits until request to `host` could be made.
+    pub async fn acquire(&self, host: &str) -> FetchPermit {
+        let slot = self.host_slot(host);
+
+        // semaphores are never closed
+        let host_permit = slot.semaphore.clone()
+            .acquire_owned()
+            .await
+            .unwrap();
+
+        // start time is reserved before sleeping, so concurrent requests
+        // to the same server line up one delay after another
+        let start_at = {
+            let mut last_start = slot.last_start.lock().unwrap();
+
+            let start_at = match *last_start {
+                Some(last_start) => Instant::now().max(last_start + self.host_delay),
+                None => Instant::now(),
+            };
+
+            *last_start = Some(start_at);
+            start_at
+        };
+
+        tokio::time::sleep_until(start_at).await;
+
+        // global slot is taken last, so it is not wasted on waiting
+        let permit = self.semaphore.clone()
+            .acquire_owned()
+            .await
+            .unwrap();
+
+        FetchPermit {
+            _host_permit: host_permit,
+            _permit: permit,
+        }
+    }
+}
+
+impl Default for FetchLimiter {
+    fn default() -> Self {
+        Self::new(
+            DEFAULT_MAX_CONCURRENT_FETCHES,
+            DEFAULT_MAX_FETCHES_PER_HOST,
+            DEFAULT_HOST_DELAY,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use tokio::time::Instant;
+    use crate::fetch_limiter::FetchLimiter;
+
+    #[actix_rt::test]
+    async fn test_fetch_limiter() {
+        let limiter = FetchLimiter::new(1, 1, Duration::ZERO);
+        let shared = limiter.clone();
+
+        let permit = limiter.acquire("a.example").await;
+        assert_eq!(shared.semaphore.available_permits(), 0);
+
+        drop(permit);
+        let _permit = shared.acquire("b.example").await;
+        assert_eq!(limiter.semaphore.available_permits(), 0);
+    }
+
+    #[actix_rt::test]
+    async fn test_host_delay() {
+        let limiter = FetchLimiter::new(8, 2, Duration::from_millis(50));
+        let started_at = Instant::now();
+
+        let _first = limiter.acquire("a.example").await;
+        let _other = limiter.acquire("b.example").await;
+        assert!(started_at.elapsed() < Duration::from_millis(50));
+
+        let _second = limiter.acquire("a.example").await;
+        assert!(started_at.elapsed() >= Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,103 @@
+use log::warn;
+use url::{Host, Url};
+
+/// Scripts letters of which look alike, so mixing them in a single label
+/// is a common trick to make host look like another one.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ConfusableScript {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+/// Returns script of `c` if it is one of confusable ones.
+fn confusable_script(c: char) -> Option<ConfusableScript> {
+    match c {
+        'a'..='z' | 'A'..='Z' => Some(ConfusableScript::Latin),
+        '\u{00C0}'..='\u{024F}' | '\u{1E00}'..='\u{1EFF}' => Some(ConfusableScript::Latin),
+        '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}' => Some(ConfusableScript::Greek),
+        '\u{0400}'..='\u{052F}' => Some(ConfusableScript::Cyrillic),
+        _ => None,
+    }
+}
+
+/// This function normalizes `host` to the form [Url] uses for http(s):
+/// internationalized domain names are converted to punycode, letters are
+/// lowercased and trailing dot is dropped, so `Bücher.DE.` becomes
+/// `xn--bcher-kva.de`.
+///
+/// Hosts that come from configuration have to be normalized so they
+/// match hosts of URLs.
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim().trim_end_matches('.');
+
+    match idna::domain_to_ascii(host) {
+        Ok(ascii_host) => ascii_host,
+
+        Err(err) => {
+            warn!("Failed to normalize host '{host}': {err:?}");
+            host.to_lowercase()
+        }
+    }
+}
+
+/// This function drops trailing dot from host of `url`, otherwise
+/// `example.com.` and `example.com` get separate cache, robots.txt and
+/// suppression state.
+pub fn normalize_url_host(url: &mut Url) {
+    let host = match url.host() {
+        Some(Host::Domain(host)) if host.ends_with('.') => {
+            host.trim_end_matches('.').to_string()
+        }
+
+        _ => return,
+    };
+
+    if let Err(err) = url.set_host(Some(&host)) {
+        warn!("Failed to normalize host of {url}: {err:?}");
+    }
+}
+
+/// Returns true if any label of `host` mixes letters of scripts that
+/// look alike, e.g. Latin and Cyrillic in `аpple.com`.
+pub fn is_homograph(host: &str) -> bool {
+    let (unicode_host, _) = idna::domain_to_unicode(host);
+
+    unicode_host.split('.').any(|label| {
+        let mut scripts = label.chars().filter_map(confusable_script);
+
+        match scripts.next() {
+            Some(first) => scripts.any(|script| script != first),
+            None => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::idn::{is_homograph, normalize_host, normalize_url_host};
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!(normalize_host("Bücher.DE."), "xn--bcher-kva.de");
+        assert_eq!(normalize_host("XN--BCHER-KVA.de"), "xn--bcher-kva.de");
+        assert_eq!(normalize_host("example.com"), "example.com");
+
+        let url = Url::parse("https://bücher.de/").unwrap();
+        assert_eq!(url.host_str().unwrap(), normalize_host("bücher.de"));
+
+        let mut url = Url::parse("https://Example.com./a?b").unwrap();
+        normalize_url_host(&mut url);
+        assert_eq!(url.as_str(), "https://example.com/a?b");
+    }
+
+    #[test]
+    fn test_homographs() {
+        // first letter is Cyrillic
+        assert!(is_homograph(&normalize_host("аpple.com")));
+        assert!(!is_homograph("apple.com"));
+        assert!(!is_homograph(&normalize_host("яндекс.рф")));
+        assert!(!is_homograph(&normalize_host("bücher.de")));
+    }
+}
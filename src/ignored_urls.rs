@@ -0,0 +1,115 @@
+use std::sync::{Arc, RwLock};
+use log::info;
+use url::Url;
+use crate::domain_list::DomainList;
+
+/// Sites ignored unless configured otherwise, these serve login walls
+/// instead of anything worth snapshot.
+pub const DEFAULT_IGNORED_HOSTS: &str = "
+    twitter.com
+    x.com
+";
+
+/// This struct keeps sites known to provide useless data or errors,
+/// URLs of which are reported as ignored rather than snapped.
+/// Sites are [DomainList] patterns, so `example.com` matches subdomains
+/// too and `*` could be used as glob, e.g. `*.cdn.example.com`.
+///
+/// List loaded from file could be reloaded without restart.
+/// Clones share the same state.
+#[derive(Clone, Debug)]
+pub struct IgnoredUrls {
+    /// File list was loaded from, if any.
+    path: Option<String>,
+
+    hosts: Arc<RwLock<DomainList>>,
+}
+
+impl Default for IgnoredUrls {
+    fn default() -> Self {
+        Self::new(DomainList::parse(DEFAULT_IGNORED_HOSTS))
+    }
+}
+
+impl IgnoredUrls {
+    /// Constructs new instance of [IgnoredUrls] with `hosts` patterns.
+    pub fn new(hosts: DomainList) -> Self {
+        Self {
+            path: None,
+            hosts: Arc::new(RwLock::new(hosts)),
+        }
+    }
+
+    /// This function loads [IgnoredUrls] from file at `path` with one
+    /// pattern per line, see [DomainList::parse].
+    pub fn load(path: &str) -> Result<Self, String> {
+        Ok(
+            Self {
+                path: Some(path.to_string()),
+                ..Self::new(DomainList::load(path)?)
+            }
+        )
+    }
+
+    /// Returns number of patterns.
+    pub fn len(&self) -> usize {
+        self.hosts.read().unwrap().len()
+    }
+
+    /// Returns true if there are no patterns.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns true if `url` is ignored. URLs without host are ignored
+    /// too, there is nothing to fetch.
+    pub fn matches(&self, url: &Url) -> bool {
+        match url.host_str() {
+            None => true,
+            Some(host) => self.hosts.read().unwrap().matches(host),
+        }
+    }
+
+    /// Loads patterns again from file they were loaded from, so operator
+    /// could change list without restart. Returns number of patterns or
+    /// error if list was not loaded from file or file is unreadable,
+    /// patterns in use are kept then.
+    pub fn reload(&self) -> Result<usize, String> {
+        let path = self.path.as_deref()
+            .ok_or("Ignored sites are not loaded from file")?;
+
+        let hosts = DomainList::load(path)?;
+        let count = hosts.len();
+
+        *self.hosts.write().unwrap() = hosts;
+
+        info!("Reloaded {count} ignored site patterns from {path}");
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::domain_list::DomainList;
+    use crate::ignored_urls::IgnoredUrls;
+
+    #[test]
+    fn test_ignored_urls() {
+        let ignored = |urls: &IgnoredUrls, url: &str| urls.matches(&Url::parse(url).unwrap());
+
+        let defaults = IgnoredUrls::default();
+        assert!(ignored(&defaults, "https://x.com/a/status/1"));
+        assert!(ignored(&defaults, "https://mobile.twitter.com/a"));
+        assert!(!ignored(&defaults, "https://box.com/a"));
+
+        let configured = IgnoredUrls::new(DomainList::parse("*.cdn.example.com"));
+        assert!(ignored(&configured, "https://a.cdn.example.com/1.png"));
+        assert!(!ignored(&configured, "https://cdn.example.com/1.png"));
+        assert!(!ignored(&configured, "https://x.com/"));
+
+        // nothing to reload from
+        assert!(configured.reload().is_err());
+        assert_eq!(configured.len(), 1);
+    }
+}
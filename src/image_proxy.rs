@@ -0,0 +1,486 @@
+use std::io::Cursor;
+use actix_web::web::Bytes;
+use image::codecs::jpeg::JpegEncoder;
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use url::Url;
+use crabo_model::Snapshot;
+use crate::image_store::{ImageStore, storage_client};
+use crate::page_client::PageClient;
+use crate::util::sniff_mime_type;
+
+/// Presets used unless configured otherwise.
+pub const DEFAULT_IMAGE_PRESETS: &str = "preview:640x640";
+
+/// Images with larger side are not decoded at all.
+const MAX_SOURCE_SIDE: u32 = 8192;
+
+/// Memory decoder could allocate for a single image.
+const MAX_DECODE_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Quality of JPEG images, good enough for previews.
+const JPEG_QUALITY: u8 = 85;
+
+/// Size preset images are scaled down to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImagePreset {
+    /// Name of preset, e.g. `preview`.
+    pub name: String,
+
+    /// Maximum width of image.
+    pub max_width: u32,
+
+    /// Maximum height of image.
+    pub max_height: u32,
+}
+
+/// This function parses presets in `text` such as
+/// `preview:640x640,large:1280x1280`. Malformed presets are skipped.
+pub fn parse_presets(text: &str) -> Vec<ImagePreset> {
+    text.split(',')
+        .map(|preset| preset.trim())
+        .filter(|preset| !preset.is_empty())
+        .filter_map(|preset| {
+            let parsed = preset.split_once(':')
+                .and_then(|(name, size)| {
+                    let (width, height) = size.split_once('x')?;
+
+                    Some(ImagePreset {
+                        name: name.trim().to_string(),
+                        max_width: width.trim().parse().ok()?,
+                        max_height: height.trim().parse().ok()?,
+                    })
+                })
+                .filter(|preset| is_valid_preset_name(&preset.name) &&
+                    preset.max_width > 0 &&
+                    preset.max_height > 0
+                );
+
+            if parsed.is_none() {
+                warn!("Ignoring invalid image preset '{preset}'");
+            }
+
+            parsed
+        })
+        .collect()
+}
+
+/// Returns true if `name` could be used as preset name, which is part of
+/// stored image path.
+fn is_valid_preset_name(name: &str) -> bool {
+    !name.is_empty() && name.chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+/// Returns true if `hash` looks like [image_hash] result, so it is safe
+/// to use as part of stored image path.
+fn is_valid_image_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.chars()
+        .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// Returns name images downloaded from `url` are stored by.
+fn image_hash(url: &Url) -> String {
+    hex::encode(Sha256::digest(url.as_str().as_bytes()))
+}
+
+/// Side of thumbnail average color is computed from, it is as good as
+/// the whole image and much cheaper.
+const COLOR_SAMPLE_SIDE: u32 = 32;
+
+/// Image scaled down to preset and encoded.
+struct ProcessedImage {
+    data: Vec<u8>,
+    mime_type: &'static str,
+    width: u32,
+    height: u32,
+
+    /// Average color of image as `#rrggbb`.
+    color: Option<String>,
+}
+
+/// This function computes average color of `image` as `#rrggbb`.
+/// Pixels are weighted by opacity, so transparent background does not
+/// turn everything black. Returns None if image is fully transparent.
+fn average_color(image: &DynamicImage) -> Option<String> {
+    let sample = image.thumbnail(COLOR_SAMPLE_SIDE, COLOR_SAMPLE_SIDE).to_rgba8();
+
+    let (sums, total_alpha) = sample.pixels()
+        .fold(([0u64; 3], 0u64), |(mut sums, total_alpha), pixel| {
+            let [r, g, b, a] = pixel.0.map(u64::from);
+
+            for (sum, channel) in sums.iter_mut().zip([r, g, b]) {
+                *sum += channel * a;
+            }
+
+            (sums, total_alpha + a)
+        });
+
+    if total_alpha == 0 {
+        return None;
+    }
+
+    let [r, g, b] = sums.map(|sum| sum / total_alpha);
+
+    Some(format!("#{r:02x}{g:02x}{b:02x}"))
+}
+
+/// Helper function to encode `image` as PNG if it is transparent,
+/// otherwise as JPEG.
+fn encode_image(image: &DynamicImage) -> Result<ProcessedImage, String> {
+    let mut data = Cursor::new(vec![]);
+
+    let mime_type = match image.color().has_alpha() {
+        true => {
+            image.write_to(&mut data, ImageFormat::Png)
+                .map_err(|err| err.to_string())?;
+
+            "image/png"
+        }
+
+        false => {
+            let encoder = JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY);
+
+            DynamicImage::ImageRgb8(image.to_rgb8())
+                .write_with_encoder(encoder)
+                .map_err(|err| err.to_string())?;
+
+            "image/jpeg"
+        }
+    };
+
+    Ok(ProcessedImage {
+        data: data.into_inner(),
+        mime_type,
+        width: image.width(),
+        height: image.height(),
+        color: None,
+    })
+}
+
+/// This function decodes image in `data` and scales it down to each of
+/// `presets`, images smaller than preset are not scaled up.
+/// Animated images are reduced to their first frame.
+fn process_image(data: &[u8], presets: &[ImagePreset]) -> Result<Vec<ProcessedImage>, String> {
+    let mut reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|err| err.to_string())?;
+
+    // decompression bombs are tiny files that decode to gigabytes
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_SIDE);
+    limits.max_image_height = Some(MAX_SOURCE_SIDE);
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+    reader.limits(limits);
+
+    let image = reader.decode().map_err(|err| err.to_string())?;
+    let color = average_color(&image);
+
+    presets.iter()
+        .map(|preset| {
+            let fits = image.width() <= preset.max_width &&
+                image.height() <= preset.max_height;
+
+            let processed = match fits {
+                true => encode_image(&image),
+                false => encode_image(&image.thumbnail(preset.max_width, preset.max_height)),
+            }?;
+
+            Ok(ProcessedImage {
+                color: color.clone(),
+                ..processed
+            })
+        })
+        .collect()
+}
+
+/// Configuration of [ImageProxy], shared by instances of all workers.
+#[derive(Clone, Debug)]
+pub struct ImageProxyConfig {
+    /// Where processed images are kept.
+    pub store: ImageStore,
+
+    /// Presets images are scaled down to, the first one is used for
+    /// preview URLs of snapshots.
+    pub presets: Vec<ImagePreset>,
+
+    /// Public address of Crabo, image URLs are relative to it.
+    pub base_url: Url,
+}
+
+/// This struct downloads preview images, scales them down to presets and
+/// stores them, so snapshots could point to Crabo instead of third-party
+/// servers. This way users do not expose their addresses to image hosts,
+/// and previews of hosts that block hotlinking, e.g. pixiv, work.
+///
+/// Holds client for object storage, so one instance per worker is needed.
+pub struct ImageProxy {
+    config: ImageProxyConfig,
+    client: awc::Client,
+}
+
+impl ImageProxy {
+    /// Constructs new instance of [ImageProxy] with `config`.
+    /// Default presets are used if `config` has none.
+    pub fn new(mut config: ImageProxyConfig) -> Self {
+        if config.presets.is_empty() {
+            config.presets = parse_presets(DEFAULT_IMAGE_PRESETS);
+        }
+
+        // otherwise joining drops the last segment of path
+        if !config.base_url.path().ends_with('/') {
+            let path = format!("{}/", config.base_url.path());
+            config.base_url.set_path(&path);
+        }
+
+        Self {
+            config,
+            client: storage_client(),
+        }
+    }
+
+    /// Returns preset by `name` or the default one if `name` is None.
+    fn preset(&self, name: Option<&str>) -> Option<&ImagePreset> {
+        match name {
+            Some(name) => self.config.presets.iter().find(|preset| preset.name == name),
+            None => self.config.presets.first(),
+        }
+    }
+
+    /// Returns public address of image stored as `hash`.
+    fn image_url(&self, hash: &str) -> Option<Url> {
+        self.config.base_url.join(&format!("img/{hash}")).ok()
+    }
+
+    /// Returns true if `url` is address of image served by Crabo.
+    fn is_stored_image_url(&self, url: &Url) -> bool {
+        self.image_url("")
+            .is_some_and(|prefix| url.as_str().starts_with(prefix.as_str()))
+    }
+
+    /// This method makes sure image at `url` found on `page_url` is
+    /// stored in all presets, downloading it with `page_client` if needed.
+    /// Returns the default preset of image.
+    async fn store(
+        &self,
+        hash: &str,
+        url: &Url,
+        page_url: &Url,
+        page_client: &PageClient,
+    ) -> Result<ProcessedImage, String> {
+        let default_preset = self.preset(None).ok_or("no presets")?;
+        let default_key = format!("{hash}/{}", default_preset.name);
+
+        // S3 denies access to missing objects unless listing is allowed
+        let stored = self.config.store.get(&self.client, &default_key)
+            .await
+            .unwrap_or_else(|err| {
+                debug!("Could not check whether {url} is stored: {err}");
+                None
+            });
+
+        // images shared by many pages, e.g. logos, are downloaded once
+        if let Some(data) = stored {
+            // stored images are small, decoding them is cheap
+            let image = image::load_from_memory(&data)
+                .map_err(|err| err.to_string())?;
+
+            return Ok(ProcessedImage {
+                mime_type: sniff_mime_type(&data).unwrap_or("image/jpeg"),
+                data: data.to_vec(),
+                width: image.width(),
+                height: image.height(),
+                color: average_color(&image),
+            });
+        }
+
+        // hosts that block hotlinking accept requests from their pages
+        let referer = format!("{}/", page_url.origin().ascii_serialization());
+        let headers = [("Referer".to_string(), referer)];
+
+        let data = page_client.get(url, &headers)
+            .await
+            .map_err(|err| format!("{err:?}"))?
+            .read_to_end()
+            .await
+            .map_err(|err| format!("{err:?}"))?;
+
+        let presets = self.config.presets.clone();
+
+        let mut processed = tokio::task::spawn_blocking(
+            move || process_image(&data, &presets)
+        )
+            .await
+            .map_err(|err| err.to_string())??;
+
+        // default preset is written last, so it tells others are there
+        for (preset, image) in self.config.presets.iter().zip(&processed).rev() {
+            self.config.store.put(
+                &self.client,
+                &format!("{hash}/{}", preset.name),
+                image.mime_type,
+                image.data.clone(),
+            ).await?;
+        }
+
+        Ok(processed.swap_remove(0))
+    }
+
+    /// This method replaces preview of `snapshot` with address of its
+    /// copy served by Crabo, downloading it with `page_client`.
+    /// If image could not be processed, `snapshot` is returned as is.
+    pub async fn proxy_preview(
+        &self,
+        snapshot: Snapshot,
+        page_client: &PageClient,
+    ) -> Snapshot {
+        let Some(preview_url) = snapshot.preview_url.as_ref() else {
+            return snapshot;
+        };
+
+        // e.g. screenshots are stored by snapper already
+        if self.is_stored_image_url(preview_url) {
+            return snapshot;
+        }
+
+        let hash = image_hash(preview_url);
+
+        match self.store(&hash, preview_url, &snapshot.url, page_client).await {
+            Ok(image) => Snapshot {
+                preview_url: self.image_url(&hash),
+                preview_mime_type: Some(image.mime_type.to_string()),
+                preview_width: Some(image.width),
+                preview_height: Some(image.height),
+                preview_color: image.color,
+                ..snapshot
+            },
+
+            Err(err) => {
+                warn!("Failed to proxy preview {preview_url}: {err}");
+                snapshot
+            }
+        }
+    }
+
+    /// This method reads image stored as `hash` in `preset`, the default
+    /// one if it is None. Returns image data and its content type, or
+    /// None if there is no such image or preset.
+    pub async fn load(
+        &self,
+        hash: &str,
+        preset: Option<&str>,
+    ) -> Result<Option<(Bytes, &'static str)>, String> {
+        if !is_valid_image_hash(hash) {
+            return Ok(None);
+        }
+
+        let Some(preset) = self.preset(preset) else {
+            return Ok(None);
+        };
+
+        let data = self.config.store
+            .get(&self.client, &format!("{hash}/{}", preset.name))
+            .await?;
+
+        Ok(data.map(|data| {
+            let mime_type = sniff_mime_type(&data).unwrap_or("application/octet-stream");
+            (data, mime_type)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use image::{DynamicImage, ImageFormat, Rgb, RgbImage, Rgba, RgbaImage};
+    use url::Url;
+    use crate::image_proxy::{
+        average_color,
+        image_hash,
+        ImagePreset,
+        is_valid_image_hash,
+        parse_presets,
+        process_image,
+    };
+
+    #[test]
+    fn test_presets_parsing() {
+        assert_eq!(
+            parse_presets(" preview:640x480, Bad:1x1, large:1280x, huge:0x10,thumb:64x64"),
+            vec![
+                ImagePreset {
+                    name: "preview".to_string(),
+                    max_width: 640,
+                    max_height: 480,
+                },
+                ImagePreset {
+                    name: "thumb".to_string(),
+                    max_width: 64,
+                    max_height: 64,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_image_hash() {
+        let hash = image_hash(&Url::parse("https://cdn.example/a.png").unwrap());
+
+        assert!(is_valid_image_hash(&hash));
+        assert!(!is_valid_image_hash("../../etc/passwd"));
+        assert!(!is_valid_image_hash(&hash.to_uppercase()));
+    }
+
+    #[test]
+    fn test_image_processing() {
+        let encode = |image: DynamicImage| {
+            let mut data = Cursor::new(vec![]);
+            image.write_to(&mut data, ImageFormat::Png).unwrap();
+            data.into_inner()
+        };
+
+        let presets = parse_presets("preview:100x100,large:400x400");
+
+        let opaque = encode(DynamicImage::ImageRgb8(RgbImage::new(300, 150)));
+        let processed = process_image(&opaque, &presets).unwrap();
+
+        assert_eq!(processed[0].mime_type, "image/jpeg");
+        assert_eq!((processed[0].width, processed[0].height), (100, 50));
+        assert_eq!((processed[1].width, processed[1].height), (300, 150));
+
+        let transparent = encode(DynamicImage::ImageRgba8(RgbaImage::new(10, 10)));
+        let processed = process_image(&transparent, &presets).unwrap();
+
+        assert_eq!(processed[0].mime_type, "image/png");
+
+        assert!(process_image(b"not an image", &presets).is_err());
+    }
+
+    #[test]
+    fn test_average_color() {
+        let halves = RgbImage::from_fn(64, 64, |x, _| match x < 32 {
+            true => Rgb([255, 0, 0]),
+            false => Rgb([0, 0, 255]),
+        });
+
+        assert_eq!(
+            average_color(&DynamicImage::ImageRgb8(halves)).as_deref(),
+            Some("#7f007f")
+        );
+
+        // transparent pixels do not count
+        let logo = RgbaImage::from_fn(64, 64, |x, _| match x < 32 {
+            true => Rgba([0, 128, 0, 255]),
+            false => Rgba([0, 0, 0, 0]),
+        });
+
+        assert_eq!(
+            average_color(&DynamicImage::ImageRgba8(logo)).as_deref(),
+            Some("#008000")
+        );
+
+        let empty = DynamicImage::ImageRgba8(RgbaImage::new(8, 8));
+        assert_eq!(average_color(&empty), None);
+    }
+}
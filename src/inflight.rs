@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+use serde::Serialize;
+
+/// Stage snapping starts at, before snapper reports anything.
+const INITIAL_STAGE: &str = "starting";
+
+tokio::task_local! {
+    /// Entries of URLs snapped by the current future, see [set_stage].
+    static CURRENT: InflightHandle;
+}
+
+/// URL being snapped, as reported by admin endpoint.
+#[derive(Debug, Serialize)]
+pub struct InflightSnap {
+    /// URL being snapped.
+    pub url: String,
+
+    /// Provider of snapper, e.g. `youtube` or `default`.
+    pub provider: String,
+
+    /// Milliseconds since snapping started.
+    pub elapsed_ms: u64,
+
+    /// What snapper is doing, e.g. `fetching page`.
+    pub stage: &'static str,
+}
+
+/// URL being snapped.
+struct Entry {
+    url: String,
+    provider: String,
+    started_at: Instant,
+    stage: &'static str,
+}
+
+type Entries = Arc<Mutex<HashMap<u64, Entry>>>;
+
+/// Entries of URLs snapped by a single future, removed once it is dropped.
+#[derive(Clone)]
+struct InflightHandle {
+    ids: Arc<Vec<u64>>,
+    entries: Entries,
+}
+
+impl InflightHandle {
+    /// Helper method to update stage of all entries.
+    fn set_stage(&self, stage: &'static str) {
+        let mut entries = self.entries.lock().unwrap();
+
+        for id in self.ids.iter() {
+            if let Some(entry) = entries.get_mut(id) {
+                entry.stage = stage;
+            }
+        }
+    }
+}
+
+/// Removes entries once snapping is done, times out or is cancelled.
+struct InflightGuard {
+    handle: InflightHandle,
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        let mut entries = self.handle.entries.lock().unwrap();
+
+        for id in self.handle.ids.iter() {
+            entries.remove(id);
+        }
+    }
+}
+
+/// This struct keeps track of URLs being snapped right now and what
+/// snappers are doing with them, so it is easy to tell which server
+/// stalls long-running batch.
+///
+/// Snappers report their progress with [set_stage], which applies to
+/// URLs of the future that calls it.
+///
+/// Clones share the same registry.
+#[derive(Clone, Default)]
+pub struct InflightRegistry {
+    entries: Entries,
+    next_id: Arc<AtomicU64>,
+}
+
+impl InflightRegistry {
+    /// This method runs `future` that snaps `urls`, which are pairs of
+    /// URL and provider, keeping track of them until it completes.
+    pub async fn track<F: Future>(
+        &self,
+        urls: Vec<(String, String)>,
+        future: F,
+    ) -> F::Output {
+        let started_at = Instant::now();
+
+        let ids = {
+            let mut entries = self.entries.lock().unwrap();
+
+            urls.into_iter()
+                .map(|(url, provider)| {
+                    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+
+                    entries.insert(id, Entry {
+                        url,
+                        provider,
+                        started_at,
+                        stage: INITIAL_STAGE,
+                    });
+
+                    id
+                })
+                .collect()
+        };
+
+        let guard = InflightGuard {
+            handle: InflightHandle {
+                ids: Arc::new(ids),
+                entries: self.entries.clone(),
+            },
+        };
+
+        CURRENT.scope(guard.handle.clone(), future).await
+    }
+
+    /// Returns URLs being snapped, the longest running first.
+    pub fn snaps(&self) -> Vec<InflightSnap> {
+        let mut snaps: Vec<_> = self.entries.lock()
+            .unwrap()
+            .values()
+            .map(|entry| InflightSnap {
+                url: entry.url.clone(),
+                provider: entry.provider.clone(),
+                elapsed_ms: entry.started_at.elapsed().as_millis() as u64,
+                stage: entry.stage,
+            })
+            .collect();
+
+        snaps.sort_by(|a, b| b.elapsed_ms.cmp(&a.elapsed_ms));
+        snaps
+    }
+}
+
+/// Reports that URLs snapped by the current future reached `stage`.
+/// Does nothing if they are not tracked, e.g. in tests.
+pub fn set_stage(stage: &'static str) {
+    let _ = CURRENT.try_with(|handle| handle.set_stage(stage));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inflight::{InflightRegistry, set_stage};
+
+    #[actix_rt::test]
+    async fn test_inflight_registry() {
+        let registry = InflightRegistry::default();
+        let shared = registry.clone();
+
+        registry.track(
+            vec![("https://a.example/".to_string(), "default".to_string())],
+            async {
+                let snaps = shared.snaps();
+                assert_eq!(snaps.len(), 1);
+                assert_eq!(snaps[0].stage, "starting");
+
+                set_stage("fetching page");
+                assert_eq!(shared.snaps()[0].stage, "fetching page");
+            },
+        ).await;
+
+        assert!(registry.snaps().is_empty());
+
+        // untracked futures are fine too
+        set_stage("fetching page");
+    }
+}
@@ -0,0 +1,61 @@
+//! Snapshotting of web-pages and videos without HTTP server around it:
+//! [snapshot::SnapshotMaker] picks snapper for URL, caches and cleans
+//! snapshots. Crabo binary serves it over HTTP, other components and
+//! tests could embed it directly.
+
+pub mod api_snapper;
+pub mod card;
+pub mod dns_cache;
+pub mod domain_list;
+pub mod error_reporter;
+pub mod extraction_rules;
+pub mod fetch_limiter;
+pub mod http_proxy;
+pub mod http_signature;
+pub mod idn;
+pub mod ignored_urls;
+pub mod image_proxy;
+pub mod image_store;
+pub mod inflight;
+pub mod log_throttle;
+pub mod metrics;
+pub mod output_limits;
+pub mod page_client;
+pub mod provider_headers;
+pub mod proxy_connector;
+pub mod renderer;
+pub mod retry;
+pub mod snapper;
+pub mod snapper_registry;
+pub mod snapper_switches;
+pub mod snapshot;
+pub mod snapshot_store;
+pub mod socks_proxy;
+pub mod suppression;
+pub mod timeouts;
+pub mod url_guard;
+pub mod url_normalizer;
+pub mod url_policy;
+pub mod user_agent;
+pub mod util;
+pub mod warc;
+pub mod youtube_quota;
+
+mod activity_pub;
+mod bilibili;
+mod charset;
+mod dailymotion;
+mod html_meta;
+mod page_meta;
+mod peertube;
+mod quality;
+mod reddit;
+mod robots;
+mod short_links;
+mod soundcloud;
+mod spotify;
+mod text_fragment;
+mod tiktok;
+mod wayback;
+mod xhtml;
+mod youtube;
@@ -0,0 +1,164 @@
+use std::fmt::Arguments;
+use std::num::NonZeroUsize;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+use log::warn;
+use lru::LruCache;
+
+/// Time within which only the first warning about server is logged,
+/// the rest are counted and reported by summary.
+pub const SUMMARY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of servers warnings are counted for.
+const TRACKED_HOSTS: usize = 4096;
+
+/// Warnings of all servers, see [warn_for_host].
+static WARNINGS: LazyLock<WarningThrottle> = LazyLock::new(
+    || WarningThrottle::new(SUMMARY_INTERVAL)
+);
+
+/// Warnings about a single server within current interval.
+struct HostWarnings {
+    /// When the first warning of interval was logged.
+    interval_started: Instant,
+
+    /// Number of warnings that were not logged since then.
+    suppressed: u64,
+}
+
+/// This struct decides which warnings about server are logged, so
+/// thousands of failures of one dead site during partial outage do not
+/// drown everything else.
+struct WarningThrottle {
+    hosts: Mutex<LruCache<String, HostWarnings>>,
+    interval: Duration,
+}
+
+impl WarningThrottle {
+    /// Constructs new instance of [WarningThrottle] that logs a single
+    /// warning about server per `interval`.
+    fn new(interval: Duration) -> Self {
+        Self {
+            hosts: Mutex::new(
+                LruCache::new(NonZeroUsize::new(TRACKED_HOSTS).unwrap())
+            ),
+            interval,
+        }
+    }
+
+    /// Returns true if warning about `host` should be logged, otherwise
+    /// it is counted. If interval is over, number of warnings that were
+    /// not logged during it is returned too.
+    fn admit(&self, host: &str) -> (bool, u64) {
+        let mut hosts = self.hosts.lock().unwrap();
+
+        match hosts.get_mut(host) {
+            Some(state) if state.interval_started.elapsed() < self.interval => {
+                state.suppressed += 1;
+                (false, 0)
+            }
+
+            Some(state) => {
+                let suppressed = std::mem::take(&mut state.suppressed);
+                state.interval_started = Instant::now();
+                (true, suppressed)
+            }
+
+            None => {
+                hosts.put(
+                    host.to_string(),
+                    HostWarnings {
+                        interval_started: Instant::now(),
+                        suppressed: 0,
+                    },
+                );
+
+                (true, 0)
+            }
+        }
+    }
+
+    /// Returns number of warnings that were not logged per server whose
+    /// interval is over, forgetting these servers.
+    fn take_summaries(&self) -> Vec<(String, u64)> {
+        let mut hosts = self.hosts.lock().unwrap();
+
+        let finished: Vec<_> = hosts.iter()
+            .filter(|(_, state)| state.interval_started.elapsed() >= self.interval)
+            .map(|(host, _)| host.clone())
+            .collect();
+
+        finished.into_iter()
+            .filter_map(|host| {
+                let state = hosts.pop(&host)?;
+                Some((host, state.suppressed))
+            })
+            .filter(|(_, suppressed)| *suppressed > 0)
+            .collect()
+    }
+}
+
+/// Helper function to log summary of `count` warnings about `host`
+/// that were not logged.
+fn log_summary(host: &str, count: u64) {
+    warn!(
+        "{host}: {count} more warnings in the last {} seconds were not logged",
+        SUMMARY_INTERVAL.as_secs(),
+    );
+}
+
+/// Logs warning `message` about server `host`, unless warning about it
+/// was logged recently already. Such warnings are counted and reported
+/// by a single summary line later.
+pub fn warn_for_host(host: &str, message: Arguments) {
+    let (should_log, suppressed) = WARNINGS.admit(host);
+
+    if suppressed > 0 {
+        log_summary(host, suppressed);
+    }
+
+    if should_log {
+        warn!("{message}");
+    }
+}
+
+/// This function periodically logs summaries of warnings about servers
+/// that went quiet, so counts are not lost.
+pub async fn log_summaries() {
+    let mut interval = tokio::time::interval(SUMMARY_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        for (host, count) in WARNINGS.take_summaries() {
+            log_summary(&host, count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::log_throttle::WarningThrottle;
+
+    #[test]
+    fn test_warning_throttle() {
+        let throttle = WarningThrottle::new(Duration::from_millis(50));
+
+        assert_eq!(throttle.admit("a.example"), (true, 0));
+        assert_eq!(throttle.admit("a.example"), (false, 0));
+        assert_eq!(throttle.admit("a.example"), (false, 0));
+        assert_eq!(throttle.admit("b.example"), (true, 0));
+        assert!(throttle.take_summaries().is_empty());
+
+        std::thread::sleep(Duration::from_millis(60));
+
+        assert_eq!(throttle.admit("a.example"), (true, 2));
+        assert_eq!(throttle.admit("a.example"), (false, 0));
+
+        std::thread::sleep(Duration::from_millis(60));
+
+        assert_eq!(throttle.take_summaries(), vec![("a.example".to_string(), 1)]);
+        assert_eq!(throttle.admit("a.example"), (true, 0));
+    }
+}
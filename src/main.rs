@@ -1,58 +1,442 @@
-#![feature(iter_intersperse)]
-
-mod snapshot;
-mod youtube;
-mod html_meta;
-mod snapper;
-mod robots;
-mod bilibili;
-mod util;
+mod admin_auth;
+mod prefetch;
+mod self_check;
+mod snap_admission;
 
 use std::env;
 use std::sync::Arc;
-use actix_web::{App, HttpServer, post, Responder, web};
+use std::time::Duration;
+use actix_web::{App, delete, get, HttpResponse, HttpServer, post, put, Responder, web};
+use actix_web::http::header::{CACHE_CONTROL, RETRY_AFTER};
 use actix_web::middleware::Logger;
+use chrono::{DateTime, Utc};
 use env_logger::{Env, init_from_env};
-use log::info;
+use log::{info, warn};
 use crabo_model::{SnapRequest, SnapResponse};
 
-use fedineko_http_client::{
-    construct_user_agent,
-    GenericClient,
-    HttpClientParameters,
-    MaxHttpVersion,
-    SuppressedClient
-};
-
 use fedineko_url_utils::required_url_from_config;
 use proxydon_client::ProxydonClient;
-use crate::snapper::Clients;
-use crate::snapshot::SnapshotMaker;
-use crate::util::CRABO_VERSION;
+use serde::Deserialize;
+use url::Url;
+use crabo_core::api_snapper::ApiSnapper;
+use crabo_core::card::{card_svg, CardRenderer, EmbeddedImage};
+use crabo_core::dns_cache::DnsCache;
+use crabo_core::domain_list::DomainList;
+use crabo_core::error_reporter;
+use crabo_core::extraction_rules::ExtractionRules;
+use crabo_core::http_proxy::HttpProxies;
+use crabo_core::http_signature::HttpSigner;
+use crabo_core::idn::normalize_host;
+use crabo_core::ignored_urls::IgnoredUrls;
+use crabo_core::image_proxy::{
+    DEFAULT_IMAGE_PRESETS,
+    ImageProxy,
+    ImageProxyConfig,
+    parse_presets,
+};
+use crabo_core::image_store::{ImageStore, S3Bucket};
+use crabo_core::fetch_limiter::{
+    DEFAULT_HOST_DELAY,
+    DEFAULT_MAX_CONCURRENT_FETCHES,
+    DEFAULT_MAX_FETCHES_PER_HOST,
+    FetchLimiter,
+};
+use crabo_core::log_throttle;
+use crabo_core::metrics::{MetricsWriter, ProviderMetrics};
+use crabo_core::output_limits::OutputLimits;
+use crabo_core::page_client::{DEFAULT_MAX_BODY_BYTES, PageClient};
+use crabo_core::provider_headers::{DomainHeaders, ProviderHeaders};
+use crabo_core::renderer::Renderer;
+use crabo_core::retry::RetryPolicy;
+use crabo_core::snapper::{Clients, SNAPSHOT_SCHEMA_VERSION};
+use crabo_core::snapper_switches::{parse_providers, SnapperSwitch};
+use crabo_core::snapshot::{
+    DEFAULT_STALE_AFTER_HOURS,
+    SnapperConfig,
+    SnapshotMaker,
+    SnapshotMakerBuilder,
+};
+use crabo_core::snapshot_store::{
+    DEFAULT_RETENTION_DAYS,
+    MAX_CHANGES,
+    prune_periodically,
+    SnapshotStore,
+    SqliteStore,
+};
+use crabo_core::socks_proxy::{SocksProxies, SocksProxy};
+use crabo_core::suppression::{HostSuppressor, SuppressionConfig};
+use crabo_core::timeouts::Timeouts;
+use crabo_core::url_guard::UrlGuard;
+use crabo_core::url_normalizer::UrlNormalizer;
+use crabo_core::url_policy::UrlPolicy;
+use crabo_core::user_agent::UserAgent;
+use crabo_core::util::{new_mime_cache, normalize_language_tag};
+use crabo_core::warc::WarcArchive;
+use crabo_core::youtube_quota::{DEFAULT_DAILY_BUDGET, YoutubeQuota};
+use crate::admin_auth::{AdminAuth, AdminToken};
+use crate::prefetch::{
+    DEFAULT_PREFETCH_QUEUE_SIZE,
+    PrefetchQueue,
+    PrefetchRequest,
+    PrefetchResponse,
+};
+use crate::self_check::SelfCheck;
+use crate::snap_admission::{
+    DEFAULT_MAX_CONCURRENT_SNAPS,
+    DEFAULT_MAX_QUEUED_SNAPS,
+    SnapAdmission,
+};
+
+/// Seconds rejected `/snap` callers are asked to wait before retrying.
+const SNAP_RETRY_AFTER_SECONDS: u32 = 5;
 
+/// Proxied images are named by their source, which rarely changes.
+const IMAGE_CACHE_CONTROL: &str = "public, max-age=604800";
+
+/// Context of a single worker. Snapper, prefetch queue, admission and
+/// suppressor are shared by all workers, clients are per worker, yet
+/// share state such as suppressed servers.
 struct SharedContext<'a> {
     snapper: Arc<SnapshotMaker<'a>>,
     clients: Clients,
+    prefetch_queue: PrefetchQueue,
+    snap_admission: SnapAdmission,
+    suppressor: Arc<HostSuppressor>,
+    self_check: SelfCheck,
+    card_renderer: CardRenderer,
+    youtube_quota: YoutubeQuota,
 }
 
 #[post("/snap")]
 async fn snap(
     request: web::Json<SnapRequest>,
     state: web::Data<SharedContext<'_>>,
-) -> impl Responder {
+) -> HttpResponse {
+    let Some(_permit) = state.snap_admission.admit().await else {
+        return HttpResponse::ServiceUnavailable()
+            .insert_header((RETRY_AFTER, SNAP_RETRY_AFTER_SECONDS))
+            .finish();
+    };
+
+    let _busy = state.prefetch_queue.busy();
     let req = request.into_inner();
 
-    let snapshots = state.snapper
-        .snap_many(req.urls, &state.clients, req.bypass_cache)
+    let results = state.snapper
+        .snap_many(
+            req.urls,
+            &state.clients,
+            req.bypass_cache,
+            req.language.as_deref(),
+        )
         .await;
 
-    web::Json(
+    HttpResponse::Ok().json(
         SnapResponse {
-            snapshots
+            schema_version: SNAPSHOT_SCHEMA_VERSION,
+            snapshots: results.snapshots,
+            failures: results.failures,
         }
     )
 }
 
+#[get("/metrics")]
+async fn metrics(state: web::Data<SharedContext<'_>>) -> impl Responder {
+    let mut writer = MetricsWriter::default();
+    state.snap_admission.write_metrics(&mut writer);
+    state.clients.provider_metrics.write_metrics(&mut writer);
+    state.youtube_quota.write_metrics(&mut writer);
+
+    HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(writer.finish())
+}
+
+#[post("/prefetch")]
+async fn prefetch(
+    request: web::Json<PrefetchRequest>,
+    state: web::Data<SharedContext<'_>>,
+) -> impl Responder {
+    let accepted = state.prefetch_queue.push_many(request.into_inner().urls);
+
+    HttpResponse::Accepted().json(
+        PrefetchResponse {
+            accepted
+        }
+    )
+}
+
+/// This function snaps URLs from prefetch queue of `state` whenever
+/// instance is idle, so snapshots are cached before anyone asks for them.
+async fn run_prefetcher(state: web::Data<SharedContext<'static>>) {
+    loop {
+        let urls = state.prefetch_queue.next_batch().await;
+
+        state.snapper
+            .snap_many(urls, &state.clients, false, None)
+            .await;
+    }
+}
+
+#[get("/readyz")]
+async fn readyz(state: web::Data<SharedContext<'_>>) -> impl Responder {
+    let readiness = state.self_check.readiness().await;
+
+    match readiness.ready {
+        true => HttpResponse::Ok().json(readiness),
+        false => HttpResponse::ServiceUnavailable().json(readiness),
+    }
+}
+
+#[get("/admin/suppressed")]
+async fn suppressed(
+    _admin: AdminAuth,
+    state: web::Data<SharedContext<'_>>,
+) -> impl Responder {
+    web::Json(state.suppressor.suppressed_hosts())
+}
+
+#[delete("/admin/suppressed/{host}")]
+async fn unsuppress(
+    _admin: AdminAuth,
+    host: web::Path<String>,
+    state: web::Data<SharedContext<'_>>,
+) -> impl Responder {
+    match state.suppressor.unsuppress(&normalize_host(&host)) {
+        true => HttpResponse::NoContent(),
+        false => HttpResponse::NotFound(),
+    }
+}
+
+#[get("/admin/inflight")]
+async fn inflight(
+    _admin: AdminAuth,
+    state: web::Data<SharedContext<'_>>,
+) -> impl Responder {
+    web::Json(state.snapper.inflight())
+}
+
+#[get("/admin/snappers")]
+async fn snappers(
+    _admin: AdminAuth,
+    state: web::Data<SharedContext<'_>>,
+) -> impl Responder {
+    web::Json(state.snapper.snapper_states())
+}
+
+#[put("/admin/snappers/{provider}")]
+async fn switch_snapper(
+    _admin: AdminAuth,
+    provider: web::Path<String>,
+    request: web::Json<SnapperSwitch>,
+    state: web::Data<SharedContext<'_>>,
+) -> impl Responder {
+    match state.snapper.set_snapper_enabled(&provider, request.enabled) {
+        true => HttpResponse::NoContent(),
+        false => HttpResponse::NotFound(),
+    }
+}
+
+#[post("/admin/ignored/reload")]
+async fn reload_ignored(
+    _admin: AdminAuth,
+    state: web::Data<SharedContext<'_>>,
+) -> impl Responder {
+    match state.snapper.reload_ignored_urls() {
+        Ok(count) => HttpResponse::Ok().json(count),
+
+        Err(err) => {
+            warn!("Failed to reload ignored sites: {err}");
+            HttpResponse::Conflict().body(err)
+        }
+    }
+}
+
+/// Query of changes endpoint.
+#[derive(Deserialize)]
+struct ChangesQuery {
+    /// Changes before this time are not listed, e.g.
+    /// `2024-03-01T12:00:00Z`.
+    since: DateTime<Utc>,
+
+    /// Maximum number of changes listed, [MAX_CHANGES] at most.
+    limit: Option<usize>,
+}
+
+#[get("/changes")]
+async fn changes(
+    query: web::Query<ChangesQuery>,
+    state: web::Data<SharedContext<'_>>,
+) -> HttpResponse {
+    let limit = query.limit.unwrap_or(MAX_CHANGES);
+
+    match state.snapper.changes(query.since, limit).await {
+        Some(changes) => HttpResponse::Ok().json(changes),
+        None => HttpResponse::NotFound().body("Changes are tracked in snapshot store only"),
+    }
+}
+
+/// Query of card endpoints.
+#[derive(Deserialize)]
+struct CardQuery {
+    /// URL card is rendered for.
+    url: Url,
+}
+
+/// Helper function to render SVG card of `url` with snapper and clients
+/// of `state`. Returns response to send instead if there is no card.
+async fn render_card(state: &SharedContext<'_>, url: Url) -> Result<String, HttpResponse> {
+    let Some(_permit) = state.snap_admission.admit().await else {
+        return Err(
+            HttpResponse::ServiceUnavailable()
+                .insert_header((RETRY_AFTER, SNAP_RETRY_AFTER_SECONDS))
+                .finish()
+        );
+    };
+
+    let _busy = state.prefetch_queue.busy();
+
+    let Some(snapshot) = state.snapper
+        .snap_many(vec![url], &state.clients, false, None)
+        .await
+        .snapshots
+        .into_iter()
+        .next() else {
+        return Err(HttpResponse::NotFound().finish());
+    };
+
+    let thumbnail = match &snapshot.preview_url {
+        Some(preview_url) => EmbeddedImage::fetch(preview_url, &state.clients).await,
+        None => None,
+    };
+
+    let icon = match &snapshot.icon_url {
+        Some(icon_url) => EmbeddedImage::fetch(icon_url, &state.clients).await,
+        None => None,
+    };
+
+    Ok(card_svg(&snapshot, thumbnail.as_ref(), icon.as_ref()))
+}
+
+#[get("/card.svg")]
+async fn card_svg_image(
+    query: web::Query<CardQuery>,
+    state: web::Data<SharedContext<'_>>,
+) -> HttpResponse {
+    match render_card(&state, query.into_inner().url).await {
+        Ok(svg) => HttpResponse::Ok()
+            .content_type("image/svg+xml")
+            .insert_header((CACHE_CONTROL, IMAGE_CACHE_CONTROL))
+            .body(svg),
+
+        Err(response) => response,
+    }
+}
+
+#[get("/card.png")]
+async fn card_png_image(
+    query: web::Query<CardQuery>,
+    state: web::Data<SharedContext<'_>>,
+) -> HttpResponse {
+    let svg = match render_card(&state, query.into_inner().url).await {
+        Ok(svg) => svg,
+        Err(response) => return response,
+    };
+
+    let renderer = state.card_renderer.clone();
+
+    let png = tokio::task::spawn_blocking(move || renderer.render_png(&svg))
+        .await
+        .map_err(|err| err.to_string())
+        .and_then(|png| png);
+
+    match png {
+        Ok(png) => HttpResponse::Ok()
+            .content_type("image/png")
+            .insert_header((CACHE_CONTROL, IMAGE_CACHE_CONTROL))
+            .body(png),
+
+        Err(err) => {
+            warn!("Failed to render card: {err}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Helper function to respond with image stored as `hash` in `preset`
+/// by image proxy of `state`.
+async fn serve_image(
+    state: &SharedContext<'_>,
+    hash: &str,
+    preset: Option<&str>,
+) -> HttpResponse {
+    let Some(image_proxy) = &state.clients.image_proxy else {
+        return HttpResponse::NotFound().finish();
+    };
+
+    match image_proxy.load(hash, preset).await {
+        Ok(Some((data, mime_type))) => HttpResponse::Ok()
+            .content_type(mime_type)
+            .insert_header((CACHE_CONTROL, IMAGE_CACHE_CONTROL))
+            .body(data),
+
+        Ok(None) => HttpResponse::NotFound().finish(),
+
+        Err(err) => {
+            warn!("Failed to load image {hash}: {err}");
+            HttpResponse::BadGateway().finish()
+        }
+    }
+}
+
+#[get("/img/{hash}")]
+async fn image(
+    hash: web::Path<String>,
+    state: web::Data<SharedContext<'_>>,
+) -> HttpResponse {
+    serve_image(&state, &hash, None).await
+}
+
+#[get("/img/{hash}/{preset}")]
+async fn image_preset(
+    path: web::Path<(String, String)>,
+    state: web::Data<SharedContext<'_>>,
+) -> HttpResponse {
+    let (hash, preset) = path.into_inner();
+    serve_image(&state, &hash, Some(&preset)).await
+}
+
+/// Helper function to configure object storage from environment
+/// variables starting with `prefix`: directory in `{prefix}_DIR` or S3
+/// bucket in `{prefix}_S3_BUCKET` with the rest of `{prefix}_S3_*`
+/// settings. Returns None if neither is set.
+fn object_store_from_env(prefix: &str) -> Option<ImageStore> {
+    let var = |name: &str| env::var(format!("{prefix}_{name}"));
+
+    match (var("DIR"), var("S3_BUCKET")) {
+        (Ok(directory), _) => Some(ImageStore::Directory(directory.into())),
+
+        (_, Ok(bucket)) => Some(ImageStore::S3(S3Bucket {
+            endpoint: required_url_from_config(
+                &format!("{prefix}_S3_ENDPOINT"),
+                "https://s3.amazonaws.com",
+            ),
+
+            bucket,
+
+            region: var("S3_REGION")
+                .unwrap_or("us-east-1".into()),
+
+            access_key: var("S3_ACCESS_KEY")
+                .unwrap_or_else(|_| panic!("Crabo needs access key in {prefix}_S3_ACCESS_KEY")),
+
+            secret_key: var("S3_SECRET_KEY")
+                .unwrap_or_else(|_| panic!("Crabo needs secret key in {prefix}_S3_SECRET_KEY")),
+        })),
+
+        _ => None,
+    }
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
     init_from_env(
@@ -70,30 +454,494 @@ async fn main() -> std::io::Result<()> {
         .unwrap_or(8003);
 
 
+    let max_page_bytes: usize = env::var("CRABO_MAX_PAGE_BYTES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_BODY_BYTES);
+
+    // shared by all workers, so it is the limit for the whole process
+    let max_concurrent_fetches: usize = env::var("CRABO_MAX_CONCURRENT_FETCHES")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_FETCHES);
+
+    let max_fetches_per_host: usize = env::var("CRABO_MAX_FETCHES_PER_HOST")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_FETCHES_PER_HOST);
+
+    let host_delay = env::var("CRABO_HOST_DELAY_MS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(DEFAULT_HOST_DELAY);
+
+    let fetch_limiter = FetchLimiter::new(
+        max_concurrent_fetches,
+        max_fetches_per_host,
+        host_delay,
+    );
+
+    // shared by all workers, batches beyond both limits are rejected
+    let max_concurrent_snaps: usize = env::var("CRABO_MAX_CONCURRENT_SNAPS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_CONCURRENT_SNAPS);
+
+    let max_queued_snaps: usize = env::var("CRABO_MAX_QUEUED_SNAPS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_MAX_QUEUED_SNAPS);
+
+    let snap_admission = SnapAdmission::new(max_concurrent_snaps, max_queued_snaps);
+
+    let prefetch_queue_size: usize = env::var("CRABO_PREFETCH_QUEUE_SIZE")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_PREFETCH_QUEUE_SIZE);
+
+    // shared by all workers, each of them prefetches when idle
+    let prefetch_queue = PrefetchQueue::new(prefetch_queue_size);
+
     let proxydon_endpoint = fedineko_url_utils::required_url_from_config(
         "PROXYDON_ENDPOINT",
         "http://127.0.0.1:8002",
     );
 
+    // e.g. relay that forwards JSON reports to Sentry or chat
+    match env::var("CRABO_ERROR_WEBHOOK").map(|value| Url::parse(&value)) {
+        Ok(Ok(webhook)) => {
+            info!("Errors are reported to {}", webhook.host_str().unwrap_or_default());
+            actix_web::rt::spawn(error_reporter::install(webhook));
+        }
+
+        Ok(Err(err)) => panic!("Crabo needs valid URL in CRABO_ERROR_WEBHOOK: {err}"),
+        Err(_) => info!("Error reporting is disabled"),
+    }
+
+    // without it YouTube snapshots are made of oEmbed
     let youtube_api_key = env::var("YOUTUBE_API_KEY")
-        .expect("Crabo needs API key provided in YOUTUBE_API_KEY");
+        .ok()
+        .filter(|api_key| !api_key.trim().is_empty());
+
+    // without them Spotify snapper uses oEmbed, which does not tell artists
+    let spotify_credentials = match (
+        env::var("CRABO_SPOTIFY_CLIENT_ID"),
+        env::var("CRABO_SPOTIFY_CLIENT_SECRET"),
+    ) {
+        (Ok(client_id), Ok(client_secret)) => Some((client_id, client_secret)),
+        _ => None,
+    };
+
+    info!("Spotify Web API is used: {}", spotify_credentials.is_some());
+
+    // units a day, e.g. when key is shared with other services or
+    // project got more quota than default
+    let youtube_daily_quota: u64 = env::var("CRABO_YOUTUBE_DAILY_QUOTA")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_DAILY_BUDGET);
+
+    info!("YouTube API budget is {youtube_daily_quota} units a day");
+    let youtube_quota = YoutubeQuota::new(youtube_daily_quota);
+
+    // misconfiguration is reported now rather than as empty snapshots
+    let self_check = SelfCheck::run(
+        proxydon_endpoint.clone(),
+        youtube_api_key.as_deref(),
+    ).await;
+
+    // rules for sites with broken OpenGraph, see ExtractionRules
+    let extraction_rules = match env::var("CRABO_EXTRACTION_RULES") {
+        Ok(path) => ExtractionRules::load(&path)
+            .expect("Crabo needs valid extraction rules in CRABO_EXTRACTION_RULES"),
+
+        Err(_) => ExtractionRules::default(),
+    };
+
+    info!("Loaded {} extraction rules", extraction_rules.len());
+
+    // simple JSON APIs defined by operator, see ApiSnapper
+    let api_snappers = match env::var("CRABO_API_PROVIDERS") {
+        Ok(path) => ApiSnapper::load_many(&path)
+            .expect("Crabo needs valid API providers in CRABO_API_PROVIDERS"),
+
+        Err(_) => vec![],
+    };
+
+    info!("Loaded {} API providers", api_snappers.len());
+
+    // e.g. {"api:musicbrainz": {"user_agent": "..."}}, see ProviderHeaders
+    let provider_headers = match env::var("CRABO_PROVIDER_HEADERS") {
+        Ok(path) => ProviderHeaders::load(&path)
+            .expect("Crabo needs valid provider headers in CRABO_PROVIDER_HEADERS"),
+
+        Err(_) => ProviderHeaders::default(),
+    };
+
+    info!("Loaded headers of {} providers", provider_headers.len());
+
+    // e.g. {"news.example": {"headers": {"Cookie": "..."}}}, see DomainHeaders
+    let domain_headers = match env::var("CRABO_DOMAIN_HEADERS") {
+        Ok(path) => DomainHeaders::load(&path)
+            .expect("Crabo needs valid domain headers in CRABO_DOMAIN_HEADERS"),
+
+        Err(_) => DomainHeaders::default(),
+    };
+
+    info!("Loaded headers of {} domain patterns", domain_headers.len());
+
+    // the same format, sent only once server responds with consent wall
+    let consent_headers = match env::var("CRABO_CONSENT_HEADERS") {
+        Ok(path) => DomainHeaders::load(&path)
+            .expect("Crabo needs valid domain headers in CRABO_CONSENT_HEADERS"),
+
+        Err(_) => DomainHeaders::default(),
+    };
+
+    info!("Loaded consent headers of {} domain patterns", consent_headers.len());
+
+    // e.g. Splash: http://127.0.0.1:8050/render.html?url={url}&wait=2
+    let renderer = Renderer::new(
+        env::var("CRABO_RENDERER_ENDPOINT").ok(),
+        env::var("CRABO_SCREENSHOT_ENDPOINT").ok(),
+    );
+
+    info!(
+        "Rendering service is {}",
+        if renderer.is_some() { "enabled" } else { "disabled" },
+    );
+
+    // internal services Crabo is allowed to talk to, e.g. "10.1.0.0/16"
+    let mut url_guard = UrlGuard::new(
+        &env::var("CRABO_INTERNAL_ALLOWLIST").unwrap_or_default()
+    );
+
+    // shared by all clients, TTLs are clamped to CRABO_DNS_MIN_TTL and
+    // CRABO_DNS_MAX_TTL seconds
+    url_guard.use_dns_cache(
+        DnsCache::new(
+            env::var("CRABO_DNS_CACHE_SIZE").ok().as_deref(),
+            env::var("CRABO_DNS_MIN_TTL").ok().as_deref(),
+            env::var("CRABO_DNS_MAX_TTL").ok().as_deref(),
+        )
+    );
+
+    // rendering service is usually internal, its hosts are allowed for
+    // its own client only, so links in posts never reach it
+    let renderer_guard = renderer.as_ref().map(|renderer| {
+        let mut renderer_guard = url_guard.clone();
+
+        for host in renderer.endpoint_hosts() {
+            renderer_guard.allow_host(&host);
+        }
+
+        renderer_guard
+    });
+
+    // in seconds, e.g. CRABO_PROVIDER_TIMEOUTS="youtube=5,default=20"
+    let timeouts = Timeouts::new(
+        env::var("CRABO_CONNECT_TIMEOUT").ok().as_deref(),
+        env::var("CRABO_READ_TIMEOUT").ok().as_deref(),
+        env::var("CRABO_TOTAL_TIMEOUT").ok().as_deref(),
+        &env::var("CRABO_PROVIDER_TIMEOUTS").unwrap_or_default(),
+    );
+
+    info!("Timeouts: {timeouts:?}");
+
+    // e.g. CRABO_RETRY_ON="connection,server_error", empty turns retries off
+    let retry_policy = RetryPolicy::new(
+        env::var("CRABO_RETRY_ATTEMPTS").ok().as_deref(),
+        env::var("CRABO_RETRY_DELAY").ok().as_deref(),
+        env::var("CRABO_RETRY_ON").ok().as_deref(),
+    );
+
+    info!("Retry policy: {retry_policy:?}");
+
+    // public key is expected in document of actor, e.g. instance actor
+    let signer = match (env::var("CRABO_ACTOR_ID"), env::var("CRABO_ACTOR_KEY")) {
+        (Ok(actor_id), Ok(path)) => {
+            let actor_id = Url::parse(&actor_id)
+                .expect("Crabo needs valid URL in CRABO_ACTOR_ID");
+
+            let signer = HttpSigner::load(&actor_id, &path)
+                .expect("Crabo needs readable private key in CRABO_ACTOR_KEY");
+
+            info!("Requests are signed with {} when required", signer.key_id());
+            Some(Arc::new(signer))
+        }
+
+        _ => None,
+    };
+
+    // e.g. "onion socks5h://127.0.0.1:9050" per line routes .onion sites
+    // through Tor, CRABO_SOCKS_PROXY routes everything else
+    let socks_proxies = match env::var("CRABO_SOCKS_PROXIES") {
+        Ok(path) => SocksProxies::load(&path)
+            .expect("Crabo needs valid proxies in CRABO_SOCKS_PROXIES"),
+
+        Err(_) => SocksProxies::default(),
+    };
+
+    let socks_proxies = socks_proxies.with_default(
+        env::var("CRABO_SOCKS_PROXY").ok().map(|proxy| {
+            SocksProxy::parse(&proxy)
+                .expect("Crabo needs valid proxy URL in CRABO_SOCKS_PROXY")
+        })
+    );
+
+    info!("SOCKS proxies: {socks_proxies:?}");
+
+    // conventional variables, lowercase ones are common too
+    let proxy_env = |name: &str| env::var(name)
+        .or_else(|_| env::var(name.to_lowercase()))
+        .ok();
+
+    let http_proxies = HttpProxies::new(
+        proxy_env("HTTP_PROXY").as_deref(),
+        proxy_env("HTTPS_PROXY").as_deref(),
+    )
+        .expect("Crabo needs valid proxy URLs in HTTP_PROXY and HTTPS_PROXY")
+        .with_no_proxy(&proxy_env("NO_PROXY").unwrap_or_default());
+
+    info!("HTTP proxies: {http_proxies:?}");
+
+    // in grapheme clusters, longer texts are cut with ellipsis
+    let output_limits = OutputLimits::new(
+        env::var("CRABO_MAX_TITLE_LENGTH").ok().as_deref(),
+        env::var("CRABO_MAX_DESCRIPTION_LENGTH").ok().as_deref(),
+        env::var("CRABO_MAX_SOURCE_LENGTH").ok().as_deref(),
+        env::var("CRABO_MAX_TAG_LENGTH").ok().as_deref(),
+    );
 
-    let snapper = Arc::new(SnapshotMaker::new(youtube_api_key));
+    info!("Output limits: {output_limits:?}");
+
+    // files with one domain pattern per line, see DomainList
+    let denylist = match env::var("CRABO_DOMAIN_DENYLIST") {
+        Ok(path) => DomainList::load(&path)
+            .expect("Crabo needs readable denylist in CRABO_DOMAIN_DENYLIST"),
+
+        Err(_) => DomainList::default(),
+    };
+
+    info!("Loaded {} denied domain patterns", denylist.len());
+
+    let allowlist = env::var("CRABO_DOMAIN_ALLOWLIST")
+        .ok()
+        .map(|path| DomainList::load(&path)
+            .expect("Crabo needs readable allowlist in CRABO_DOMAIN_ALLOWLIST")
+        );
+
+    if let Some(allowlist) = &allowlist {
+        info!("Only {} allowed domain patterns are fetched", allowlist.len());
+    }
+
+    // twitter.com and x.com unless set, reloaded via /admin/ignored/reload
+    let ignored_urls = match env::var("CRABO_IGNORED_SITES") {
+        Ok(path) => IgnoredUrls::load(&path)
+            .expect("Crabo needs readable list of ignored sites in CRABO_IGNORED_SITES"),
+
+        Err(_) => IgnoredUrls::default(),
+    };
+
+    info!("Loaded {} ignored site patterns", ignored_urls.len());
+
+    // e.g. CRABO_DROPPED_PARAMETERS="utm*,fbclid", replaces default list
+    let url_normalizer = UrlNormalizer::new(
+        env::var("CRABO_DROPPED_PARAMETERS").ok().as_deref(),
+    );
+
+    // only http(s) on ports 80 and 443 is fetched, unless e.g. "8080,8443"
+    let url_policy = UrlPolicy::new(
+        &env::var("CRABO_EXTRA_PORTS").unwrap_or_default(),
+        denylist,
+        allowlist,
+        env::var("CRABO_REJECT_HOMOGRAPHS").is_ok_and(|value| value == "true"),
+    );
+
+    let wayback_fallback = env::var("CRABO_WAYBACK_FALLBACK")
+        .is_ok_and(|value| value == "true");
+
+    info!(
+        "Wayback Machine fallback is {}",
+        if wayback_fallback { "enabled" } else { "disabled" },
+    );
+
+    // e.g. "bilibili,api:example", could be changed via /admin/snappers
+    let disabled_snappers = parse_providers(
+        &env::var("CRABO_DISABLED_SNAPPERS").unwrap_or_default()
+    );
+
+    // callers are expected to ask for refresh of stale snapshots
+    let stale_after_hours: i64 = env::var("CRABO_STALE_AFTER_HOURS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_STALE_AFTER_HOURS);
 
     let server_url = required_url_from_config(
         "FEDINEKO_URL",
         "http://127.0.0.1",
     );
 
-    let crabo_user_agent = construct_user_agent(
-        &server_url,
-        "crabo",
-        CRABO_VERSION,
+    // sent to every server along with page describing crawler, which is
+    // Fedineko itself unless set
+    let user_agent = UserAgent::new(
+        &env::var("CRABO_USER_AGENT").unwrap_or_default(),
+        Some(required_url_from_config("CRABO_CONTACT_URL", server_url.as_str())),
+    );
+
+    let crabo_user_agent = user_agent.header();
+    info!("User agent: {crabo_user_agent}");
+
+    // e.g. `ja`, so Japanese-speaking instance gets Japanese titles
+    // from APIs that translate them
+    let default_language = env::var("CRABO_LANGUAGE")
+        .ok()
+        .map(|language| normalize_language_tag(&language)
+            .expect("Crabo needs valid language tag in CRABO_LANGUAGE")
+        );
+
+    info!("Default language of snapshots: {default_language:?}");
+
+    let snapper_config = SnapperConfig {
+        youtube_api_key,
+        youtube_quota: youtube_quota.clone(),
+        spotify_credentials,
+        user_agent,
+        wayback_fallback,
+        provider_headers,
+        domain_headers,
+        consent_headers,
+        disabled_snappers,
+        stale_after: chrono::Duration::hours(stale_after_hours),
+        default_language,
+        ..SnapperConfig::default()
+    };
+
+    // snapshots outlive cache entries if database is set
+    let snapshot_store: Option<Arc<dyn SnapshotStore>> = match env::var("CRABO_SNAPSHOT_DB") {
+        Ok(path) => match SqliteStore::open(&path) {
+            Ok(store) => {
+                info!("Snapshots are stored in {path}");
+                Some(Arc::new(store))
+            }
+
+            Err(err) => {
+                warn!("Snapshots are not stored: {err}");
+                None
+            }
+        },
+
+        Err(_) => None,
+    };
+
+    let store_retention_days: i64 = env::var("CRABO_STORE_RETENTION_DAYS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_RETENTION_DAYS);
+
+    let snapper = Arc::new(
+        SnapshotMakerBuilder::new(snapper_config)
+            .extraction_rules(extraction_rules)
+            .renderer(renderer)
+            .api_snappers(api_snappers)
+            .url_policy(url_policy.clone())
+            .ignored_urls(ignored_urls)
+            .url_normalizer(url_normalizer)
+            .timeouts(timeouts.clone())
+            .output_limits(output_limits)
+            .store(snapshot_store.clone())
+            .build()
     );
 
+    // previews are downloaded and served by Crabo if storage is set
+    let image_store = object_store_from_env("CRABO_IMAGE");
+
+    // fetched pages are kept as WARC files if storage is set
+    let warc_store = object_store_from_env("CRABO_WARC");
+
+    if let Some(store) = &warc_store {
+        info!("Fetched pages are archived as WARC files in {store:?}");
+    }
+
+    let image_proxy_config = image_store.map(|store| {
+        info!("Preview images are proxied, stored in {store:?}");
+
+        ImageProxyConfig {
+            store,
+
+            // e.g. "preview:640x640,large:1280x1280", the first is default
+            presets: parse_presets(
+                &env::var("CRABO_IMAGE_PRESETS")
+                    .unwrap_or(DEFAULT_IMAGE_PRESETS.into())
+            ),
+
+            base_url: env::var("CRABO_PUBLIC_URL")
+                .ok()
+                .and_then(|value| Url::parse(&value).ok())
+                .expect("Crabo needs its public URL in CRABO_PUBLIC_URL to proxy images"),
+        }
+    });
+
+    // servers that keep failing are left alone for a while, except these
+    let suppression_exempt = match env::var("CRABO_SUPPRESSION_EXEMPT") {
+        Ok(path) => DomainList::load(&path)
+            .expect("Crabo needs readable list in CRABO_SUPPRESSION_EXEMPT"),
+
+        Err(_) => DomainList::default(),
+    };
+
+    // e.g. CRABO_SUPPRESSION_STATUSES="5xx,429" counts as failures
+    let suppression_config = SuppressionConfig::new(
+        env::var("CRABO_SUPPRESSION_FAILURES").ok().as_deref(),
+        env::var("CRABO_SUPPRESSION_MINUTES").ok().as_deref(),
+        env::var("CRABO_SUPPRESSION_STATUSES").ok().as_deref(),
+        suppression_exempt,
+    );
+
+    info!("Suppression: {suppression_config:?}");
+
+    // state shared by clients of all workers
+    let suppressor = Arc::new(HostSuppressor::with_config(suppression_config));
+    let mime_cache = Arc::new(new_mime_cache());
+    let provider_metrics = ProviderMetrics::default();
+
     info!("Fedineko URL: {server_url}");
     info!("Crabo listens on {}:{}", host, port);
     info!("Proxydon endpoint: {proxydon_endpoint}");
+    info!("Responses are read up to {max_page_bytes} bytes");
+    info!("Up to {prefetch_queue_size} URLs could wait to be prefetched");
+    info!(
+        "Up to {max_concurrent_snaps} batches are snapped concurrently, \
+        {max_queued_snaps} more could wait"
+    );
+    info!(
+        "Up to {max_concurrent_fetches} requests are made concurrently, \
+        {max_fetches_per_host} per server with {host_delay:?} delay"
+    );
+
+    // repeated warnings about the same server are summarized periodically
+    actix_web::rt::spawn(log_throttle::log_summaries());
+
+    if let Some(store) = snapshot_store {
+        info!("Stored snapshots are kept for {store_retention_days} days");
+
+        actix_web::rt::spawn(prune_periodically(
+            store,
+            chrono::Duration::days(store_retention_days),
+        ));
+    }
+
+    // admin endpoints share listener with public ones, so they need token
+    let admin_token = AdminToken::new(env::var("CRABO_ADMIN_TOKEN").ok().as_deref());
+
+    info!(
+        "Admin endpoints are {}",
+        if admin_token.is_enabled() { "enabled" } else { "disabled, CRABO_ADMIN_TOKEN is not set" },
+    );
+
+    let card_renderer = CardRenderer::default();
+    info!("Cards are rendered with {} font faces", card_renderer.font_count());
 
     HttpServer::new(move || {
         let context = SharedContext {
@@ -102,31 +950,74 @@ async fn main() -> std::io::Result<()> {
             clients: Clients {
                 proxydon_client: ProxydonClient::new(&proxydon_endpoint),
 
-                generic_client: GenericClient::new_with_user_agent(
-                    &crabo_user_agent
-                ),
-
-                no_follow_client: GenericClient::new_with_parameters(
-                    HttpClientParameters {
-                        extra_headers: vec![
-                            GenericClient::user_agent_header(&crabo_user_agent)
-                        ],
+                page_client: PageClient::new(
+                    &crabo_user_agent,
+                    max_page_bytes,
+                    url_guard.clone(),
+                    url_policy.clone(),
+                    &timeouts,
+                    fetch_limiter.clone(),
+                    suppressor.clone(),
+                    socks_proxies.clone(),
+                    http_proxies.clone(),
+                )
+                    .with_retry_policy(retry_policy.clone())
+                    .with_signer(signer.clone()),
 
-                        middleware: None,
-                        max_http_version: MaxHttpVersion::V2,
-                        max_redirects: 0,
-                    }
+                // internal service is reached directly, not through proxies
+                renderer_client: renderer_guard.clone().map(|renderer_guard| PageClient::new(
+                    &crabo_user_agent,
+                    max_page_bytes,
+                    renderer_guard,
+                    url_policy.clone(),
+                    &timeouts,
+                    fetch_limiter.clone(),
+                    suppressor.clone(),
+                    SocksProxies::default(),
+                    HttpProxies::default(),
+                )
+                    .with_retry_policy(retry_policy.clone())
                 ),
 
-                suppressed_client: SuppressedClient::new(
-                    GenericClient::new_with_user_agent(&crabo_user_agent),
-                ),
+                url_guard: url_guard.clone(),
+                fetch_limiter: fetch_limiter.clone(),
+                mime_cache: mime_cache.clone(),
+                provider_metrics: provider_metrics.clone(),
+                image_proxy: image_proxy_config.clone().map(ImageProxy::new),
+                warc_archive: warc_store.clone().map(WarcArchive::new),
             },
+
+            prefetch_queue: prefetch_queue.clone(),
+            snap_admission: snap_admission.clone(),
+            suppressor: suppressor.clone(),
+            self_check: self_check.clone(),
+            card_renderer: card_renderer.clone(),
+            youtube_quota: youtube_quota.clone(),
         };
 
+        let context = web::Data::new(context);
+
+        // factory runs on worker thread, so prefetcher uses its clients
+        actix_web::rt::spawn(run_prefetcher(context.clone()));
+
         App::new()
             .service(snap)
-            .app_data(web::Data::new(context))
+            .service(prefetch)
+            .service(metrics)
+            .service(readyz)
+            .service(suppressed)
+            .service(unsuppress)
+            .service(inflight)
+            .service(snappers)
+            .service(switch_snapper)
+            .service(reload_ignored)
+            .service(changes)
+            .service(card_svg_image)
+            .service(card_png_image)
+            .service(image)
+            .service(image_preset)
+            .app_data(context)
+            .app_data(web::Data::new(admin_token.clone()))
             .wrap(Logger::default())
     })
         .bind((host, port))?
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use itertools::Itertools;
+use crate::error_reporter::report_error;
+
+/// Upper bounds of snapper latency histogram buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 10] = [0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 20.0, 30.0];
+
+/// Writer of metrics in Prometheus text exposition format, see
+/// <https://prometheus.io/docs/instrumenting/exposition_formats/>.
+#[derive(Default)]
+pub struct MetricsWriter {
+    text: String,
+}
+
+/// Helper function to escape label `value`.
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+impl MetricsWriter {
+    /// Writes help and type of metric `name` of `kind`, must be followed by
+    /// its samples.
+    pub fn describe(&mut self, name: &str, help: &str, kind: &str) {
+        // writing to String never fails
+        let _ = writeln!(self.text, "# HELP {name} {help}");
+        let _ = writeln!(self.text, "# TYPE {name} {kind}");
+    }
+
+    /// Writes sample `value` of metric `name` with `labels`.
+    pub fn sample(
+        &mut self,
+        name: &str,
+        labels: &[(&str, &str)],
+        value: impl Display,
+    ) {
+        let labels = labels.iter()
+            .map(|(label, value)| format!("{label}=\"{}\"", escape_label_value(value)))
+            .join(",");
+
+        let _ = match labels.is_empty() {
+            true => writeln!(self.text, "{name} {value}"),
+            false => writeln!(self.text, "{name}{{{labels}}} {value}"),
+        };
+    }
+
+    /// Writes gauge `name` with `help` and current `value`.
+    pub fn gauge(&mut self, name: &str, help: &str, value: impl Display) {
+        self.describe(name, help, "gauge");
+        self.sample(name, &[], value);
+    }
+
+    /// Writes counter `name` with `help` and current `value`.
+    pub fn counter(&mut self, name: &str, help: &str, value: impl Display) {
+        self.describe(name, help, "counter");
+        self.sample(name, &[], value);
+    }
+
+    /// Returns metrics written so far.
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+/// Returns true if `count` is 10, 100, 1000 and so on.
+fn is_report_milestone(count: u64) -> bool {
+    count >= 10 && 10u64.pow(count.ilog10()) == count
+}
+
+/// Outcome of snapping a single URL.
+pub enum SnapOutcome {
+    /// Snapper produced snapshot.
+    Success,
+
+    /// Snapper finished, but produced nothing.
+    Empty,
+
+    /// Snapper did not finish in time.
+    TimedOut,
+}
+
+/// Counters and latency histogram of a single snapper.
+#[derive(Default)]
+struct ProviderStats {
+    attempts: u64,
+    successes: u64,
+    empty_results: u64,
+    timeouts: u64,
+    api_errors: u64,
+
+    /// Number of failed API calls since the latest success.
+    consecutive_api_errors: u64,
+
+    /// Number of snaps that took up to [LATENCY_BUCKETS] seconds,
+    /// not cumulative.
+    latency_buckets: [u64; LATENCY_BUCKETS.len()],
+    latency_sum: f64,
+}
+
+/// Metrics of snappers by provider, e.g. `youtube` or `default`.
+///
+/// When e.g. YouTube API key expires, snapshots silently turn empty,
+/// failing API calls and empty results of a provider make it obvious.
+///
+/// Clones share the same metrics.
+#[derive(Clone, Default)]
+pub struct ProviderMetrics {
+    providers: Arc<Mutex<HashMap<String, ProviderStats>>>,
+}
+
+impl ProviderMetrics {
+    /// Records `outcome` of snapping URL by snapper of `provider`
+    /// that took `latency`.
+    pub fn record_snap(
+        &self,
+        provider: &str,
+        outcome: SnapOutcome,
+        latency: Duration,
+    ) {
+        let mut providers = self.providers.lock().unwrap();
+        let stats = providers.entry(provider.to_string()).or_default();
+
+        stats.attempts += 1;
+
+        match outcome {
+            SnapOutcome::Success => {
+                stats.successes += 1;
+                stats.consecutive_api_errors = 0;
+            }
+
+            SnapOutcome::Empty => stats.empty_results += 1,
+            SnapOutcome::TimedOut => stats.timeouts += 1,
+        }
+
+        let seconds = latency.as_secs_f64();
+
+        let bucket = LATENCY_BUCKETS.iter()
+            .position(|bound| seconds <= *bound);
+
+        // slower ones are counted only by +Inf bucket
+        if let Some(bucket) = bucket {
+            stats.latency_buckets[bucket] += 1;
+        }
+
+        stats.latency_sum += seconds;
+    }
+
+    /// Records failed API call of snapper of `provider`.
+    /// Failures in a row are reported once there are 10, 100, 1000
+    /// and so on of them, e.g. when API key expires.
+    pub fn record_api_error(&self, provider: &str) {
+        let consecutive_errors = {
+            let mut providers = self.providers.lock().unwrap();
+            let stats = providers.entry(provider.to_string()).or_default();
+
+            stats.api_errors += 1;
+            stats.consecutive_api_errors += 1;
+            stats.consecutive_api_errors
+        };
+
+        if is_report_milestone(consecutive_errors) {
+            report_error(
+                "snapper",
+                format!("API calls of {provider} failed {consecutive_errors} times in a row"),
+                &[("provider", provider)],
+            );
+        }
+    }
+
+    /// This method writes metrics of all providers with `writer`.
+    pub fn write_metrics(&self, writer: &mut MetricsWriter) {
+        let providers = self.providers.lock().unwrap();
+
+        let counters: [(&str, &str, fn(&ProviderStats) -> u64); 5] = [
+            (
+                "crabo_snapper_attempts_total",
+                "Number of URLs snapper tried to snap.",
+                |stats| stats.attempts,
+            ),
+            (
+                "crabo_snapper_successes_total",
+                "Number of snapshots snapper produced.",
+                |stats| stats.successes,
+            ),
+            (
+                "crabo_snapper_empty_results_total",
+                "Number of URLs snapper produced no snapshot for.",
+                |stats| stats.empty_results,
+            ),
+            (
+                "crabo_snapper_timeouts_total",
+                "Number of URLs snapper did not snap in time.",
+                |stats| stats.timeouts,
+            ),
+            (
+                "crabo_snapper_api_errors_total",
+                "Number of failed API calls of snapper.",
+                |stats| stats.api_errors,
+            ),
+        ];
+
+        for (name, help, value) in counters {
+            writer.describe(name, help, "counter");
+
+            for (provider, stats) in providers.iter() {
+                writer.sample(name, &[("provider", provider.as_str())], value(stats));
+            }
+        }
+
+        let name = "crabo_snapper_latency_seconds";
+        writer.describe(name, "Time snapper took to snap URL.", "histogram");
+
+        for (provider, stats) in providers.iter() {
+            let provider = provider.as_str();
+            let mut cumulative = 0;
+
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(stats.latency_buckets) {
+                cumulative += count;
+                let bound = bound.to_string();
+
+                writer.sample(
+                    &format!("{name}_bucket"),
+                    &[("provider", provider), ("le", bound.as_str())],
+                    cumulative,
+                );
+            }
+
+            writer.sample(
+                &format!("{name}_bucket"),
+                &[("provider", provider), ("le", "+Inf")],
+                stats.attempts,
+            );
+
+            writer.sample(&format!("{name}_sum"), &[("provider", provider)], stats.latency_sum);
+            writer.sample(&format!("{name}_count"), &[("provider", provider)], stats.attempts);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::metrics::{
+        is_report_milestone,
+        MetricsWriter,
+        ProviderMetrics,
+        SnapOutcome,
+    };
+
+    #[test]
+    fn test_metrics_writer() {
+        let mut writer = MetricsWriter::default();
+        writer.counter("crabo_things_total", "Number of things.", 3);
+        writer.sample("crabo_things_total", &[("kind", "a\"b")], 1);
+
+        assert_eq!(
+            writer.finish(),
+            "# HELP crabo_things_total Number of things.\n\
+            # TYPE crabo_things_total counter\n\
+            crabo_things_total 3\n\
+            crabo_things_total{kind=\"a\\\"b\"} 1\n"
+        );
+    }
+
+    #[test]
+    fn test_report_milestones() {
+        let milestones: Vec<_> = (0..=1000)
+            .filter(|count| is_report_milestone(*count))
+            .collect();
+
+        assert_eq!(milestones, vec![10, 100, 1000]);
+    }
+
+    #[test]
+    fn test_provider_metrics() {
+        let metrics = ProviderMetrics::default();
+
+        metrics.record_snap("youtube", SnapOutcome::Success, Duration::from_millis(200));
+        metrics.record_snap("youtube", SnapOutcome::TimedOut, Duration::from_secs(60));
+        metrics.record_api_error("youtube");
+
+        let mut writer = MetricsWriter::default();
+        metrics.write_metrics(&mut writer);
+        let text = writer.finish();
+
+        assert!(text.contains("crabo_snapper_attempts_total{provider=\"youtube\"} 2\n"));
+        assert!(text.contains("crabo_snapper_api_errors_total{provider=\"youtube\"} 1\n"));
+
+        assert!(text.contains(
+            "crabo_snapper_latency_seconds_bucket{provider=\"youtube\",le=\"0.25\"} 1\n"
+        ));
+
+        assert!(text.contains(
+            "crabo_snapper_latency_seconds_bucket{provider=\"youtube\",le=\"+Inf\"} 2\n"
+        ));
+    }
+}
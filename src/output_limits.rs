@@ -0,0 +1,122 @@
+use log::warn;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Default maximum length of title.
+const DEFAULT_MAX_TITLE: usize = 300;
+
+/// Default maximum length of description.
+const DEFAULT_MAX_DESCRIPTION: usize = 1000;
+
+/// Default maximum length of source.
+const DEFAULT_MAX_SOURCE: usize = 100;
+
+/// Default maximum length of a single tag.
+const DEFAULT_MAX_TAG: usize = 64;
+
+/// Appended to text that was cut.
+const ELLIPSIS: &str = "…";
+
+/// Maximum lengths of snapshot text fields in grapheme clusters, so
+/// multi-megabyte descriptions do not bloat cache or break layout of
+/// frontends.
+#[derive(Clone, Debug)]
+pub struct OutputLimits {
+    pub title: usize,
+    pub description: usize,
+    pub source: usize,
+    pub tag: usize,
+}
+
+impl Default for OutputLimits {
+    fn default() -> Self {
+        Self {
+            title: DEFAULT_MAX_TITLE,
+            description: DEFAULT_MAX_DESCRIPTION,
+            source: DEFAULT_MAX_SOURCE,
+            tag: DEFAULT_MAX_TAG,
+        }
+    }
+}
+
+/// Helper function to parse limit from `text`.
+fn parse_limit(text: &str) -> Option<usize> {
+    match text.trim().parse() {
+        Ok(limit) if limit > 0 => Some(limit),
+
+        _ => {
+            warn!("Ignoring invalid length limit '{text}'");
+            None
+        }
+    }
+}
+
+impl OutputLimits {
+    /// Constructs new instance of [OutputLimits] from `title`,
+    /// `description`, `source` and `tag` limits, defaults are used for
+    /// values that are not set.
+    pub fn new(
+        title: Option<&str>,
+        description: Option<&str>,
+        source: Option<&str>,
+        tag: Option<&str>,
+    ) -> Self {
+        let defaults = Self::default();
+
+        Self {
+            title: title.and_then(parse_limit).unwrap_or(defaults.title),
+            description: description.and_then(parse_limit).unwrap_or(defaults.description),
+            source: source.and_then(parse_limit).unwrap_or(defaults.source),
+            tag: tag.and_then(parse_limit).unwrap_or(defaults.tag),
+        }
+    }
+}
+
+/// This function cuts `text` to `max_length` grapheme clusters, ellipsis
+/// included. Text is never cut in the middle of emoji sequence or letter
+/// with combining marks.
+pub fn truncate_graphemes(text: &str, max_length: usize) -> String {
+    // cheap check first, graphemes are never shorter than one char
+    if text.chars().count() <= max_length {
+        return text.to_string();
+    }
+
+    let mut graphemes = text.grapheme_indices(true);
+
+    match graphemes.nth(max_length.saturating_sub(1)) {
+        Some((end, _)) if graphemes.next().is_some() => format!(
+            "{}{ELLIPSIS}",
+            text[..end].trim_end(),
+        ),
+
+        _ => text.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::output_limits::{OutputLimits, truncate_graphemes};
+
+    #[test]
+    fn test_truncate_graphemes() {
+        assert_eq!(truncate_graphemes("short", 10), "short");
+        assert_eq!(truncate_graphemes("abcdef", 4), "abc…");
+        assert_eq!(truncate_graphemes("ab cdef", 4), "ab…");
+
+        // family emoji is a single grapheme made of several chars
+        let family = "👨‍👩‍👧‍👦";
+        assert_eq!(truncate_graphemes(&family.repeat(2), 2), family.repeat(2));
+        assert_eq!(truncate_graphemes(&family.repeat(3), 2), format!("{family}…"));
+
+        // e with combining acute accent
+        assert_eq!(truncate_graphemes("e\u{301}e\u{301}e\u{301}", 2), "e\u{301}…");
+    }
+
+    #[test]
+    fn test_limits() {
+        let limits = OutputLimits::new(Some("50"), Some("0"), None, Some("x"));
+
+        assert_eq!(limits.title, 50);
+        assert_eq!(limits.description, OutputLimits::default().description);
+        assert_eq!(limits.tag, OutputLimits::default().tag);
+    }
+}
@@ -0,0 +1,750 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use actix_web::http::header::{
+    ACCEPT,
+    CONTENT_TYPE,
+    HeaderMap,
+    HeaderName,
+    HeaderValue,
+    LOCATION,
+    USER_AGENT,
+};
+use actix_web::http::{Method, StatusCode};
+use awc::error::{ConnectError, PayloadError, SendRequestError};
+use chrono::Utc;
+use futures::future::LocalBoxFuture;
+use futures::stream::LocalBoxStream;
+use futures::StreamExt;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use tokio_util::bytes::{Bytes, BytesMut};
+use url::Url;
+use crabo_model::SnapError;
+use crate::fetch_limiter::{FetchLimiter, FetchPermit};
+use crate::http_proxy::HttpProxies;
+use crate::http_signature::HttpSigner;
+use crate::proxy_connector::ProxyConnector;
+use crate::retry::RetryPolicy;
+use crate::socks_proxy::SocksProxies;
+use crate::suppression::HostSuppressor;
+use crate::timeouts::Timeouts;
+use crate::url_guard::{GuardError, UrlGuard};
+use crate::url_policy::UrlPolicy;
+
+/// Default maximum number of bytes of response body read by [PageClient],
+/// applies to pages as well as to robots.txt and API responses.
+pub const DEFAULT_MAX_BODY_BYTES: usize = 2 * 1024 * 1024;
+
+/// Maximum number of redirects [PageClient] follows.
+const MAX_REDIRECTS: usize = 5;
+
+/// Returns true if request with `headers` asks for ActivityPub object,
+/// servers in authorized fetch mode refuse such requests unless signed.
+fn accepts_activity(headers: &[(String, String)]) -> bool {
+    headers.iter().any(|(name, value)| {
+        name.eq_ignore_ascii_case(ACCEPT.as_str()) &&
+            (value.contains("application/activity+json") ||
+                value.contains("https://www.w3.org/ns/activitystreams"))
+    })
+}
+
+/// Errors [PageClient] could report.
+#[derive(Debug)]
+pub enum FetchError {
+    /// Server is suppressed, no request was made.
+    Suppressed,
+
+    /// Server responded with status code other than success.
+    UnexpectedStatusCode(StatusCode),
+
+    /// Request failed, e.g. connection was refused or timed out.
+    RequestFailed(String),
+
+    /// URL must not be fetched, e.g. it points to internal network.
+    Forbidden(String),
+
+    /// Response body exceeds size limit, so it could not be read whole.
+    TooLarge(usize),
+
+    /// Response body is not what was expected, e.g. JSON is malformed.
+    Malformed(String),
+
+    /// Server redirects more than [MAX_REDIRECTS] times.
+    TooManyRedirects,
+}
+
+impl FetchError {
+    /// Returns class of snapping failure this error leads to, so callers
+    /// could tell missing pages from servers that are down.
+    pub fn snap_error(&self) -> SnapError {
+        match self {
+            Self::Suppressed => SnapError::Suppressed,
+
+            Self::UnexpectedStatusCode(StatusCode::NOT_FOUND | StatusCode::GONE) => {
+                SnapError::NotFound
+            }
+
+            Self::TooLarge(_) | Self::Malformed(_) => SnapError::ParseFailed,
+
+            Self::UnexpectedStatusCode(_) |
+            Self::RequestFailed(_) |
+            Self::Forbidden(_) |
+            Self::TooManyRedirects => SnapError::ProviderError,
+        }
+    }
+}
+
+/// Outcome of a single request made by [PageClient].
+enum Hop {
+    /// Server responded with content.
+    Response(PageResponse),
+
+    /// Server redirected to another URL.
+    Redirect(Url),
+}
+
+/// Response of server with body that is yet to be read.
+pub struct PageResponse {
+    /// Address response came from, after redirects.
+    pub url: Url,
+
+    /// Value of Content-Type header.
+    pub content_type: Option<String>,
+
+    /// Status code of response.
+    pub status: StatusCode,
+
+    /// Headers of response.
+    pub headers: HeaderMap,
+
+    /// Headers sent with request, except defaults of client such as
+    /// User-Agent.
+    pub request_headers: Vec<(String, String)>,
+
+    /// Body stream.
+    body: LocalBoxStream<'static, Result<Bytes, PayloadError>>,
+
+    /// Number of bytes that could be read before body is cut.
+    remaining_bytes: usize,
+
+    /// Time to wait for next chunk of body.
+    read_timeout: Duration,
+
+    /// Set if body was cut because it exceeds size limit.
+    is_cut: bool,
+
+    /// Request slot is taken until body is read.
+    _permit: FetchPermit,
+}
+
+impl PageResponse {
+    /// Returns next chunk of body or None if body is read completely.
+    /// Broken or stalled body stream is treated as the end of body, so is
+    /// body that exceeds size limit: whatever was read before is all
+    /// caller gets.
+    pub async fn next_chunk(&mut self) -> Option<Bytes> {
+        if self.remaining_bytes == 0 {
+            return None;
+        }
+
+        let chunk = match tokio::time::timeout(
+            self.read_timeout,
+            self.body.next(),
+        ).await {
+            Ok(chunk) => chunk?,
+
+            Err(_) => {
+                warn!("Timed out reading response body");
+                return None;
+            }
+        };
+
+        match chunk {
+            Ok(chunk) if chunk.len() > self.remaining_bytes => {
+                info!("Response body is too large, cutting it");
+
+                let chunk = chunk.slice(..self.remaining_bytes);
+                self.remaining_bytes = 0;
+                self.is_cut = true;
+
+                Some(chunk)
+            }
+
+            Ok(chunk) => {
+                self.remaining_bytes -= chunk.len();
+                Some(chunk)
+            }
+
+            Err(err) => {
+                warn!("Failed to read response body: {err:?}");
+                None
+            }
+        }
+    }
+}
+
+impl PageResponse {
+    /// Returns true if body was cut because it exceeds size limit.
+    pub fn is_cut(&self) -> bool {
+        self.is_cut
+    }
+
+    /// This method reads the whole body. Unlike pages, which are fine to
+    /// parse partially, cut robots.txt or JSON is useless, so error is
+    /// returned if body exceeds size limit.
+    pub async fn read_to_end(mut self) -> Result<Bytes, FetchError> {
+        let mut body = BytesMut::new();
+
+        while let Some(chunk) = self.next_chunk().await {
+            body.extend_from_slice(&chunk);
+        }
+
+        match self.is_cut {
+            true => Err(FetchError::TooLarge(body.len())),
+            false => Ok(body.freeze()),
+        }
+    }
+}
+
+/// Response to a single request as [Transport] returns it.
+pub struct TransportResponse {
+    /// Status code of response.
+    pub status: StatusCode,
+
+    /// Headers of response.
+    pub headers: HeaderMap,
+
+    /// Body stream.
+    pub body: LocalBoxStream<'static, Result<Bytes, PayloadError>>,
+}
+
+/// Sends single requests of [PageClient], which takes care of redirects,
+/// limits and failing servers. Real requests are sent by awc, tests use
+/// [CannedTransport], so they do not depend on live sites.
+pub trait Transport {
+    /// Sends `method` request for `url` with `headers`, not following
+    /// redirects. Headers are set in order, so later ones replace earlier
+    /// ones and defaults of the same name.
+    fn send<'a>(
+        &'a self,
+        method: Method,
+        url: &'a Url,
+        headers: &'a [(String, String)],
+    ) -> LocalBoxFuture<'a, Result<TransportResponse, FetchError>>;
+}
+
+/// Transport that sends requests over network.
+struct AwcTransport {
+    client: awc::Client,
+}
+
+impl Transport for AwcTransport {
+    fn send<'a>(
+        &'a self,
+        method: Method,
+        url: &'a Url,
+        headers: &'a [(String, String)],
+    ) -> LocalBoxFuture<'a, Result<TransportResponse, FetchError>> {
+        Box::pin(async move {
+            let request = headers.iter()
+                .fold(
+                    self.client.request(method, url.as_str()),
+                    |request, (name, value)| request.insert_header(
+                        (name.as_str(), value.as_str())
+                    ),
+                );
+
+            match request.send().await {
+                Ok(response) => Ok(
+                    TransportResponse {
+                        status: response.status(),
+                        headers: response.headers().clone(),
+                        body: response.boxed_local(),
+                    }
+                ),
+
+                Err(SendRequestError::Connect(ConnectError::Resolver(err)))
+                    if err.is::<GuardError>() =>
+                {
+                    Err(FetchError::Forbidden(err.to_string()))
+                }
+
+                Err(err) => Err(FetchError::RequestFailed(err.to_string())),
+            }
+        })
+    }
+}
+
+/// Response [CannedTransport] returns.
+#[derive(Clone, Debug)]
+pub struct CannedResponse {
+    /// Status code of response.
+    pub status: StatusCode,
+
+    /// Headers of response, e.g. Content-Type or Location.
+    pub headers: Vec<(String, String)>,
+
+    /// Body of response.
+    pub body: Bytes,
+}
+
+impl CannedResponse {
+    /// Constructs successful response with `content_type` and `body`.
+    pub fn ok(content_type: &str, body: impl Into<Bytes>) -> Self {
+        Self {
+            status: StatusCode::OK,
+            headers: vec![(CONTENT_TYPE.to_string(), content_type.to_string())],
+            body: body.into(),
+        }
+    }
+
+    /// Constructs response with `status` and no body.
+    pub fn status(status: StatusCode) -> Self {
+        Self {
+            status,
+            headers: vec![],
+            body: Bytes::new(),
+        }
+    }
+}
+
+/// Transport that returns canned responses instead of sending requests,
+/// so tests are deterministic and work offline. Requests for anything
+/// else fail as if server did not respond.
+#[derive(Clone, Debug, Default)]
+pub struct CannedTransport {
+    responses: HashMap<(Method, String), CannedResponse>,
+}
+
+impl CannedTransport {
+    /// Returns [CannedTransport] that responds to `method` requests for
+    /// `url` with `response`.
+    pub fn respond(mut self, method: Method, url: &str, response: CannedResponse) -> Self {
+        self.responses.insert((method, url.to_string()), response);
+        self
+    }
+}
+
+impl Transport for CannedTransport {
+    fn send<'a>(
+        &'a self,
+        method: Method,
+        url: &'a Url,
+        _headers: &'a [(String, String)],
+    ) -> LocalBoxFuture<'a, Result<TransportResponse, FetchError>> {
+        let response = self.responses.get(&(method.clone(), url.to_string()))
+            .cloned()
+            .ok_or_else(|| FetchError::RequestFailed(
+                format!("no canned response to {method} {url}")
+            ))
+            .map(|response| TransportResponse {
+                status: response.status,
+
+                headers: response.headers.iter()
+                    .filter_map(|(name, value)| Some((
+                        HeaderName::try_from(name.as_str()).ok()?,
+                        HeaderValue::try_from(value.as_str()).ok()?,
+                    )))
+                    .collect(),
+
+                body: futures::stream::once(
+                    futures::future::ready(Ok(response.body))
+                ).boxed_local(),
+            });
+
+        Box::pin(futures::future::ready(response))
+    }
+}
+
+/// HTTP client to fetch web-pages.
+///
+/// Unlike [fedineko_http_client::GenericClient] this one streams response
+/// body, so caller could stop reading it once everything needed is there.
+/// It also knows how to ignore servers that report errors.
+///
+/// Redirects are followed by [PageClient] itself rather than by awc,
+/// so every hop is validated the same way as the original URL.
+pub struct PageClient {
+    transport: Box<dyn Transport>,
+    suppressor: Arc<HostSuppressor>,
+    url_guard: UrlGuard,
+    url_policy: UrlPolicy,
+    fetch_limiter: FetchLimiter,
+    read_timeout: Duration,
+
+    /// Response body is cut after this number of bytes, protecting Crabo
+    /// from multi-hundred-megabyte responses.
+    max_body_bytes: usize,
+
+    /// Tells which failed requests are repeated.
+    retry_policy: RetryPolicy,
+
+    /// Signs requests for servers that refuse unsigned ones, if set.
+    signer: Option<Arc<HttpSigner>>,
+}
+
+impl PageClient {
+    /// Constructs new instance of [PageClient] that identifies itself
+    /// with `user_agent` and reads up to `max_body_bytes` of response body.
+    /// URLs `url_guard` or `url_policy` refuse are not fetched, neither
+    /// are redirects to them. Hosts are resolved by `url_guard` too, so
+    /// connection is made only to checked addresses.
+    /// Connection and reads are limited by `timeouts`, number of
+    /// concurrent requests is limited by `fetch_limiter`.
+    /// Servers that fail are tracked by `suppressor`, which is expected
+    /// to be shared by clients of all workers.
+    /// Hosts `socks_proxies` or `http_proxies` have proxy for are
+    /// connected to through it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        user_agent: &str,
+        max_body_bytes: usize,
+        url_guard: UrlGuard,
+        url_policy: UrlPolicy,
+        timeouts: &Timeouts,
+        fetch_limiter: FetchLimiter,
+        suppressor: Arc<HostSuppressor>,
+        socks_proxies: SocksProxies,
+        http_proxies: HttpProxies,
+    ) -> Self {
+        let client = awc::Client::builder()
+            .connector(
+                awc::Connector::new()
+                    .connector(ProxyConnector::new(
+                        socks_proxies,
+                        http_proxies,
+                        url_guard.clone(),
+                    ))
+                    .timeout(timeouts.connect)
+            )
+            .timeout(timeouts.read)
+            .disable_redirects()
+            .add_default_header((USER_AGENT, user_agent))
+            .finish();
+
+        Self::with_transport(
+            Box::new(AwcTransport { client }),
+            max_body_bytes,
+            url_guard,
+            url_policy,
+            timeouts,
+            fetch_limiter,
+            suppressor,
+        )
+    }
+
+    /// Constructs new instance of [PageClient] that sends requests with
+    /// `transport`, e.g. [CannedTransport] in tests. The rest of arguments
+    /// are the same as of [PageClient::new].
+    pub fn with_transport(
+        transport: Box<dyn Transport>,
+        max_body_bytes: usize,
+        url_guard: UrlGuard,
+        url_policy: UrlPolicy,
+        timeouts: &Timeouts,
+        fetch_limiter: FetchLimiter,
+        suppressor: Arc<HostSuppressor>,
+    ) -> Self {
+        Self {
+            transport,
+            suppressor,
+            url_guard,
+            url_policy,
+            fetch_limiter,
+            read_timeout: timeouts.read,
+            max_body_bytes,
+            retry_policy: RetryPolicy::default(),
+            signer: None,
+        }
+    }
+
+    /// Returns this client repeating failed requests as `retry_policy`
+    /// tells.
+    pub fn with_retry_policy(self, retry_policy: RetryPolicy) -> Self {
+        Self { retry_policy, ..self }
+    }
+
+    /// Returns this client signing requests with `signer`, if it is set.
+    /// ActivityPub objects are requested signed right away, any other
+    /// request is signed and repeated only if server responds with 401.
+    pub fn with_signer(self, signer: Option<Arc<HttpSigner>>) -> Self {
+        Self { signer, ..self }
+    }
+
+    /// Sends GET request for `url` with `extra_headers`, following
+    /// redirects. Returns response which body could be read in chunks.
+    pub async fn get(
+        &self,
+        url: &Url,
+        extra_headers: &[(String, String)],
+    ) -> Result<PageResponse, FetchError> {
+        self.get_checking_hops(url, extra_headers, |_| vec![], |_| async { true }).await
+    }
+
+    /// Sends GET request for `url` with `extra_headers`, following
+    /// redirects only to URLs `is_hop_allowed` accepts, e.g. ones
+    /// robots.txt allows access to. Headers `hop_headers` returns for URL
+    /// of every hop are sent to that hop only, after `extra_headers`.
+    /// Returns response which body could be read in chunks.
+    pub async fn get_checking_hops<H, F, Fut>(
+        &self,
+        url: &Url,
+        extra_headers: &[(String, String)],
+        hop_headers: H,
+        is_hop_allowed: F,
+    ) -> Result<PageResponse, FetchError>
+    where
+        H: Fn(&Url) -> Vec<(String, String)>,
+        F: Fn(Url) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        self.request_checking_hops(
+            Method::GET,
+            url,
+            extra_headers,
+            hop_headers,
+            is_hop_allowed,
+        ).await
+    }
+
+    /// Sends HEAD request for `url`, following redirects.
+    /// Returns response which body is empty.
+    pub async fn head(&self, url: &Url) -> Result<PageResponse, FetchError> {
+        self.request_checking_hops(
+            Method::HEAD,
+            url,
+            &[],
+            |_| vec![],
+            |_| async { true },
+        ).await
+    }
+
+    /// Sends HEAD request for `url` without following redirects, e.g. to
+    /// resolve short link. Returns address server redirects to, or None
+    /// if it responds with content instead.
+    pub async fn redirect_location(&self, url: &Url) -> Result<Option<Url>, FetchError> {
+        match self.send_hop_with_retries(Method::HEAD, url, &[]).await? {
+            Hop::Redirect(location) => Ok(Some(location)),
+            Hop::Response(_) => Ok(None),
+        }
+    }
+
+    /// Helper method to send `method` request for `url` with
+    /// `extra_headers` and `hop_headers` of every hop, following redirects
+    /// only to URLs `is_hop_allowed` accepts.
+    async fn request_checking_hops<H, F, Fut>(
+        &self,
+        method: Method,
+        url: &Url,
+        extra_headers: &[(String, String)],
+        hop_headers: H,
+        is_hop_allowed: F,
+    ) -> Result<PageResponse, FetchError>
+    where
+        H: Fn(&Url) -> Vec<(String, String)>,
+        F: Fn(Url) -> Fut,
+        Fut: Future<Output = bool>,
+    {
+        let mut hop_url = url.clone();
+
+        for _ in 0..=MAX_REDIRECTS {
+            // e.g. cookies of one site are not sent to site it redirects to
+            let headers: Vec<_> = extra_headers.iter()
+                .cloned()
+                .chain(hop_headers(&hop_url))
+                .collect();
+
+            match self.send_hop_with_retries(method.clone(), &hop_url, &headers).await? {
+                Hop::Response(response) => return Ok(response),
+
+                Hop::Redirect(next_url) => {
+                    if let Err(reason) = self.url_policy.check(&next_url) {
+                        return Err(
+                            FetchError::Forbidden(
+                                format!("redirect to {next_url}: {reason}")
+                            )
+                        );
+                    }
+
+                    if !is_hop_allowed(next_url.clone()).await {
+                        return Err(
+                            FetchError::Forbidden(
+                                format!("redirect to {next_url} is not allowed")
+                            )
+                        );
+                    }
+
+                    info!("{hop_url} redirects to {next_url}");
+                    hop_url = next_url;
+                }
+            }
+        }
+
+        Err(FetchError::TooManyRedirects)
+    }
+
+    /// Helper method to send `method` request for `url` with
+    /// `extra_headers` without following redirects, repeating it with
+    /// growing delays as retry policy tells, and signed if server asks
+    /// for signature.
+    async fn send_hop_with_retries(
+        &self,
+        method: Method,
+        url: &Url,
+        extra_headers: &[(String, String)],
+    ) -> Result<Hop, FetchError> {
+        let mut attempt = 1;
+        let mut is_signed = self.signer.is_some() && accepts_activity(extra_headers);
+
+        loop {
+            // signature covers date, so every attempt is signed anew
+            let headers = match (&self.signer, is_signed) {
+                (Some(signer), true) => extra_headers.iter()
+                    .cloned()
+                    .chain(signer.sign(&method, url, Utc::now()))
+                    .collect(),
+
+                _ => extra_headers.to_vec(),
+            };
+
+            let err = match self.send_hop(method.clone(), url, &headers).await {
+                Err(err) => err,
+                hop => return hop,
+            };
+
+            // e.g. Mastodon in authorized fetch mode
+            let is_unauthorized = matches!(
+                err,
+                FetchError::UnexpectedStatusCode(StatusCode::UNAUTHORIZED)
+            );
+
+            if is_unauthorized && self.signer.is_some() && !is_signed {
+                info!("{url}: server requires signature, signing request");
+                is_signed = true;
+                continue;
+            }
+
+            let Some(delay) = self.retry_policy.retry_delay(attempt, &err) else {
+                return Err(err);
+            };
+
+            info!("{url}: attempt {attempt} failed with {err:?}, retrying in {delay:?}");
+
+            // request slot is free while waiting, as response is dropped
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    /// Helper method to send `method` request for `url` with
+    /// `extra_headers` without following redirects.
+    async fn send_hop(
+        &self,
+        method: Method,
+        url: &Url,
+        extra_headers: &[(String, String)],
+    ) -> Result<Hop, FetchError> {
+        let host = url.host_str().unwrap_or_default();
+
+        if self.suppressor.is_suppressed(host) {
+            return Err(FetchError::Suppressed);
+        }
+
+        // domains are checked by resolver when connecting,
+        // IP addresses are never passed to it
+        if let Err(err) = self.url_guard.check_ip_host(url) {
+            return Err(FetchError::Forbidden(err.to_string()));
+        }
+
+        let permit = self.fetch_limiter.acquire(host).await;
+
+        let response = match self.transport.send(method, url, extra_headers).await {
+            Ok(response) => response,
+
+            Err(FetchError::RequestFailed(reason)) => {
+                self.suppressor.report_failure(host, &reason);
+                return Err(FetchError::RequestFailed(reason));
+            }
+
+            Err(err) => return Err(err),
+        };
+
+        let status = response.status;
+
+        // client errors are page specific, server ones are not
+        if self.suppressor.is_failure_status(status) {
+            self.suppressor.report_failure(host, &format!("status {status}"));
+        } else {
+            self.suppressor.report_success(host);
+        }
+
+        if status.is_redirection() {
+            let location = response.headers
+                .get(LOCATION)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| url.join(value).ok());
+
+            if let Some(location) = location {
+                return Ok(Hop::Redirect(location));
+            }
+        }
+
+        if !status.is_success() {
+            return Err(FetchError::UnexpectedStatusCode(status));
+        }
+
+        let content_type = response.headers
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string());
+
+        Ok(
+            Hop::Response(
+                PageResponse {
+                    url: url.clone(),
+                    content_type,
+                    status,
+                    headers: response.headers,
+                    request_headers: extra_headers.to_vec(),
+                    body: response.body,
+                    remaining_bytes: self.max_body_bytes,
+                    read_timeout: self.read_timeout,
+                    is_cut: false,
+                    _permit: permit,
+                }
+            )
+        )
+    }
+
+    /// Sends GET request for `url` and reads the whole response body.
+    pub async fn get_bytes(&self, url: &Url) -> Result<Bytes, FetchError> {
+        self.get(url, &[])
+            .await?
+            .read_to_end()
+            .await
+    }
+
+    /// Sends GET request for `url` with `extra_headers` and parses
+    /// response body as JSON.
+    pub async fn get_json<T: DeserializeOwned>(
+        &self,
+        url: &Url,
+        extra_headers: &[(String, String)],
+    ) -> Result<T, FetchError> {
+        // headers are set in order, so extra ones could replace Accept
+        let headers: Vec<_> = [("Accept".to_string(), "application/json".to_string())]
+            .into_iter()
+            .chain(extra_headers.iter().cloned())
+            .collect();
+
+        let body = self.get(url, &headers)
+            .await?
+            .read_to_end()
+            .await?;
+
+        serde_json::from_slice(&body)
+            .map_err(|err| FetchError::Malformed(err.to_string()))
+    }
+}
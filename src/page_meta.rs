@@ -0,0 +1,1199 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use lol_html::{element, HtmlRewriter, Settings, text};
+use lol_html::html_content::TextType;
+use url::Url;
+use crate::extraction_rules::{ExtractionRule, RuleField};
+use crate::text_fragment::TextFragment;
+use crate::user_agent::DEFAULT_CRAWLER_NAME;
+
+/// If this key is set to "true" then Crabo can make snapshots of page.
+///
+/// Crabo can try to produce snapshot for mention or RT link,
+/// which is undesired if points to e.g. social networking site.
+///
+/// Most ActivityPub instances opt-out from indexing and Crabo follows
+/// "robots" meta-tags like:
+/// ```html
+///  <meta name="robots" content="noindex">
+///  <meta name="fedineko-crabo" content="noindex">
+///  <meta name="fedineko-crabo, some-other-bot" content="noindex, noarchive">
+/// ```
+/// by basic substring match, `fedineko-crabo` being the name Crabo is
+/// configured with. Crabo also follows robots.txt instructions.
+///
+/// This affects Crabo only as it makes snippets of web-pages with accepted
+/// content type specified as text/html. Other Fedineko components work with
+/// ActivityPub and get instructions from related attributes of content or
+/// actor's account.
+pub const FEDINEKO_CAN_INDEX_KEY: &str = "fedineko-can-index";
+
+/// OpenGraph properties that could be repeated and structured,
+/// see <https://ogp.me/#structured>.
+const OG_MEDIA_PROPERTIES: [&str; 3] = ["og:image", "og:video", "og:audio"];
+
+/// Meta tag names Crabo looks for or sees on most pages. These are interned,
+/// so page with dozens of meta tags does not keep a copy of each name.
+const KNOWN_PROPERTIES: [&str; 42] = [
+    "application-name",
+    "article:author",
+    "article:modified_time",
+    "article:published_time",
+    "article:tag",
+    "author",
+    "description",
+    "Description",
+    "fediverse:creator",
+    "generator",
+    "isFamilyFriendly",
+    "keywords",
+    "misskey:note-id",
+    "misskey:user-id",
+    "misskey:user-username",
+    "og:description",
+    "og:image",
+    "og:image:alt",
+    "og:image:height",
+    "og:image:type",
+    "og:image:width",
+    "og:locale",
+    "og:profile:username",
+    "og:restrictions:age",
+    "og:site_name",
+    "og:title",
+    "og:type",
+    "og:updated_time",
+    "og:url",
+    "og:video:duration",
+    "profile:username",
+    "rating",
+    "robots",
+    "theme-color",
+    "twitter:card",
+    "twitter:description",
+    "twitter:image",
+    "twitter:image:alt",
+    "twitter:site",
+    "twitter:title",
+    "video:duration",
+    "viewport",
+];
+
+/// Maximum number of bytes of paragraphs text collected per
+/// [ParagraphsCollector], so large pages do not bloat memory.
+const MAX_PARAGRAPHS_BYTES: usize = 8 * 1024;
+
+/// Maximum number of bytes of `<h1>` text collected.
+const MAX_H1_BYTES: usize = 1024;
+
+/// Maximum number of bytes of `<title>` text collected.
+const MAX_TITLE_BYTES: usize = 2048;
+
+/// Maximum number of feed links collected, sites rarely offer more than
+/// posts and comments feeds.
+const MAX_FEEDS: usize = 8;
+
+/// Content types of `<link rel="alternate">` that point to feeds.
+const FEED_TYPES: [&str; 2] = ["application/rss+xml", "application/atom+xml"];
+
+/// Maximum number of bytes of body text text fragment is looked for in.
+const MAX_BODY_TEXT_BYTES: usize = 256 * 1024;
+
+/// Maximum number of bytes collected per field of [ExtractionRule].
+const MAX_RULE_VALUE_BYTES: usize = 4096;
+
+/// Elements JavaScript frameworks mount single-page applications to.
+const SPA_ROOT_SELECTOR: &str =
+    "div#root, div#app, div#__next, div#__nuxt, app-root, [data-reactroot]";
+
+/// Elements and scripts of common consent management platforms, e.g.
+/// OneTrust and Didomi.
+const CONSENT_BANNER_SELECTOR: &str =
+    "#onetrust-consent-sdk, #onetrust-banner-sdk, #didomi-host, #didomi-notice, \
+    script[src*=\"cdn.cookielaw.org\"], script[src*=\"sdk.privacy-center.org\"], \
+    form[action*=\"consent.\"]";
+
+/// Paragraphs shorter than this are likely captions, bylines or
+/// navigation leftovers, so are not used for description.
+const MIN_PARAGRAPH_CHARS: usize = 60;
+
+/// Number of sentences taken from page text for description.
+const BODY_DESCRIPTION_SENTENCES: usize = 2;
+
+/// Description made from page text is cut to this number of characters
+/// if sentences turned out to be too long.
+const MAX_BODY_DESCRIPTION_CHARS: usize = 400;
+
+/// Structured OpenGraph media property such as `og:image`, `og:video`
+/// or `og:audio` with optional attributes that follow it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OgMedia {
+    /// Value of the root property, e.g. `og:video`.
+    pub url: String,
+
+    /// Value of `:secure_url` attribute.
+    pub secure_url: Option<String>,
+
+    /// Value of `:type` attribute.
+    pub mime_type: Option<String>,
+
+    /// Value of `:width` attribute.
+    pub width: Option<u32>,
+
+    /// Value of `:height` attribute.
+    pub height: Option<u32>,
+
+    /// Value of `:alt` attribute.
+    pub alt: Option<String>,
+}
+
+/// Icon of site declared by `<link rel="icon">` or
+/// `<link rel="apple-touch-icon">`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IconLink {
+    /// Value of `href` attribute.
+    pub href: String,
+
+    /// Value of `type` attribute.
+    pub mime_type: Option<String>,
+
+    /// The largest side declared by `sizes` attribute.
+    pub size: Option<u32>,
+
+    /// True if this is Apple touch icon, these are usually large enough
+    /// even if size is not declared.
+    pub is_apple_touch: bool,
+}
+
+/// Parses `sizes` attribute of icon link, e.g. `16x16 32x32`,
+/// and returns the largest side mentioned there.
+fn parse_icon_sizes(sizes: &str) -> Option<u32> {
+    sizes.split_whitespace()
+        .filter_map(|size| size.to_lowercase()
+            .split_once('x')
+            .and_then(|(width, height)| {
+                let width: u32 = width.parse().ok()?;
+                let height: u32 = height.parse().ok()?;
+                Some(width.max(height))
+            })
+        )
+        .max()
+}
+
+/// Collected structured OpenGraph media by root property name.
+type OgMediaMap = HashMap<&'static str, Vec<OgMedia>>;
+
+/// Meta tags by name, names of well-known ones are interned.
+pub type MetaProperties = HashMap<Cow<'static, str>, String>;
+
+/// Returns interned copy of `name` if it is one of [KNOWN_PROPERTIES],
+/// otherwise `name` itself.
+fn intern_property(name: String) -> Cow<'static, str> {
+    match KNOWN_PROPERTIES.into_iter().find(|known| *known == name) {
+        Some(known) => Cow::Borrowed(known),
+        None => Cow::Owned(name),
+    }
+}
+
+/// Everything [MetaParser] managed to extract from page.
+pub struct PageMeta {
+    /// Meta tags plus evaluated robots instructions.
+    /// If meta tag is repeated, the last one wins.
+    pub properties: MetaProperties,
+
+    /// Repeated structured properties in order of appearance.
+    media: OgMediaMap,
+
+    /// Parsed JSON-LD scripts, e.g. schema.org `Article` definitions.
+    pub json_ld: Vec<serde_json::Value>,
+
+    /// `datetime` attribute of the first `<time>` element on page.
+    pub first_time: Option<String>,
+
+    /// The first `theme-color` not limited to dark color scheme.
+    pub theme_color: Option<String>,
+
+    /// `lang` attribute of `<html>` element.
+    pub html_lang: Option<String>,
+
+    /// Address of AMP version of page from `<link rel="amphtml">`.
+    pub amp_url: Option<String>,
+
+    /// Address from the first `<link rel="canonical">`.
+    pub canonical_url: Option<String>,
+
+    /// Address page was read from after redirects. Set only if it is
+    /// the page itself rather than its archived or rendered copy.
+    pub page_url: Option<Url>,
+
+    /// Icons of site in order of appearance.
+    pub icons: Vec<IconLink>,
+
+    /// Addresses of RSS and Atom feeds from `<link rel="alternate">` in
+    /// order of appearance.
+    pub feeds: Vec<String>,
+
+    /// Values of repeated `article:tag` properties.
+    pub article_tags: Vec<String>,
+
+    /// Whitespace normalized text of the first `<h1>` on page.
+    pub first_h1: Option<String>,
+
+    /// Bounded text of paragraphs inside `<article>`.
+    pub article_paragraphs: ParagraphsCollector,
+
+    /// Bounded text of any paragraphs on page.
+    pub paragraphs: ParagraphsCollector,
+
+    /// True if page looks like JavaScript application that renders
+    /// its content in browser, e.g. it asks to enable JavaScript.
+    pub looks_like_spa: bool,
+
+    /// True if page has banner or form of consent management platform,
+    /// which alone does not make it consent wall.
+    pub has_consent_banner: bool,
+
+    /// Passage of body text fragment of page URL points to, if parser
+    /// was given one and passage was found.
+    pub text_fragment_passage: Option<String>,
+
+    /// Values found by operator-defined [ExtractionRule].
+    rule_values: HashMap<RuleField, Vec<String>>,
+}
+
+impl PageMeta {
+    /// Returns all collected media for `root` property, e.g. `og:video`.
+    pub fn media(&self, root: &str) -> &[OgMedia] {
+        self.media.get(root)
+            .map(|items| items.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns all non-empty values found for `field` by extraction rule.
+    pub fn rule_values(&self, field: RuleField) -> &[String] {
+        self.rule_values.get(&field)
+            .map(|values| values.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Returns the first value found for `field` by extraction rule.
+    pub fn rule_value(&self, field: RuleField) -> Option<&String> {
+        self.rule_values(field).first()
+    }
+}
+
+/// Collects text of paragraphs on page up to [MAX_PARAGRAPHS_BYTES].
+#[derive(Default)]
+pub struct ParagraphsCollector {
+    /// Text of every paragraph seen.
+    paragraphs: Vec<String>,
+
+    /// Identifier of paragraph the last text chunk belongs to.
+    current_id: Option<usize>,
+
+    /// Total bytes of text collected.
+    collected_bytes: usize,
+}
+
+impl ParagraphsCollector {
+    /// Adds `text` chunk of paragraph identified by `paragraph_id`.
+    /// Chunks of the same paragraph are joined together.
+    fn push(&mut self, paragraph_id: usize, text: &str) {
+        if self.is_full() {
+            return;
+        }
+
+        if self.current_id != Some(paragraph_id) {
+            self.current_id = Some(paragraph_id);
+            self.paragraphs.push(String::new());
+        }
+
+        if let Some(paragraph) = self.paragraphs.last_mut() {
+            paragraph.push_str(text);
+            self.collected_bytes += text.len();
+        }
+    }
+
+    /// Returns true if collector does not accept text anymore.
+    fn is_full(&self) -> bool {
+        self.collected_bytes >= MAX_PARAGRAPHS_BYTES
+    }
+
+    /// Returns the first couple of sentences of the first paragraph
+    /// that looks like actual content.
+    pub fn description(&self) -> Option<String> {
+        self.paragraphs.iter()
+            .map(|paragraph| paragraph.split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+            )
+            .find(|paragraph| paragraph.chars().count() >= MIN_PARAGRAPH_CHARS)
+            .map(|paragraph| first_sentences(&paragraph, BODY_DESCRIPTION_SENTENCES))
+            .map(|description| truncate_chars(&description, MAX_BODY_DESCRIPTION_CHARS))
+    }
+}
+
+/// Returns up to `count` first sentences of `text`.
+fn first_sentences(text: &str, count: usize) -> String {
+    let mut chars = text.char_indices().peekable();
+    let mut found = 0;
+
+    while let Some((index, c)) = chars.next() {
+        let is_sentence_end = match c {
+            '。' | '！' | '？' => true,
+
+            '.' | '!' | '?' => chars.peek()
+                .is_none_or(|(_, next)| next.is_whitespace()),
+
+            _ => false,
+        };
+
+        if is_sentence_end {
+            found += 1;
+
+            if found == count {
+                return text[..index + c.len_utf8()].to_string();
+            }
+        }
+    }
+
+    text.to_string()
+}
+
+/// Cuts `text` to `max_chars` characters adding ellipsis if it was longer.
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((index, _)) => format!("{}…", text[..index].trim_end()),
+        None => text.to_string(),
+    }
+}
+
+/// This function adds `content` of meta tag `property` to `media` if tag
+/// is structured OpenGraph media property. Attributes such as `:width`
+/// apply to the latest root property seen before, as OpenGraph says.
+fn collect_og_media(media: &mut OgMediaMap, property: &str, content: &str) {
+    let found = OG_MEDIA_PROPERTIES.into_iter()
+        .find_map(|root| property.strip_prefix(root).map(|x| (root, x)));
+
+    let (root, attribute) = match found {
+        Some(found) => found,
+        None => return,
+    };
+
+    let items = media.entry(root).or_default();
+
+    if attribute.is_empty() || attribute == ":url" {
+        items.push(OgMedia {
+            url: content.to_string(),
+            ..OgMedia::default()
+        });
+
+        return;
+    }
+
+    // attribute without root property is meaningless
+    let item = match items.last_mut() {
+        Some(item) => item,
+        None => return,
+    };
+
+    match attribute {
+        ":secure_url" => item.secure_url = Some(content.to_string()),
+        ":type" => item.mime_type = Some(content.to_string()),
+        ":width" => item.width = content.trim().parse().ok(),
+        ":height" => item.height = content.trim().parse().ok(),
+        ":alt" => item.alt = Some(content.to_string()),
+        _ => { /* not interesting */ }
+    }
+}
+
+/// A tiny helper function that returns true if `text` contains known
+/// instruction to deny index.
+fn cannot_index(text: &str) -> bool {
+    text.contains("noindex") |
+        text.contains("none") |
+        text.contains("nosnippet")
+}
+
+/// This function looks up the first value of `key` in `values`,
+/// descending into nested objects and arrays such as JSON-LD `@graph`.
+pub fn find_json_ld_value<'a>(
+    values: &'a [serde_json::Value],
+    key: &str,
+) -> Option<&'a serde_json::Value> {
+    values.iter().find_map(|value| match value {
+        serde_json::Value::Object(object) => object.get(key)
+            .or_else(|| object.values().find_map(
+                |x| find_json_ld_value(std::slice::from_ref(x), key)
+            )),
+
+        serde_json::Value::Array(array) => find_json_ld_value(array, key),
+
+        _ => None,
+    })
+}
+
+/// Data handlers of [MetaParser] collect while document is parsed.
+#[derive(Default)]
+struct ParseState {
+    properties: MetaProperties,
+    media: OgMediaMap,
+    noindex: bool,
+    title_count: usize,
+    title: String,
+    json_ld_scripts: Vec<String>,
+    json_ld_buffer: String,
+    first_time: Option<String>,
+    theme_color: Option<String>,
+    html_lang: Option<String>,
+    amp_url: Option<String>,
+    canonical_url: Option<String>,
+    icons: Vec<IconLink>,
+    feeds: Vec<String>,
+    article_tags: Vec<String>,
+    h1_count: usize,
+    first_h1: String,
+    paragraph_id: usize,
+    article_paragraphs: ParagraphsCollector,
+    paragraphs: ParagraphsCollector,
+    body_started: bool,
+    looks_like_spa: bool,
+    has_consent_banner: bool,
+    text_fragment: Option<TextFragment>,
+    body_text: String,
+    rule_fields: Vec<RuleField>,
+    rule_values: HashMap<RuleField, Vec<String>>,
+}
+
+impl ParseState {
+    /// Returns true if nothing else is expected to be found in the rest of
+    /// document, so there is no point to download and parse it.
+    ///
+    /// Meta tags live in `<head>`, so once `<body>` starts and page has
+    /// title, description and image, parsing is done. Otherwise, text
+    /// fallbacks collected from body are needed until collectors are full.
+    fn is_satisfied(&self) -> bool {
+        if !self.body_started {
+            return false;
+        }
+
+        // elements extraction rules look for are usually in body
+        let rules_satisfied = self.rule_fields.iter()
+            .all(|field| match field {
+                // tags are spread across page, there is no telling
+                // whether all of them are collected
+                RuleField::Tags => false,
+                _ => self.rule_values.contains_key(field),
+            });
+
+        if !rules_satisfied {
+            return false;
+        }
+
+        // passage could be anywhere in body
+        if self.text_fragment.is_some() && self.body_text.len() < MAX_BODY_TEXT_BYTES {
+            return false;
+        }
+
+        let has = |keys: &[&str]| keys.iter()
+            .any(|key| self.properties.contains_key(*key));
+
+        let has_title = has(&["og:title"]) || !self.title.trim().is_empty();
+
+        let has_description = has(
+            &["og:description", "twitter:description", "description"]
+        );
+
+        let has_image = self.media.get("og:image")
+            .is_some_and(|images| !images.is_empty()) ||
+            has(&["twitter:image"]);
+
+        if has_title && has_description && has_image {
+            return true;
+        }
+
+        (has_title || self.h1_count > 1) &&
+            (has_description || self.paragraphs.is_full())
+    }
+}
+
+/// Streaming parser that extracts [PageMeta] from HTML document
+/// using [lol_html] rewriter, chunk by chunk.
+pub struct MetaParser {
+    rewriter: HtmlRewriter<'static, fn(&[u8])>,
+    state: Rc<RefCell<ParseState>>,
+
+    /// Set once everything needed is found, the rest of document is
+    /// not parsed at all.
+    is_done: bool,
+}
+
+impl MetaParser {
+    /// Constructs new instance of [MetaParser].
+    /// Document is expected to be UTF-8, see [crate::charset].
+    pub fn new() -> Self {
+        Self::with_extraction_rule(DEFAULT_CRAWLER_NAME, None)
+    }
+
+    /// Constructs new instance of [MetaParser] that follows robots
+    /// meta-tags addressed to `crawler_name` and also collects values
+    /// for fields of extraction `rule`, if it is set.
+    pub fn with_extraction_rule(
+        crawler_name: &str,
+        rule: Option<&ExtractionRule>,
+    ) -> Self {
+        let state = Rc::new(RefCell::new(ParseState::default()));
+        let crawler_name = crawler_name.to_lowercase();
+
+        let meta_state = state.clone();
+        let title_state = state.clone();
+        let title_text_state = state.clone();
+        let json_ld_state = state.clone();
+        let amp_state = state.clone();
+        let lang_state = state.clone();
+        let link_state = state.clone();
+        let h1_state = state.clone();
+        let h1_text_state = state.clone();
+        let paragraph_state = state.clone();
+        let article_text_state = state.clone();
+        let paragraph_text_state = state.clone();
+        let time_state = state.clone();
+        let spa_state = state.clone();
+        let noscript_state = state.clone();
+        let consent_state = state.clone();
+        let body_state = state.clone();
+        let body_text_state = state.clone();
+
+        let mut element_content_handlers = vec![
+            element!("meta", move |el| {
+                let property = el.get_attribute("property")
+                    .or_else(|| el.get_attribute("name"));
+
+                let content = el.get_attribute("content");
+
+                if property.is_some() && content.is_some() {
+                    let property = property.unwrap();
+                    let content = content.unwrap();
+                    let mut state = meta_state.borrow_mut();
+
+                    // check rule for all robots
+                    if property == "robots" {
+                        state.noindex |= cannot_index(&content);
+                    }
+
+                    // check rule for Crabo specifically
+                    if property.to_lowercase().contains(&crawler_name) {
+                        state.noindex |= cannot_index(&content);
+                    }
+
+                    collect_og_media(&mut state.media, &property, &content);
+
+                    // article:tag is repeated once per tag
+                    if property == "article:tag" {
+                        state.article_tags.push(content.clone());
+                    }
+
+                    // theme-color could be repeated per color scheme,
+                    // light one is expected to suit most cards.
+                    if property == "theme-color" && state.theme_color.is_none() {
+                        let dark_only = el.get_attribute("media")
+                            .is_some_and(|media| media.contains("dark"));
+
+                        if !dark_only {
+                            state.theme_color = Some(content.clone());
+                        }
+                    }
+
+                    state.properties.insert(
+                        intern_property(property),
+                        content,
+                    );
+                }
+
+                Ok(())
+            }),
+            element!("title", move |_el| {
+                title_state.borrow_mut().title_count += 1;
+                Ok(())
+            }),
+            text!("title", move |el| {
+                let mut state = title_text_state.borrow_mut();
+
+                // SVG images have titles too, these are not interesting
+                if state.title_count == 1 && state.title.len() < MAX_TITLE_BYTES {
+                    state.title.push_str(el.as_str());
+                }
+
+                Ok(())
+            }),
+            text!("script[type='application/ld+json']", move |el| {
+                let mut state = json_ld_state.borrow_mut();
+                state.json_ld_buffer.push_str(el.as_str());
+
+                if el.last_in_text_node() {
+                    let script = std::mem::take(&mut state.json_ld_buffer);
+                    state.json_ld_scripts.push(script);
+                }
+
+                Ok(())
+            }),
+            element!("html[lang]", move |el| {
+                lang_state.borrow_mut().html_lang = el.get_attribute("lang");
+                Ok(())
+            }),
+            element!("link[rel='amphtml'][href]", move |el| {
+                let mut state = amp_state.borrow_mut();
+
+                if state.amp_url.is_none() {
+                    state.amp_url = el.get_attribute("href");
+                }
+
+                Ok(())
+            }),
+            element!("link[rel][href]", move |el| {
+                let rel = el.get_attribute("rel")
+                    .unwrap_or_default()
+                    .to_lowercase();
+
+                let rel_words: Vec<_> = rel.split_whitespace().collect();
+
+                let is_apple_touch = rel_words.iter()
+                    .any(|x| x.starts_with("apple-touch-icon"));
+
+                let mut state = link_state.borrow_mut();
+
+                if rel_words.contains(&"canonical") && state.canonical_url.is_none() {
+                    state.canonical_url = el.get_attribute("href");
+                }
+
+                if is_apple_touch || rel_words.contains(&"icon") {
+                    state.icons.push(IconLink {
+                        href: el.get_attribute("href").unwrap_or_default(),
+                        mime_type: el.get_attribute("type"),
+                        size: el.get_attribute("sizes")
+                            .and_then(|sizes| parse_icon_sizes(&sizes)),
+                        is_apple_touch,
+                    });
+                }
+
+                let is_feed = el.get_attribute("type")
+                    .is_some_and(|x| FEED_TYPES.contains(&x.trim().to_lowercase().as_str()));
+
+                if rel_words.contains(&"alternate") && is_feed && state.feeds.len() < MAX_FEEDS {
+                    state.feeds.push(el.get_attribute("href").unwrap_or_default());
+                }
+
+                Ok(())
+            }),
+            element!("body", move |_el| {
+                body_state.borrow_mut().body_started = true;
+                Ok(())
+            }),
+            text!("body", move |t| {
+                let mut state = body_text_state.borrow_mut();
+
+                // scripts and styles are not text reader could quote
+                let is_collected = state.text_fragment.is_some() &&
+                    t.text_type() == TextType::Data &&
+                    state.body_text.len() < MAX_BODY_TEXT_BYTES;
+
+                if is_collected {
+                    state.body_text.push_str(t.as_str());
+
+                    // text of adjacent elements is not glued together
+                    if t.last_in_text_node() {
+                        state.body_text.push(' ');
+                    }
+                }
+
+                Ok(())
+            }),
+            element!("h1", move |_el| {
+                h1_state.borrow_mut().h1_count += 1;
+                Ok(())
+            }),
+            text!("h1", move |el| {
+                let mut state = h1_text_state.borrow_mut();
+
+                if state.h1_count == 1 && state.first_h1.len() < MAX_H1_BYTES {
+                    state.first_h1.push_str(el.as_str());
+                }
+
+                Ok(())
+            }),
+            element!("p", move |_el| {
+                paragraph_state.borrow_mut().paragraph_id += 1;
+                Ok(())
+            }),
+            text!("article p", move |el| {
+                let mut state = article_text_state.borrow_mut();
+                let paragraph_id = state.paragraph_id;
+                state.article_paragraphs.push(paragraph_id, el.as_str());
+                Ok(())
+            }),
+            text!("p", move |el| {
+                let mut state = paragraph_text_state.borrow_mut();
+                let paragraph_id = state.paragraph_id;
+                state.paragraphs.push(paragraph_id, el.as_str());
+                Ok(())
+            }),
+            element!("time[datetime]", move |el| {
+                let mut state = time_state.borrow_mut();
+
+                if state.first_time.is_none() {
+                    state.first_time = el.get_attribute("datetime");
+                }
+
+                Ok(())
+            }),
+            element!(SPA_ROOT_SELECTOR, move |_el| {
+                spa_state.borrow_mut().looks_like_spa = true;
+                Ok(())
+            }),
+            text!("noscript", move |t| {
+                if t.as_str().to_lowercase().contains("javascript") {
+                    noscript_state.borrow_mut().looks_like_spa = true;
+                }
+
+                Ok(())
+            }),
+            element!(CONSENT_BANNER_SELECTOR, move |_el| {
+                consent_state.borrow_mut().has_consent_banner = true;
+                Ok(())
+            }),
+        ];
+
+        if let Some(rule) = rule {
+            let mut state = state.borrow_mut();
+            state.rule_fields = rule.fields().into_iter()
+                .map(|(field, _)| field)
+                .collect();
+        }
+
+        for (field, field_rule) in rule.map(|x| x.fields()).unwrap_or_default() {
+            let rule_state = state.clone();
+            let attribute = field_rule.attribute.clone();
+
+            // every matching element starts new value, text of element
+            // is appended to it by text handler below
+            element_content_handlers.push(
+                element!(field_rule.selector.as_str(), move |el| {
+                    let mut state = rule_state.borrow_mut();
+                    let values = state.rule_values.entry(field).or_default();
+
+                    let collected_bytes: usize = values.iter()
+                        .map(|value| value.len())
+                        .sum();
+
+                    if collected_bytes < MAX_RULE_VALUE_BYTES {
+                        let value = match &attribute {
+                            Some(attribute) => el.get_attribute(attribute)
+                                .unwrap_or_default(),
+
+                            None => String::new(),
+                        };
+
+                        values.push(value);
+                    }
+
+                    Ok(())
+                })
+            );
+
+            if field_rule.attribute.is_some() {
+                continue;
+            }
+
+            let rule_text_state = state.clone();
+
+            element_content_handlers.push(
+                text!(field_rule.selector.as_str(), move |t| {
+                    let mut state = rule_text_state.borrow_mut();
+
+                    if let Some(values) = state.rule_values.get_mut(&field) {
+                        let collected_bytes: usize = values.iter()
+                            .map(|value| value.len())
+                            .sum();
+
+                        if collected_bytes < MAX_RULE_VALUE_BYTES {
+                            if let Some(value) = values.last_mut() {
+                                value.push_str(t.as_str());
+                            }
+                        }
+                    }
+
+                    Ok(())
+                })
+            );
+        }
+
+        let rewriter = HtmlRewriter::new(
+            Settings {
+                // document is transcoded to UTF-8 before parsing,
+                // so meta tags must not switch encoding again.
+                adjust_charset_on_meta_tag: false,
+                element_content_handlers,
+                ..Settings::default()
+            },
+            (|_c: &[u8]| {}) as fn(&[u8]),
+        );
+
+        Self {
+            rewriter,
+            state,
+            is_done: false,
+        }
+    }
+
+    /// Returns this parser that also looks for passage `text_fragment`
+    /// points to in body, see [PageMeta::text_fragment_passage]. Body is
+    /// parsed further than usual then.
+    pub fn with_text_fragment(self, text_fragment: Option<TextFragment>) -> Self {
+        self.state.borrow_mut().text_fragment = text_fragment;
+        self
+    }
+
+    /// Parses next `chunk` of document.
+    /// Returns true if more data is needed, otherwise false.
+    /// Chunks written after that are ignored.
+    pub fn write(&mut self, chunk: &[u8]) -> bool {
+        if self.is_done {
+            return false;
+        }
+
+        self.rewriter.write(chunk).unwrap_or(());
+        self.is_done = self.state.borrow().is_satisfied();
+
+        !self.is_done
+    }
+
+    /// Finishes parsing and returns [PageMeta] with properties extracted
+    /// from document. These properties include meta tags plus evaluated
+    /// robots instructions.
+    pub fn finish(self) -> PageMeta {
+        self.rewriter.end().unwrap_or(());
+
+        let state = self.state.take();
+        let mut properties = state.properties;
+
+        if state.title_count > 0 {
+            properties.insert("title".into(), state.title);
+        }
+
+        properties.insert(
+            FEDINEKO_CAN_INDEX_KEY.into(),
+            (!state.noindex).to_string()
+        );
+
+        let json_ld = state.json_ld_scripts.iter()
+            .filter_map(|script| serde_json::from_str(script).ok())
+            .collect();
+
+        let first_h1 = state.first_h1.split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let rule_values = state.rule_values.into_iter()
+            .map(|(field, values)| {
+                let values: Vec<_> = values.into_iter()
+                    .map(|value| value.split_whitespace()
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                    )
+                    .filter(|value| !value.is_empty())
+                    .collect();
+
+                (field, values)
+            })
+            .collect();
+
+        PageMeta {
+            properties,
+            media: state.media,
+            json_ld,
+            first_time: state.first_time,
+            theme_color: state.theme_color,
+            html_lang: state.html_lang,
+            amp_url: state.amp_url,
+            canonical_url: state.canonical_url,
+            page_url: None,
+            icons: state.icons,
+            feeds: state.feeds,
+            article_tags: state.article_tags,
+            first_h1: Some(first_h1).filter(|h1| !h1.is_empty()),
+            article_paragraphs: state.article_paragraphs,
+            paragraphs: state.paragraphs,
+            looks_like_spa: state.looks_like_spa,
+            has_consent_banner: state.has_consent_banner,
+
+            text_fragment_passage: state.text_fragment
+                .and_then(|text_fragment| text_fragment.locate(&state.body_text))
+                .map(|passage| truncate_chars(&passage, MAX_BODY_DESCRIPTION_CHARS)),
+
+            rule_values,
+        }
+    }
+}
+
+/// This function parses the whole HTML document in `bytes` at once.
+#[cfg(test)]
+pub fn parse_page_meta(bytes: &[u8]) -> PageMeta {
+    let mut parser = MetaParser::new();
+    parser.write(bytes);
+    parser.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+    use url::Url;
+    use crate::extraction_rules::{ExtractionRules, RuleField};
+    use crate::page_meta::{
+        FEDINEKO_CAN_INDEX_KEY,
+        intern_property,
+        MetaParser,
+        parse_page_meta,
+    };
+    use crate::text_fragment::TextFragment;
+
+    #[test]
+    fn test_structured_og_media() {
+        let html = r#"<html><head>
+            <meta property="og:video" content="https://a.example/1.mp4">
+            <meta property="og:video:type" content="video/mp4">
+            <meta property="og:video:width" content="640">
+            <meta property="og:video" content="https://a.example/2.webm">
+            <meta property="og:video:secure_url" content="https://b.example/2.webm">
+            <meta property="og:audio:type" content="audio/mpeg">
+        </head></html>"#;
+
+        let page_meta = parse_page_meta(html.as_bytes());
+        let videos = page_meta.media("og:video");
+
+        assert_eq!(videos.len(), 2);
+        assert_eq!(videos[0].mime_type, Some("video/mp4".to_string()));
+        assert_eq!(videos[0].width, Some(640));
+        assert_eq!(videos[0].height, None);
+
+        assert_eq!(
+            videos[1].secure_url,
+            Some("https://b.example/2.webm".to_string())
+        );
+
+        // attribute without root property is ignored
+        assert!(page_meta.media("og:audio").is_empty());
+    }
+
+    #[test]
+    fn test_body_description() {
+        let html = r#"<html><body>
+            <nav><p>Home</p></nav>
+            <p>This paragraph is outside of article, but long enough
+            to be a description of the page.</p>
+            <article>
+                <p>By Someone</p>
+                <p>First sentence of the <a href="/">actual</a> article
+                is here. Second one is here! Third one is not needed.</p>
+            </article>
+        </body></html>"#;
+
+        let page_meta = parse_page_meta(html.as_bytes());
+
+        assert_eq!(
+            page_meta.article_paragraphs.description(),
+            Some(
+                "First sentence of the actual article is here. \
+                Second one is here!".to_string()
+            )
+        );
+
+        assert_eq!(
+            page_meta.paragraphs.description(),
+            Some(
+                "This paragraph is outside of article, but long enough \
+                to be a description of the page.".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_first_h1() {
+        let page_meta = parse_page_meta(
+            b"<body><h1>\n  Hello, <em>world</em>  </h1><h1>Second</h1></body>"
+        );
+
+        assert_eq!(page_meta.first_h1, Some("Hello, world".to_string()));
+    }
+
+    #[test]
+    fn test_icons() {
+        let page_meta = parse_page_meta(br#"<head>
+            <link rel="shortcut icon" href="/favicon.ico">
+            <link rel="icon" type="image/png" sizes="16x16 32X32" href="/32.png">
+            <link rel="apple-touch-icon-precomposed" href="/apple.png">
+            <link rel="stylesheet" href="/style.css">
+        </head>"#);
+
+        assert_eq!(
+            page_meta.icons.iter().map(|x| x.href.as_str()).collect::<Vec<_>>(),
+            vec!["/favicon.ico", "/32.png", "/apple.png"]
+        );
+
+        assert_eq!(page_meta.icons[1].size, Some(32));
+        assert!(page_meta.icons[2].is_apple_touch);
+    }
+
+    #[test]
+    fn test_feeds() {
+        let page_meta = parse_page_meta(br#"<head>
+            <link rel="alternate" type="application/rss+xml" href="/feed.xml">
+            <link rel="alternate" type="Application/Atom+XML" href="/atom.xml">
+            <link rel="alternate" hreflang="de" href="/de/">
+            <link rel="alternate" type="application/json+oembed" href="/oembed">
+        </head>"#);
+
+        assert_eq!(page_meta.feeds, vec!["/feed.xml", "/atom.xml"]);
+    }
+
+    #[test]
+    fn test_text_fragment_passage() {
+        let url = Url::parse("https://a.example/#:~:text=second,paragraph").unwrap();
+        let mut parser = MetaParser::new().with_text_fragment(TextFragment::from_url(&url));
+
+        parser.write(br#"<html><head><title>T</title>
+            <meta name="description" content="D"><meta property="og:image" content="/i.png">
+            </head><body><p>First paragraph.</p><script>var second = 1;</script>
+            <p>Second <b>quoted</b></p><p>paragraph.</p></body></html>"#);
+
+        let page_meta = parser.finish();
+        assert_eq!(page_meta.text_fragment_passage.as_deref(), Some("Second quoted paragraph"));
+    }
+
+    #[test]
+    fn test_extraction_rule() {
+        let rules = ExtractionRules::from_json(r#"[{
+            "domain": "example.com",
+            "title": {"selector": "div.headline"},
+            "image": {"selector": "div.cover img", "attribute": "data-src"},
+            "tags": {"selector": "ul.tags > li"}
+        }]"#).unwrap();
+
+        let mut parser = MetaParser::with_extraction_rule(
+            "fedineko-crabo",
+            rules.for_host("example.com"),
+        );
+
+        parser.write(br#"<html><body>
+            <div class="headline">  Broken <b>OpenGraph</b> </div>
+            <div class="cover"><img data-src="/cover.png"></div>
+            <ul class="tags"><li>rust</li><li> </li><li>html</li></ul>
+        </body></html>"#);
+
+        let page_meta = parser.finish();
+
+        assert_eq!(
+            page_meta.rule_value(RuleField::Title).unwrap(),
+            "Broken OpenGraph"
+        );
+
+        assert_eq!(page_meta.rule_value(RuleField::Image).unwrap(), "/cover.png");
+        assert_eq!(page_meta.rule_values(RuleField::Tags), ["rust", "html"]);
+        assert!(page_meta.rule_value(RuleField::Description).is_none());
+    }
+
+    #[test]
+    fn test_robots_meta_for_crawler_name() {
+        let can_index = |crawler_name, html: &str| {
+            let mut parser = MetaParser::with_extraction_rule(crawler_name, None);
+            parser.write(html.as_bytes());
+            parser.finish().properties[FEDINEKO_CAN_INDEX_KEY] == "true"
+        };
+
+        let html = r#"<head><meta name="Example-Bot, other" content="noindex"></head>"#;
+
+        assert!(!can_index("example-bot", html));
+        assert!(can_index("fedineko-crabo", html));
+    }
+
+    #[test]
+    fn test_consent_banner_detection() {
+        let page_meta = parse_page_meta(br#"<html><head><title>News</title>
+            <script src="https://cdn.cookielaw.org/scripttemplates/otSDKStub.js"></script>
+            </head><body></body></html>"#);
+
+        assert!(page_meta.has_consent_banner);
+
+        let page_meta = parse_page_meta(
+            b"<html><body><div id=\"didomi-host\"></div></body></html>"
+        );
+
+        assert!(page_meta.has_consent_banner);
+
+        let page_meta = parse_page_meta(
+            b"<html><body><form action=\"/search\"><p>Text</p></form></body></html>"
+        );
+
+        assert!(!page_meta.has_consent_banner);
+    }
+
+    #[test]
+    fn test_spa_detection() {
+        let page_meta = parse_page_meta(br#"<html><head><title>App</title>
+            </head><body><noscript>You need to enable JavaScript to run
+            this app.</noscript><div id="root"></div></body></html>"#);
+
+        assert!(page_meta.looks_like_spa);
+
+        let page_meta = parse_page_meta(
+            b"<html><body><div id=\"content\"><p>Text</p></div></body></html>"
+        );
+
+        assert!(!page_meta.looks_like_spa);
+    }
+
+    #[test]
+    fn test_title_split_across_chunks() {
+        let mut parser = MetaParser::new();
+        parser.write(b"<head><title>Split ti");
+        parser.write(b"tle</title></head><svg><title>Icon</title></svg>");
+
+        let page_meta = parser.finish();
+
+        assert_eq!(
+            page_meta.properties.get("title"),
+            Some(&"Split title".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parsing_stops_after_head() {
+        let mut parser = MetaParser::new();
+
+        assert!(parser.write(
+            br#"<head>
+            <meta property="og:title" content="Title">
+            <meta property="og:description" content="Description">
+            <meta property="og:image" content="https://a.example/1.png">
+            </head>"#
+        ));
+
+        assert!(!parser.write(b"<body><p>Text</p>"));
+        assert!(!parser.write(b"<h1>Ignored</h1>"));
+        assert!(parser.finish().first_h1.is_none());
+    }
+
+    #[test]
+    fn test_property_interning() {
+        assert!(matches!(intern_property("og:title".to_string()), Cow::Borrowed(_)));
+        assert!(matches!(intern_property("x:custom".to_string()), Cow::Owned(_)));
+
+        let page_meta = parse_page_meta(
+            br#"<meta name="og:title" content="Title"><meta name="x:custom" content="1">"#
+        );
+
+        assert_eq!(page_meta.properties.get("og:title").unwrap(), "Title");
+        assert_eq!(page_meta.properties.get("x:custom").unwrap(), "1");
+    }
+}
@@ -0,0 +1,414 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use chrono::{DateTime, Utc};
+use futures::future::LocalBoxFuture;
+use log::{info, warn};
+use lru::LruCache;
+use regex::Regex;
+use serde::Deserialize;
+use url::Url;
+
+use crabo_model::{SnapError, Snapshot};
+
+use crate::inflight::set_stage;
+use crate::page_client::FetchError;
+use crate::provider_headers::RequestHeaders;
+use crate::quality::{DataSource, with_quality};
+use crate::snapper::{
+    bare_snapshot,
+    CacheHints,
+    Clients,
+    Snapper,
+    SnapshotAndHints,
+};
+use crate::util::normalize_language_tag;
+
+/// Number of servers remembered to be PeerTube instances or not.
+const TRACKED_INSTANCES: usize = 1024;
+
+/// Paths of PeerTube video pages, with either short UUID or UUID of video.
+const PEERTUBE_WATCH_PATTERN: &str =
+    r"^https?://[^/]+/(?:w/(?:[1-9A-HJ-NP-Za-km-z]{22}|[0-9a-fA-F-]{36})|videos/watch/[0-9a-fA-F-]{36})/?(?:[?#].*)?$";
+
+/// Whether server is known to be PeerTube instance.
+#[derive(Clone, Debug, PartialEq)]
+enum Instance {
+    /// PeerTube instance with its name, if it has one.
+    PeerTube(Option<String>),
+
+    /// Anything else, URLs of it are left to other snappers.
+    Other,
+}
+
+/// Configuration of PeerTube instance, only fields telling it apart.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct InstanceConfig {
+    /// Version of PeerTube, which other servers do not have.
+    server_version: String,
+
+    /// Details of instance.
+    instance: Option<InstanceDetails>,
+}
+
+/// Details of PeerTube instance.
+#[derive(Deserialize)]
+struct InstanceDetails {
+    /// Name of instance, e.g. `Framatube`.
+    name: Option<String>,
+}
+
+/// Account or channel video is published by.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Actor {
+    display_name: Option<String>,
+}
+
+/// Language of video.
+#[derive(Deserialize)]
+struct Language {
+    /// Language code, e.g. `en`.
+    id: Option<String>,
+}
+
+/// A very simplified version of PeerTube's video details.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Video {
+    /// Title of video.
+    name: Option<String>,
+
+    /// Description of video in Markdown.
+    description: Option<String>,
+
+    /// Path of large preview image on instance.
+    preview_path: Option<String>,
+
+    /// Path of small thumbnail image on instance.
+    thumbnail_path: Option<String>,
+
+    /// Duration of video in seconds.
+    duration: Option<u64>,
+
+    /// Time of publication.
+    published_at: Option<DateTime<Utc>>,
+
+    /// Account that uploaded video.
+    account: Option<Actor>,
+
+    /// Channel video is published in.
+    channel: Option<Actor>,
+
+    /// Tags of video.
+    #[serde(default)]
+    tags: Vec<String>,
+
+    /// True if video is marked as sensitive.
+    #[serde(default)]
+    nsfw: bool,
+
+    /// Language of video.
+    language: Option<Language>,
+
+    /// Address of video on its origin instance.
+    url: Option<Url>,
+}
+
+/// This function extracts ID of video from `url` of PeerTube video page,
+/// either short UUID of `/w/` page or UUID of `/videos/watch/` page.
+fn extract_video_id(url: &Url) -> Option<String> {
+    let mut segments = url.path_segments()?;
+
+    let id = match (segments.next(), segments.next(), segments.next()) {
+        (Some("w"), Some(id), _) => id,
+        (Some("videos"), Some("watch"), Some(id)) => id,
+        _ => return None,
+    };
+
+    match !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        true => Some(id.to_string()),
+        false => None,
+    }
+}
+
+/// This snapper uses API of PeerTube instances to get video details, as
+/// video pages are JavaScript applications with little meta-data.
+///
+/// PeerTube could be on any server, so URLs that look like PeerTube
+/// video pages are snapped only once server is confirmed to be PeerTube
+/// instance. Other servers are remembered, so their URLs are left to the
+/// general purpose snapper, starting with URL that made snapper ask.
+pub struct PeerTubeSnapper {
+    /// Headers sent to API along with requests.
+    request_headers: RequestHeaders,
+
+    /// Servers known to be PeerTube instances or not, by host.
+    instances: Mutex<LruCache<String, Instance>>,
+}
+
+impl PeerTubeSnapper {
+    /// Constructs new instance of [PeerTubeSnapper] that sends
+    /// `request_headers` along with API requests.
+    pub fn new(request_headers: RequestHeaders) -> Self {
+        Self {
+            request_headers,
+
+            instances: Mutex::new(
+                LruCache::new(NonZeroUsize::new(TRACKED_INSTANCES).unwrap())
+            ),
+        }
+    }
+
+    /// Returns matcher of URLs that look like PeerTube video pages.
+    pub fn url_pattern() -> Regex {
+        Regex::new(PEERTUBE_WATCH_PATTERN).unwrap()
+    }
+
+    /// This method finds out whether server of `url` is PeerTube instance
+    /// by asking for its configuration, unless it is known already.
+    /// Returns error if server could not be asked.
+    async fn instance(&self, url: &Url, clients: &Clients) -> Result<Instance, SnapError> {
+        let host = url.host_str().unwrap_or_default().to_string();
+
+        if let Some(instance) = self.instances.lock().unwrap().get(&host) {
+            return Ok(instance.clone());
+        }
+
+        let config_url = url.join("/api/v1/config")
+            .map_err(|_| SnapError::ParseFailed)?;
+
+        set_stage("checking PeerTube instance");
+
+        let instance = match clients.page_client.get_json::<InstanceConfig>(
+            &config_url,
+            &self.request_headers.merged_with(&[]),
+        ).await {
+            Ok(config) if !config.server_version.is_empty() => Instance::PeerTube(
+                config.instance.and_then(|instance| instance.name)
+            ),
+
+            Ok(_) |
+            Err(FetchError::UnexpectedStatusCode(_)) |
+            Err(FetchError::Malformed(_)) => Instance::Other,
+
+            // server is not known to be anything yet
+            Err(err) => {
+                warn!("Failed to check whether {host} is PeerTube instance: {err:?}");
+                return Err(err.snap_error());
+            }
+        };
+
+        self.instances.lock().unwrap().put(host, instance.clone());
+        Ok(instance)
+    }
+
+    /// This method converts `video` of instance named `instance_name`
+    /// to Crabo [Snapshot]. Paths of images are relative to `url`.
+    fn video_to_snapshot(
+        &self,
+        url: Url,
+        instance_name: Option<String>,
+        video: Video,
+    ) -> Snapshot {
+        let preview_url = video.preview_path
+            .or(video.thumbnail_path)
+            .and_then(|path| url.join(&path).ok());
+
+        let preview_mime_type = preview_url.as_ref()
+            .and_then(|x| mime_guess::from_path(x.path()).first())
+            .map(|m| m.to_string());
+
+        let author = video.channel
+            .and_then(|channel| channel.display_name)
+            .or(video.account.and_then(|account| account.display_name));
+
+        let snapshot = Snapshot {
+            title: video.name,
+            description: video.description,
+            preview_url,
+            preview_mime_type,
+            author,
+            duration_seconds: video.duration.filter(|seconds| *seconds > 0),
+            published_at: video.published_at,
+
+            tags: video.tags.into_iter()
+                .filter(|tag| !tag.trim().is_empty())
+                .map(|tag| format!("#{tag}"))
+                .collect(),
+
+            sensitive: video.nsfw,
+
+            language: video.language
+                .and_then(|language| language.id)
+                .as_deref()
+                .and_then(normalize_language_tag),
+
+            source: Option::from("PeerTube".to_string()),
+            site_name: instance_name.or(url.host_str().map(|host| host.to_string())),
+            canonical_url: video.url,
+            ..bare_snapshot(url)
+        };
+
+        with_quality(snapshot, DataSource::Api)
+    }
+}
+
+impl Snapper for PeerTubeSnapper {
+    fn provider(&self) -> String {
+        "peertube".into()
+    }
+
+    /// Servers known not to be PeerTube instances get no hints, so
+    /// general purpose snapper deals with them.
+    fn cache_hints(&self, url: &Url) -> Option<CacheHints> {
+        let host = url.host_str()?;
+
+        if self.instances.lock().unwrap().peek(host) == Some(&Instance::Other) {
+            return None;
+        }
+
+        extract_video_id(url)
+            .map(|id| CacheHints {
+                provider: self.provider(),
+                id: format!("peertube:{host}/{id}"),
+                language: None,
+            })
+    }
+
+    fn snap<'a>(
+        &'a self,
+        url: Url,
+        cache_hints: CacheHints,
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, SnapshotAndHints> {
+        Box::pin(async move {
+            let instance_name = match self.instance(&url, clients).await {
+                Ok(Instance::PeerTube(name)) => name,
+
+                // snapper does not hint URL anymore, so snapshot maker
+                // passes it to general purpose snapper right away
+                Ok(Instance::Other) => {
+                    info!("{url}: server is not PeerTube instance");
+
+                    return SnapshotAndHints {
+                        snapshot: Err(SnapError::ProviderError),
+                        hints: cache_hints,
+                    };
+                }
+
+                Err(err) => return SnapshotAndHints {
+                    snapshot: Err(err),
+                    hints: cache_hints,
+                },
+            };
+
+            let Some(api_url) = extract_video_id(&url)
+                .and_then(|id| url.join(&format!("/api/v1/videos/{id}")).ok()) else {
+                return SnapshotAndHints {
+                    snapshot: Err(SnapError::NotFound),
+                    hints: cache_hints,
+                };
+            };
+
+            set_stage("calling PeerTube API");
+
+            let snapshot = match clients.page_client.get_json::<Video>(
+                &api_url,
+                &self.request_headers.merged_with(&[]),
+            ).await {
+                Ok(video) => Ok(self.video_to_snapshot(url, instance_name, video)),
+
+                Err(err) => {
+                    warn!("Failed to get details for PeerTube video {url}: {err:?}");
+                    clients.provider_metrics.record_api_error("peertube");
+                    Err(err.snap_error())
+                }
+            };
+
+            SnapshotAndHints {
+                snapshot,
+                hints: cache_hints,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::peertube::{extract_video_id, Instance, PeerTubeSnapper, Video};
+    use crate::provider_headers::RequestHeaders;
+    use crate::snapper::Snapper;
+
+    #[test]
+    fn test_url_pattern() {
+        let pattern = PeerTubeSnapper::url_pattern();
+
+        for url in [
+            "https://framatube.org/w/9c9de5e8-0a1e-484a-b099-e80766180a6d",
+            "https://framatube.org/w/kkGMgK9ZtnKfYAgnEtQxbv",
+            "https://framatube.org/videos/watch/9c9de5e8-0a1e-484a-b099-e80766180a6d?start=1m",
+        ] {
+            assert!(pattern.is_match(url), "{url}");
+
+            let url = Url::parse(url).unwrap();
+            assert!(extract_video_id(&url).is_some());
+        }
+
+        assert!(!pattern.is_match("https://en.wikipedia.org/w/index.php?title=Cat"));
+        assert!(!pattern.is_match("https://framatube.org/w/p/kkGMgK9ZtnKfYAgnEtQxbv"));
+    }
+
+    #[test]
+    fn test_cache_hints() {
+        let snapper = PeerTubeSnapper::new(RequestHeaders::default());
+        let url = Url::parse("https://tube.example/w/kkGMgK9ZtnKfYAgnEtQxbv").unwrap();
+
+        assert_eq!(
+            snapper.cache_hints(&url).unwrap().id,
+            "peertube:tube.example/kkGMgK9ZtnKfYAgnEtQxbv"
+        );
+
+        snapper.instances.lock().unwrap().put("tube.example".to_string(), Instance::Other);
+        assert!(snapper.cache_hints(&url).is_none());
+    }
+
+    #[test]
+    fn test_video_to_snapshot() {
+        let video: Video = serde_json::from_str(r#"{
+            "name": "Cats",
+            "description": "All about cats",
+            "previewPath": "/lazy-static/previews/a.jpg",
+            "thumbnailPath": "/lazy-static/thumbnails/a.jpg",
+            "duration": 95,
+            "publishedAt": "2024-05-01T12:00:00.000Z",
+            "account": {"displayName": "Uploader"},
+            "channel": {"displayName": "Cat channel"},
+            "tags": ["cats", ""],
+            "nsfw": false,
+            "language": {"id": "en", "label": "English"},
+            "url": "https://origin.example/videos/watch/9c9de5e8-0a1e-484a-b099-e80766180a6d"
+        }"#).unwrap();
+
+        let snapper = PeerTubeSnapper::new(RequestHeaders::default());
+
+        let snapshot = snapper.video_to_snapshot(
+            Url::parse("https://tube.example/w/kkGMgK9ZtnKfYAgnEtQxbv").unwrap(),
+            Some("Tube".to_string()),
+            video,
+        );
+
+        assert_eq!(snapshot.title.as_deref(), Some("Cats"));
+        assert_eq!(snapshot.author.as_deref(), Some("Cat channel"));
+        assert_eq!(snapshot.site_name.as_deref(), Some("Tube"));
+        assert_eq!(snapshot.duration_seconds, Some(95));
+        assert_eq!(snapshot.tags, vec!["#cats"]);
+        assert_eq!(snapshot.language.as_deref(), Some("en"));
+
+        assert_eq!(
+            snapshot.preview_url.unwrap().as_str(),
+            "https://tube.example/lazy-static/previews/a.jpg"
+        );
+    }
+}
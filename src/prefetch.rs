@@ -0,0 +1,192 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Notify;
+use url::Url;
+
+/// Default maximum number of URLs waiting to be prefetched.
+pub(crate) const DEFAULT_PREFETCH_QUEUE_SIZE: usize = 1024;
+
+/// Number of URLs snapped at once by prefetcher.
+const PREFETCH_BATCH_SIZE: usize = 8;
+
+/// Time prefetcher waits before checking again if instance is idle.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Body of `/prefetch` request.
+#[derive(Deserialize)]
+pub(crate) struct PrefetchRequest {
+    /// URLs to snap when there is nothing else to do.
+    pub(crate) urls: Vec<Url>,
+}
+
+/// Body of `/prefetch` response.
+#[derive(Serialize)]
+pub(crate) struct PrefetchResponse {
+    /// Number of URLs added to queue, the rest were either queued
+    /// already or did not fit.
+    pub(crate) accepted: usize,
+}
+
+/// Marks interactive request as being processed, prefetching pauses
+/// until all guards are dropped.
+pub(crate) struct BusyGuard {
+    active_requests: Arc<AtomicUsize>,
+}
+
+impl Drop for BusyGuard {
+    fn drop(&mut self) {
+        self.active_requests.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Low-priority queue of URLs to snap ahead of time.
+///
+/// Other Fedineko components know about URLs before anyone asks for their
+/// snapshots, so they could enqueue them to populate cache while Crabo has
+/// nothing better to do. URLs are taken from queue only when no `/snap`
+/// request is in progress, so interactive calls never wait for prefetching
+/// to finish more than a single batch.
+///
+/// Queue lives in memory only, it is fine to lose it on restart.
+/// Clones share the same queue.
+#[derive(Clone)]
+pub(crate) struct PrefetchQueue {
+    urls: Arc<Mutex<VecDeque<Url>>>,
+    notify: Arc<Notify>,
+    active_requests: Arc<AtomicUsize>,
+    max_size: usize,
+}
+
+impl PrefetchQueue {
+    /// Constructs new instance of [PrefetchQueue] that keeps up to
+    /// `max_size` URLs.
+    pub(crate) fn new(max_size: usize) -> Self {
+        Self {
+            urls: Arc::new(Mutex::new(VecDeque::new())),
+            notify: Arc::new(Notify::new()),
+            active_requests: Arc::new(AtomicUsize::new(0)),
+            max_size,
+        }
+    }
+
+    /// This method adds `urls` to queue, skipping ones that are queued
+    /// already and ones that do not fit.
+    /// Returns number of URLs added.
+    pub(crate) fn push_many(&self, urls: Vec<Url>) -> usize {
+        let mut queue = self.urls.lock().unwrap();
+        let mut accepted = 0;
+
+        for url in urls {
+            if queue.len() >= self.max_size {
+                break;
+            }
+
+            if !queue.contains(&url) {
+                queue.push_back(url);
+                accepted += 1;
+            }
+        }
+
+        if accepted > 0 {
+            self.notify.notify_one();
+        }
+
+        accepted
+    }
+
+    /// Returns guard that pauses prefetching until it is dropped.
+    pub(crate) fn busy(&self) -> BusyGuard {
+        self.active_requests.fetch_add(1, Ordering::SeqCst);
+
+        BusyGuard {
+            active_requests: self.active_requests.clone(),
+        }
+    }
+
+    /// Returns true if no interactive request is in progress.
+    fn is_idle(&self) -> bool {
+        self.active_requests.load(Ordering::SeqCst) == 0
+    }
+
+    /// Helper method to take up to [PREFETCH_BATCH_SIZE] URLs from queue.
+    fn pop_batch(&self) -> Vec<Url> {
+        let mut queue = self.urls.lock().unwrap();
+        let size = queue.len().min(PREFETCH_BATCH_SIZE);
+        let batch: Vec<_> = queue.drain(..size).collect();
+
+        // another prefetcher could pick up the rest
+        if !queue.is_empty() {
+            self.notify.notify_one();
+        }
+
+        batch
+    }
+
+    /// This method waits until there are URLs in queue and instance
+    /// is idle, then returns batch of URLs to snap.
+    pub(crate) async fn next_batch(&self) -> Vec<Url> {
+        loop {
+            if !self.is_idle() {
+                tokio::time::sleep(IDLE_CHECK_INTERVAL).await;
+                continue;
+            }
+
+            let batch = self.pop_batch();
+
+            if !batch.is_empty() {
+                return batch;
+            }
+
+            // permit is stored if URLs were pushed after check above
+            self.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use url::Url;
+    use crate::prefetch::{PREFETCH_BATCH_SIZE, PrefetchQueue};
+
+    fn urls(count: usize) -> Vec<Url> {
+        (0..count)
+            .map(|i| Url::parse(&format!("https://example.com/{i}")).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_push_many() {
+        let queue = PrefetchQueue::new(3);
+
+        assert_eq!(queue.push_many(urls(2)), 2);
+        assert_eq!(queue.push_many(urls(2)), 0);
+        assert_eq!(queue.push_many(urls(5)), 1);
+        assert_eq!(queue.urls.lock().unwrap().len(), 3);
+    }
+
+    #[actix_rt::test]
+    async fn test_next_batch() {
+        let queue = PrefetchQueue::new(100);
+        queue.push_many(urls(PREFETCH_BATCH_SIZE + 1));
+
+        assert_eq!(queue.next_batch().await.len(), PREFETCH_BATCH_SIZE);
+        assert_eq!(queue.next_batch().await.len(), 1);
+
+        let busy = queue.busy();
+        queue.push_many(urls(1));
+
+        let waiting = tokio::time::timeout(
+            Duration::from_millis(50),
+            queue.next_batch(),
+        ).await;
+
+        assert!(waiting.is_err());
+
+        drop(busy);
+        assert_eq!(queue.next_batch().await.len(), 1);
+    }
+}
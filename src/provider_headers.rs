@@ -0,0 +1,232 @@
+use std::collections::{BTreeMap, HashMap};
+use actix_web::http::header::{HeaderName, HeaderValue, USER_AGENT};
+use serde::Deserialize;
+use url::Url;
+use crate::domain_list::DomainList;
+
+/// Headers snapper of a single provider sends along with its requests,
+/// e.g. descriptive User-Agent some APIs mandate or browser-like one
+/// sites serve better OpenGraph data to.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct RequestHeaders {
+    /// Replaces User-Agent Crabo identifies itself with, if set.
+    pub user_agent: Option<String>,
+
+    /// Any other headers by name, e.g. `Accept-Language`.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+impl RequestHeaders {
+    /// Returns `defaults` snapper sends anyway followed by configured
+    /// headers. [crate::page_client::PageClient] sets headers in order,
+    /// so configured ones replace defaults of the same name.
+    pub fn merged_with(&self, defaults: &[(&str, &str)]) -> Vec<(String, String)> {
+        let user_agent = self.user_agent.iter()
+            .map(|user_agent| (USER_AGENT.as_str(), user_agent.as_str()));
+
+        let headers = self.headers.iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()));
+
+        defaults.iter()
+            .copied()
+            .chain(user_agent)
+            .chain(headers)
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    /// Returns error if any header could not be sent as is.
+    fn validate(&self) -> Result<(), String> {
+        for (name, value) in self.merged_with(&[]) {
+            HeaderName::try_from(name.as_str())
+                .map_err(|err| format!("Invalid header name '{name}': {err:?}"))?;
+
+            HeaderValue::try_from(value.as_str())
+                .map_err(|err| format!("Invalid value of header '{name}': {err:?}"))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Operator-defined headers of snappers by provider, see [RequestHeaders].
+#[derive(Clone, Debug, Default)]
+pub struct ProviderHeaders {
+    providers: HashMap<String, RequestHeaders>,
+}
+
+impl ProviderHeaders {
+    /// This function constructs new instance of [ProviderHeaders] from
+    /// `json` which is object of [RequestHeaders] by provider, e.g.
+    /// ```json
+    /// {
+    ///     "api:musicbrainz": {"user_agent": "crabo/0.3 (ops@crabo.example)"},
+    ///     "default": {"headers": {"Accept-Language": "en"}}
+    /// }
+    /// ```
+    /// Returns error if `json` is malformed or has invalid headers.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let providers: HashMap<String, RequestHeaders> = serde_json::from_str(json)
+            .map_err(|err| format!("Malformed provider headers: {err:?}"))?;
+
+        for (provider, headers) in &providers {
+            headers.validate()
+                .map_err(|err| format!("{err} for provider {provider}"))?;
+        }
+
+        Ok(Self { providers })
+    }
+
+    /// This function loads [ProviderHeaders] from JSON file at `path`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {path}: {err:?}"))?;
+
+        Self::from_json(&json)
+    }
+
+    /// Returns number of providers headers are set for.
+    pub fn len(&self) -> usize {
+        self.providers.len()
+    }
+
+    /// Returns true if headers are not set for any provider.
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+
+    /// Returns headers of `provider`, which are empty unless set.
+    pub fn for_provider(&self, provider: &str) -> RequestHeaders {
+        self.providers.get(provider)
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Operator-defined headers of pages by domain, e.g. cookie that gets
+/// past benign consent wall or token of self-hosted service.
+#[derive(Clone, Debug, Default)]
+pub struct DomainHeaders {
+    /// Headers by [DomainList] pattern, the least specific pattern first.
+    domains: Vec<(DomainList, RequestHeaders)>,
+}
+
+impl DomainHeaders {
+    /// This function constructs new instance of [DomainHeaders] from
+    /// `json` which is object of [RequestHeaders] by domain pattern, e.g.
+    /// ```json
+    /// {
+    ///     "news.example": {"headers": {"Cookie": "consent=essential"}},
+    ///     "*.git.example": {"headers": {"Authorization": "Bearer 123"}}
+    /// }
+    /// ```
+    /// Returns error if `json` is malformed or has invalid headers.
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        let domains: BTreeMap<String, RequestHeaders> = serde_json::from_str(json)
+            .map_err(|err| format!("Malformed domain headers: {err:?}"))?;
+
+        let mut domains: Vec<_> = domains.into_iter()
+            .map(|(pattern, headers)| {
+                headers.validate()
+                    .map_err(|err| format!("{err} for domain {pattern}"))?;
+
+                Ok((pattern, headers))
+            })
+            .collect::<Result<_, String>>()?;
+
+        // headers are set in order, so longer patterns win
+        domains.sort_by_key(|(pattern, _)| pattern.len());
+
+        Ok(
+            Self {
+                domains: domains.into_iter()
+                    .map(|(pattern, headers)| (DomainList::parse(&pattern), headers))
+                    .collect(),
+            }
+        )
+    }
+
+    /// This function loads [DomainHeaders] from JSON file at `path`.
+    pub fn load(path: &str) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read {path}: {err:?}"))?;
+
+        Self::from_json(&json)
+    }
+
+    /// Returns number of domain patterns headers are set for.
+    pub fn len(&self) -> usize {
+        self.domains.len()
+    }
+
+    /// Returns true if headers are not set for any domain.
+    pub fn is_empty(&self) -> bool {
+        self.domains.is_empty()
+    }
+
+    /// Returns headers of all patterns host of `url` matches, the most
+    /// specific pattern last.
+    pub fn for_url(&self, url: &Url) -> Vec<(String, String)> {
+        let host = url.host_str().unwrap_or_default();
+
+        self.domains.iter()
+            .filter(|(domains, _)| domains.matches(host))
+            .flat_map(|(_, headers)| headers.merged_with(&[]))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::provider_headers::{DomainHeaders, ProviderHeaders};
+
+    #[test]
+    fn test_provider_headers() {
+        let headers = ProviderHeaders::from_json(r#"{
+            "api:musicbrainz": {"user_agent": "crabo/0.3 (ops@crabo.example)"},
+            "default": {"headers": {"Accept-Language": "en"}}
+        }"#).unwrap();
+
+        assert_eq!(headers.len(), 2);
+
+        assert_eq!(
+            headers.for_provider("api:musicbrainz").merged_with(&[("Accept", "*/*")]),
+            vec![
+                ("Accept".to_string(), "*/*".to_string()),
+                ("user-agent".to_string(), "crabo/0.3 (ops@crabo.example)".to_string()),
+            ]
+        );
+
+        assert!(headers.for_provider("youtube").merged_with(&[]).is_empty());
+
+        assert!(
+            ProviderHeaders::from_json(r#"{"default": {"headers": {"Bad Name": "x"}}}"#)
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_domain_headers() {
+        let headers = DomainHeaders::from_json(r#"{
+            "news.example": {"headers": {"Cookie": "consent=essential"}},
+            "eu.news.example": {"headers": {"Cookie": "consent=all"}},
+            "*.git.example": {"user_agent": "crabo"}
+        }"#).unwrap();
+
+        let for_url = |url: &str| headers.for_url(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            for_url("https://eu.news.example/a"),
+            vec![
+                ("Cookie".to_string(), "consent=essential".to_string()),
+                ("Cookie".to_string(), "consent=all".to_string()),
+            ]
+        );
+
+        assert_eq!(for_url("https://news.example/a").len(), 1);
+        assert_eq!(for_url("https://a.git.example/").len(), 1);
+        assert!(for_url("https://git.example/").is_empty());
+    }
+}
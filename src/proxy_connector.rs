@@ -0,0 +1,133 @@
+use std::sync::Arc;
+use actix_tls::connect::{
+    ConnectError,
+    ConnectInfo,
+    Connection,
+    Connector,
+    ConnectorService,
+    Resolver,
+};
+use actix_web::dev::{always_ready, Service};
+use actix_web::http::Uri;
+use actix_web::rt::net::TcpStream;
+use futures::future::LocalBoxFuture;
+use crate::http_proxy::{HttpProxies, HttpProxy};
+use crate::socks_proxy::{SocksProxies, SocksProxy};
+use crate::url_guard::{GuardResolver, UrlGuard};
+
+/// Proxy connection to host is made through.
+enum Route {
+    Socks(SocksProxy),
+    Http(HttpProxy),
+}
+
+impl Route {
+    /// Returns host and port of proxy.
+    fn address(&self) -> &str {
+        match self {
+            Self::Socks(proxy) => proxy.address(),
+            Self::Http(proxy) => proxy.address(),
+        }
+    }
+}
+
+/// TCP connector of [awc::Client] that connects to hosts through proxy
+/// configured for them and to the rest of hosts directly, resolving
+/// them with [GuardResolver]. SOCKS5 proxies take precedence over HTTP
+/// ones, as they are set per domain.
+///
+/// Proxy address itself is not validated by [UrlGuard], it is expected
+/// to be internal service. Hosts are resolved and validated before
+/// tunnel is opened, so HTTP proxy is asked for checked address rather
+/// than host name. Only SOCKS5 proxies with remote DNS resolve hosts on
+/// their own, they are expected to refuse internal ones.
+#[derive(Clone)]
+pub struct ProxyConnector {
+    socks_proxies: Arc<SocksProxies>,
+    http_proxies: Arc<HttpProxies>,
+    url_guard: UrlGuard,
+    direct: ConnectorService,
+}
+
+impl ProxyConnector {
+    /// Constructs new instance of [ProxyConnector] with `socks_proxies`
+    /// and `http_proxies` that validates addresses it resolves with
+    /// `url_guard`.
+    pub fn new(
+        socks_proxies: SocksProxies,
+        http_proxies: HttpProxies,
+        url_guard: UrlGuard,
+    ) -> Self {
+        let resolver = Resolver::custom(GuardResolver::new(url_guard.clone()));
+
+        Self {
+            socks_proxies: Arc::new(socks_proxies),
+            http_proxies: Arc::new(http_proxies),
+            url_guard,
+            direct: Connector::new(resolver).service(),
+        }
+    }
+
+    /// Helper method to pick proxy connection for `req` is made through.
+    fn route(&self, req: &ConnectInfo<Uri>) -> Option<Route> {
+        self.socks_proxies.for_host(req.hostname())
+            .map(|proxy| Route::Socks(proxy.clone()))
+            .or_else(|| self.http_proxies.for_uri(req.request())
+                .map(|proxy| Route::Http(proxy.clone()))
+            )
+    }
+}
+
+impl Service<ConnectInfo<Uri>> for ProxyConnector {
+    type Response = Connection<Uri, TcpStream>;
+    type Error = ConnectError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    always_ready!();
+
+    fn call(&self, req: ConnectInfo<Uri>) -> Self::Future {
+        let Some(route) = self.route(&req) else {
+            return Box::pin(self.direct.call(req));
+        };
+
+        let url_guard = self.url_guard.clone();
+        let direct = self.direct.clone();
+
+        Box::pin(async move {
+            let host = req.hostname().to_string();
+            let port = req.port();
+
+            let proxy_address = tokio::net::lookup_host(route.address())
+                .await
+                .map_err(ConnectError::Io)?
+                .next()
+                .ok_or(ConnectError::NoRecords)?;
+
+            // address is set, so resolver is skipped and connection
+            // still belongs to original request
+            let mut connection = direct.call(req.set_addr(Some(proxy_address))).await?;
+
+            match route {
+                Route::Socks(proxy) => {
+                    proxy.handshake(connection.io_mut(), &host, port, &url_guard).await?;
+                }
+
+                Route::Http(proxy) => {
+                    // proxy must not resolve host to address guard refuses
+                    let address = url_guard.resolve(&host, port)
+                        .await
+                        .map_err(|err| ConnectError::Resolver(Box::new(err)))?
+                        .into_iter()
+                        .next()
+                        .ok_or(ConnectError::NoRecords)?;
+
+                    proxy.open_tunnel(connection.io_mut(), &address.ip().to_string(), port)
+                        .await
+                        .map_err(ConnectError::Io)?;
+                }
+            }
+
+            Ok(connection)
+        })
+    }
+}
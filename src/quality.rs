@@ -0,0 +1,107 @@
+use crabo_model::Snapshot;
+
+/// Descriptions at least this long are considered complete.
+const FULL_DESCRIPTION_CHARS: usize = 100;
+
+/// Where snapper found data of snapshot.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DataSource {
+    /// Official or operator-configured API, data is exactly what
+    /// publisher provided.
+    Api,
+
+    /// OpenGraph tags or JSON-LD of page.
+    StructuredData,
+
+    /// oEmbed of provider, used when its API is unavailable. Data is
+    /// what publisher provided, but there is little of it.
+    Embed,
+
+    /// Whatever was found in markup, e.g. title and paragraphs.
+    Markup,
+}
+
+impl DataSource {
+    /// Returns points this source is worth.
+    fn points(self) -> u32 {
+        match self {
+            Self::Api => 25,
+            Self::StructuredData => 20,
+            Self::Embed => 15,
+            Self::Markup => 5,
+        }
+    }
+}
+
+/// This function scores how rich `snapshot` made from `source` is,
+/// from 0 to 100. Indexer prefers snapshots with higher score when
+/// multiple URLs point to the same content.
+///
+/// Preview image is worth the most, then data source, description and
+/// title. Dimensions of image, date and author add a few points.
+pub fn quality_score(snapshot: &Snapshot, source: DataSource) -> u8 {
+    let has_text = |text: &Option<String>| text.as_ref()
+        .is_some_and(|text| !text.trim().is_empty());
+
+    let description_chars = snapshot.description.as_ref()
+        .map(|description| description.trim().chars().count())
+        .unwrap_or_default();
+
+    let points = [
+        (snapshot.preview_url.is_some(), 30),
+        (snapshot.preview_width.is_some() && snapshot.preview_height.is_some(), 5),
+        (has_text(&snapshot.title), 15),
+        (description_chars > 0, 10),
+        (description_chars >= FULL_DESCRIPTION_CHARS, 10),
+        (snapshot.published_at.is_some() || has_text(&snapshot.author), 5),
+        (true, source.points()),
+    ].into_iter()
+        .filter(|(applies, _)| *applies)
+        .map(|(_, points)| points)
+        .sum::<u32>();
+
+    points.min(100) as u8
+}
+
+/// Returns `snapshot` made from `source` with its quality score set.
+pub fn with_quality(snapshot: Snapshot, source: DataSource) -> Snapshot {
+    Snapshot {
+        quality: Some(quality_score(&snapshot, source)),
+        ..snapshot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crabo_model::Snapshot;
+    use crate::quality::{DataSource, quality_score};
+    use crate::snapper::bare_snapshot;
+
+    #[test]
+    fn test_quality_score() {
+        let bare = || bare_snapshot(Url::parse("https://a.example/").unwrap());
+        assert_eq!(quality_score(&bare(), DataSource::Markup), 5);
+
+        let rich = Snapshot {
+            preview_url: Url::parse("https://a.example/a.png").ok(),
+            preview_width: Some(640),
+            preview_height: Some(480),
+            title: Some("Title".to_string()),
+            description: Some("word ".repeat(30)),
+            author: Some("Jane".to_string()),
+            ..bare()
+        };
+
+        assert_eq!(quality_score(&rich, DataSource::Api), 100);
+        assert_eq!(quality_score(&rich, DataSource::StructuredData), 95);
+
+        let text_only = Snapshot {
+            title: Some("Title".to_string()),
+            description: Some("Short".to_string()),
+            ..bare()
+        };
+
+        assert_eq!(quality_score(&text_only, DataSource::Markup), 30);
+    }
+}
@@ -0,0 +1,429 @@
+use std::fmt::{Display, Formatter};
+use futures::future::LocalBoxFuture;
+use log::{debug, warn};
+use serde::Deserialize;
+use url::Url;
+
+use crabo_model::{SnapError, Snapshot};
+
+use crate::inflight::set_stage;
+use crate::output_limits::truncate_graphemes;
+use crate::provider_headers::RequestHeaders;
+use crate::quality::{DataSource, with_quality};
+use crate::short_links::{hints_id, parse_hints_id, ShortLinks};
+use crate::snapper::{
+    bare_snapshot,
+    CacheHints,
+    Clients,
+    Snapper,
+    SnapshotAndHints,
+};
+use crate::util::is_host_of;
+
+/// Icon of Reddit shown next to site name.
+const REDDIT_ICON_URL: &str = "https://www.reddit.com/favicon.ico";
+
+/// Base of post URLs, `.json` appended to them describes post.
+const REDDIT_BASE_URL: &str = "https://www.reddit.com/";
+
+/// Maximum length of text of post in description, in graphemes.
+const SELFTEXT_EXCERPT_LENGTH: usize = 500;
+
+/// Listing of things, JSON of post is listing of post itself followed
+/// by listing of comments.
+#[derive(Deserialize)]
+struct Listing {
+    data: ListingData,
+}
+
+#[derive(Deserialize)]
+struct ListingData {
+    children: Vec<Thing>,
+}
+
+/// Thing in listing, only posts have kind `t3`.
+#[derive(Deserialize)]
+struct Thing {
+    kind: String,
+    data: serde_json::Value,
+}
+
+/// A very simplified version of Reddit post.
+#[derive(Deserialize)]
+struct Post {
+    /// Post ID, e.g. `1abcde`.
+    id: Option<String>,
+
+    title: Option<String>,
+
+    /// Text of post, empty for link posts.
+    selftext: Option<String>,
+
+    /// Name of subreddit, e.g. `r/rust`.
+    subreddit_name_prefixed: Option<String>,
+
+    /// Name of author without `u/`.
+    author: Option<String>,
+
+    /// UNIX timestamp of publication.
+    created_utc: Option<f64>,
+
+    #[serde(default)]
+    over_18: bool,
+
+    /// Path of post page, e.g. `/r/rust/comments/1abcde/title/`.
+    permalink: Option<String>,
+
+    preview: Option<Preview>,
+}
+
+/// Previews of post.
+#[derive(Deserialize)]
+struct Preview {
+    #[serde(default)]
+    images: Vec<PreviewImage>,
+}
+
+#[derive(Deserialize)]
+struct PreviewImage {
+    /// Image in original size.
+    source: ImageSource,
+}
+
+#[derive(Deserialize)]
+struct ImageSource {
+    url: Url,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+/// Post ID as it is found in URL.
+#[derive(Clone, Debug, PartialEq)]
+enum PostId {
+    /// ID of post, e.g. `1abcde`.
+    Post(String),
+
+    /// Short link, e.g. `https://redd.it/1abcde` or share link
+    /// `https://www.reddit.com/r/rust/s/abcdEFG`, which needs to be
+    /// resolved to post ID.
+    Short(Url),
+}
+
+impl PostId {
+    /// Parses post ID from cache hints `id` made by [PostId::to_string].
+    fn parse(id: &str) -> Option<Self> {
+        match parse_hints_id(id) {
+            Some(url) => Some(Self::Short(url)),
+            None => Some(Self::Post(id.to_string())),
+        }
+    }
+
+    /// Returns address of JSON that describes this post, short links have
+    /// to be resolved first.
+    fn json_url(&self) -> Option<Url> {
+        let Self::Post(id) = self else {
+            return None;
+        };
+
+        // without raw_json entities in text and URLs are escaped
+        let mut url = Url::parse(REDDIT_BASE_URL)
+            .and_then(|base| base.join(&format!("comments/{id}.json")))
+            .ok()?;
+
+        url.query_pairs_mut().append_pair("raw_json", "1");
+        Some(url)
+    }
+}
+
+impl Display for PostId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Post(id) => write!(f, "{id}"),
+            Self::Short(url) => write!(f, "{}", hints_id(url)),
+        }
+    }
+}
+
+/// Returns true if `id` looks like ID of post or code of short link.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// This function extracts Reddit post ID from `url`,
+/// and returns either that ID or None.
+fn extract_post_id(url: &Url) -> Option<PostId> {
+    let host = url.host_str()?;
+
+    let segments: Vec<_> = url.path_segments()?
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    if is_host_of(host, "redd.it") {
+        return match segments.as_slice() {
+            [code] if is_valid_id(code) => Url::parse("https://redd.it/")
+                .and_then(|base| base.join(code))
+                .ok()
+                .map(PostId::Short),
+
+            _ => None,
+        };
+    }
+
+    if !is_host_of(host, "reddit.com") {
+        debug!("Could not extract Reddit post ID from URL {}", url);
+        return None;
+    }
+
+    match segments.as_slice() {
+        ["r", _, "comments", id, ..] | ["comments", id, ..] if is_valid_id(id) => {
+            Some(PostId::Post(id.to_lowercase()))
+        }
+
+        ["r", subreddit, "s", code] if is_valid_id(code) => {
+            Url::parse(REDDIT_BASE_URL)
+                .and_then(|base| base.join(&format!("r/{subreddit}/s/{code}")))
+                .ok()
+                .map(PostId::Short)
+        }
+
+        _ => None,
+    }
+}
+
+/// This function converts `post` to Crabo [Snapshot] of `url`. Subreddit
+/// is source of snapshot.
+fn post_to_snapshot(url: Url, post: Post) -> Snapshot {
+    let preview = post.preview
+        .and_then(|preview| preview.images.into_iter().next())
+        .map(|image| image.source);
+
+    let preview_mime_type = preview.as_ref()
+        .and_then(|x| mime_guess::from_path(x.url.path()).first())
+        .map(|m| m.to_string());
+
+    let snapshot = Snapshot {
+        title: post.title,
+
+        description: post.selftext
+            .filter(|text| !text.trim().is_empty())
+            .map(|text| truncate_graphemes(text.trim(), SELFTEXT_EXCERPT_LENGTH)),
+
+        author: post.author.filter(|author| author != "[deleted]"),
+        source: post.subreddit_name_prefixed,
+        site_name: Option::from("Reddit".to_string()),
+        icon_url: Url::parse(REDDIT_ICON_URL).ok(),
+
+        published_at: post.created_utc
+            .and_then(|x| chrono::DateTime::from_timestamp(x as i64, 0)),
+
+        sensitive: post.over_18,
+        preview_width: preview.as_ref().and_then(|image| image.width),
+        preview_height: preview.as_ref().and_then(|image| image.height),
+        preview_url: preview.map(|image| image.url),
+        preview_mime_type,
+
+        canonical_url: post.permalink
+            .and_then(|permalink| Url::parse(REDDIT_BASE_URL)
+                .and_then(|base| base.join(&permalink))
+                .ok()
+            ),
+
+        ..bare_snapshot(url)
+    };
+
+    with_quality(snapshot, DataSource::Api)
+}
+
+/// This snapper describes Reddit posts with public JSON of post pages, as
+/// HTML of Reddit requires cookies and consent.
+pub struct RedditSnapper {
+    /// Headers sent to Reddit along with requests.
+    request_headers: RequestHeaders,
+
+    /// Post IDs resolved short links lead to.
+    short_links: ShortLinks<PostId>,
+}
+
+impl RedditSnapper {
+    /// Constructs new instance of [RedditSnapper] that sends
+    /// `request_headers` along with requests.
+    pub fn new(request_headers: RequestHeaders) -> Self {
+        Self {
+            request_headers,
+            short_links: ShortLinks::default(),
+        }
+    }
+}
+
+impl Snapper for RedditSnapper {
+    fn provider(&self) -> String {
+        "reddit".into()
+    }
+
+    /// Post pages with and without subreddit and title share cache hints
+    /// with ID of post.
+    fn cache_hints(&self, post_url: &Url) -> Option<CacheHints> {
+        extract_post_id(post_url)
+            .map(|id| match id {
+                PostId::Short(url) => self.short_links.get(&url)
+                    .unwrap_or(PostId::Short(url)),
+
+                id => id,
+            })
+            .map(|id| CacheHints {
+                provider: self.provider(),
+                id: id.to_string(),
+                language: None,
+            })
+    }
+
+    fn snap<'a>(
+        &'a self,
+        url: Url,
+        cache_hints: CacheHints,
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, SnapshotAndHints> {
+        Box::pin(async move {
+            let post_id = match PostId::parse(&cache_hints.id) {
+                Some(PostId::Short(short_url)) => {
+                    set_stage("resolving short URL");
+
+                    self.short_links.resolve(
+                        &short_url,
+                        clients,
+                        |location| extract_post_id(location)
+                            .filter(|post_id| !matches!(post_id, PostId::Short(_))),
+                    ).await
+                }
+
+                post_id => post_id,
+            };
+
+            let Some((post_id, json_url)) = post_id
+                .and_then(|post_id| post_id.json_url().map(|url| (post_id, url))) else {
+                return SnapshotAndHints {
+                    snapshot: Err(SnapError::NotFound),
+                    hints: cache_hints,
+                };
+            };
+
+            set_stage("calling Reddit JSON API");
+
+            match clients.page_client.get_json::<Vec<Listing>>(
+                &json_url,
+                &self.request_headers.merged_with(&[]),
+            ).await {
+                Ok(listings) => {
+                    let snapshot = listings.into_iter()
+                        .next()
+                        .and_then(|listing| listing.data.children.into_iter().next())
+                        .filter(|thing| thing.kind == "t3")
+                        .and_then(|thing| serde_json::from_value::<Post>(thing.data).ok())
+                        .map(|post| post_to_snapshot(url, post))
+                        .ok_or(SnapError::ParseFailed);
+
+                    SnapshotAndHints {
+                        snapshot,
+
+                        hints: CacheHints {
+                            id: post_id.to_string(),
+                            ..cache_hints
+                        },
+                    }
+                }
+
+                Err(err) => {
+                    warn!(
+                        "Failed to get details for Reddit post '{post_id}', \
+                        API call result is: {err:?}",
+                    );
+
+                    clients.provider_metrics.record_api_error("reddit");
+
+                    SnapshotAndHints {
+                        snapshot: Err(err.snap_error()),
+                        hints: cache_hints,
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+
+    use crate::reddit::{extract_post_id, Listing, Post, post_to_snapshot, PostId};
+
+    #[test]
+    fn test_extract_post_id() {
+        let extract = |url: &str| extract_post_id(&Url::parse(url).unwrap());
+        let post = |id: &str| Some(PostId::Post(id.to_string()));
+        let short = |url: &str| Some(PostId::Short(Url::parse(url).unwrap()));
+
+        assert_eq!(extract("https://www.reddit.com/r/rust/comments/1abcde/some_title/"), post("1abcde"));
+        assert_eq!(extract("https://old.reddit.com/comments/1ABCDE"), post("1abcde"));
+        assert_eq!(extract("https://redd.it/1abcde"), short("https://redd.it/1abcde"));
+
+        assert_eq!(
+            extract("https://www.reddit.com/r/rust/s/abcdEFG"),
+            short("https://www.reddit.com/r/rust/s/abcdEFG")
+        );
+
+        assert_eq!(extract("https://www.reddit.com/r/rust/"), None);
+        assert_eq!(extract("https://notreddit.com/comments/1abcde"), None);
+    }
+
+    #[test]
+    fn test_post_id_round_trip() {
+        for post_id in [
+            PostId::Post("1abcde".to_string()),
+            PostId::Short(Url::parse("https://redd.it/1abcde").unwrap()),
+        ] {
+            assert_eq!(PostId::parse(&post_id.to_string()), Some(post_id));
+        }
+
+        assert_eq!(
+            PostId::Post("1abcde".to_string()).json_url().unwrap().as_str(),
+            "https://www.reddit.com/comments/1abcde.json?raw_json=1"
+        );
+    }
+
+    #[test]
+    fn test_post_to_snapshot() {
+        let listings: Vec<Listing> = serde_json::from_str(r#"[
+            {"kind": "Listing", "data": {"children": [{"kind": "t3", "data": {
+                "id": "1abcde",
+                "title": "Title",
+                "selftext": "  Text of post  ",
+                "subreddit_name_prefixed": "r/rust",
+                "author": "someone",
+                "created_utc": 1700000000.0,
+                "over_18": false,
+                "permalink": "/r/rust/comments/1abcde/title/",
+                "preview": {"images": [{"source": {
+                    "url": "https://preview.redd.it/abc.png?width=640&s=x",
+                    "width": 640,
+                    "height": 480
+                }}]}
+            }}]}},
+            {"kind": "Listing", "data": {"children": []}}
+        ]"#).unwrap();
+
+        let thing = listings.into_iter().next().unwrap().data.children.into_iter().next().unwrap();
+        let post: Post = serde_json::from_value(thing.data).unwrap();
+        let snapshot = post_to_snapshot(Url::parse("https://redd.it/1abcde").unwrap(), post);
+
+        assert_eq!(snapshot.source.as_deref(), Some("r/rust"));
+        assert_eq!(snapshot.description.as_deref(), Some("Text of post"));
+        assert_eq!(snapshot.preview_width, Some(640));
+        assert_eq!(snapshot.preview_mime_type.as_deref(), Some("image/png"));
+        assert!(!snapshot.sensitive);
+
+        assert_eq!(
+            snapshot.canonical_url.unwrap().as_str(),
+            "https://www.reddit.com/r/rust/comments/1abcde/title/"
+        );
+    }
+}
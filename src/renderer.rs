@@ -0,0 +1,126 @@
+use log::warn;
+use url::Url;
+
+/// Placeholder for encoded page address in endpoint templates.
+const URL_PLACEHOLDER: &str = "{url}";
+
+/// This function fills `template` of rendering service endpoint with
+/// encoded `url` of page, e.g. `http://127.0.0.1:8050/render.html?url={url}`
+/// becomes `http://127.0.0.1:8050/render.html?url=https%3A%2F%2F...`.
+fn fill_endpoint_template(template: &str, url: &Url) -> Option<Url> {
+    let encoded_url: String = url::form_urlencoded::byte_serialize(
+        url.as_str().as_bytes()
+    ).collect();
+
+    let endpoint = template.replace(URL_PLACEHOLDER, &encoded_url);
+
+    match Url::parse(&endpoint) {
+        Ok(endpoint_url) => Some(endpoint_url),
+
+        Err(err) => {
+            warn!("Invalid rendering service endpoint '{endpoint}': {err:?}");
+            None
+        }
+    }
+}
+
+/// Client of external rendering service such as Splash or headless
+/// browser behind HTTP API.
+///
+/// It is used for pages that are built by JavaScript, so static HTML
+/// has nothing to make snapshot of, and for screenshots of pages that
+/// have no preview image.
+pub struct Renderer {
+    /// Endpoint template that returns rendered DOM of page as HTML.
+    html_endpoint: Option<String>,
+
+    /// Endpoint template that returns PNG screenshot of page.
+    /// Screenshots are stored by image proxy, frontends never load them
+    /// from it.
+    screenshot_endpoint: Option<String>,
+}
+
+impl Renderer {
+    /// Constructs new instance of [Renderer] for service which returns
+    /// rendered HTML at `html_endpoint` and screenshots at
+    /// `screenshot_endpoint`. Endpoints are expected to have `{url}`
+    /// placeholder for address of page.
+    /// Returns None if neither endpoint is set.
+    pub fn new(
+        html_endpoint: Option<String>,
+        screenshot_endpoint: Option<String>,
+    ) -> Option<Self> {
+        let endpoints = html_endpoint.iter()
+            .chain(screenshot_endpoint.iter());
+
+        for endpoint in endpoints {
+            if !endpoint.contains(URL_PLACEHOLDER) {
+                warn!(
+                    "Rendering service endpoint '{endpoint}' has no \
+                    {URL_PLACEHOLDER} placeholder, all pages will look the same"
+                );
+            }
+        }
+
+        if html_endpoint.is_none() && screenshot_endpoint.is_none() {
+            return None;
+        }
+
+        Some(
+            Self {
+                html_endpoint,
+                screenshot_endpoint,
+            }
+        )
+    }
+
+    /// Returns hosts of rendering service endpoints, these are usually
+    /// internal, so have to be allowed explicitly.
+    pub fn endpoint_hosts(&self) -> Vec<String> {
+        self.html_endpoint.iter()
+            .chain(self.screenshot_endpoint.iter())
+            .filter_map(|endpoint| Url::parse(
+                &endpoint.replace(URL_PLACEHOLDER, "")
+            ).ok())
+            .filter_map(|endpoint_url| endpoint_url.host_str()
+                .map(|host| host.to_string())
+            )
+            .collect()
+    }
+
+    /// Returns address of rendered HTML of page `url`.
+    pub fn html_url(&self, url: &Url) -> Option<Url> {
+        fill_endpoint_template(self.html_endpoint.as_ref()?, url)
+    }
+
+    /// Returns address of screenshot of page `url`.
+    pub fn screenshot_url(&self, url: &Url) -> Option<Url> {
+        fill_endpoint_template(self.screenshot_endpoint.as_ref()?, url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::renderer::Renderer;
+
+    #[test]
+    fn test_endpoint_template() {
+        let renderer = Renderer::new(
+            Some("http://127.0.0.1:8050/render.html?url={url}&wait=2".into()),
+            None,
+        ).unwrap();
+
+        let url = Url::parse("https://a.example/app?x=1#/feed").unwrap();
+
+        assert_eq!(
+            renderer.html_url(&url).unwrap().as_str(),
+            "http://127.0.0.1:8050/render.html\
+            ?url=https%3A%2F%2Fa.example%2Fapp%3Fx%3D1%23%2Ffeed&wait=2"
+        );
+
+        assert!(renderer.screenshot_url(&url).is_none());
+        assert_eq!(renderer.endpoint_hosts(), vec!["127.0.0.1"]);
+        assert!(Renderer::new(None, None).is_none());
+    }
+}
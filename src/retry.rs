@@ -0,0 +1,199 @@
+use std::time::Duration;
+use actix_web::http::StatusCode;
+use log::warn;
+use crate::page_client::FetchError;
+
+/// Default number of attempts of a single request, including the first.
+const DEFAULT_MAX_ATTEMPTS: u32 = 3;
+
+/// Default delay before the first retry, doubled for every next one.
+const DEFAULT_BASE_DELAY: Duration = Duration::from_millis(500);
+
+/// Delay between retries never grows beyond this.
+const MAX_DELAY: Duration = Duration::from_secs(8);
+
+/// Classes of failures request could be retried on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RetryClass {
+    /// Connection could not be made or was dropped, or server did not
+    /// respond in time.
+    Connection,
+
+    /// Server responded with 5xx status code.
+    ServerError,
+
+    /// Server responded with 429 Too Many Requests.
+    RateLimited,
+}
+
+impl RetryClass {
+    /// Returns class of `err` or None if request failed for a reason
+    /// retry would not fix, e.g. page is not found.
+    fn of(err: &FetchError) -> Option<Self> {
+        match err {
+            FetchError::RequestFailed(_) => Some(Self::Connection),
+
+            FetchError::UnexpectedStatusCode(StatusCode::TOO_MANY_REQUESTS) => {
+                Some(Self::RateLimited)
+            }
+
+            FetchError::UnexpectedStatusCode(status) if status.is_server_error() => {
+                Some(Self::ServerError)
+            }
+
+            _ => None,
+        }
+    }
+
+    /// Helper function to parse class from its `name`, e.g. `server_error`.
+    fn parse(name: &str) -> Option<Self> {
+        match name.trim() {
+            "connection" => Some(Self::Connection),
+            "server_error" => Some(Self::ServerError),
+            "rate_limited" => Some(Self::RateLimited),
+
+            _ => {
+                warn!("Ignoring unknown retry class '{name}'");
+                None
+            }
+        }
+    }
+}
+
+/// Tells whether and when [crate::page_client::PageClient] repeats
+/// requests that failed, so a single dropped connection does not end up
+/// as negative result. Delay doubles after every failed attempt.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Number of attempts of a single request, including the first.
+    pub max_attempts: u32,
+
+    /// Delay before the first retry.
+    pub base_delay: Duration,
+
+    /// Failures request is retried on.
+    pub retry_on: Vec<RetryClass>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            base_delay: DEFAULT_BASE_DELAY,
+
+            retry_on: vec![
+                RetryClass::Connection,
+                RetryClass::ServerError,
+                RetryClass::RateLimited,
+            ],
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Constructs new instance of [RetryPolicy], defaults are used for
+    /// values that are not set.
+    /// `max_attempts` is number of attempts including the first one,
+    /// `base_delay` is number of seconds before the first retry and
+    /// `retry_on` lists classes separated by comma, e.g.
+    /// `connection,server_error,rate_limited`.
+    pub fn new(
+        max_attempts: Option<&str>,
+        base_delay: Option<&str>,
+        retry_on: Option<&str>,
+    ) -> Self {
+        let defaults = Self::default();
+
+        let max_attempts = max_attempts.and_then(|text| {
+            let max_attempts = text.trim().parse::<u32>().ok().filter(|x| *x > 0);
+
+            if max_attempts.is_none() {
+                warn!("Ignoring invalid number of attempts '{text}'");
+            }
+
+            max_attempts
+        });
+
+        let base_delay = base_delay.and_then(|text| {
+            let base_delay = text.trim()
+                .parse()
+                .ok()
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok());
+
+            if base_delay.is_none() {
+                warn!("Ignoring invalid retry delay '{text}'");
+            }
+
+            base_delay
+        });
+
+        let retry_on = retry_on.map(|classes| classes.split(',')
+            .filter(|name| !name.trim().is_empty())
+            .filter_map(RetryClass::parse)
+            .collect()
+        );
+
+        Self {
+            max_attempts: max_attempts.unwrap_or(defaults.max_attempts),
+            base_delay: base_delay.unwrap_or(defaults.base_delay),
+            retry_on: retry_on.unwrap_or(defaults.retry_on),
+        }
+    }
+
+    /// Returns delay before next attempt of request which `attempt`,
+    /// counting from 1, failed with `err`. Returns None if request is not
+    /// to be retried.
+    pub fn retry_delay(&self, attempt: u32, err: &FetchError) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+
+        let class = RetryClass::of(err)?;
+
+        if !self.retry_on.contains(&class) {
+            return None;
+        }
+
+        let factor = 1u32 << attempt.saturating_sub(1).min(16);
+
+        Some(self.base_delay.saturating_mul(factor).min(MAX_DELAY))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use actix_web::http::StatusCode;
+    use crate::page_client::FetchError;
+    use crate::retry::{RetryClass, RetryPolicy};
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy::new(Some("4"), Some("0.5"), None);
+        let dropped = FetchError::RequestFailed("connection reset".to_string());
+
+        assert_eq!(policy.retry_delay(1, &dropped), Some(Duration::from_millis(500)));
+        assert_eq!(policy.retry_delay(3, &dropped), Some(Duration::from_secs(2)));
+        assert_eq!(policy.retry_delay(4, &dropped), None);
+
+        let not_found = FetchError::UnexpectedStatusCode(StatusCode::NOT_FOUND);
+        assert_eq!(policy.retry_delay(1, &not_found), None);
+        assert_eq!(policy.retry_delay(1, &FetchError::Suppressed), None);
+    }
+
+    #[test]
+    fn test_retry_classes() {
+        let policy = RetryPolicy::new(None, None, Some("rate_limited, typo"));
+        assert_eq!(policy.retry_on, vec![RetryClass::RateLimited]);
+
+        let bad_gateway = FetchError::UnexpectedStatusCode(StatusCode::BAD_GATEWAY);
+        let rate_limited = FetchError::UnexpectedStatusCode(StatusCode::TOO_MANY_REQUESTS);
+
+        assert_eq!(policy.retry_delay(1, &bad_gateway), None);
+        assert!(policy.retry_delay(1, &rate_limited).is_some());
+
+        // nothing is retried if no classes are listed
+        let policy = RetryPolicy::new(None, None, Some(""));
+        assert!(policy.retry_on.is_empty());
+    }
+}
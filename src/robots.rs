@@ -1,14 +1,15 @@
 use std::collections::HashMap;
 use std::num::NonZeroUsize;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use actix_web::http::StatusCode;
 use chrono::Duration;
 use log::{info, warn};
 use lru::LruCache;
 use serde::{Deserialize, Serialize};
 use texting_robots::Robot;
-use fedineko_http_client::ClientError;
 use proxydon_cache::typed_cache::TypedCache;
+use crate::log_throttle::warn_for_host;
+use crate::page_client::FetchError;
 use crate::snapper::Clients;
 
 /// Status of robots.txt
@@ -62,15 +63,20 @@ impl ServerIndexingPermissions {
 
 /// This struct keeps cache of robots.txt to avoid unnecessary queries
 /// to servers and provides methods to validate permission to access page.
-pub(crate) struct RobotsValidator {
+pub struct RobotsValidator {
     user_agent: String,
     robots_txt_permissions: TypedCache<ServerIndexingPermissions>,
     robots_cache: Mutex<LruCache<String, Robot>>,
+
+    /// Locks of sites robots.txt of which is being looked up, so several
+    /// pages of the same site do not download the same robots.txt.
+    site_locks: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
 }
 
 impl RobotsValidator {
     /// This method constructs new instance of [RobotsValidator].
-    /// `user_agent` is used to identify Crabo in HTTP requests.
+    /// `user_agent` is name of Crabo robots.txt groups are matched
+    /// against, see [crate::user_agent::UserAgent::name].
     pub fn new(user_agent: &str) -> Self {
         Self {
             user_agent: user_agent.to_string(),
@@ -86,6 +92,7 @@ impl RobotsValidator {
             ),
             // actual matchers
             robots_cache: Mutex::new(LruCache::new(NonZeroUsize::new(256).unwrap())),
+            site_locks: Mutex::new(HashMap::new()),
         }
     }
     /// Helper method to download robots.txt from `site`.
@@ -104,7 +111,7 @@ impl RobotsValidator {
         let robots_address = format!("{}://{site}/robots.txt", url.scheme());
         let robots_url = url::Url::parse(&robots_address).unwrap();
 
-        match clients.generic_client.get_bytes(&robots_url, None).await {
+        match clients.page_client.get_bytes(&robots_url).await {
             Ok(bytes) => {
                 match String::from_utf8(bytes.into()) {
                     Ok(data) => Some(
@@ -128,7 +135,7 @@ impl RobotsValidator {
 
             Err(err) => {
                 match err {
-                    ClientError::UnexpectedStatusCode(status) => {
+                    FetchError::UnexpectedStatusCode(status) => {
                         match status {
                             // if status code is 404 then we are allowed
                             // to access any URL
@@ -148,18 +155,21 @@ impl RobotsValidator {
                             )
                         }
                     }
-                    ClientError::Suppressed => {
-                        warn!(
+                    FetchError::Suppressed => {
+                        warn_for_host(site, format_args!(
                             "Requests to server for {robots_address} \
                             are suppressed"
-                        );
+                        ));
 
                         // requests are suppressed, regardless of robots.txt settings nothing to do.
                         None
                     }
                     _ => {
                         // failed to fetch, it should be cached
-                        warn!("Failed to fetch {robots_address}: {err:?}");
+                        warn_for_host(
+                            site,
+                            format_args!("Failed to fetch {robots_address}: {err:?}"),
+                        );
                         Some(
                             ServerIndexingPermissions::new(
                                 RobotsTxtStatus::RequestedFailed
@@ -210,6 +220,40 @@ impl RobotsValidator {
         site: String,
         url: &url::Url,
         clients: &Clients,
+    ) -> ServerIndexingPermissions {
+        let site_lock = self.site_locks.lock()
+            .unwrap()
+            .entry(site.clone())
+            .or_default()
+            .clone();
+
+        // whoever comes first downloads robots.txt, the rest wait
+        // and find it in cache
+        let permissions = {
+            let _guard = site_lock.lock().await;
+
+            self.get_or_download_permissions(site.clone(), url, clients)
+                .await
+        };
+
+        // map and this method are the only holders, nobody else waits
+        let mut site_locks = self.site_locks.lock().unwrap();
+
+        if Arc::strong_count(&site_lock) <= 2 {
+            site_locks.remove(&site);
+        }
+
+        permissions
+    }
+
+    /// Helper method to get permissions for `site` from cache or to
+    /// download robots.txt if there is nothing in cache.
+    /// `url` and `clients` are used as in [Self::download_robots_txt].
+    async fn get_or_download_permissions(
+        &self,
+        site: String,
+        url: &url::Url,
+        clients: &Clients,
     ) -> ServerIndexingPermissions {
         let permissions = self.get_permissions_from_cache(
             site.clone(),
@@ -0,0 +1,224 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use actix_web::http::StatusCode;
+use log::{error, info, warn};
+use serde::Serialize;
+use serde_json::Value;
+use url::Url;
+use crabo_core::error_reporter::report_error;
+
+/// Time single check has to complete.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Video that surely exists, so valid key gets non-empty response.
+const YOUTUBE_CHECK_VIDEO_ID: &str = "jNQXAC9IVRw";
+
+/// Result of a single check.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status", content = "reason")]
+pub(crate) enum CheckStatus {
+    /// Dependency works as expected.
+    Passed,
+
+    /// Dependency is misconfigured or down, Crabo is not ready.
+    Failed(String),
+
+    /// Dependency could not be checked, e.g. due to network error,
+    /// this does not affect readiness.
+    Unverified(String),
+
+    /// Dependency is not configured, so there is nothing to check.
+    Skipped,
+}
+
+/// Results of all checks, returned by `/readyz`.
+#[derive(Clone, Debug, Serialize)]
+pub(crate) struct Readiness {
+    /// True if no check failed.
+    pub(crate) ready: bool,
+
+    /// Whether Proxydon endpoint responds.
+    pub(crate) proxydon: CheckStatus,
+
+    /// Whether YouTube API accepts key.
+    pub(crate) youtube: CheckStatus,
+}
+
+/// Helper function to construct client for checks.
+/// Checks talk to internal services and well-known APIs, so unlike
+/// [crabo_core::page_client::PageClient] it does not guard addresses.
+fn check_client() -> awc::Client {
+    awc::Client::builder()
+        .timeout(CHECK_TIMEOUT)
+        .finish()
+}
+
+/// This function checks that Proxydon at `endpoint` responds.
+/// Any HTTP response means it is up, as there is no dedicated health
+/// endpoint.
+async fn check_proxydon(endpoint: &Url) -> CheckStatus {
+    match check_client().get(endpoint.as_str()).send().await {
+        Ok(_) => CheckStatus::Passed,
+        Err(err) => CheckStatus::Failed(format!("{endpoint} does not respond: {err}")),
+    }
+}
+
+/// This function checks that YouTube API accepts `api_key` by requesting
+/// ID of a single video, which costs a single quota unit. Check is
+/// skipped if there is no key.
+async fn check_youtube_key(api_key: Option<&str>) -> CheckStatus {
+    let Some(api_key) = api_key.filter(|api_key| !api_key.trim().is_empty()) else {
+        return CheckStatus::Skipped;
+    };
+
+    let query_url = Url::parse_with_params(
+        "https://www.googleapis.com/youtube/v3/videos",
+        &[("id", YOUTUBE_CHECK_VIDEO_ID), ("key", api_key), ("part", "id")],
+    ).unwrap();
+
+    let mut response = match check_client().get(query_url.as_str()).send().await {
+        Ok(response) => response,
+        Err(err) => return CheckStatus::Unverified(err.to_string()),
+    };
+
+    let status = response.status();
+
+    if status.is_success() {
+        return CheckStatus::Passed;
+    }
+
+    // e.g. {"error": {"code": 400, "message": "API key not valid..."}}
+    let message = response.json::<Value>()
+        .await
+        .ok()
+        .and_then(|body| body.pointer("/error/message")
+            .and_then(|message| message.as_str())
+            .map(|message| message.to_string())
+        )
+        .unwrap_or_default();
+
+    match status {
+        StatusCode::BAD_REQUEST | StatusCode::FORBIDDEN => {
+            CheckStatus::Failed(format!("status {status}: {message}"))
+        }
+
+        _ => CheckStatus::Unverified(format!("status {status}: {message}")),
+    }
+}
+
+/// Helper function to log `status` of check of `dependency`.
+fn log_status(dependency: &str, status: &CheckStatus) {
+    match status {
+        CheckStatus::Passed => info!("Self-check: {dependency} is fine"),
+        CheckStatus::Failed(reason) => error!("Self-check: {dependency} failed: {reason}"),
+        CheckStatus::Unverified(reason) => warn!("Self-check: could not check {dependency}: {reason}"),
+        CheckStatus::Skipped => info!("Self-check: {dependency} is not configured"),
+    }
+}
+
+/// This struct verifies on boot that dependencies Crabo needs are
+/// configured and reachable, so misconfiguration shows up immediately
+/// rather than as empty snapshots.
+///
+/// Failed Proxydon check is repeated whenever readiness is asked for,
+/// as Proxydon could start later than Crabo. YouTube key is checked
+/// once, it does not change without restart and checks cost quota.
+///
+/// Clones share the same results.
+#[derive(Clone)]
+pub(crate) struct SelfCheck {
+    proxydon_endpoint: Url,
+    readiness: Arc<Mutex<Readiness>>,
+}
+
+impl SelfCheck {
+    /// This function checks Proxydon at `proxydon_endpoint` and
+    /// `youtube_api_key` if it is set, logging clear errors for failed checks.
+    pub(crate) async fn run(proxydon_endpoint: Url, youtube_api_key: Option<&str>) -> Self {
+        let proxydon = check_proxydon(&proxydon_endpoint).await;
+        let youtube = check_youtube_key(youtube_api_key).await;
+
+        log_status("Proxydon", &proxydon);
+        log_status("YouTube API key", &youtube);
+
+        // cache is the backend every snapshot goes through
+        if let CheckStatus::Failed(reason) = &proxydon {
+            report_error(
+                "cache",
+                format!("Proxydon does not respond: {reason}"),
+                &[("endpoint", proxydon_endpoint.as_str())],
+            );
+        }
+
+        if let CheckStatus::Failed(reason) = &youtube {
+            report_error(
+                "self-check",
+                format!("YouTube API key is not accepted: {reason}"),
+                &[],
+            );
+        }
+
+        Self {
+            proxydon_endpoint,
+            readiness: Arc::new(Mutex::new(readiness(proxydon, youtube))),
+        }
+    }
+
+    /// Returns results of checks, repeating failed Proxydon check.
+    pub(crate) async fn readiness(&self) -> Readiness {
+        let current = self.readiness.lock().unwrap().clone();
+
+        if !matches!(current.proxydon, CheckStatus::Failed(_)) {
+            return current;
+        }
+
+        let proxydon = check_proxydon(&self.proxydon_endpoint).await;
+
+        if proxydon == CheckStatus::Passed {
+            log_status("Proxydon", &proxydon);
+        }
+
+        let updated = readiness(proxydon, current.youtube);
+        *self.readiness.lock().unwrap() = updated.clone();
+
+        updated
+    }
+}
+
+/// Helper function to combine `proxydon` and `youtube` check results.
+fn readiness(proxydon: CheckStatus, youtube: CheckStatus) -> Readiness {
+    let ready = [&proxydon, &youtube].into_iter()
+        .all(|status| !matches!(status, CheckStatus::Failed(_)));
+
+    Readiness {
+        ready,
+        proxydon,
+        youtube,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::self_check::{CheckStatus, readiness};
+
+    #[test]
+    fn test_readiness() {
+        assert!(readiness(CheckStatus::Passed, CheckStatus::Skipped).ready);
+        assert!(readiness(CheckStatus::Passed, CheckStatus::Unverified("".into())).ready);
+        assert!(!readiness(CheckStatus::Failed("".into()), CheckStatus::Passed).ready);
+        assert!(!readiness(CheckStatus::Passed, CheckStatus::Failed("".into())).ready);
+    }
+
+    #[test]
+    fn test_check_status_serialization() {
+        assert_eq!(
+            serde_json::to_string(&CheckStatus::Failed("bad key".into())).unwrap(),
+            r#"{"status":"failed","reason":"bad key"}"#
+        );
+
+        assert_eq!(
+            serde_json::to_string(&CheckStatus::Passed).unwrap(),
+            r#"{"status":"passed"}"#
+        );
+    }
+}
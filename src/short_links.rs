@@ -0,0 +1,140 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use log::warn;
+use lru::LruCache;
+use url::Url;
+use crate::snapper::Clients;
+
+/// Number of resolved short links remembered, so links seen again share
+/// cache entry with full page without resolving them.
+const RESOLVED_SHORT_LINKS: usize = 4096;
+
+/// Prefix of cache hints IDs of short links that are not resolved yet,
+/// so snappers never take them for IDs of content.
+const HINTS_ID_PREFIX: &str = "short:";
+
+/// Returns cache hints ID of short link `url` that is not resolved yet.
+/// Once resolved, link shares cache hints with content it leads to.
+pub fn hints_id(url: &Url) -> String {
+    format!("{HINTS_ID_PREFIX}{url}")
+}
+
+/// Parses short link from cache hints `id` made by [hints_id].
+/// Returns None if `id` is ID of content rather than short link.
+pub fn parse_hints_id(id: &str) -> Option<Url> {
+    id.strip_prefix(HINTS_ID_PREFIX)
+        .and_then(|url| Url::parse(url).ok())
+}
+
+/// This struct resolves short links of providers, e.g. `b23.tv` or
+/// `redd.it`, to IDs of content they redirect to and remembers resolved
+/// links, so snappers do not repeat requests for the same link.
+/// Snappers answer resolved links with cache hints of content they lead
+/// to, so short link and full page share cache entry.
+pub struct ShortLinks<T> {
+    /// IDs resolved short links lead to, by short link.
+    resolved: Mutex<LruCache<String, T>>,
+}
+
+impl<T: Clone> Default for ShortLinks<T> {
+    fn default() -> Self {
+        Self {
+            resolved: Mutex::new(
+                LruCache::new(NonZeroUsize::new(RESOLVED_SHORT_LINKS).unwrap())
+            ),
+        }
+    }
+}
+
+impl<T: Clone> ShortLinks<T> {
+    /// Returns ID short link `url` was resolved to earlier, if any.
+    pub fn get(&self, url: &Url) -> Option<T> {
+        self.resolved.lock()
+            .unwrap()
+            .get(url.as_str())
+            .cloned()
+    }
+
+    /// This method attempts to resolve short link `url` with `clients` to
+    /// ID `extract_id` finds in location link redirects to. Redirect is
+    /// not followed, page client only validates and connects to short
+    /// link host the same way it does for any other URL.
+    /// Returns either resolved ID or None.
+    pub async fn resolve(
+        &self,
+        url: &Url,
+        clients: &Clients,
+        extract_id: impl Fn(&Url) -> Option<T>,
+    ) -> Option<T> {
+        let location = match clients.page_client.redirect_location(url).await {
+            Ok(location) => location,
+
+            Err(err) => {
+                warn!("Failed to resolve short URL {url}: {err:?}");
+                return None;
+            }
+        };
+
+        let id = location.and_then(|location| extract_id(&location));
+
+        match &id {
+            Some(id) => {
+                self.resolved.lock().unwrap().put(url.to_string(), id.clone());
+            }
+
+            None => warn!("Short URL {url} does not lead to supported page"),
+        }
+
+        id
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::{Method, StatusCode};
+    use url::Url;
+    use crate::page_client::{CannedResponse, CannedTransport};
+    use crate::short_links::{hints_id, parse_hints_id, ShortLinks};
+    use crate::snapper::test_clients;
+
+    #[test]
+    fn test_hints_id() {
+        let short_url = Url::parse("https://redd.it/1abcde").unwrap();
+
+        assert_eq!(hints_id(&short_url), "short:https://redd.it/1abcde");
+        assert_eq!(parse_hints_id(&hints_id(&short_url)), Some(short_url));
+        assert_eq!(parse_hints_id("1abcde"), None);
+    }
+
+    #[actix_rt::test]
+    async fn test_resolve() {
+        let redirect = CannedResponse {
+            headers: vec![("Location".to_string(), "/video/x8abcde".to_string())],
+            ..CannedResponse::status(StatusCode::MOVED_PERMANENTLY)
+        };
+
+        // target of redirect is never requested
+        let transport = CannedTransport::default()
+            .respond(Method::HEAD, "https://dai.ly/x8abcde", redirect)
+            .respond(Method::HEAD, "https://dai.ly/gone", CannedResponse::status(StatusCode::OK));
+
+        let clients = test_clients(transport);
+
+        let short_links = ShortLinks::default();
+        let short_url = Url::parse("https://dai.ly/x8abcde").unwrap();
+        let extract = |location: &Url| Some(location.path().to_string());
+
+        assert_eq!(short_links.get(&short_url), None);
+
+        assert_eq!(
+            short_links.resolve(&short_url, &clients, extract).await.as_deref(),
+            Some("/video/x8abcde")
+        );
+
+        assert_eq!(short_links.get(&short_url).as_deref(), Some("/video/x8abcde"));
+
+        let gone = Url::parse("https://dai.ly/gone").unwrap();
+        assert_eq!(short_links.resolve(&gone, &clients, extract).await, None);
+        assert_eq!(short_links.get(&gone), None);
+    }
+}
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use crabo_core::metrics::MetricsWriter;
+
+/// Default maximum number of `/snap` batches processed concurrently.
+pub(crate) const DEFAULT_MAX_CONCURRENT_SNAPS: usize = 16;
+
+/// Default maximum number of `/snap` batches waiting to be processed.
+pub(crate) const DEFAULT_MAX_QUEUED_SNAPS: usize = 64;
+
+/// Permission to process batch, slot is released once it is dropped.
+pub(crate) struct SnapPermit {
+    _permit: OwnedSemaphorePermit,
+}
+
+/// Place in queue of batches waiting to be processed, released once
+/// it is dropped, e.g. when client disconnects while waiting.
+struct QueueSlot {
+    queued: Arc<AtomicUsize>,
+}
+
+impl Drop for QueueSlot {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// This struct limits number of `/snap` batches processed at once across
+/// all workers.
+///
+/// Each batch fans out into many futures holding pages being parsed,
+/// so accepting everything under load piles them up until memory runs
+/// out. Only a few batches are processed concurrently, a few more wait
+/// for their turn, the rest are rejected right away, so caller could
+/// retry later.
+///
+/// Clones share the same limits.
+#[derive(Clone)]
+pub(crate) struct SnapAdmission {
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    queued: Arc<AtomicUsize>,
+    max_queued: usize,
+    rejected: Arc<AtomicUsize>,
+}
+
+impl SnapAdmission {
+    /// Constructs new instance of [SnapAdmission] that processes up to
+    /// `max_concurrent` batches at once, with up to `max_queued` more
+    /// waiting for their turn.
+    pub(crate) fn new(max_concurrent: usize, max_queued: usize) -> Self {
+        let max_concurrent = max_concurrent.max(1);
+
+        Self {
+            semaphore: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent,
+            queued: Arc::new(AtomicUsize::new(0)),
+            max_queued,
+            rejected: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// This method waits until batch could be processed.
+    /// Returns None if too many batches are waiting already, so batch
+    /// must be rejected.
+    pub(crate) async fn admit(&self) -> Option<SnapPermit> {
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Some(SnapPermit { _permit: permit });
+        }
+
+        if self.queued.fetch_add(1, Ordering::SeqCst) >= self.max_queued {
+            self.queued.fetch_sub(1, Ordering::SeqCst);
+            self.rejected.fetch_add(1, Ordering::SeqCst);
+            return None;
+        }
+
+        let _slot = QueueSlot {
+            queued: self.queued.clone(),
+        };
+
+        // semaphore is never closed
+        let permit = self.semaphore.clone()
+            .acquire_owned()
+            .await
+            .unwrap();
+
+        Some(SnapPermit { _permit: permit })
+    }
+
+    /// This method writes state of admission with `writer`.
+    pub(crate) fn write_metrics(&self, writer: &mut MetricsWriter) {
+        writer.gauge(
+            "crabo_snap_batches_in_progress",
+            "Number of /snap batches being processed.",
+            self.max_concurrent - self.semaphore.available_permits(),
+        );
+
+        writer.gauge(
+            "crabo_snap_batches_queued",
+            "Number of /snap batches waiting to be processed.",
+            self.queued.load(Ordering::SeqCst),
+        );
+
+        writer.counter(
+            "crabo_snap_batches_rejected_total",
+            "Number of /snap batches rejected due to overload.",
+            self.rejected.load(Ordering::SeqCst),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::snap_admission::SnapAdmission;
+
+    #[actix_rt::test]
+    async fn test_admission() {
+        let admission = SnapAdmission::new(1, 1);
+        let first = admission.admit().await.unwrap();
+
+        let shared = admission.clone();
+        let waiting = actix_rt::spawn(async move { shared.admit().await.is_some() });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+
+        assert!(admission.admit().await.is_none());
+
+        drop(first);
+        assert!(waiting.await.unwrap());
+        assert!(admission.admit().await.is_some());
+    }
+}
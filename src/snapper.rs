@@ -1,54 +1,211 @@
+use std::sync::Arc;
+use futures::future::{join_all, LocalBoxFuture};
 use url::Url;
-use fedineko_http_client::{GenericClient, SuppressedClient};
 use proxydon_client::ProxydonClient;
-use crabo_model::Snapshot;
+use proxydon_cache::typed_cache::TypedCache;
+use crabo_model::{SnapError, Snapshot};
+use crate::fetch_limiter::FetchLimiter;
+use crate::image_proxy::ImageProxy;
+use crate::metrics::ProviderMetrics;
+use crate::page_client::PageClient;
+use crate::url_guard::UrlGuard;
+use crate::util::MimeGuess;
+use crate::warc::WarcArchive;
 
 /// Defines interface for site snapshot producers.
-pub(crate) trait Snapper {
+/// Snappers are kept in [crate::snapper_registry::SnapperRegistry] as
+/// trait objects shared by all workers, so futures are boxed. Clients
+/// are per worker and not Send, so neither are futures.
+pub trait Snapper: Send + Sync {
+    /// Returns provider of this snapper, as found in its cache hints,
+    /// e.g. `youtube` or `api:example`.
+    fn provider(&self) -> String;
+
     /// Returns some [CacheHints] for given `url` if this snapper
     /// could deal with URL.
     fn cache_hints(&self, url: &Url) -> Option<CacheHints>;
 
+    /// Returns true if snapshot of `url` depends on language it is asked
+    /// in, so snapshots in different languages are cached apart.
+    /// Snappers that ignore language keep one cache entry for all.
+    fn is_localized(&self, _url: &Url) -> bool {
+        false
+    }
+
     /// This method produces snapshot for `url` and `cache_hints`,
     /// `clients` provide HTTP and Proxydon clients.
-    async fn snap(
-        &self,
+    fn snap<'a>(
+        &'a self,
         url: Url,
         cache_hints: CacheHints,
-        clients: &Clients
-    ) -> SnapshotAndHints;
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, SnapshotAndHints>;
+
+    /// This method produces snapshots for all `urls` with hints this
+    /// snapper returned, in the same order. Snappers which APIs accept
+    /// many IDs at once override it, by default URLs are snapped one by
+    /// one concurrently.
+    fn snap_many<'a>(
+        &'a self,
+        urls: Vec<(Url, CacheHints)>,
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, Vec<SnapshotAndHints>> {
+        Box::pin(join_all(
+            urls.into_iter()
+                .map(|(url, cache_hints)| self.snap(url, cache_hints, clients))
+        ))
+    }
 }
 
-pub(crate) struct Clients {
+/// HTTP and cache clients, one set per worker, as awc clients could not
+/// be shared across threads. State that should be consistent for all
+/// workers, e.g. suppressed servers or request limits, is shared by sets.
+pub struct Clients {
     /// Cache client.
-    pub(crate) proxydon_client: ProxydonClient,
+    pub proxydon_client: ProxydonClient,
+
+    /// This client streams web-pages and knows how to ignore servers
+    /// that report errors.
+    pub page_client: PageClient,
+
+    /// Talks to rendering service if it is configured. Unlike
+    /// `page_client` it is allowed to reach internal hosts of service,
+    /// so it must never fetch URLs found in posts.
+    pub renderer_client: Option<PageClient>,
+
+    /// Validates URLs before requests made with clients above,
+    /// [PageClient] does it on its own.
+    pub url_guard: UrlGuard,
+
+    /// Limits number of concurrent requests made with clients above,
+    /// [PageClient] shares it.
+    pub fetch_limiter: FetchLimiter,
+
+    /// Content types of resources found by requests.
+    pub mime_cache: Arc<TypedCache<MimeGuess>>,
+
+    /// Outcomes of snappers, which report failed API calls there.
+    pub provider_metrics: ProviderMetrics,
+
+    /// Serves copies of preview images if configured.
+    pub image_proxy: Option<ImageProxy>,
 
-    /// The simplest HTTP client.
-    pub(crate) generic_client: GenericClient,
+    /// Keeps fetched pages as WARC files if configured.
+    pub warc_archive: Option<WarcArchive>,
+}
+
+/// Helper function to construct clients for tests, pages are served
+/// by `transport`.
+#[cfg(test)]
+pub(crate) fn test_clients(transport: crate::page_client::CannedTransport) -> Clients {
+    use crate::page_client::DEFAULT_MAX_BODY_BYTES;
+    use crate::suppression::HostSuppressor;
+    use crate::timeouts::Timeouts;
+    use crate::url_policy::UrlPolicy;
+    use crate::util::new_mime_cache;
+
+    let proxydon_url = Url::parse("http://127.0.0.1").unwrap();
 
-    // Unfortunately awc used under the hood does not expose configuration,
-    // so setting it per request is not possible, yet creating new instances
-    // of client for each request does not feel quite right.
-    /// This client does not follow redirects.
-    pub(crate) no_follow_client: GenericClient,
+    Clients {
+        proxydon_client: ProxydonClient::new(&proxydon_url),
 
-    /// This client knows how to ignore servers that report errors.
-    pub(crate) suppressed_client: SuppressedClient,
+        page_client: PageClient::with_transport(
+            Box::new(transport),
+            DEFAULT_MAX_BODY_BYTES,
+            UrlGuard::default(),
+            UrlPolicy::default(),
+            &Timeouts::default(),
+            FetchLimiter::default(),
+            Arc::new(HostSuppressor::new()),
+        ),
+
+        renderer_client: None,
+        url_guard: UrlGuard::default(),
+        fetch_limiter: FetchLimiter::default(),
+        mime_cache: Arc::new(new_mime_cache()),
+        provider_metrics: ProviderMetrics::default(),
+        image_proxy: None,
+        warc_archive: None,
+    }
 }
 
 /// This structure is used tp provide hints for snapshotting.
 #[derive(Clone)]
-pub(crate) struct CacheHints {
+pub struct CacheHints {
     /// Identifies snapper for this hints object.
     pub provider: String,
 
     /// ID of object, e.g. video ID to pass into some service API client.
     pub id: String,
+
+    /// Language snapshot is preferred in, e.g. `de` or `pt-BR`, if caller
+    /// asked for one and snapper makes localized snapshots of URL, see
+    /// [Snapper::is_localized].
+    pub language: Option<String>,
+}
+
+impl CacheHints {
+    /// Returns key snapshot is cached with. Snapshots of the same object
+    /// in different languages are cached apart, so multilingual sites do
+    /// not serve one cached language to everyone.
+    pub fn cache_key(&self) -> String {
+        match &self.language {
+            Some(language) => format!("{}@{language}", self.id),
+            None => self.id.clone(),
+        }
+    }
 }
 
 
 /// Wrapper to pass snapshot and hints together.
-pub(crate) struct SnapshotAndHints {
-    pub snapshot: Option<Snapshot>,
+/// If there is no snapshot, error tells why, so it is cached and
+/// reported accordingly.
+pub struct SnapshotAndHints {
+    pub snapshot: Result<Snapshot, SnapError>,
     pub hints: CacheHints,
 }
+
+/// Version of [Snapshot] layout, bumped whenever its fields change,
+/// so consumers could tell apart snapshots made by different versions
+/// of Crabo during rolling upgrades. Snapshots cached before versions
+/// were introduced have version 0.
+pub const SNAPSHOT_SCHEMA_VERSION: u32 = 2;
+
+/// Returns [Snapshot] of `url` with no details filled in.
+/// Snappers use it as a base in struct update syntax, so only fields
+/// they actually know about have to be listed.
+pub fn bare_snapshot(url: Url) -> Snapshot {
+    Snapshot {
+        url,
+        schema_version: SNAPSHOT_SCHEMA_VERSION,
+        preview_url: None,
+        title: None,
+        description: None,
+        source: None,
+        site_name: None,
+        icon_url: None,
+        preview_mime_type: None,
+        preview_alt: None,
+        preview_width: None,
+        preview_height: None,
+        tags: vec![],
+        application_name: None,
+        video: None,
+        audio: None,
+        published_at: None,
+        author: None,
+        language: None,
+        canonical_url: None,
+        feeds: vec![],
+        fetched_at: None,
+        cache: None,
+        preview_color: None,
+        quality: None,
+        updated_at: None,
+        accent_color: None,
+        sensitive: false,
+        fediverse_creator: None,
+        duration_seconds: None,
+        archived_at: None,
+    }
+}
@@ -0,0 +1,204 @@
+use log::warn;
+use regex::Regex;
+use url::Url;
+use crate::snapper::{CacheHints, Snapper};
+
+/// Priority of snappers using APIs of particular sites, e.g. YouTube.
+pub const SITE_API_PRIORITY: i32 = 200;
+
+/// Priority of snappers for JSON APIs defined in configuration.
+pub const CONFIGURED_API_PRIORITY: i32 = 100;
+
+/// Priority of general purpose snappers, which could snap any page.
+pub const FALLBACK_PRIORITY: i32 = 0;
+
+/// Tells which URLs snapper could possibly deal with, so it is not asked
+/// about every URL.
+#[derive(Clone, Debug)]
+pub enum UrlMatcher {
+    /// Any URL.
+    Any,
+
+    /// URLs on any of these hosts or their subdomains.
+    Hosts(Vec<String>),
+
+    /// URLs matching regular expression.
+    Pattern(Regex),
+}
+
+impl UrlMatcher {
+    /// Returns true if snapper could deal with `url`.
+    pub fn matches(&self, url: &Url) -> bool {
+        match self {
+            Self::Any => true,
+
+            Self::Hosts(hosts) => {
+                let host = url.host_str().unwrap_or_default();
+
+                hosts.iter().any(|known| host == known || host
+                    .strip_suffix(known.as_str())
+                    .is_some_and(|prefix| prefix.ends_with('.'))
+                )
+            }
+
+            Self::Pattern(pattern) => pattern.is_match(url.as_str()),
+        }
+    }
+}
+
+/// Snapper registered along with its priority and matcher.
+struct SnapperEntry {
+    provider: String,
+    priority: i32,
+    matcher: UrlMatcher,
+    snapper: Box<dyn Snapper>,
+}
+
+/// This struct keeps all snappers, so new ones are registered in a single
+/// place rather than wired into [crate::snapshot::SnapshotMaker].
+///
+/// Snappers are asked about URL from the highest priority to the lowest,
+/// the first one that returns cache hints snaps it. Snappers of equal
+/// priority are asked in order of registration.
+#[derive(Default)]
+pub struct SnapperRegistry {
+    entries: Vec<SnapperEntry>,
+}
+
+impl SnapperRegistry {
+    /// Registers `snapper` with `priority` for URLs `matcher` accepts.
+    /// Provider of snapper should be unique, otherwise snapper is not
+    /// registered.
+    pub fn register(
+        &mut self,
+        priority: i32,
+        matcher: UrlMatcher,
+        snapper: Box<dyn Snapper>,
+    ) {
+        let provider = snapper.provider();
+
+        if self.get(&provider).is_some() {
+            warn!("Snapper {provider} is registered already, ignoring another one");
+            return;
+        }
+
+        let position = self.entries
+            .partition_point(|entry| entry.priority >= priority);
+
+        self.entries.insert(
+            position,
+            SnapperEntry {
+                provider,
+                priority,
+                matcher,
+                snapper,
+            },
+        );
+    }
+
+    /// Returns cache hints of the first snapper that could deal with `url`.
+    pub fn cache_hints(&self, url: &Url) -> Option<CacheHints> {
+        self.entries.iter()
+            .filter(|entry| entry.matcher.matches(url))
+            .find_map(|entry| entry.snapper.cache_hints(url))
+    }
+
+    /// Returns snapper of `provider` if there is one.
+    pub fn get(&self, provider: &str) -> Option<&dyn Snapper> {
+        self.entries.iter()
+            .find(|entry| entry.provider == provider)
+            .map(|entry| entry.snapper.as_ref())
+    }
+
+    /// Returns all snappers, the highest priority first.
+    pub fn iter(&self) -> impl Iterator<Item = &dyn Snapper> {
+        self.entries.iter().map(|entry| entry.snapper.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::LocalBoxFuture;
+    use regex::Regex;
+    use url::Url;
+    use crabo_model::SnapError;
+    use crate::snapper::{CacheHints, Clients, Snapper, SnapshotAndHints};
+    use crate::snapper_registry::{SnapperRegistry, UrlMatcher};
+
+    struct NamedSnapper(&'static str);
+
+    impl Snapper for NamedSnapper {
+        fn provider(&self) -> String {
+            self.0.to_string()
+        }
+
+        fn cache_hints(&self, url: &Url) -> Option<CacheHints> {
+            Some(
+                CacheHints {
+                    provider: self.provider(),
+                    id: url.to_string(),
+                    language: None,
+                }
+            )
+        }
+
+        fn snap<'a>(
+            &'a self,
+            _url: Url,
+            cache_hints: CacheHints,
+            _clients: &'a Clients,
+        ) -> LocalBoxFuture<'a, SnapshotAndHints> {
+            Box::pin(async move {
+                SnapshotAndHints {
+                    snapshot: Err(SnapError::NotFound),
+                    hints: cache_hints,
+                }
+            })
+        }
+    }
+
+    fn provider_of(registry: &SnapperRegistry, url: &str) -> String {
+        registry.cache_hints(&Url::parse(url).unwrap())
+            .unwrap()
+            .provider
+    }
+
+    #[test]
+    fn test_priorities() {
+        let mut registry = SnapperRegistry::default();
+
+        registry.register(0, UrlMatcher::Any, Box::new(NamedSnapper("default")));
+
+        registry.register(
+            200,
+            UrlMatcher::Hosts(vec!["video.example".to_string()]),
+            Box::new(NamedSnapper("video")),
+        );
+
+        registry.register(
+            100,
+            UrlMatcher::Pattern(Regex::new("^https://a\\.example/").unwrap()),
+            Box::new(NamedSnapper("first")),
+        );
+
+        registry.register(
+            100,
+            UrlMatcher::Pattern(Regex::new("^https://a\\.example/b/").unwrap()),
+            Box::new(NamedSnapper("second")),
+        );
+
+        assert_eq!(provider_of(&registry, "https://www.video.example/1"), "video");
+        assert_eq!(provider_of(&registry, "https://notvideo.example/1"), "default");
+        assert_eq!(provider_of(&registry, "https://a.example/b/c"), "first");
+        assert_eq!(provider_of(&registry, "https://b.example/"), "default");
+
+        assert_eq!(
+            registry.iter().map(|snapper| snapper.provider()).collect::<Vec<_>>(),
+            vec!["video", "first", "second", "default"]
+        );
+
+        // providers are unique
+        registry.register(300, UrlMatcher::Any, Box::new(NamedSnapper("video")));
+        assert_eq!(provider_of(&registry, "https://b.example/"), "default");
+    }
+}
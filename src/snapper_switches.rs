@@ -0,0 +1,104 @@
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock};
+use log::info;
+use serde::{Deserialize, Serialize};
+
+/// Whether snapper of provider is enabled, as reported by admin endpoint.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SnapperState {
+    /// Provider of snapper, e.g. `youtube` or `api:example`.
+    pub provider: String,
+
+    /// False if snapper is turned off.
+    pub enabled: bool,
+}
+
+/// Body of request that turns snapper on or off.
+#[derive(Deserialize)]
+pub struct SnapperSwitch {
+    /// True to turn snapper on.
+    pub enabled: bool,
+}
+
+/// This function splits `list` of providers separated by comma, e.g.
+/// `bilibili,api:example`.
+pub fn parse_providers(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(|provider| provider.trim())
+        .filter(|provider| !provider.is_empty())
+        .map(|provider| provider.to_string())
+        .collect()
+}
+
+/// This struct keeps track of snappers operator turned off, e.g. when
+/// unofficial API starts returning captchas, so they could be turned off
+/// and back on without redeploying.
+///
+/// URLs of disabled snapper are not snapped at all rather than passed to
+/// general purpose HTML snapper, pages of such sites are likely broken
+/// the same way.
+///
+/// Clones share the same state.
+#[derive(Clone, Debug, Default)]
+pub struct SnapperSwitches {
+    disabled: Arc<RwLock<HashSet<String>>>,
+}
+
+impl SnapperSwitches {
+    /// Constructs new instance of [SnapperSwitches] with `disabled`
+    /// providers separated by comma, e.g. `bilibili,api:example`.
+    pub fn new(disabled: &str) -> Self {
+        Self::with_disabled(parse_providers(disabled))
+    }
+
+    /// Constructs new instance of [SnapperSwitches] with `disabled`
+    /// providers.
+    pub fn with_disabled(disabled: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            disabled: Arc::new(RwLock::new(disabled.into_iter().collect())),
+        }
+    }
+
+    /// Returns true if snapper of `provider` is enabled.
+    pub fn is_enabled(&self, provider: &str) -> bool {
+        !self.disabled.read()
+            .unwrap()
+            .contains(provider)
+    }
+
+    /// Turns snapper of `provider` on or off depending on `enabled`.
+    pub fn set_enabled(&self, provider: &str, enabled: bool) {
+        let mut disabled = self.disabled.write().unwrap();
+
+        match enabled {
+            true => disabled.remove(provider),
+            false => disabled.insert(provider.to_string()),
+        };
+
+        info!(
+            "Snapper {provider} is {}",
+            if enabled { "enabled" } else { "disabled" },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::snapper_switches::SnapperSwitches;
+
+    #[test]
+    fn test_switches() {
+        let switches = SnapperSwitches::new(" bilibili, api:example ,");
+        let shared = switches.clone();
+
+        assert!(!switches.is_enabled("bilibili"));
+        assert!(!switches.is_enabled("api:example"));
+        assert!(switches.is_enabled("youtube"));
+
+        shared.set_enabled("bilibili", true);
+        shared.set_enabled("youtube", false);
+
+        assert!(switches.is_enabled("bilibili"));
+        assert!(!switches.is_enabled("youtube"));
+    }
+}
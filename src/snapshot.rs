@@ -1,20 +1,112 @@
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use chrono::Duration;
-use futures::future::join_all;
-use log::{debug, info};
+use std::time::Instant;
+use chrono::{DateTime, Duration, Utc};
+use futures::future::{join, join_all};
+use itertools::Itertools;
+use log::{debug, info, warn};
 use url::Url;
-use crabo_model::Snapshot;
+use crabo_model::{CacheStatus, SnapError, SnapFailure, Snapshot, SnapshotMedia};
 use language_utils::content_cleaner::ContentCleaner;
 use proxydon_client::cache::ProxydonCache;
 use proxydon_client::CacheItem;
+use crate::api_snapper::ApiSnapper;
 use crate::bilibili::BiliBiliSnapper;
+use crate::dailymotion::DailymotionSnapper;
+use crate::extraction_rules::ExtractionRules;
+use crate::ignored_urls::IgnoredUrls;
+use crate::inflight::{InflightRegistry, InflightSnap};
+use crate::output_limits::{OutputLimits, truncate_graphemes};
+use crate::provider_headers::{DomainHeaders, ProviderHeaders};
 use crate::html_meta::HtmlMetaSnapper;
-use crate::snapper::{CacheHints, Clients, Snapper, SnapshotAndHints};
+use crate::metrics::SnapOutcome;
+use crate::peertube::PeerTubeSnapper;
+use crate::reddit::RedditSnapper;
+use crate::renderer::Renderer;
+use crate::snapper::{
+    CacheHints,
+    Clients,
+    Snapper,
+    SnapshotAndHints,
+    SNAPSHOT_SCHEMA_VERSION,
+};
+use crate::snapper_registry::{
+    FALLBACK_PRIORITY,
+    SITE_API_PRIORITY,
+    SnapperRegistry,
+    UrlMatcher,
+};
+use crate::snapper_switches::{SnapperState, SnapperSwitches};
+use crate::snapshot_store::{SnapshotChange, SnapshotStore, StoredSnapshot};
+use crate::soundcloud::SoundCloudSnapper;
+use crate::spotify::SpotifySnapper;
+use crate::tiktok::TikTokSnapper;
+use crate::timeouts::Timeouts;
+use crate::url_normalizer::UrlNormalizer;
+use crate::url_policy::{RejectReason, UrlPolicy};
+use crate::user_agent::UserAgent;
+use crate::util::{normalize_language_tag, sanitize_url};
 use crate::youtube::YoutubeSnapper;
+use crate::youtube_quota::YoutubeQuota;
+
+/// Cached snapshots older than this are reported as stale by default.
+pub const DEFAULT_STALE_AFTER_HOURS: i64 = 24;
+
+/// Returns URLs of `hints` grouped by cache keys of their hints, in
+/// order they were requested.
+fn urls_by_cache_key(hints: &[(Url, CacheHints)]) -> HashMap<String, Vec<Url>> {
+    hints.iter()
+        .map(|(url, cache_hints)| (cache_hints.cache_key(), url.clone()))
+        .into_group_map()
+}
+
+/// Returns copy of `snapshot` for every one of `urls`, different forms
+/// of URL snapshot is made for, so each form is answered under URL it
+/// was requested with. Snapshot is returned as is if there are no URLs.
+fn snapshot_per_url(snapshot: Snapshot, urls: &[Url]) -> Vec<Snapshot> {
+    match urls {
+        [] => vec![snapshot],
+
+        urls => urls.iter()
+            .map(|url| Snapshot {
+                url: url.clone(),
+                ..snapshot.clone()
+            })
+            .collect(),
+    }
+}
+
+/// Returns true if snapping failure of `error` class is cached, so URL
+/// is not snapped again until cache entry expires. Transient failures
+/// are not cached, next request for URL gets another chance.
+fn is_cached_failure(error: SnapError) -> bool {
+    match error {
+        SnapError::RobotsDenied |
+        SnapError::NotFound |
+        SnapError::ParseFailed => true,
+
+        // consent wall could be gone next time, e.g. cookie is configured
+        SnapError::Interstitial |
+        SnapError::Ignored |
+        SnapError::Rejected |
+        SnapError::Suppressed |
+        SnapError::Timeout |
+        SnapError::ProviderError => false,
+    }
+}
+
+/// Snapshots of URLs and reasons why other URLs have none.
+pub struct SnapResults {
+    /// Snapshots, cached or just made.
+    pub snapshots: Vec<Snapshot>,
+
+    /// URLs snapping of which just failed or that are ignored. Negative
+    /// hits of cache are not listed, class of failure is not cached.
+    pub failures: Vec<SnapFailure>,
+}
 
 /// This is where all processing logic happens.
-pub(crate) struct SnapshotMaker<'a> {
+pub struct SnapshotMaker<'a> {
     /// Typeless Proxydon cache instance.
     cache: Arc<ProxydonCache>,
 
@@ -22,53 +114,363 @@ pub(crate) struct SnapshotMaker<'a> {
     /// and descriptions used in snapshot.
     content_cleaner: ContentCleaner<'a>,
 
-    /// YouTube videos metadata snapper.
-    /// It uses official API so needs key for it.
-    youtube: YoutubeSnapper,
+    /// All snappers, asked about URLs in order of priority.
+    snappers: SnapperRegistry,
+
+    /// Decides which URLs are fetched at all.
+    url_policy: UrlPolicy,
+
+    /// Sites known to provide useless data or errors.
+    ignored_urls: IgnoredUrls,
+
+    /// Brings URLs to the same form before they are routed, so cache
+    /// keys of all snappers are derived from normalized URLs.
+    url_normalizer: UrlNormalizer,
+
+    /// Limits time snappers have to make snapshot.
+    timeouts: Timeouts,
+
+    /// Maximum lengths of text fields of snapshots.
+    output_limits: OutputLimits,
 
-    /// BiliBili videos metadata snapper.
-    /// It uses unofficial API as official does not seem to exist.
-    bilibili: BiliBiliSnapper,
+    /// Snappers operator turned off.
+    switches: SnapperSwitches,
 
-    /// General purpose HTML snapper
-    html_meta: HtmlMetaSnapper,
+    /// URLs being snapped right now.
+    inflight: InflightRegistry,
+
+    /// Age after which cached snapshots are reported as stale.
+    stale_after: Duration,
+
+    /// Persistent storage snapshots are looked up in when cache does not
+    /// have them, if set.
+    store: Option<Arc<dyn SnapshotStore>>,
+
+    /// Language snapshots are requested in unless request sets one.
+    default_language: Option<String>,
 }
 
-impl SnapshotMaker<'_> {
-    /// This method constructs new instance of [SnapshotMaker]
-    /// with `youtube_api_key` for YouTube snapper.
-    pub(crate) fn new(youtube_api_key: String) -> Self {
+/// Settings of snappers [SnapshotMaker] uses.
+#[derive(Clone, Debug)]
+pub struct SnapperConfig {
+    /// Key of YouTube Data API v3, YouTube snapper uses oEmbed without it.
+    pub youtube_api_key: Option<String>,
+
+    /// Budget of YouTube API quota, shared with whoever reports it.
+    pub youtube_quota: YoutubeQuota,
+
+    /// Client ID and secret of Spotify Web API application. Spotify
+    /// snapper uses oEmbed, which does not tell artists, without them.
+    pub spotify_credentials: Option<(String, String)>,
+
+    /// How Crabo identifies itself in robots.txt and robots meta-tags.
+    pub user_agent: UserAgent,
+
+    /// If set, general purpose HTML snapper uses Wayback Machine copies
+    /// of pages when server fails.
+    pub wayback_fallback: bool,
+
+    /// Providers of snappers turned off on start, e.g. `bilibili`.
+    pub disabled_snappers: Vec<String>,
+
+    /// Age after which cached snapshots are reported as stale.
+    pub stale_after: Duration,
+
+    /// Headers snappers send along with their requests, by provider.
+    pub provider_headers: ProviderHeaders,
+
+    /// Headers general purpose HTML snapper sends along with requests of
+    /// pages, by domain.
+    pub domain_headers: DomainHeaders,
+
+    /// Headers, usually consent cookies, general purpose HTML snapper
+    /// requests page again with if server responds with consent wall,
+    /// by domain.
+    pub consent_headers: DomainHeaders,
+
+    /// Language snapshots are requested in when request does not set one,
+    /// e.g. `ja` for Japanese-speaking instance.
+    pub default_language: Option<String>,
+}
+
+impl Default for SnapperConfig {
+    fn default() -> Self {
+        Self {
+            youtube_api_key: None,
+            youtube_quota: YoutubeQuota::default(),
+            spotify_credentials: None,
+            user_agent: UserAgent::default(),
+            wayback_fallback: false,
+            disabled_snappers: vec![],
+            stale_after: Duration::hours(DEFAULT_STALE_AFTER_HOURS),
+            provider_headers: ProviderHeaders::default(),
+            domain_headers: DomainHeaders::default(),
+            consent_headers: DomainHeaders::default(),
+            default_language: None,
+        }
+    }
+}
+
+/// This struct constructs [SnapshotMaker] from [SnapperConfig] and
+/// optional parts, which are default unless set.
+pub struct SnapshotMakerBuilder {
+    config: SnapperConfig,
+    extraction_rules: ExtractionRules,
+    renderer: Option<Renderer>,
+    api_snappers: Vec<ApiSnapper>,
+    url_policy: UrlPolicy,
+    ignored_urls: IgnoredUrls,
+    url_normalizer: UrlNormalizer,
+    timeouts: Timeouts,
+    output_limits: OutputLimits,
+    store: Option<Arc<dyn SnapshotStore>>,
+}
+
+impl SnapshotMakerBuilder {
+    /// Constructs new instance of [SnapshotMakerBuilder] with `config`.
+    pub fn new(config: SnapperConfig) -> Self {
         Self {
+            config,
+            extraction_rules: ExtractionRules::default(),
+            renderer: None,
+            api_snappers: vec![],
+            url_policy: UrlPolicy::default(),
+            ignored_urls: IgnoredUrls::default(),
+            url_normalizer: UrlNormalizer::default(),
+            timeouts: Timeouts::default(),
+            output_limits: OutputLimits::default(),
+            store: None,
+        }
+    }
+
+    /// Sets `extraction_rules` general purpose HTML snapper applies to
+    /// pages of domains they are set for.
+    pub fn extraction_rules(self, extraction_rules: ExtractionRules) -> Self {
+        Self { extraction_rules, ..self }
+    }
+
+    /// Sets `renderer` general purpose HTML snapper uses for pages built
+    /// by JavaScript, if it is set.
+    pub fn renderer(self, renderer: Option<Renderer>) -> Self {
+        Self { renderer, ..self }
+    }
+
+    /// Sets snappers for JSON APIs defined in configuration.
+    pub fn api_snappers(self, api_snappers: Vec<ApiSnapper>) -> Self {
+        Self { api_snappers, ..self }
+    }
+
+    /// Sets `url_policy` URLs are checked against.
+    pub fn url_policy(self, url_policy: UrlPolicy) -> Self {
+        Self { url_policy, ..self }
+    }
+
+    /// Sets `ignored_urls`, sites URLs of which are reported as ignored.
+    pub fn ignored_urls(self, ignored_urls: IgnoredUrls) -> Self {
+        Self { ignored_urls, ..self }
+    }
+
+    /// Sets `url_normalizer` URLs are normalized with before routing.
+    pub fn url_normalizer(self, url_normalizer: UrlNormalizer) -> Self {
+        Self { url_normalizer, ..self }
+    }
+
+    /// Sets `timeouts` that limit snapping of a single URL.
+    pub fn timeouts(self, timeouts: Timeouts) -> Self {
+        Self { timeouts, ..self }
+    }
+
+    /// Sets `output_limits` text fields of snapshots are cut to.
+    pub fn output_limits(self, output_limits: OutputLimits) -> Self {
+        Self { output_limits, ..self }
+    }
+
+    /// Sets persistent `store` snapshots are kept in, if it is set.
+    pub fn store(self, store: Option<Arc<dyn SnapshotStore>>) -> Self {
+        Self { store, ..self }
+    }
+
+    /// Constructs [SnapshotMaker], registering snappers configured.
+    pub fn build<'a>(self) -> SnapshotMaker<'a> {
+        let config = self.config;
+        let headers = &config.provider_headers;
+        let mut snappers = SnapperRegistry::default();
+
+        // official API, oEmbed is used without key
+        if config.youtube_api_key.is_none() {
+            info!("YouTube API key is not set, YouTube snapshots are made of oEmbed");
+        }
+
+        snappers.register(
+            SITE_API_PRIORITY,
+            UrlMatcher::Hosts(vec![
+                "youtube.com".into(),
+                "youtube-nocookie.com".into(),
+                "youtu.be".into(),
+            ]),
+            Box::new(YoutubeSnapper::new(
+                config.youtube_api_key,
+                headers.for_provider("youtube"),
+            ).with_quota(config.youtube_quota.clone())),
+        );
+
+        // unofficial API as official does not seem to exist
+        snappers.register(
+            SITE_API_PRIORITY,
+            UrlMatcher::Hosts(vec!["bilibili.com".into(), "b23.tv".into()]),
+            Box::new(BiliBiliSnapper::new(headers.for_provider("bilibili"))),
+        );
+
+        snappers.register(
+            SITE_API_PRIORITY,
+            UrlMatcher::Hosts(vec!["dailymotion.com".into(), "dai.ly".into()]),
+            Box::new(DailymotionSnapper::new(headers.for_provider("dailymotion"))),
+        );
+
+        snappers.register(
+            SITE_API_PRIORITY,
+            UrlMatcher::Hosts(vec!["open.spotify.com".into()]),
+            Box::new(SpotifySnapper::new(
+                config.spotify_credentials,
+                headers.for_provider("spotify"),
+            )),
+        );
+
+        snappers.register(
+            SITE_API_PRIORITY,
+            UrlMatcher::Hosts(vec!["soundcloud.com".into()]),
+            Box::new(SoundCloudSnapper::new(headers.for_provider("soundcloud"))),
+        );
+
+        snappers.register(
+            SITE_API_PRIORITY,
+            UrlMatcher::Hosts(vec!["tiktok.com".into()]),
+            Box::new(TikTokSnapper::new(headers.for_provider("tiktok"))),
+        );
+
+        // HTML of Reddit requires cookies and consent
+        snappers.register(
+            SITE_API_PRIORITY,
+            UrlMatcher::Hosts(vec!["reddit.com".into(), "redd.it".into()]),
+            Box::new(RedditSnapper::new(headers.for_provider("reddit"))),
+        );
+
+        // PeerTube could be on any server, only its video pages are matched
+        snappers.register(
+            SITE_API_PRIORITY,
+            UrlMatcher::Pattern(PeerTubeSnapper::url_pattern()),
+            Box::new(PeerTubeSnapper::new(headers.for_provider("peertube"))),
+        );
+
+        for api_snapper in self.api_snappers {
+            let api_snapper = api_snapper.with_request_headers(
+                headers.for_provider(&api_snapper.provider())
+            );
+
+            snappers.register(
+                api_snapper.priority(),
+                api_snapper.url_matcher(),
+                Box::new(api_snapper),
+            );
+        }
+
+        snappers.register(
+            FALLBACK_PRIORITY,
+            UrlMatcher::Any,
+            Box::new(HtmlMetaSnapper::new(
+                &config.user_agent,
+                self.extraction_rules,
+                self.renderer,
+                config.wayback_fallback,
+                headers.for_provider("default"),
+            )
+                .with_domain_headers(config.domain_headers)
+                .with_consent_headers(config.consent_headers)),
+        );
+
+        SnapshotMaker {
             cache: Arc::new(ProxydonCache::new(
                 "thumbnail",
                 None,
             )),
 
-            youtube: YoutubeSnapper::new(youtube_api_key),
             content_cleaner: ContentCleaner::new(),
-            bilibili: BiliBiliSnapper {},
-            html_meta: HtmlMetaSnapper::new(),
+            snappers,
+            url_policy: self.url_policy,
+            ignored_urls: self.ignored_urls,
+            url_normalizer: self.url_normalizer,
+            timeouts: self.timeouts,
+            output_limits: self.output_limits,
+            switches: SnapperSwitches::with_disabled(config.disabled_snappers),
+            inflight: InflightRegistry::default(),
+            stale_after: config.stale_after,
+            store: self.store,
+            default_language: config.default_language
+                .as_deref()
+                .and_then(normalize_language_tag),
         }
     }
+}
+
+impl SnapshotMaker<'_> {
+    /// Returns URLs being snapped right now, the longest running first.
+    pub fn inflight(&self) -> Vec<InflightSnap> {
+        self.inflight.snaps()
+    }
 
-    /// This method selects one of snappers that could snap `url`.
-    /// If special ones are not applicable, general purpose HTML
-    /// snapper is hinted.
-    fn cache_hints(&self, url: &Url) -> CacheHints {
-        if let Some(hints) = self.youtube.cache_hints(url) {
-            return hints;
-        }
+    /// Returns whether snappers of all providers are enabled.
+    pub fn snapper_states(&self) -> Vec<SnapperState> {
+        self.snappers.iter()
+            .map(|snapper| snapper.provider())
+            .map(|provider| SnapperState {
+                enabled: self.switches.is_enabled(&provider),
+                provider,
+            })
+            .collect()
+    }
 
-        if let Some(hints) = self.bilibili.cache_hints(url) {
-            return hints;
+    /// Turns snapper of `provider` on or off depending on `enabled`.
+    /// Returns false if there is no such snapper.
+    pub fn set_snapper_enabled(&self, provider: &str, enabled: bool) -> bool {
+        if self.snappers.get(provider).is_none() {
+            return false;
         }
 
-        CacheHints {
-            provider: "default".into(),
-            id: url.to_string(),
+        self.switches.set_enabled(provider, enabled);
+        true
+    }
+
+    /// Returns up to `limit` snapshots which content changed since
+    /// `since`, see [SnapshotStore::changes]. Returns None if there is no
+    /// store to track changes in.
+    pub async fn changes(
+        &self,
+        since: DateTime<Utc>,
+        limit: usize,
+    ) -> Option<Vec<SnapshotChange>> {
+        match &self.store {
+            Some(store) => Some(store.changes(since, limit).await),
+            None => None,
         }
     }
 
+    /// Loads ignored sites again from file, see [IgnoredUrls::reload].
+    pub fn reload_ignored_urls(&self) -> Result<usize, String> {
+        self.ignored_urls.reload()
+    }
+
+    /// This method selects one of snappers that could snap `url`.
+    /// If special ones are not applicable, general purpose HTML
+    /// snapper is hinted.
+    fn cache_hints(&self, url: &Url) -> CacheHints {
+        self.snappers.cache_hints(url)
+            .unwrap_or_else(|| CacheHints {
+                provider: "default".into(),
+                id: url.to_string(),
+                language: None,
+            })
+    }
+
     /// This method does a lousy unescaping of `text` string.
     /// `\n` becomes `<br />`
     /// `\\n` becomes `\<br />`
@@ -80,37 +482,89 @@ impl SnapshotMaker<'_> {
 
     /// This method performs cleaning of all text fields so `snapshot` data
     /// is somewhat safe to render in HTML page later.
+    /// Text is cut to configured limits before cleaning, so cleaner does
+    /// not process megabytes only to throw them away, and `<br />` added
+    /// by cleaning is never cut in half.
     fn clean_snapshot(
         &self,
-        snapshot: Option<Snapshot>,
-    ) -> Option<Snapshot> {
+        snapshot: Result<Snapshot, SnapError>,
+    ) -> Result<Snapshot, SnapError> {
+        let limits = &self.output_limits;
+
         snapshot.map(|snapshot| Snapshot {
             title: snapshot.title.map(
                 |title| self.content_cleaner.clean_content(
-                    &title,
+                    &truncate_graphemes(&title, limits.title),
                     false,
                 )
             ),
 
             description: snapshot.description.map(
-                |description| self.unescape_newline_and_clean(&description)
+                |description| self.unescape_newline_and_clean(
+                    &truncate_graphemes(&description, limits.description)
+                )
             ),
 
             source: snapshot.source.map(
-                |source| self.content_cleaner.clean_content(&source, false)
+                |source| self.content_cleaner.clean_content(
+                    &truncate_graphemes(&source, limits.source),
+                    false,
+                )
+            ),
+
+            site_name: snapshot.site_name.map(
+                |site_name| self.content_cleaner.clean_content(
+                    &truncate_graphemes(&site_name, limits.source),
+                    false,
+                )
+            ),
+
+            author: snapshot.author.map(
+                |author| self.content_cleaner.clean_content(
+                    &truncate_graphemes(&author, limits.source),
+                    false,
+                )
             ),
 
             tags: snapshot.tags.into_iter()
-                .map(|tag| self.content_cleaner.clean_content(&tag, false))
+                .map(|tag| self.content_cleaner.clean_content(
+                    &truncate_graphemes(&tag, limits.tag),
+                    false,
+                ))
                 .filter(|tag| !tag.is_empty())
                 .collect(),
+
+            // whatever snapper produced, frontends get only http(s) URLs
+            preview_url: snapshot.preview_url.and_then(sanitize_url),
+            icon_url: snapshot.icon_url.and_then(sanitize_url),
+            canonical_url: snapshot.canonical_url.and_then(sanitize_url),
+
+            feeds: snapshot.feeds.into_iter()
+                .filter_map(sanitize_url)
+                .collect(),
+
+            video: snapshot.video.and_then(|media| Some(
+                SnapshotMedia {
+                    url: sanitize_url(media.url)?,
+                    ..media
+                }
+            )),
+
+            audio: snapshot.audio.and_then(|media| Some(
+                SnapshotMedia {
+                    url: sanitize_url(media.url)?,
+                    ..media
+                }
+            )),
+
             ..snapshot
         })
     }
 
     /// This method updates cache with `snapshot_and_hints` data
     /// to avoid repeated queries for the same page on web-server side.
-    /// `clients` provides Proxydon client.
+    /// Failures are cached as negative hits only if retry would fail
+    /// the same way. `clients` provides Proxydon client.
     async fn update_cache_many(
         &self,
         clients: &Clients,
@@ -121,24 +575,28 @@ impl SnapshotMaker<'_> {
         let local_cache_expires_at = None;
 
         let items: Vec<_> = snapshot_and_hints.into_iter()
-            .map(|sh| {
+            .filter_map(|sh| {
                 match &sh.snapshot {
-                    None => CacheItem {
-                        id: sh.hints.id.clone(),
+                    Err(error) => is_cached_failure(*error).then(|| CacheItem {
+                        id: sh.hints.cache_key(),
                         content: None,
                         expires_at,
                         local_cache_expires_at,
-                    },
+                    }),
 
-                    Some(snapshot) => CacheItem {
-                        id: sh.hints.id.clone(),
+                    Ok(snapshot) => Some(CacheItem {
+                        id: sh.hints.cache_key(),
                         content: Some(serde_json::to_string(&snapshot).unwrap()),
                         expires_at,
                         local_cache_expires_at,
-                    }
+                    })
                 }
             }).collect();
 
+        if items.is_empty() {
+            return;
+        }
+
         self.cache
             .put(items, &clients.proxydon_client)
             .await;
@@ -164,76 +622,322 @@ impl SnapshotMaker<'_> {
         serde_json::from_str(&content).ok()
     }
 
+    /// Returns cache status of `snapshot` served from cache at `now`.
+    /// Snapshots of older schema lack fields, so they are stale no matter
+    /// how old they are. Snapshots cached before fetch time was recorded
+    /// are of unknown age, these are reported as hits.
+    fn cached_status(&self, snapshot: &Snapshot, now: DateTime<Utc>) -> CacheStatus {
+        if snapshot.schema_version < SNAPSHOT_SCHEMA_VERSION {
+            return CacheStatus::Stale;
+        }
+
+        match snapshot.fetched_at {
+            Some(fetched_at) if now - fetched_at > self.stale_after => CacheStatus::Stale,
+            _ => CacheStatus::Hit,
+        }
+    }
+
+    /// Helper method to record outcome of snapping URL that started at
+    /// `started_at` and produced `snapshot_and_hints` in `clients` metrics.
+    fn record_snap(
+        &self,
+        clients: &Clients,
+        snapshot_and_hints: &SnapshotAndHints,
+        started_at: Instant,
+    ) {
+        let outcome = match snapshot_and_hints.snapshot {
+            Ok(_) => SnapOutcome::Success,
+            Err(_) => SnapOutcome::Empty,
+        };
+
+        clients.provider_metrics.record_snap(
+            &snapshot_and_hints.hints.provider,
+            outcome,
+            started_at.elapsed(),
+        );
+    }
+
+    /// This method runs [Self::snap_with_cache_hints] for `url` within
+    /// total timeout of provider from `cache_hints`, so a single hanging
+    /// server does not stall the whole batch.
+    async fn snap_with_timeout(
+        &self,
+        url: Url,
+        cache_hints: CacheHints,
+        language: Option<&str>,
+        clients: &Clients,
+    ) -> SnapshotAndHints {
+        let timeout = self.timeouts.total_for(&cache_hints.provider);
+        let started_at = Instant::now();
+
+        let snap = tokio::time::timeout(
+            timeout,
+            self.snap_with_cache_hints(url.clone(), cache_hints.clone(), language, clients),
+        );
+
+        let tracked = vec![(url.to_string(), cache_hints.provider.clone())];
+
+        match self.inflight.track(tracked, snap).await {
+            Ok(snapshot_and_hints) => {
+                self.record_snap(clients, &snapshot_and_hints, started_at);
+                snapshot_and_hints
+            }
+
+            Err(_) => {
+                warn!("Timed out after {timeout:?} making snapshot of {url}");
+
+                clients.provider_metrics.record_snap(
+                    &cache_hints.provider,
+                    SnapOutcome::TimedOut,
+                    started_at.elapsed(),
+                );
+
+                SnapshotAndHints {
+                    snapshot: Err(SnapError::Timeout),
+                    hints: cache_hints,
+                }
+            }
+        }
+    }
+
+    /// This method snaps all YouTube `videos` at once within total timeout
+    /// of YouTube provider, so they are requested in as few API calls as
+    /// possible.
+    async fn snap_youtube_with_timeout(
+        &self,
+        videos: Vec<(Url, CacheHints)>,
+        clients: &Clients,
+    ) -> Vec<SnapshotAndHints> {
+        let Some(youtube) = self.snappers.get("youtube") else {
+            return vec![];
+        };
+
+        if videos.is_empty() {
+            return vec![];
+        }
+
+        let timeout = self.timeouts.total_for("youtube");
+        let started_at = Instant::now();
+        let hints: Vec<_> = videos.iter()
+            .map(|(_, cache_hints)| cache_hints.clone())
+            .collect();
+
+        let tracked = videos.iter()
+            .map(|(url, cache_hints)| (url.to_string(), cache_hints.provider.clone()))
+            .collect();
+
+        let snap = tokio::time::timeout(
+            timeout,
+            youtube.snap_many(videos, clients),
+        );
+
+        match self.inflight.track(tracked, snap).await {
+            Ok(snapshots_and_hints) => {
+                // every video took as long as the whole batch
+                for snapshot_and_hints in &snapshots_and_hints {
+                    self.record_snap(clients, snapshot_and_hints, started_at);
+                }
+
+                snapshots_and_hints
+            }
+
+            Err(_) => {
+                warn!(
+                    "Timed out after {timeout:?} making snapshots of {} \
+                    YouTube videos",
+                    hints.len(),
+                );
+
+                hints.into_iter()
+                    .map(|cache_hints| {
+                        clients.provider_metrics.record_snap(
+                            &cache_hints.provider,
+                            SnapOutcome::TimedOut,
+                            started_at.elapsed(),
+                        );
+
+                        SnapshotAndHints {
+                            snapshot: Err(SnapError::Timeout),
+                            hints: cache_hints,
+                        }
+                    })
+                    .collect()
+            }
+        }
+    }
+
     /// This method figures out from `cache_hints` which snapper to use
     /// to produce snapshots for `url`. `clients` are used under the hood
     /// to access cache or API.
+    ///
+    /// Some snappers find out they do not deal with URL only by trying,
+    /// e.g. PeerTube one asks server whether it is PeerTube instance.
+    /// If snapper fails and stops hinting `url`, it is passed to snapper
+    /// it is routed to now within the same request, asking it for
+    /// snapshot in `language` if it makes localized ones.
     async fn snap_with_cache_hints(
         &self,
         url: Url,
         cache_hints: CacheHints,
+        language: Option<&str>,
         clients: &Clients,
     ) -> SnapshotAndHints {
-        match cache_hints.provider.as_str() {
-            "youtube" => self.youtube.snap(url, cache_hints, clients).await,
-            "bilibili" => self.bilibili.snap(url, cache_hints, clients).await,
-            "default" => self.html_meta.snap(url, cache_hints, clients).await,
-
-            _ => SnapshotAndHints {
-                snapshot: None,
+        let Some(snapper) = self.snappers.get(&cache_hints.provider) else {
+            return SnapshotAndHints {
+                snapshot: Err(SnapError::ProviderError),
                 hints: cache_hints,
+            };
+        };
+
+        let snapshot_and_hints = snapper.snap(url.clone(), cache_hints.clone(), clients).await;
+
+        if snapshot_and_hints.snapshot.is_ok() {
+            return snapshot_and_hints;
+        }
+
+        let normalized = self.url_normalizer.normalize(&url);
+        let rerouted = self.with_language(&normalized, self.cache_hints(&normalized), language);
+
+        let is_rerouted = rerouted.provider != cache_hints.provider &&
+            self.switches.is_enabled(&rerouted.provider);
+
+        match self.snappers.get(&rerouted.provider).filter(|_| is_rerouted) {
+            Some(snapper) => {
+                info!(
+                    "{url}: {} gave up, passing it to {}",
+                    cache_hints.provider,
+                    rerouted.provider,
+                );
+
+                snapper.snap(url, rerouted, clients).await
             }
+
+            None => snapshot_and_hints,
         }
     }
 
-    /// Returns true for `url` if site is known to provide useless data
-    /// or errors.
-    fn ignored_url(&self, url: &Url) -> bool {
-        // TODO: Twitch video URLs snapper using Twitch API
-        // "twitch.com"
-        // "www.twitch.com"
-        match url.host() {
-            None => true,
-            Some(host) => {
-                let host_string = host.to_string();
-                host_string.ends_with("twitter.com") ||
-                    host_string.ends_with(".x.com") ||
-                    host_string == "x.com"
-            }
+    /// This method routes `url` to one of snappers, which is asked for
+    /// snapshot in `language` if it is set and snapper makes localized
+    /// snapshots of `url`. Cache hints are derived from
+    /// normalized `url`, while snapper gets `url` as it was requested.
+    /// Returns reason if `url` must not be snapped at all.
+    fn route(
+        &self,
+        url: &Url,
+        language: Option<&str>,
+    ) -> Result<CacheHints, RejectReason> {
+        self.url_policy.check(url)?;
+
+        let normalized = self.url_normalizer.normalize(url);
+
+        if self.ignored_urls.matches(&normalized) {
+            return Err(RejectReason::Ignored);
+        }
+
+        let cache_hints = self.cache_hints(&normalized);
+
+        if !self.switches.is_enabled(&cache_hints.provider) {
+            return Err(RejectReason::Disabled(cache_hints.provider));
+        }
+
+        Ok(self.with_language(&normalized, cache_hints, language))
+    }
+
+    /// This method routes every one of `urls` requested in `language`,
+    /// see [Self::route]. Returns unique URLs with their cache hints, and
+    /// failures of URLs that must not be snapped, so callers know why
+    /// these got no snapshot.
+    fn route_all(
+        &self,
+        urls: Vec<Url>,
+        language: Option<&str>,
+    ) -> (Vec<(Url, CacheHints)>, Vec<SnapFailure>) {
+        let mut failures = vec![];
+
+        let hints = urls.into_iter()
+            .unique()
+            .filter_map(|url| match self.route(&url, language) {
+                Ok(cache_hints) => Some((url, cache_hints)),
+
+                Err(reason) => {
+                    match reason {
+                        RejectReason::Ignored => debug!("{url} is ignored"),
+                        _ => info!("{url} is rejected: {reason}"),
+                    }
+
+                    failures.push(SnapFailure {
+                        url,
+                        error: reason.snap_error(),
+                    });
+
+                    None
+                }
+            })
+            .collect();
+
+        (hints, failures)
+    }
+
+    /// Returns `cache_hints` of `url` with `language` if snapper they are
+    /// of makes localized snapshots of `url`, otherwise snapshot in any
+    /// language would be cached apart for nothing.
+    fn with_language(
+        &self,
+        url: &Url,
+        cache_hints: CacheHints,
+        language: Option<&str>,
+    ) -> CacheHints {
+        let is_localized = self.snappers.get(&cache_hints.provider)
+            .is_some_and(|snapper| snapper.is_localized(url));
+
+        CacheHints {
+            language: language
+                .filter(|_| is_localized)
+                .map(|language| language.to_string()),
+
+            ..cache_hints
         }
     }
 
     /// This method makes snapshots for multiple `urls` using giving `clients`.
     /// If `bypass_cache` is specified then cached earlier snapshots for URL
-    /// are ignored.
-    pub(crate) async fn snap_many(
+    /// are ignored. Snappers that could are asked for snapshots in
+    /// preferred `language`, e.g. `de` or `pt-BR`, or in default language
+    /// of deployment if it is not set.
+    pub async fn snap_many(
         &self,
         urls: Vec<Url>,
         clients: &Clients,
         bypass_cache: bool,
-    ) -> Vec<Snapshot> {
+        language: Option<&str>,
+    ) -> SnapResults {
         debug!(
-            "Got request to snap {:?}, bypass cache option is {}",
+            "Got request to snap {:?}, bypass cache option is {}, language is {:?}",
             urls.iter().map(|x| x.as_str()).collect::<Vec<_>>(),
             bypass_cache,
+            language,
         );
 
-        let hints: HashMap<_, _> = urls.into_iter()
-            .filter(|url| {
-                let is_ignored = self.ignored_url(url);
+        // tags differing only in case are the same language
+        let language = language.and_then(|language| {
+            let normalized = normalize_language_tag(language);
 
-                if is_ignored {
-                    info!("{url} is ignored");
-                }
+            if normalized.is_none() {
+                warn!("Ignoring invalid language '{language}'");
+            }
 
-                !is_ignored
-            })
-            .map(|url| (self.cache_hints(&url), url))
-            .map(|(x, y)| (y, x))
-            .collect();
+            normalized
+        }).or_else(|| self.default_language.clone());
 
-        let ids: Vec<_> = hints.values()
-            .map(|cache_hints| cache_hints.id.clone())
-            .collect();
+        // rejected URLs are reported, so callers do not ask for them again,
+        // normalized URLs are for cache keys only, pages are fetched and
+        // reported as they were requested
+        let (hints, mut failures) = self.route_all(urls, language.as_deref());
+
+        // snapshots and failures are reported by URL, while snappers know
+        // only IDs, different forms of the same URL share one
+        let urls_by_id = urls_by_cache_key(&hints);
+        let ids: Vec<_> = urls_by_id.keys().cloned().collect();
 
         let have_in_cache = match bypass_cache {
             false => self.cache
@@ -243,47 +947,299 @@ impl SnapshotMaker<'_> {
             true => vec![],
         };
 
-        let have_in_cache_set: HashSet<_> = have_in_cache.iter()
-            .map(|x| x.id.as_str())
+        let mut have_in_cache_set: HashSet<_> = have_in_cache.iter()
+            .map(|x| x.id.clone())
             .collect();
 
-        let futures_to_await: Vec<_> = hints.into_iter()
+        // cache entries expire, store keeps snapshots until retention ends
+        let have_in_store = match (&self.store, bypass_cache) {
+            (Some(store), false) => store
+                .get(
+                    urls_by_id.keys()
+                        .filter(|id| !have_in_cache_set.contains(*id))
+                        .cloned()
+                        .collect()
+                )
+                .await,
+
+            _ => vec![],
+        };
+
+        have_in_cache_set.extend(have_in_store.iter().map(|x| x.id.clone()));
+
+        // YouTube API takes many videos at once, so they are snapped together,
+        // different forms of the same URL are snapped once
+        let (youtube_videos, others): (Vec<_>, Vec<_>) = hints.into_iter()
             .filter(|(_, cache_hints)| !have_in_cache_set.contains(
-                cache_hints.id.as_str()
+                &cache_hints.cache_key()
             ))
-            .map(|(url, cache_hints)| self.snap_with_cache_hints(
+            .unique_by(|(_, cache_hints)| cache_hints.cache_key())
+            .partition(|(_, cache_hints)| cache_hints.provider == "youtube");
+
+        // rerouted URLs come back with hints of another snapper, and
+        // snappers drop language from hints of pages that turn out to be
+        // the same in any, so results are reported by IDs URLs were
+        // requested with
+        let requested_ids: Vec<_> = others.iter()
+            .map(|(_, cache_hints)| cache_hints.cache_key())
+            .collect();
+
+        let futures_to_await: Vec<_> = others.into_iter()
+            .map(|(url, cache_hints)| self.snap_with_timeout(
                 url,
                 cache_hints,
+                language.as_deref(),
                 clients
             ))
             .collect();
 
-        let just_loaded: Vec<_> = join_all(futures_to_await)
-            .await
-            .into_iter()
+        let (others_loaded, youtube_loaded) = join(
+            join_all(futures_to_await),
+            self.snap_youtube_with_timeout(youtube_videos, clients),
+        ).await;
+
+        let requested_ids: Vec<_> = requested_ids.into_iter()
+            .chain(youtube_loaded.iter().map(|sh| sh.hints.cache_key()))
+            .collect();
+
+        let just_loaded: Vec<_> = others_loaded.into_iter()
+            .chain(youtube_loaded)
             .map(|sh| SnapshotAndHints {
                 snapshot: self.clean_snapshot(sh.snapshot),
                 ..sh
             }).collect();
 
+        let just_loaded = match &clients.image_proxy {
+            Some(image_proxy) => join_all(
+                just_loaded.into_iter()
+                    .map(|sh| async move {
+                        let snapshot = match sh.snapshot {
+                            Ok(snapshot) => Ok(
+                                image_proxy
+                                    .proxy_preview(snapshot, &clients.page_client)
+                                    .await
+                            ),
+
+                            Err(error) => Err(error),
+                        };
+
+                        SnapshotAndHints {
+                            snapshot,
+                            ..sh
+                        }
+                    })
+            ).await,
+
+            None => just_loaded,
+        };
+
+        let now = Utc::now();
+
+        let just_loaded: Vec<_> = just_loaded.into_iter()
+            .map(|sh| SnapshotAndHints {
+                snapshot: sh.snapshot.map(|snapshot| Snapshot {
+                    fetched_at: Some(now),
+                    ..snapshot
+                }),
+                ..sh
+            }).collect();
+
         self.update_cache_many(
             clients,
             just_loaded.iter().collect(),
         ).await;
 
-        let just_loaded_cache_items: Vec<_> = just_loaded.into_iter()
-            .filter_map(|x| x.snapshot)
-            .collect();
+        if let Some(store) = &self.store {
+            store.put(
+                just_loaded.iter()
+                    .filter_map(|sh| Some(StoredSnapshot {
+                        id: sh.hints.cache_key(),
+                        provider: sh.hints.provider.clone(),
+                        snapshot: sh.snapshot.as_ref().ok()?.clone(),
+                    }))
+                    .collect()
+            ).await;
+        }
+
+        // status is about this response, so it is never cached
+        let just_loaded_status = match bypass_cache {
+            true => CacheStatus::Bypass,
+            false => CacheStatus::Miss,
+        };
+
+        let mut just_loaded_cache_items = vec![];
+
+        let urls_of = |id: &str| urls_by_id.get(id)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
 
+        for (sh, requested_id) in just_loaded.into_iter().zip(requested_ids) {
+            match sh.snapshot {
+                Ok(snapshot) => just_loaded_cache_items.extend(snapshot_per_url(
+                    Snapshot {
+                        cache: Some(just_loaded_status),
+                        ..snapshot
+                    },
+                    urls_of(&requested_id),
+                )),
+
+                Err(error) => failures.extend(
+                    urls_of(&requested_id).iter()
+                        .map(|url| SnapFailure {
+                            url: url.clone(),
+                            error,
+                        })
+                ),
+            }
+        }
+
+        // cached snapshot could be made for another form of requested URL
         let have_in_cache_items = have_in_cache.into_iter()
-            .filter_map(|item| self.cache_item_to_snapshot(item))
+            .filter_map(|item| Some((item.id.clone(), self.cache_item_to_snapshot(item)?)))
+            .chain(have_in_store.into_iter().map(|stored| (stored.id, stored.snapshot)))
+            .flat_map(|(id, snapshot)| snapshot_per_url(
+                Snapshot {
+                    cache: Some(self.cached_status(&snapshot, now)),
+                    ..snapshot
+                },
+                urls_of(&id),
+            ))
             .collect();
 
-        [
+        let snapshots = [
             have_in_cache_items,
             just_loaded_cache_items
         ].into_iter()
             .flatten()
-            .collect()
+            .collect();
+
+        SnapResults {
+            snapshots,
+            failures,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crabo_model::SnapError;
+    use crate::snapper::{bare_snapshot, CacheHints};
+    use crate::snapshot::{
+        is_cached_failure,
+        SnapperConfig,
+        SnapshotMakerBuilder,
+        snapshot_per_url,
+        urls_by_cache_key,
+    };
+
+    #[test]
+    fn test_cached_failures() {
+        assert!(is_cached_failure(SnapError::NotFound));
+        assert!(is_cached_failure(SnapError::RobotsDenied));
+        assert!(!is_cached_failure(SnapError::Timeout));
+        assert!(!is_cached_failure(SnapError::Suppressed));
+        assert!(!is_cached_failure(SnapError::Interstitial));
+        assert!(!is_cached_failure(SnapError::Ignored));
+        assert!(!is_cached_failure(SnapError::Rejected));
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let hints = CacheHints {
+            provider: "youtube".to_string(),
+            id: "a1".to_string(),
+            language: None,
+        };
+
+        assert_eq!(hints.cache_key(), "a1");
+
+        let hints = CacheHints {
+            language: Some("pt-BR".to_string()),
+            ..hints
+        };
+
+        // snapper still gets bare ID
+        assert_eq!(hints.id, "a1");
+        assert_eq!(hints.cache_key(), "a1@pt-BR");
+    }
+
+    #[test]
+    fn test_forms_of_url_in_batch() {
+        let maker = SnapshotMakerBuilder::new(SnapperConfig::default()).build();
+
+        let urls: Vec<_> = [
+            "https://youtu.be/jNQXAC9IVRw",
+            "https://www.youtube.com/watch?v=jNQXAC9IVRw&utm_source=feed",
+            "https://youtu.be/dQw4w9WgXcQ",
+        ].into_iter()
+            .map(|url| Url::parse(url).unwrap())
+            .collect();
+
+        let hints: Vec<_> = urls.iter()
+            .map(|url| (url.clone(), maker.route(url, None).unwrap()))
+            .collect();
+
+        let urls_by_id = urls_by_cache_key(&hints);
+        assert_eq!(urls_by_id.len(), 2);
+
+        let forms = &urls_by_id[&hints[0].1.cache_key()];
+        assert_eq!(forms, &urls[..2]);
+
+        // snapshot made once is answered under every requested form
+        let snapshots = snapshot_per_url(bare_snapshot(urls[1].clone()), forms);
+
+        assert_eq!(
+            snapshots.iter().map(|snapshot| snapshot.url.as_str()).collect::<Vec<_>>(),
+            vec![urls[0].as_str(), urls[1].as_str()]
+        );
+
+        assert_eq!(snapshot_per_url(bare_snapshot(urls[2].clone()), &[]).len(), 1);
+    }
+
+    #[test]
+    fn test_rejected_urls() {
+        let maker = SnapshotMakerBuilder::new(SnapperConfig::default()).build();
+
+        let urls: Vec<_> = [
+            "file:///etc/passwd",
+            "gopher://example.com/",
+            "http://example.com:8080/",
+            "https://example.com/",
+        ].into_iter()
+            .map(|url| Url::parse(url).unwrap())
+            .collect();
+
+        let (hints, failures) = maker.route_all(urls, None);
+
+        assert_eq!(hints.len(), 1);
+        assert_eq!(hints[0].0.as_str(), "https://example.com/");
+
+        assert_eq!(
+            failures.iter()
+                .map(|failure| (failure.url.as_str(), failure.error))
+                .collect::<Vec<_>>(),
+
+            vec![
+                ("file:///etc/passwd", SnapError::Rejected),
+                ("gopher://example.com/", SnapError::Rejected),
+                ("http://example.com:8080/", SnapError::Rejected),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_localized_cache_keys() {
+        let maker = SnapshotMakerBuilder::new(SnapperConfig::default()).build();
+
+        let key = |url: &str| maker.route(&Url::parse(url).unwrap(), Some("de"))
+            .unwrap()
+            .cache_key();
+
+        assert_eq!(key("https://youtu.be/jNQXAC9IVRw"), "jNQXAC9IVRw@de");
+        assert_eq!(key("https://news.example/a"), "https://news.example/a@de");
+
+        // this snapper ignores language
+        assert_eq!(key("https://www.bilibili.com/video/BV1a2b3c/"), "BV1a2b3c");
     }
 }
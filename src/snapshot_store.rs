@@ -0,0 +1,396 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration as StdDuration;
+use chrono::{DateTime, Duration, Utc};
+use futures::future::LocalBoxFuture;
+use log::{info, warn};
+use rusqlite::{Connection, OptionalExtension, params};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use crabo_model::Snapshot;
+
+/// Snapshots are kept this long by default.
+pub const DEFAULT_RETENTION_DAYS: i64 = 90;
+
+/// How often snapshots past retention are removed.
+const PRUNE_INTERVAL: StdDuration = StdDuration::from_secs(60 * 60);
+
+/// Maximum number of changes [SnapshotStore::changes] returns at once.
+pub const MAX_CHANGES: usize = 1000;
+
+/// Snapshot kept in [SnapshotStore].
+pub struct StoredSnapshot {
+    /// ID from cache hints of snapper that made snapshot.
+    pub id: String,
+
+    /// Provider of snapper that made snapshot, e.g. `youtube`.
+    pub provider: String,
+
+    pub snapshot: Snapshot,
+}
+
+/// Snapshot which content changed, as [SnapshotStore::changes] reports it.
+#[derive(Debug, Serialize)]
+pub struct SnapshotChange {
+    /// URL snapshot was made of.
+    pub url: String,
+
+    /// Provider of snapper that made snapshot, e.g. `youtube`.
+    pub provider: String,
+
+    /// When snapshot with different content was stored.
+    pub changed_at: DateTime<Utc>,
+}
+
+/// Returns hash of what `snapshot` shows, which is the same for
+/// snapshots of unchanged page made at different times.
+pub fn content_hash(snapshot: &Snapshot) -> String {
+    let content = Snapshot {
+        fetched_at: None,
+        cache: None,
+        ..snapshot.clone()
+    };
+
+    hex::encode(Sha256::digest(serde_json::to_vec(&content).unwrap()))
+}
+
+/// Defines interface for persistent storage of snapshots, which keeps
+/// them beyond expiry of Proxydon cache and across restarts. Snapshots
+/// are looked up there when cache does not have them.
+///
+/// Storage failures are logged rather than returned, snapshots are made
+/// with or without storage.
+pub trait SnapshotStore: Send + Sync {
+    /// Returns snapshots stored for `ids`, missing ones are skipped.
+    fn get<'a>(&'a self, ids: Vec<String>) -> LocalBoxFuture<'a, Vec<StoredSnapshot>>;
+
+    /// Stores `snapshots`, replacing ones stored earlier for the same IDs.
+    fn put<'a>(&'a self, snapshots: Vec<StoredSnapshot>) -> LocalBoxFuture<'a, ()>;
+
+    /// Removes snapshots fetched before `fetched_before`.
+    /// Returns number of snapshots removed.
+    fn prune<'a>(&'a self, fetched_before: DateTime<Utc>) -> LocalBoxFuture<'a, usize>;
+
+    /// Returns up to `limit` snapshots which content changed at or after
+    /// `since`, the earliest changes first, so indexers could resync only
+    /// what moved. Snapshot stored for the first time counts as changed.
+    /// Changes stored at once share time, so next page starts at time of
+    /// the last change returned, repeating it rather than missing any.
+    fn changes<'a>(
+        &'a self,
+        since: DateTime<Utc>,
+        limit: usize,
+    ) -> LocalBoxFuture<'a, Vec<SnapshotChange>>;
+}
+
+/// [SnapshotStore] in SQLite database. Besides JSON of snapshot, table
+/// has columns analytics queries are likely to filter by, e.g.
+/// ```sql
+/// SELECT provider, avg(quality) FROM snapshots GROUP BY provider;
+/// ```
+pub struct SqliteStore {
+    connection: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStore {
+    /// This function opens SQLite database at `path`, creating it if
+    /// there is none. `:memory:` opens database that is never saved.
+    pub fn open(path: &str) -> Result<Self, String> {
+        let connection = Connection::open(path)
+            .map_err(|err| format!("Failed to open {path}: {err:?}"))?;
+
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS snapshots (
+                id TEXT PRIMARY KEY,
+                provider TEXT NOT NULL,
+                url TEXT NOT NULL,
+                fetched_at TEXT NOT NULL,
+                quality INTEGER,
+                schema_version INTEGER NOT NULL,
+                snapshot TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS snapshots_fetched_at
+                ON snapshots (fetched_at);"
+        ).map_err(|err| format!("Failed to create tables in {path}: {err:?}"))?;
+
+        Self::add_change_columns(&connection)
+            .map_err(|err| format!("Failed to migrate tables in {path}: {err:?}"))?;
+
+        Ok(
+            Self {
+                connection: Arc::new(Mutex::new(connection)),
+            }
+        )
+    }
+
+    /// Helper function to add columns changes are tracked with to tables
+    /// created before changes were tracked.
+    fn add_change_columns(connection: &Connection) -> rusqlite::Result<()> {
+        let columns = connection
+            .prepare("SELECT name FROM pragma_table_info('snapshots')")?
+            .query_map([], |row| row.get::<_, String>(0))?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+
+        if !columns.iter().any(|column| column == "content_hash") {
+            connection.execute_batch(
+                "ALTER TABLE snapshots ADD COLUMN content_hash TEXT;
+                ALTER TABLE snapshots ADD COLUMN changed_at TEXT;"
+            )?;
+        }
+
+        connection.execute_batch(
+            "CREATE INDEX IF NOT EXISTS snapshots_changed_at
+                ON snapshots (changed_at);"
+        )
+    }
+
+    /// Helper method to run `query` with connection on blocking thread,
+    /// so workers are not stalled by disk. Returns None if query failed.
+    async fn run<T, F>(&self, query: F) -> Option<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            query(&mut connection.lock().unwrap())
+        }).await;
+
+        match result {
+            Ok(Ok(value)) => Some(value),
+
+            Ok(Err(err)) => {
+                warn!("Snapshot store query failed: {err:?}");
+                None
+            }
+
+            Err(err) => {
+                warn!("Snapshot store query panicked: {err:?}");
+                None
+            }
+        }
+    }
+}
+
+impl SnapshotStore for SqliteStore {
+    fn get<'a>(&'a self, ids: Vec<String>) -> LocalBoxFuture<'a, Vec<StoredSnapshot>> {
+        Box::pin(async move {
+            if ids.is_empty() {
+                return vec![];
+            }
+
+            let rows = self.run(move |connection| {
+                let mut statement = connection.prepare_cached(
+                    "SELECT provider, snapshot FROM snapshots WHERE id = ?1"
+                )?;
+
+                let mut rows = vec![];
+
+                for id in ids {
+                    let row = statement
+                        .query_row(params![id], |row| Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, String>(1)?,
+                        )))
+                        .optional()?;
+
+                    if let Some((provider, json)) = row {
+                        rows.push((id, provider, json));
+                    }
+                }
+
+                Ok(rows)
+            }).await.unwrap_or_default();
+
+            rows.into_iter()
+                .filter_map(|(id, provider, json)| {
+                    match serde_json::from_str(&json) {
+                        Ok(snapshot) => Some(StoredSnapshot { id, provider, snapshot }),
+
+                        Err(err) => {
+                            warn!("Stored snapshot of {id} is malformed: {err:?}");
+                            None
+                        }
+                    }
+                })
+                .collect()
+        })
+    }
+
+    fn put<'a>(&'a self, snapshots: Vec<StoredSnapshot>) -> LocalBoxFuture<'a, ()> {
+        Box::pin(async move {
+            if snapshots.is_empty() {
+                return;
+            }
+
+            let now = Utc::now().to_rfc3339();
+
+            let rows: Vec<_> = snapshots.into_iter()
+                .map(|stored| (
+                    stored.id,
+                    stored.provider,
+                    stored.snapshot.url.to_string(),
+                    stored.snapshot.fetched_at.unwrap_or_else(Utc::now).to_rfc3339(),
+                    stored.snapshot.quality,
+                    stored.snapshot.schema_version,
+                    serde_json::to_string(&stored.snapshot).unwrap(),
+                    content_hash(&stored.snapshot),
+                ))
+                .collect();
+
+            self.run(move |connection| {
+                let transaction = connection.transaction()?;
+
+                {
+                    // refreshed snapshot that shows the same keeps time
+                    // of the latest change
+                    let mut statement = transaction.prepare_cached(
+                        "INSERT INTO snapshots
+                        (id, provider, url, fetched_at, quality, schema_version, snapshot,
+                            content_hash, changed_at)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+                        ON CONFLICT (id) DO UPDATE SET
+                            provider = excluded.provider,
+                            url = excluded.url,
+                            fetched_at = excluded.fetched_at,
+                            quality = excluded.quality,
+                            schema_version = excluded.schema_version,
+                            snapshot = excluded.snapshot,
+                            changed_at = CASE
+                                WHEN content_hash IS excluded.content_hash THEN changed_at
+                                ELSE excluded.changed_at
+                            END,
+                            content_hash = excluded.content_hash"
+                    )?;
+
+                    for row in rows {
+                        statement.execute(params![
+                            row.0, row.1, row.2, row.3, row.4, row.5, row.6, row.7, now
+                        ])?;
+                    }
+                }
+
+                transaction.commit()
+            }).await;
+        })
+    }
+
+    fn prune<'a>(&'a self, fetched_before: DateTime<Utc>) -> LocalBoxFuture<'a, usize> {
+        Box::pin(async move {
+            let fetched_before = fetched_before.to_rfc3339();
+
+            self.run(move |connection| connection.execute(
+                "DELETE FROM snapshots WHERE fetched_at < ?1",
+                params![fetched_before],
+            )).await.unwrap_or_default()
+        })
+    }
+
+    fn changes<'a>(
+        &'a self,
+        since: DateTime<Utc>,
+        limit: usize,
+    ) -> LocalBoxFuture<'a, Vec<SnapshotChange>> {
+        Box::pin(async move {
+            let since = since.to_rfc3339();
+            let limit = limit.min(MAX_CHANGES);
+
+            let rows = self.run(move |connection| {
+                connection
+                    .prepare_cached(
+                        "SELECT url, provider, changed_at FROM snapshots
+                        WHERE changed_at >= ?1
+                        ORDER BY changed_at
+                        LIMIT ?2"
+                    )?
+                    .query_map(params![since, limit], |row| Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                    )))?
+                    .collect::<rusqlite::Result<Vec<_>>>()
+            }).await.unwrap_or_default();
+
+            rows.into_iter()
+                .filter_map(|(url, provider, changed_at)| {
+                    let changed_at = DateTime::parse_from_rfc3339(&changed_at).ok()?;
+
+                    Some(SnapshotChange {
+                        url,
+                        provider,
+                        changed_at: changed_at.with_timezone(&Utc),
+                    })
+                })
+                .collect()
+        })
+    }
+}
+
+/// This function removes snapshots older than `retention` from `store`
+/// every hour, so database does not grow forever.
+pub async fn prune_periodically(store: Arc<dyn SnapshotStore>, retention: Duration) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let removed = store.prune(Utc::now() - retention).await;
+
+        if removed > 0 {
+            info!("Removed {removed} snapshots past retention from store");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, Utc};
+    use url::Url;
+    use crabo_model::Snapshot;
+    use crate::snapper::bare_snapshot;
+    use crate::snapshot_store::{SnapshotStore, SqliteStore, StoredSnapshot};
+
+    fn stored(id: &str, age: Duration) -> StoredSnapshot {
+        StoredSnapshot {
+            id: id.to_string(),
+            provider: "default".to_string(),
+
+            snapshot: Snapshot {
+                title: Some(id.to_string()),
+                fetched_at: Some(Utc::now() - age),
+                ..bare_snapshot(Url::parse("https://a.example/").unwrap())
+            },
+        }
+    }
+
+    #[actix_rt::test]
+    async fn test_sqlite_store() {
+        let store = SqliteStore::open(":memory:").unwrap();
+
+        store.put(vec![
+            stored("fresh", Duration::hours(1)),
+            stored("old", Duration::days(100)),
+        ]).await;
+
+        let found = store.get(vec!["fresh".into(), "missing".into()]).await;
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].snapshot.title.as_deref(), Some("fresh"));
+
+        assert_eq!(store.prune(Utc::now() - Duration::days(90)).await, 1);
+
+        // the same content fetched again is not a change
+        let since = Utc::now();
+        store.put(vec![stored("fresh", Duration::zero())]).await;
+        assert!(store.changes(since, 10).await.is_empty());
+
+        let mut changed = stored("fresh", Duration::zero());
+        changed.snapshot.description = Some("updated".to_string());
+        store.put(vec![changed]).await;
+
+        let changes = store.changes(since, 10).await;
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].url, "https://a.example/");
+        assert!(store.get(vec!["old".into()]).await.is_empty());
+        assert_eq!(store.get(vec!["fresh".into()]).await.len(), 1);
+    }
+}
@@ -0,0 +1,233 @@
+use futures::future::LocalBoxFuture;
+use log::warn;
+use serde::Deserialize;
+use url::Url;
+
+use crabo_model::{SnapError, Snapshot};
+
+use crate::inflight::set_stage;
+use crate::provider_headers::RequestHeaders;
+use crate::quality::{DataSource, with_quality};
+use crate::snapper::{
+    bare_snapshot,
+    CacheHints,
+    Clients,
+    Snapper,
+    SnapshotAndHints,
+};
+
+/// Icon of SoundCloud shown next to site name.
+const SOUNDCLOUD_ICON_URL: &str = "https://soundcloud.com/favicon.ico";
+
+/// Endpoint that describes tracks and playlists.
+const SOUNDCLOUD_OEMBED_URL: &str = "https://soundcloud.com/oembed";
+
+/// Prefix of cache hints IDs of SoundCloud.
+const ID_PREFIX: &str = "soundcloud:";
+
+/// First segments of paths that are pages of SoundCloud itself rather
+/// than of users.
+const RESERVED_PATHS: [&str; 19] = [
+    "charts", "discover", "feed", "imprint", "jobs", "messages", "mobile",
+    "notifications", "pages", "people", "pro", "search", "settings", "signin",
+    "stations", "stream", "tags", "upload", "you",
+];
+
+/// Second segments of paths that are tabs of user page.
+const USER_TABS: [&str; 10] = [
+    "albums", "comments", "followers", "following", "likes",
+    "popular-tracks", "reposts", "sets", "spotlight", "tracks",
+];
+
+/// Response of oEmbed endpoint, only fields snapshot is made of.
+#[derive(Deserialize)]
+struct OEmbed {
+    /// Title, e.g. `Track by Artist`.
+    title: Option<String>,
+
+    description: Option<String>,
+    author_name: Option<String>,
+    thumbnail_url: Option<Url>,
+}
+
+/// This function extracts permalink of track or playlist from `url`, e.g.
+/// `artist/track` or `artist/sets/playlist`. Returns None for pages of
+/// users and of SoundCloud itself.
+fn extract_permalink(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+
+    if host != "soundcloud.com" && host != "www.soundcloud.com" && host != "m.soundcloud.com" {
+        return None;
+    }
+
+    let segments: Vec<_> = url.path_segments()?
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| segment.to_lowercase())
+        .collect();
+
+    let is_permalink = match segments.as_slice() {
+        [user, track] => !USER_TABS.contains(&track.as_str()) && !RESERVED_PATHS.contains(&user.as_str()),
+        [user, sets, _] => sets == "sets" && !RESERVED_PATHS.contains(&user.as_str()),
+        _ => false,
+    };
+
+    match is_permalink {
+        true => Some(segments.join("/")),
+        false => None,
+    }
+}
+
+/// This function converts `oembed` of track or playlist with `permalink`
+/// into [Snapshot] of `url`.
+fn oembed_to_snapshot(url: Url, permalink: &str, oembed: OEmbed) -> Snapshot {
+    let preview_mime_type = oembed.thumbnail_url.as_ref()
+        .and_then(|x| mime_guess::from_path(x.path()).first())
+        .map(|m| m.to_string());
+
+    let snapshot = Snapshot {
+        title: oembed.title,
+        description: oembed.description.filter(|description| !description.trim().is_empty()),
+        author: oembed.author_name,
+        preview_url: oembed.thumbnail_url,
+        preview_mime_type,
+        source: Option::from("SoundCloud".to_string()),
+        site_name: Option::from("SoundCloud".to_string()),
+        icon_url: Url::parse(SOUNDCLOUD_ICON_URL).ok(),
+
+        canonical_url: Url::parse("https://soundcloud.com/")
+            .and_then(|base| base.join(permalink))
+            .ok(),
+
+        ..bare_snapshot(url)
+    };
+
+    with_quality(snapshot, DataSource::Embed)
+}
+
+/// This snapper describes SoundCloud tracks and playlists with public
+/// oEmbed endpoint, which needs no credentials.
+pub struct SoundCloudSnapper {
+    /// Headers sent to oEmbed endpoint along with requests.
+    request_headers: RequestHeaders,
+}
+
+impl SoundCloudSnapper {
+    /// Constructs new instance of [SoundCloudSnapper] that sends
+    /// `request_headers` along with requests.
+    pub fn new(request_headers: RequestHeaders) -> Self {
+        Self { request_headers }
+    }
+}
+
+impl Snapper for SoundCloudSnapper {
+    fn provider(&self) -> String {
+        "soundcloud".into()
+    }
+
+    fn cache_hints(&self, url: &Url) -> Option<CacheHints> {
+        extract_permalink(url)
+            .map(|permalink| CacheHints {
+                provider: self.provider(),
+                id: format!("{ID_PREFIX}{permalink}"),
+                language: None,
+            })
+    }
+
+    fn snap<'a>(
+        &'a self,
+        url: Url,
+        cache_hints: CacheHints,
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, SnapshotAndHints> {
+        Box::pin(async move {
+            let permalink = cache_hints.id
+                .strip_prefix(ID_PREFIX)
+                .unwrap_or_default();
+
+            let oembed_url = Url::parse_with_params(
+                SOUNDCLOUD_OEMBED_URL,
+                &[
+                    ("url", format!("https://soundcloud.com/{permalink}").as_str()),
+                    ("format", "json"),
+                ],
+            );
+
+            let Ok(oembed_url) = oembed_url else {
+                return SnapshotAndHints {
+                    snapshot: Err(SnapError::ParseFailed),
+                    hints: cache_hints,
+                };
+            };
+
+            set_stage("calling SoundCloud oEmbed");
+
+            let snapshot = match clients.page_client.get_json::<OEmbed>(
+                &oembed_url,
+                &self.request_headers.merged_with(&[]),
+            ).await {
+                Ok(oembed) => Ok(oembed_to_snapshot(url, permalink, oembed)),
+
+                Err(err) => {
+                    warn!("Failed to get oEmbed of SoundCloud '{permalink}': {err:?}");
+                    clients.provider_metrics.record_api_error("soundcloud");
+                    Err(err.snap_error())
+                }
+            };
+
+            SnapshotAndHints {
+                snapshot,
+                hints: cache_hints,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::soundcloud::{extract_permalink, OEmbed, oembed_to_snapshot};
+
+    #[test]
+    fn test_extract_permalink() {
+        let extract = |url: &str| extract_permalink(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            extract("https://m.soundcloud.com/Artist/Track?in=artist/sets/a").as_deref(),
+            Some("artist/track")
+        );
+
+        assert_eq!(
+            extract("https://soundcloud.com/artist/sets/playlist/").as_deref(),
+            Some("artist/sets/playlist")
+        );
+
+        assert_eq!(extract("https://soundcloud.com/artist"), None);
+        assert_eq!(extract("https://soundcloud.com/artist/likes"), None);
+        assert_eq!(extract("https://soundcloud.com/discover/sets"), None);
+        assert_eq!(extract("https://api.soundcloud.com/artist/track"), None);
+    }
+
+    #[test]
+    fn test_oembed_to_snapshot() {
+        let oembed: OEmbed = serde_json::from_str(r#"{
+            "version": 1.0,
+            "type": "rich",
+            "title": "Track by Artist",
+            "description": "",
+            "author_name": "Artist",
+            "thumbnail_url": "https://i1.sndcdn.com/artworks-000-t500x500.jpg"
+        }"#).unwrap();
+
+        let snapshot = oembed_to_snapshot(
+            Url::parse("https://m.soundcloud.com/artist/track").unwrap(),
+            "artist/track",
+            oembed,
+        );
+
+        assert_eq!(snapshot.title.as_deref(), Some("Track by Artist"));
+        assert_eq!(snapshot.author.as_deref(), Some("Artist"));
+        assert_eq!(snapshot.description, None);
+        assert_eq!(snapshot.preview_mime_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(snapshot.canonical_url.unwrap().as_str(), "https://soundcloud.com/artist/track");
+    }
+}
@@ -0,0 +1,517 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use futures::future::LocalBoxFuture;
+use itertools::Itertools;
+use log::{info, warn};
+use serde::Deserialize;
+use url::Url;
+
+use crabo_model::{SnapError, Snapshot};
+
+use crate::inflight::set_stage;
+use crate::provider_headers::RequestHeaders;
+use crate::quality::{DataSource, with_quality};
+use crate::snapper::{
+    bare_snapshot,
+    CacheHints,
+    Clients,
+    Snapper,
+    SnapshotAndHints,
+};
+
+/// Icon of Spotify shown next to site name.
+const SPOTIFY_ICON_URL: &str = "https://open.spotify.com/favicon.ico";
+
+/// Endpoint that describes tracks, albums and playlists without credentials.
+const SPOTIFY_OEMBED_URL: &str = "https://open.spotify.com/oembed";
+
+/// Base of Web API endpoints.
+const SPOTIFY_API_URL: &str = "https://api.spotify.com/v1/";
+
+/// Endpoint that issues access tokens of Web API.
+const SPOTIFY_TOKEN_URL: &str = "https://accounts.spotify.com/api/token";
+
+/// Time access token is requested within.
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Access token is renewed this long before it expires.
+const TOKEN_RENEWAL_MARGIN: Duration = Duration::from_secs(60);
+
+/// Covers are served without extension, they are always JPEG images.
+const COVER_MIME_TYPE: &str = "image/jpeg";
+
+/// Kinds of Spotify items snapper knows about.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ItemKind {
+    Track,
+    Album,
+    Playlist,
+}
+
+impl ItemKind {
+    /// Parses kind of item from its `name` found in URL or URI.
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "track" => Some(Self::Track),
+            "album" => Some(Self::Album),
+            "playlist" => Some(Self::Playlist),
+            _ => None,
+        }
+    }
+
+    /// Returns name of kind used in URLs and URIs.
+    fn name(self) -> &'static str {
+        match self {
+            Self::Track => "track",
+            Self::Album => "album",
+            Self::Playlist => "playlist",
+        }
+    }
+
+    /// Returns path of Web API endpoint of items of this kind, relative
+    /// to [SPOTIFY_API_URL].
+    fn endpoint(self) -> &'static str {
+        match self {
+            Self::Track => "tracks/",
+            Self::Album => "albums/",
+            Self::Playlist => "playlists/",
+        }
+    }
+}
+
+/// This function extracts kind and ID of item from `url` of Spotify
+/// page, e.g. `https://open.spotify.com/intl-de/track/4uLU6hMCjMI75M1A2tKUQC`.
+fn extract_item(url: &Url) -> Option<(ItemKind, String)> {
+    if url.host_str()? != "open.spotify.com" {
+        return None;
+    }
+
+    // localized pages have prefix, e.g. `intl-de`
+    let mut segments = url.path_segments()?
+        .skip_while(|segment| segment.starts_with("intl-"));
+
+    let kind = ItemKind::parse(segments.next()?)?;
+    let id = segments.next()?;
+
+    match !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric()) {
+        true => Some((kind, id.to_string())),
+        false => None,
+    }
+}
+
+/// Returns Spotify URI of item with `kind` and `id`, which is its cache
+/// hints ID as well, e.g. `spotify:track:4uLU6hMCjMI75M1A2tKUQC`.
+fn item_uri(kind: ItemKind, id: &str) -> String {
+    format!("spotify:{}:{id}", kind.name())
+}
+
+/// Parses kind and ID of item from its Spotify `uri`.
+fn parse_item_uri(uri: &str) -> Option<(ItemKind, &str)> {
+    let mut parts = uri.strip_prefix("spotify:")?.splitn(2, ':');
+    let kind = ItemKind::parse(parts.next()?)?;
+    Some((kind, parts.next()?))
+}
+
+/// Response of oEmbed endpoint, only fields snapshot is made of.
+#[derive(Deserialize)]
+struct OEmbed {
+    title: Option<String>,
+    thumbnail_url: Option<Url>,
+    thumbnail_width: Option<u32>,
+    thumbnail_height: Option<u32>,
+}
+
+/// Response of token endpoint.
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+
+    /// Lifetime of token in seconds.
+    expires_in: u64,
+}
+
+/// Access token of Web API along with time it expires at.
+struct AccessToken {
+    value: String,
+    expires_at: Instant,
+}
+
+/// Artist of track or album.
+#[derive(Deserialize)]
+struct Artist {
+    name: Option<String>,
+}
+
+/// Owner of playlist.
+#[derive(Deserialize)]
+struct Owner {
+    display_name: Option<String>,
+}
+
+/// Cover image of album or playlist.
+#[derive(Deserialize)]
+struct Image {
+    url: Option<Url>,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+/// Album track belongs to.
+#[derive(Deserialize)]
+struct Album {
+    #[serde(default)]
+    images: Vec<Image>,
+}
+
+/// A very simplified version of track, album or playlist of Web API,
+/// fields of other kinds are missing.
+#[derive(Deserialize)]
+struct Item {
+    name: Option<String>,
+
+    /// Description of playlist.
+    description: Option<String>,
+
+    /// Artists of track or album.
+    #[serde(default)]
+    artists: Vec<Artist>,
+
+    /// Owner of playlist.
+    owner: Option<Owner>,
+
+    /// Covers of album or playlist, the widest first. Playlists without
+    /// cover have it null.
+    images: Option<Vec<Image>>,
+
+    /// Album of track.
+    album: Option<Album>,
+
+    /// Duration of track in milliseconds.
+    duration_ms: Option<u64>,
+}
+
+/// Returns canonical address of page of item with `kind` and `id`.
+fn item_url(kind: ItemKind, id: &str) -> Option<Url> {
+    Url::parse("https://open.spotify.com/")
+        .and_then(|base| base.join(&format!("{}/{id}", kind.name())))
+        .ok()
+}
+
+/// This function converts `item` of Web API with `kind` and `id` into
+/// [Snapshot] of `url`. Artists of track or album, or owner of playlist,
+/// are its source.
+fn item_to_snapshot(url: Url, kind: ItemKind, id: &str, item: Item) -> Snapshot {
+    let artist = match item.owner {
+        Some(owner) => owner.display_name,
+
+        None => Some(
+            item.artists.into_iter()
+                .filter_map(|artist| artist.name)
+                .join(", ")
+        ).filter(|artists| !artists.is_empty()),
+    };
+
+    let cover = item.images.into_iter()
+        .flatten()
+        .chain(item.album.into_iter().flat_map(|album| album.images))
+        .find(|image| image.url.is_some());
+
+    let snapshot = Snapshot {
+        title: item.name,
+        description: item.description.filter(|description| !description.is_empty()),
+        author: artist.clone(),
+        source: artist,
+        site_name: Option::from("Spotify".to_string()),
+        icon_url: Url::parse(SPOTIFY_ICON_URL).ok(),
+        preview_mime_type: cover.as_ref().map(|_| COVER_MIME_TYPE.to_string()),
+        preview_width: cover.as_ref().and_then(|cover| cover.width),
+        preview_height: cover.as_ref().and_then(|cover| cover.height),
+        preview_url: cover.and_then(|cover| cover.url),
+        duration_seconds: item.duration_ms.map(|ms| ms / 1000).filter(|seconds| *seconds > 0),
+        canonical_url: item_url(kind, id),
+        ..bare_snapshot(url)
+    };
+
+    with_quality(snapshot, DataSource::Api)
+}
+
+/// This function converts `oembed` of item with `kind` and `id` into
+/// degraded [Snapshot] of `url`, which has only title and cover.
+fn oembed_to_snapshot(url: Url, kind: ItemKind, id: &str, oembed: OEmbed) -> Snapshot {
+    let snapshot = Snapshot {
+        title: oembed.title,
+        source: Option::from("Spotify".to_string()),
+        site_name: Option::from("Spotify".to_string()),
+        icon_url: Url::parse(SPOTIFY_ICON_URL).ok(),
+        preview_mime_type: oembed.thumbnail_url.as_ref().map(|_| COVER_MIME_TYPE.to_string()),
+        preview_url: oembed.thumbnail_url,
+        preview_width: oembed.thumbnail_width,
+        preview_height: oembed.thumbnail_height,
+        canonical_url: item_url(kind, id),
+        ..bare_snapshot(url)
+    };
+
+    with_quality(snapshot, DataSource::Embed)
+}
+
+/// This snapper describes Spotify tracks, albums and playlists, as their
+/// pages are heavy and often refuse to serve plain scrapers.
+///
+/// Web API is used if client credentials are set, as it tells artists.
+/// Otherwise, or if API fails, oEmbed that needs no credentials is used.
+pub struct SpotifySnapper {
+    /// Client ID and secret of Web API application, if set.
+    credentials: Option<(String, String)>,
+
+    /// Headers sent to APIs along with requests.
+    request_headers: RequestHeaders,
+
+    /// Access token of Web API, if there is one yet.
+    token: Mutex<Option<AccessToken>>,
+}
+
+impl SpotifySnapper {
+    /// Constructs new instance of [SpotifySnapper] that uses Web API with
+    /// `credentials`, which are client ID and secret, if they are set.
+    /// `request_headers` are sent along with API requests.
+    pub fn new(
+        credentials: Option<(String, String)>,
+        request_headers: RequestHeaders,
+    ) -> Self {
+        Self {
+            credentials,
+            request_headers,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// This method returns access token of Web API, requesting new one
+    /// if there is none or it is about to expire.
+    /// Returns None if credentials are not set or token is not issued.
+    async fn access_token(&self) -> Option<String> {
+        let (client_id, client_secret) = self.credentials.as_ref()?;
+
+        if let Some(token) = self.token.lock().unwrap().as_ref() {
+            if token.expires_at > Instant::now() + TOKEN_RENEWAL_MARGIN {
+                return Some(token.value.clone());
+            }
+        }
+
+        set_stage("requesting Spotify token");
+
+        let client = awc::Client::builder()
+            .timeout(TOKEN_TIMEOUT)
+            .finish();
+
+        let authorization = BASE64.encode(format!("{client_id}:{client_secret}"));
+
+        let response = client.post(SPOTIFY_TOKEN_URL)
+            .insert_header(("Authorization", format!("Basic {authorization}")))
+            .send_form(&[("grant_type", "client_credentials")])
+            .await;
+
+        let token = match response {
+            Ok(mut response) if response.status().is_success() => {
+                response.json::<TokenResponse>().await
+                    .map_err(|err| warn!("Malformed Spotify token: {err}"))
+                    .ok()?
+            }
+
+            Ok(response) => {
+                warn!("Spotify refused to issue token: {}", response.status());
+                return None;
+            }
+
+            Err(err) => {
+                warn!("Failed to request Spotify token: {err}");
+                return None;
+            }
+        };
+
+        *self.token.lock().unwrap() = Some(AccessToken {
+            value: token.access_token.clone(),
+            expires_at: Instant::now() + Duration::from_secs(token.expires_in),
+        });
+
+        Some(token.access_token)
+    }
+
+    /// This method describes item with `kind` and `id` with Web API.
+    /// Returns None if API is not configured or fails.
+    async fn get_item(&self, kind: ItemKind, id: &str, clients: &Clients) -> Option<Item> {
+        let token = self.access_token().await?;
+
+        let api_url = Url::parse(SPOTIFY_API_URL)
+            .and_then(|base| base.join(kind.endpoint()))
+            .and_then(|endpoint| endpoint.join(id))
+            .ok()?;
+
+        let authorization = format!("Bearer {token}");
+
+        set_stage("calling Spotify API");
+
+        match clients.page_client.get_json::<Item>(
+            &api_url,
+            &self.request_headers.merged_with(&[("Authorization", &authorization)]),
+        ).await {
+            Ok(item) => Some(item),
+
+            Err(err) => {
+                warn!("Failed to get details for Spotify '{id}': {err:?}");
+                clients.provider_metrics.record_api_error("spotify");
+                None
+            }
+        }
+    }
+
+    /// This method makes degraded snapshot of `url` from oEmbed of item
+    /// with `kind` and `id`.
+    async fn snap_oembed(
+        &self,
+        url: Url,
+        kind: ItemKind,
+        id: &str,
+        clients: &Clients,
+    ) -> Result<Snapshot, SnapError> {
+        let item_url = item_url(kind, id).ok_or(SnapError::ParseFailed)?;
+
+        let oembed_url = Url::parse_with_params(
+            SPOTIFY_OEMBED_URL,
+            &[("url", item_url.as_str())],
+        ).map_err(|_| SnapError::ParseFailed)?;
+
+        set_stage("calling Spotify oEmbed");
+
+        match clients.page_client.get_json::<OEmbed>(
+            &oembed_url,
+            &self.request_headers.merged_with(&[]),
+        ).await {
+            Ok(oembed) => Ok(oembed_to_snapshot(url, kind, id, oembed)),
+
+            Err(err) => {
+                warn!("Failed to get oEmbed of Spotify '{id}': {err:?}");
+                Err(err.snap_error())
+            }
+        }
+    }
+}
+
+impl Snapper for SpotifySnapper {
+    fn provider(&self) -> String {
+        "spotify".into()
+    }
+
+    fn cache_hints(&self, url: &Url) -> Option<CacheHints> {
+        extract_item(url)
+            .map(|(kind, id)| CacheHints {
+                provider: self.provider(),
+                id: item_uri(kind, &id),
+                language: None,
+            })
+    }
+
+    fn snap<'a>(
+        &'a self,
+        url: Url,
+        cache_hints: CacheHints,
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, SnapshotAndHints> {
+        Box::pin(async move {
+            let Some((kind, id)) = parse_item_uri(&cache_hints.id) else {
+                return SnapshotAndHints {
+                    snapshot: Err(SnapError::NotFound),
+                    hints: cache_hints,
+                };
+            };
+
+            let snapshot = match self.get_item(kind, id, clients).await {
+                Some(item) => Ok(item_to_snapshot(url, kind, id, item)),
+
+                None => {
+                    info!("{url}: Spotify API is unavailable, trying oEmbed");
+                    self.snap_oembed(url, kind, id, clients).await
+                }
+            };
+
+            SnapshotAndHints {
+                snapshot,
+                hints: cache_hints,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::spotify::{
+        extract_item,
+        item_to_snapshot,
+        item_uri,
+        Item,
+        ItemKind,
+        OEmbed,
+        oembed_to_snapshot,
+        parse_item_uri,
+    };
+
+    #[test]
+    fn test_extract_item() {
+        let extract = |url: &str| extract_item(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            extract("https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC?si=abc"),
+            Some((ItemKind::Track, "4uLU6hMCjMI75M1A2tKUQC".to_string()))
+        );
+
+        assert_eq!(
+            extract("https://open.spotify.com/intl-de/album/1DFixLWuPkv3KT3TnV35m3"),
+            Some((ItemKind::Album, "1DFixLWuPkv3KT3TnV35m3".to_string()))
+        );
+
+        assert_eq!(extract("https://open.spotify.com/artist/0OdUWJ0sBjDrqHygGUXeCF"), None);
+        assert_eq!(extract("https://spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"), None);
+
+        let uri = item_uri(ItemKind::Playlist, "37i9dQZF1DXcBWIGoYBM5M");
+        assert_eq!(uri, "spotify:playlist:37i9dQZF1DXcBWIGoYBM5M");
+        assert_eq!(parse_item_uri(&uri), Some((ItemKind::Playlist, "37i9dQZF1DXcBWIGoYBM5M")));
+    }
+
+    #[test]
+    fn test_item_to_snapshot() {
+        let item: Item = serde_json::from_str(r#"{
+            "name": "Song",
+            "artists": [{"name": "First"}, {"name": "Second"}],
+            "album": {"images": [{"url": "https://i.scdn.co/image/ab67", "width": 640, "height": 640}]},
+            "duration_ms": 201500
+        }"#).unwrap();
+
+        let url = Url::parse("https://open.spotify.com/intl-de/track/4uLU6hMCjMI75M1A2tKUQC").unwrap();
+        let snapshot = item_to_snapshot(url.clone(), ItemKind::Track, "4uLU6hMCjMI75M1A2tKUQC", item);
+
+        assert_eq!(snapshot.title.as_deref(), Some("Song"));
+        assert_eq!(snapshot.source.as_deref(), Some("First, Second"));
+        assert_eq!(snapshot.preview_url.unwrap().as_str(), "https://i.scdn.co/image/ab67");
+        assert_eq!(snapshot.preview_mime_type.as_deref(), Some("image/jpeg"));
+        assert_eq!(snapshot.duration_seconds, Some(201));
+
+        assert_eq!(
+            snapshot.canonical_url.unwrap().as_str(),
+            "https://open.spotify.com/track/4uLU6hMCjMI75M1A2tKUQC"
+        );
+
+        let oembed: OEmbed = serde_json::from_str(r#"{
+            "title": "Playlist",
+            "thumbnail_url": "https://image-cdn-fa.spotifycdn.com/image/ab67",
+            "thumbnail_width": 300,
+            "thumbnail_height": 300
+        }"#).unwrap();
+
+        let snapshot = oembed_to_snapshot(url, ItemKind::Playlist, "37i9dQZF1DXcBWIGoYBM5M", oembed);
+        assert_eq!(snapshot.title.as_deref(), Some("Playlist"));
+        assert_eq!(snapshot.source.as_deref(), Some("Spotify"));
+        assert_eq!(snapshot.preview_width, Some(300));
+    }
+}
@@ -0,0 +1,338 @@
+use std::num::NonZeroUsize;
+use std::ops::RangeInclusive;
+use std::sync::Mutex;
+use actix_web::http::StatusCode;
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use lru::LruCache;
+use serde::Serialize;
+use crate::domain_list::DomainList;
+
+/// Number of consecutive failures after which server is suppressed.
+const DEFAULT_MAX_FAILURES: u32 = 3;
+
+/// Minutes no requests are made to suppressed server by default.
+const DEFAULT_SUPPRESSION_MINUTES: i64 = 60;
+
+/// Status codes that count as failures by default.
+const DEFAULT_FAILURE_STATUSES: &str = "5xx,429";
+
+/// Number of servers failure state is kept for.
+const TRACKED_HOSTS: usize = 4096;
+
+/// Failure state of a single server.
+#[derive(Clone, Default)]
+struct HostState {
+    /// Number of consecutive failed requests.
+    failures: u32,
+
+    /// If set, no requests to server are made until then.
+    suppressed_until: Option<DateTime<Utc>>,
+
+    /// Why the latest request failed.
+    last_failure: Option<String>,
+}
+
+/// Server that is suppressed at the moment.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct SuppressedHost {
+    /// Host name of server.
+    pub host: String,
+
+    /// Why the latest request before suppression failed.
+    pub reason: Option<String>,
+
+    /// No requests to server are made until then.
+    pub until: DateTime<Utc>,
+}
+
+/// This function parses status codes separated by comma from `text`,
+/// either exact ones, e.g. `429`, or whole classes, e.g. `5xx`.
+fn parse_statuses(text: &str) -> Vec<RangeInclusive<u16>> {
+    text.split(',')
+        .map(|status| status.trim().to_lowercase())
+        .filter(|status| !status.is_empty())
+        .filter_map(|status| {
+            let range = match status.strip_suffix("xx") {
+                Some(class) => class.parse::<u16>()
+                    .ok()
+                    .filter(|class| (1..=5).contains(class))
+                    .map(|class| class * 100..=class * 100 + 99),
+
+                None => status.parse::<u16>()
+                    .ok()
+                    .filter(|code| StatusCode::from_u16(*code).is_ok())
+                    .map(|code| code..=code),
+            };
+
+            if range.is_none() {
+                warn!("Ignoring invalid status code '{status}'");
+            }
+
+            range
+        })
+        .collect()
+}
+
+/// Settings of [HostSuppressor].
+#[derive(Clone, Debug)]
+pub struct SuppressionConfig {
+    /// Number of consecutive failures after which server is suppressed.
+    pub max_failures: u32,
+
+    /// Time no requests are made to suppressed server.
+    pub duration: Duration,
+
+    /// Status codes that count as failures, failed connections always do.
+    pub failure_statuses: Vec<RangeInclusive<u16>>,
+
+    /// Servers that are never suppressed, e.g. flaky but important ones.
+    pub exempt_hosts: DomainList,
+}
+
+impl Default for SuppressionConfig {
+    fn default() -> Self {
+        Self {
+            max_failures: DEFAULT_MAX_FAILURES,
+            duration: Duration::minutes(DEFAULT_SUPPRESSION_MINUTES),
+            failure_statuses: parse_statuses(DEFAULT_FAILURE_STATUSES),
+            exempt_hosts: DomainList::default(),
+        }
+    }
+}
+
+impl SuppressionConfig {
+    /// Constructs new instance of [SuppressionConfig], defaults are used
+    /// for values that are not set.
+    /// `max_failures` is number of consecutive failures, `minutes` is
+    /// duration of suppression, `failure_statuses` lists status codes
+    /// separated by comma, e.g. `5xx,429`. Servers matching
+    /// `exempt_hosts` are never suppressed.
+    pub fn new(
+        max_failures: Option<&str>,
+        minutes: Option<&str>,
+        failure_statuses: Option<&str>,
+        exempt_hosts: DomainList,
+    ) -> Self {
+        let defaults = Self::default();
+
+        let parse_positive = |name: &str, text: &str| {
+            let value = text.trim().parse::<u32>().ok().filter(|x| *x > 0);
+
+            if value.is_none() {
+                warn!("Ignoring invalid {name} '{text}'");
+            }
+
+            value
+        };
+
+        Self {
+            max_failures: max_failures
+                .and_then(|text| parse_positive("number of failures", text))
+                .unwrap_or(defaults.max_failures),
+
+            duration: minutes
+                .and_then(|text| parse_positive("suppression duration", text))
+                .map(|minutes| Duration::minutes(minutes.into()))
+                .unwrap_or(defaults.duration),
+
+            failure_statuses: failure_statuses
+                .map(parse_statuses)
+                .unwrap_or(defaults.failure_statuses),
+
+            exempt_hosts,
+        }
+    }
+}
+
+/// This struct keeps track of servers that fail requests and suppresses
+/// further requests to them for a while, so dead or overloaded servers
+/// are not hammered and do not slow down snapshotting.
+pub struct HostSuppressor {
+    hosts: Mutex<LruCache<String, HostState>>,
+    config: SuppressionConfig,
+}
+
+impl Default for HostSuppressor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl HostSuppressor {
+    /// Constructs new instance of [HostSuppressor] with default settings.
+    pub fn new() -> Self {
+        Self::with_config(SuppressionConfig::default())
+    }
+
+    /// Constructs new instance of [HostSuppressor] with `config`.
+    pub fn with_config(config: SuppressionConfig) -> Self {
+        Self {
+            hosts: Mutex::new(
+                LruCache::new(NonZeroUsize::new(TRACKED_HOSTS).unwrap())
+            ),
+            config,
+        }
+    }
+
+    /// Returns true if response with `status` counts as failure of server.
+    pub fn is_failure_status(&self, status: StatusCode) -> bool {
+        self.config.failure_statuses.iter()
+            .any(|statuses| statuses.contains(&status.as_u16()))
+    }
+
+    /// Returns true if requests to `host` should not be made.
+    pub fn is_suppressed(&self, host: &str) -> bool {
+        let mut hosts = self.hosts.lock().unwrap();
+
+        match hosts.get(host).and_then(|state| state.suppressed_until) {
+            Some(until) => until > Utc::now(),
+            None => false,
+        }
+    }
+
+    /// Records successful request to `host`, which resets its failures.
+    pub fn report_success(&self, host: &str) {
+        self.hosts.lock()
+            .unwrap()
+            .pop(host);
+    }
+
+    /// Records request to `host` that failed for `reason`.
+    /// If it failed too many times in a row, it gets suppressed, unless
+    /// it is exempt.
+    pub fn report_failure(&self, host: &str, reason: &str) {
+        if self.config.exempt_hosts.matches(host) {
+            return;
+        }
+
+        let mut hosts = self.hosts.lock().unwrap();
+
+        let state = hosts.get_or_insert_mut(
+            host.to_string(),
+            HostState::default,
+        );
+
+        state.failures += 1;
+        state.last_failure = Some(reason.to_string());
+
+        if state.failures >= self.config.max_failures {
+            warn!(
+                "Server {host} failed {} times in a row, suppressing it \
+                for {} minutes",
+                state.failures,
+                self.config.duration.num_minutes(),
+            );
+
+            state.failures = 0;
+            state.suppressed_until = Some(Utc::now() + self.config.duration);
+        }
+    }
+
+    /// Returns servers that are suppressed at the moment.
+    pub fn suppressed_hosts(&self) -> Vec<SuppressedHost> {
+        let now = Utc::now();
+
+        self.hosts.lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(host, state)| Some(
+                SuppressedHost {
+                    host: host.clone(),
+                    reason: state.last_failure.clone(),
+                    until: state.suppressed_until.filter(|until| *until > now)?,
+                }
+            ))
+            .collect()
+    }
+
+    /// Lifts suppression of `host`, e.g. once operator knows it is back.
+    /// Returns false if `host` was not suppressed.
+    pub fn unsuppress(&self, host: &str) -> bool {
+        if !self.is_suppressed(host) {
+            return false;
+        }
+
+        info!("Server {host} is not suppressed anymore");
+
+        self.hosts.lock()
+            .unwrap()
+            .pop(host);
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use crate::domain_list::DomainList;
+    use crate::suppression::{HostSuppressor, SuppressionConfig};
+
+    #[test]
+    fn test_suppression() {
+        let suppressor = HostSuppressor::new();
+
+        suppressor.report_failure("a.example", "503");
+        suppressor.report_failure("a.example", "503");
+        assert!(!suppressor.is_suppressed("a.example"));
+
+        suppressor.report_failure("a.example", "503");
+        assert!(suppressor.is_suppressed("a.example"));
+        assert!(!suppressor.is_suppressed("b.example"));
+
+        suppressor.report_success("a.example");
+        assert!(!suppressor.is_suppressed("a.example"));
+    }
+
+    #[test]
+    fn test_unsuppress() {
+        let suppressor = HostSuppressor::new();
+
+        for _ in 0..3 {
+            suppressor.report_failure("a.example", "connection refused");
+        }
+
+        suppressor.report_failure("b.example", "timeout");
+
+        let suppressed = suppressor.suppressed_hosts();
+        assert_eq!(suppressed.len(), 1);
+        assert_eq!(suppressed[0].host, "a.example");
+        assert_eq!(suppressed[0].reason.as_deref(), Some("connection refused"));
+
+        assert!(!suppressor.unsuppress("b.example"));
+        assert!(suppressor.unsuppress("a.example"));
+        assert!(!suppressor.is_suppressed("a.example"));
+        assert!(suppressor.suppressed_hosts().is_empty());
+    }
+
+    #[test]
+    fn test_config() {
+        let suppressor = HostSuppressor::with_config(
+            SuppressionConfig::new(
+                Some("1"),
+                Some("x"),
+                Some("502, 503,4xx,6xx"),
+                DomainList::parse("important.example"),
+            )
+        );
+
+        assert!(suppressor.is_failure_status(StatusCode::BAD_GATEWAY));
+        assert!(suppressor.is_failure_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!suppressor.is_failure_status(StatusCode::INTERNAL_SERVER_ERROR));
+
+        suppressor.report_failure("api.important.example", "503");
+        assert!(!suppressor.is_suppressed("api.important.example"));
+
+        suppressor.report_failure("a.example", "503");
+        assert!(suppressor.is_suppressed("a.example"));
+
+        // invalid duration is ignored
+        assert_eq!(suppressor.config.duration, chrono::Duration::minutes(60));
+
+        let default = HostSuppressor::new();
+        assert!(default.is_failure_status(StatusCode::SERVICE_UNAVAILABLE));
+        assert!(default.is_failure_status(StatusCode::TOO_MANY_REQUESTS));
+        assert!(!default.is_failure_status(StatusCode::NOT_FOUND));
+    }
+}
@@ -0,0 +1,213 @@
+use itertools::Itertools;
+use percent_encoding::percent_decode_str;
+use url::Url;
+
+/// Number of places passage could start at that are checked, so pages
+/// full of repeated text do not stall parsing.
+const MAX_START_CANDIDATES: usize = 10_000;
+
+/// Text fragment directive of URL, e.g. `#:~:text=prefix-,start,end,-suffix`,
+/// which points to passage on page rather than to element.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TextFragment {
+    /// Text right before passage.
+    prefix: Option<String>,
+
+    /// Passage itself or its beginning if `end` is set.
+    start: String,
+
+    /// End of passage.
+    end: Option<String>,
+
+    /// Text right after passage.
+    suffix: Option<String>,
+}
+
+/// Helper function to decode `part` of directive with whitespace
+/// normalized.
+fn decode(part: &str) -> Option<String> {
+    let decoded = percent_decode_str(part).decode_utf8().ok()?;
+    Some(decoded.split_whitespace().join(" "))
+}
+
+/// Returns `text` in lower case. Characters which lower case form is of
+/// different length are kept as is, so byte offsets of both match.
+fn fold_case(text: &str) -> String {
+    text.chars()
+        .map(|c| {
+            let mut lower = c.to_lowercase();
+
+            match (lower.next(), lower.next()) {
+                (Some(l), None) if l.len_utf8() == c.len_utf8() => l,
+                _ => c,
+            }
+        })
+        .collect()
+}
+
+impl TextFragment {
+    /// Parses the first text directive of `url` fragment, if there is one.
+    pub fn from_url(url: &Url) -> Option<Self> {
+        let (_, directives) = url.fragment()?.split_once(":~:")?;
+
+        let directive = directives.split('&')
+            .find_map(|directive| directive.strip_prefix("text="))?;
+
+        // dashes that mark prefix and suffix are never encoded,
+        // dashes of text itself are
+        let mut parts: Vec<_> = directive.split(',').collect();
+
+        let prefix = match parts.first() {
+            Some(part) if parts.len() > 1 && part.ends_with('-') => {
+                Some(decode(part.trim_end_matches('-'))?)
+            }
+
+            _ => None,
+        };
+
+        if prefix.is_some() {
+            parts.remove(0);
+        }
+
+        let suffix = match parts.last() {
+            Some(part) if parts.len() > 1 && part.starts_with('-') => {
+                Some(decode(part.trim_start_matches('-'))?)
+            }
+
+            _ => None,
+        };
+
+        if suffix.is_some() {
+            parts.pop();
+        }
+
+        let (start, end) = match parts.as_slice() {
+            [start] => (decode(start)?, None),
+            [start, end] => (decode(start)?, Some(decode(end)?)),
+            _ => return None,
+        };
+
+        if start.is_empty() {
+            return None;
+        }
+
+        Some(
+            Self {
+                prefix: prefix.filter(|prefix| !prefix.is_empty()),
+                start,
+                end: end.filter(|end| !end.is_empty()),
+                suffix: suffix.filter(|suffix| !suffix.is_empty()),
+            }
+        )
+    }
+
+    /// Returns the first passage of `text` this fragment points to,
+    /// whitespace normalized. Text is matched ignoring case, but word
+    /// boundaries are not checked. Only the first
+    /// [MAX_START_CANDIDATES] places passage could start at are checked.
+    pub fn locate(&self, text: &str) -> Option<String> {
+        let text = text.split_whitespace().join(" ");
+
+        // byte offsets are the same in both
+        let folded = fold_case(&text);
+        let start_needle = fold_case(&self.start);
+        let end_needle = self.end.as_deref().map(fold_case);
+        let prefix = self.prefix.as_deref().map(fold_case);
+        let suffix = self.suffix.as_deref().map(fold_case);
+
+        // the nearest end after one start is the nearest after the next
+        // ones too, until they pass it
+        let mut end_match: Option<(usize, usize)> = None;
+        let mut from = 0;
+
+        for _ in 0..MAX_START_CANDIDATES {
+            let start = from + folded[from..].find(&start_needle)?;
+            let start_end = start + start_needle.len();
+
+            // next candidate starts at the next character
+            from = start + folded[start..].chars().next().map_or(1, |c| c.len_utf8());
+
+            let end = match &end_needle {
+                Some(end_needle) => {
+                    if end_match.is_none_or(|(end_start, _)| end_start < start_end) {
+                        let end_start = start_end + folded[start_end..].find(end_needle.as_str())?;
+                        end_match = Some((end_start, end_start + end_needle.len()));
+                    }
+
+                    end_match?.1
+                }
+
+                None => start_end,
+            };
+
+            let has_prefix = prefix.as_ref()
+                .is_none_or(|prefix| folded[..start].trim_end().ends_with(prefix.as_str()));
+
+            let has_suffix = suffix.as_ref()
+                .is_none_or(|suffix| folded[end..].trim_start().starts_with(suffix.as_str()));
+
+            if has_prefix && has_suffix {
+                return Some(text[start..end].to_string());
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::text_fragment::TextFragment;
+
+    fn fragment(url: &str) -> Option<TextFragment> {
+        TextFragment::from_url(&Url::parse(url).unwrap())
+    }
+
+    #[test]
+    fn test_from_url() {
+        let parsed = fragment(
+            "https://a.example/#:~:text=the%20-,quick%2Dbrown,fox,-jumps&text=other"
+        ).unwrap();
+
+        assert_eq!(parsed.prefix.as_deref(), Some("the"));
+        assert_eq!(parsed.start, "quick-brown");
+        assert_eq!(parsed.end.as_deref(), Some("fox"));
+        assert_eq!(parsed.suffix.as_deref(), Some("jumps"));
+
+        assert_eq!(fragment("https://a.example/#top"), None);
+        assert_eq!(fragment("https://a.example/#:~:text="), None);
+        assert_eq!(fragment("https://a.example/#:~:text=a,b,c"), None);
+        assert_eq!(fragment("https://a.example/#:~:text=%FF"), None);
+    }
+
+    #[test]
+    fn test_locate() {
+        let text = "Cats sleep a lot.\n Dogs  sleep less. Dogs bark, cats do not.";
+
+        let locate = |url: &str| fragment(url).unwrap().locate(text);
+
+        assert_eq!(locate("https://a.example/#:~:text=dogs%20sleep").as_deref(), Some("Dogs sleep"));
+        assert_eq!(locate("https://a.example/#:~:text=dogs,cats").as_deref(), Some("Dogs sleep less. Dogs bark, cats"));
+        assert_eq!(locate("https://a.example/#:~:text=less.-,dogs").as_deref(), Some("Dogs"));
+        assert_eq!(locate("https://a.example/#:~:text=dogs,-bark").as_deref(), Some("Dogs"));
+        assert_eq!(locate("https://a.example/#:~:text=birds"), None);
+        assert_eq!(locate("https://a.example/#:~:text=CATS,-do").as_deref(), Some("cats"));
+    }
+
+    #[test]
+    fn test_locate_repeated_text() {
+        let text = format!("{}z", "a".repeat(250_000));
+
+        // every "a" is a candidate, none has the prefix
+        let parsed = fragment("https://a.example/#:~:text=q-,a,z").unwrap();
+        assert_eq!(parsed.locate(&text), None);
+
+        let parsed = fragment("https://a.example/#:~:text=aaaz").unwrap();
+        assert_eq!(parsed.locate(&text).as_deref(), Some("aaaz"));
+
+        let text = format!("{} q ab", "a ".repeat(1000));
+        let parsed = fragment("https://a.example/#:~:text=q-,a,b").unwrap();
+        assert_eq!(parsed.locate(&text).as_deref(), Some("ab"));
+    }
+}
@@ -0,0 +1,237 @@
+use futures::future::LocalBoxFuture;
+use log::warn;
+use serde::Deserialize;
+use url::Url;
+
+use crabo_model::{SnapError, Snapshot};
+
+use crate::inflight::set_stage;
+use crate::provider_headers::RequestHeaders;
+use crate::quality::{DataSource, with_quality};
+use crate::snapper::{
+    bare_snapshot,
+    CacheHints,
+    Clients,
+    Snapper,
+    SnapshotAndHints,
+};
+
+/// Icon of TikTok shown next to site name.
+const TIKTOK_ICON_URL: &str = "https://www.tiktok.com/favicon.ico";
+
+/// Endpoint that describes videos.
+const TIKTOK_OEMBED_URL: &str = "https://www.tiktok.com/oembed";
+
+/// Prefix of cache hints IDs of TikTok.
+const ID_PREFIX: &str = "tiktok:";
+
+/// Hosts of short links, e.g. `https://vm.tiktok.com/ZMabcdef/`.
+const SHORT_LINK_HOSTS: [&str; 2] = ["vm.tiktok.com", "vt.tiktok.com"];
+
+/// Response of oEmbed endpoint, only fields snapshot is made of.
+#[derive(Deserialize)]
+struct OEmbed {
+    /// Caption of video.
+    title: Option<String>,
+
+    /// Display name of author.
+    author_name: Option<String>,
+
+    /// Handle of author without `@`.
+    author_unique_id: Option<String>,
+
+    thumbnail_url: Option<Url>,
+    thumbnail_width: Option<u32>,
+    thumbnail_height: Option<u32>,
+}
+
+/// This function extracts ID of video from `url`, either numeric ID of
+/// video page, e.g. `https://www.tiktok.com/@user/video/123`, or host and
+/// code of short link, which only TikTok could resolve.
+fn extract_video_id(url: &Url) -> Option<String> {
+    let host = url.host_str()?;
+
+    let segments: Vec<_> = url.path_segments()?
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    let is_code = |code: &str| code.chars().all(|c| c.is_ascii_alphanumeric());
+
+    if SHORT_LINK_HOSTS.contains(&host) {
+        return match segments.as_slice() {
+            [code] if is_code(code) => Some(format!("{host}/{code}")),
+            _ => None,
+        };
+    }
+
+    if host != "tiktok.com" && !host.ends_with(".tiktok.com") {
+        return None;
+    }
+
+    let id = match segments.as_slice() {
+        [user, "video", id] if user.starts_with('@') => *id,
+        ["v", page] => page.strip_suffix(".html")?,
+        ["t", code] if is_code(code) => return Some(format!("{host}/t/{code}")),
+        _ => return None,
+    };
+
+    match !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()) {
+        true => Some(id.to_string()),
+        false => None,
+    }
+}
+
+/// This function converts `oembed` of video with `id` into [Snapshot] of
+/// `url`. Author is its source.
+fn oembed_to_snapshot(url: Url, id: &str, oembed: OEmbed) -> Snapshot {
+    let preview_mime_type = oembed.thumbnail_url.as_ref()
+        .and_then(|x| mime_guess::from_path(x.path()).first())
+        .map(|m| m.to_string());
+
+    // short links are known by code only
+    let canonical_url = oembed.author_unique_id.as_ref()
+        .filter(|_| id.chars().all(|c| c.is_ascii_digit()))
+        .and_then(|user| Url::parse(&format!("https://www.tiktok.com/@{user}/video/{id}")).ok());
+
+    let snapshot = Snapshot {
+        title: oembed.title.filter(|title| !title.trim().is_empty()),
+        author: oembed.author_name.clone(),
+        source: oembed.author_name,
+        site_name: Option::from("TikTok".to_string()),
+        icon_url: Url::parse(TIKTOK_ICON_URL).ok(),
+        preview_url: oembed.thumbnail_url,
+        preview_width: oembed.thumbnail_width,
+        preview_height: oembed.thumbnail_height,
+        preview_mime_type,
+        canonical_url,
+        ..bare_snapshot(url)
+    };
+
+    with_quality(snapshot, DataSource::Embed)
+}
+
+/// This snapper describes TikTok videos with oEmbed endpoint, as TikTok
+/// pages have almost nothing for clients other than browsers.
+pub struct TikTokSnapper {
+    /// Headers sent to oEmbed endpoint along with requests.
+    request_headers: RequestHeaders,
+}
+
+impl TikTokSnapper {
+    /// Constructs new instance of [TikTokSnapper] that sends
+    /// `request_headers` along with requests.
+    pub fn new(request_headers: RequestHeaders) -> Self {
+        Self { request_headers }
+    }
+}
+
+impl Snapper for TikTokSnapper {
+    fn provider(&self) -> String {
+        "tiktok".into()
+    }
+
+    fn cache_hints(&self, url: &Url) -> Option<CacheHints> {
+        extract_video_id(url)
+            .map(|id| CacheHints {
+                provider: self.provider(),
+                id: format!("{ID_PREFIX}{id}"),
+                language: None,
+            })
+    }
+
+    fn snap<'a>(
+        &'a self,
+        url: Url,
+        cache_hints: CacheHints,
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, SnapshotAndHints> {
+        Box::pin(async move {
+            let id = cache_hints.id
+                .strip_prefix(ID_PREFIX)
+                .unwrap_or_default();
+
+            // oEmbed resolves short links on its own
+            let Ok(oembed_url) = Url::parse_with_params(
+                TIKTOK_OEMBED_URL,
+                &[("url", url.as_str())],
+            ) else {
+                return SnapshotAndHints {
+                    snapshot: Err(SnapError::ParseFailed),
+                    hints: cache_hints,
+                };
+            };
+
+            set_stage("calling TikTok oEmbed");
+
+            let snapshot = match clients.page_client.get_json::<OEmbed>(
+                &oembed_url,
+                &self.request_headers.merged_with(&[]),
+            ).await {
+                Ok(oembed) => Ok(oembed_to_snapshot(url, id, oembed)),
+
+                Err(err) => {
+                    warn!("Failed to get oEmbed of TikTok '{id}': {err:?}");
+                    clients.provider_metrics.record_api_error("tiktok");
+                    Err(err.snap_error())
+                }
+            };
+
+            SnapshotAndHints {
+                snapshot,
+                hints: cache_hints,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::tiktok::{extract_video_id, OEmbed, oembed_to_snapshot};
+
+    #[test]
+    fn test_extract_video_id() {
+        let extract = |url: &str| extract_video_id(&Url::parse(url).unwrap());
+
+        assert_eq!(
+            extract("https://www.tiktok.com/@scout2015/video/6718335390845095173?lang=en").as_deref(),
+            Some("6718335390845095173")
+        );
+
+        assert_eq!(extract("https://m.tiktok.com/v/6718335390845095173.html").as_deref(), Some("6718335390845095173"));
+        assert_eq!(extract("https://vm.tiktok.com/ZMabcdef/").as_deref(), Some("vm.tiktok.com/ZMabcdef"));
+        assert_eq!(extract("https://www.tiktok.com/t/ZTabcdef/").as_deref(), Some("www.tiktok.com/t/ZTabcdef"));
+        assert_eq!(extract("https://www.tiktok.com/@scout2015"), None);
+        assert_eq!(extract("https://www.tiktok.com/@scout2015/video/abc"), None);
+        assert_eq!(extract("https://nottiktok.com/@a/video/1"), None);
+    }
+
+    #[test]
+    fn test_oembed_to_snapshot() {
+        let oembed: OEmbed = serde_json::from_str(r#"{
+            "version": "1.0",
+            "type": "video",
+            "title": "Scramble up ur name & I'll try to guess it",
+            "author_name": "Scout & Suki",
+            "author_unique_id": "scout2015",
+            "thumbnail_url": "https://p16-sign.tiktokcdn-us.com/obj/a.jpeg",
+            "thumbnail_width": 720,
+            "thumbnail_height": 1280
+        }"#).unwrap();
+
+        let snapshot = oembed_to_snapshot(
+            Url::parse("https://m.tiktok.com/v/6718335390845095173.html").unwrap(),
+            "6718335390845095173",
+            oembed,
+        );
+
+        assert_eq!(snapshot.source.as_deref(), Some("Scout & Suki"));
+        assert_eq!(snapshot.preview_height, Some(1280));
+        assert_eq!(snapshot.preview_mime_type.as_deref(), Some("image/jpeg"));
+
+        assert_eq!(
+            snapshot.canonical_url.unwrap().as_str(),
+            "https://www.tiktok.com/@scout2015/video/6718335390845095173"
+        );
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use log::warn;
+
+/// Default time to establish connection.
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Default time to wait for response or next chunk of its body.
+const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Default time snapper has to make snapshot of a single URL.
+const DEFAULT_TOTAL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Timeouts of outbound requests.
+///
+/// One hanging server should not stall snapshotting of all other URLs
+/// in the same request, so every snapper has limited time to finish.
+#[derive(Clone, Debug)]
+pub struct Timeouts {
+    /// Time to establish connection.
+    pub connect: Duration,
+
+    /// Time to wait for response headers or next chunk of body.
+    pub read: Duration,
+
+    /// Time snapper has to make snapshot, unless overridden for provider.
+    pub total: Duration,
+
+    /// Total timeouts of specific providers, e.g. `youtube`.
+    per_provider: HashMap<String, Duration>,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            connect: DEFAULT_CONNECT_TIMEOUT,
+            read: DEFAULT_READ_TIMEOUT,
+            total: DEFAULT_TOTAL_TIMEOUT,
+            per_provider: HashMap::new(),
+        }
+    }
+}
+
+/// Helper function to parse number of seconds from `text`.
+fn parse_seconds(text: &str) -> Option<Duration> {
+    let timeout = text.trim()
+        .parse()
+        .ok()
+        .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok());
+
+    if timeout.is_none() {
+        warn!("Ignoring invalid timeout '{text}'");
+    }
+
+    timeout
+}
+
+impl Timeouts {
+    /// Constructs new instance of [Timeouts] from number of seconds,
+    /// defaults are used for values that are not set.
+    /// `per_provider` lists total timeouts of providers separated by
+    /// comma, e.g. `youtube=5,api:example=3`.
+    pub fn new(
+        connect: Option<&str>,
+        read: Option<&str>,
+        total: Option<&str>,
+        per_provider: &str,
+    ) -> Self {
+        let defaults = Self::default();
+
+        let per_provider = per_provider.split(',')
+            .filter_map(|entry| entry.split_once('='))
+            .filter_map(|(provider, seconds)| parse_seconds(seconds)
+                .map(|timeout| (provider.trim().to_string(), timeout))
+            )
+            .collect();
+
+        Self {
+            connect: connect.and_then(parse_seconds).unwrap_or(defaults.connect),
+            read: read.and_then(parse_seconds).unwrap_or(defaults.read),
+            total: total.and_then(parse_seconds).unwrap_or(defaults.total),
+            per_provider,
+        }
+    }
+
+    /// Returns time snapper of `provider` has to make snapshot.
+    pub fn total_for(&self, provider: &str) -> Duration {
+        self.per_provider.get(provider)
+            .copied()
+            .unwrap_or(self.total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::timeouts::Timeouts;
+
+    #[test]
+    fn test_timeouts() {
+        let timeouts = Timeouts::new(
+            Some("2.5"),
+            None,
+            Some("x"),
+            "youtube=5, api:example = 3,broken",
+        );
+
+        assert_eq!(timeouts.connect, Duration::from_millis(2500));
+        assert_eq!(timeouts.read, Timeouts::default().read);
+        assert_eq!(timeouts.total_for("default"), Timeouts::default().total);
+        assert_eq!(timeouts.total_for("youtube"), Duration::from_secs(5));
+        assert_eq!(timeouts.total_for("api:example"), Duration::from_secs(3));
+    }
+}
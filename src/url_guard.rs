@@ -0,0 +1,418 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use actix_tls::connect::Resolve;
+use futures::future::LocalBoxFuture;
+use url::{Host, Url};
+use crate::dns_cache::DnsCache;
+use crate::idn::normalize_host;
+
+/// Reasons [UrlGuard] refuses to fetch URL.
+#[derive(Debug)]
+pub enum GuardError {
+    /// URL has no host or port to connect to.
+    NoHost,
+
+    /// Host could not be resolved.
+    Unresolvable(String),
+
+    /// Host resolves to private, loopback or otherwise reserved address.
+    Reserved(IpAddr),
+}
+
+impl Display for GuardError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoHost => write!(f, "no host to connect to"),
+            Self::Unresolvable(host) => write!(f, "failed to resolve {host}"),
+            Self::Reserved(ip) => write!(f, "{ip} is reserved address"),
+        }
+    }
+}
+
+impl Error for GuardError {}
+
+/// IP network in CIDR notation, e.g. `10.0.0.0/8`.
+#[derive(Clone, Debug, PartialEq)]
+struct IpNetwork {
+    address: IpAddr,
+    prefix: u32,
+}
+
+impl IpNetwork {
+    /// Parses network from `text` such as `10.0.0.0/8` or `fd00::/8`.
+    /// Address without prefix is network of that single address.
+    fn parse(text: &str) -> Option<Self> {
+        let (address, prefix) = match text.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (text, None),
+        };
+
+        let address: IpAddr = address.trim().parse().ok()?;
+
+        let max_prefix = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok()?,
+            None => max_prefix,
+        };
+
+        match prefix <= max_prefix {
+            true => Some(Self { address, prefix }),
+            false => None,
+        }
+    }
+
+    /// Returns true if `ip` belongs to this network.
+    fn contains(&self, ip: IpAddr) -> bool {
+        match (self.address, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix).unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix).unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
+            }
+
+            _ => false,
+        }
+    }
+}
+
+/// Returns true if IPv4 `ip` is not globally reachable, see
+/// <https://www.iana.org/assignments/iana-ipv4-special-registry/>.
+fn is_reserved_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, c, _] = ip.octets();
+
+    ip.is_unspecified() ||
+        ip.is_loopback() ||
+        ip.is_private() ||
+        ip.is_link_local() ||
+        ip.is_broadcast() ||
+        ip.is_documentation() ||
+        ip.is_multicast() ||
+        a == 0 ||
+        // shared address space, carrier-grade NAT
+        (a == 100 && (64..128).contains(&b)) ||
+        // IETF protocol assignments
+        (a == 192 && b == 0 && c == 0) ||
+        // benchmarking
+        (a == 198 && (18..20).contains(&b)) ||
+        // reserved for future use
+        a >= 240
+}
+
+/// Returns true if IPv6 `ip` is not globally reachable, see
+/// <https://www.iana.org/assignments/iana-ipv6-special-registry/>.
+fn is_reserved_v6(ip: Ipv6Addr) -> bool {
+    // IPv4-mapped and NAT64 addresses lead to IPv4 hosts
+    if let Some(ipv4) = ip.to_ipv4_mapped() {
+        return is_reserved_v4(ipv4);
+    }
+
+    let segments = ip.segments();
+
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        let [a, b] = segments[6].to_be_bytes();
+        let [c, d] = segments[7].to_be_bytes();
+
+        return is_reserved_v4(Ipv4Addr::new(a, b, c, d));
+    }
+
+    ip.is_unspecified() ||
+        ip.is_loopback() ||
+        ip.is_multicast() ||
+        // unique local
+        (segments[0] & 0xfe00) == 0xfc00 ||
+        // link-local
+        (segments[0] & 0xffc0) == 0xfe80 ||
+        // documentation
+        (segments[0] == 0x2001 && segments[1] == 0xdb8)
+}
+
+/// Returns true if `ip` is private, loopback, link-local or otherwise
+/// reserved address Crabo should not connect to.
+fn is_reserved(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_reserved_v4(ip),
+        IpAddr::V6(ip) => is_reserved_v6(ip),
+    }
+}
+
+/// This struct protects Crabo from being used to reach internal network,
+/// which is what malicious post linking e.g. `http://169.254.169.254/`
+/// would like Crabo to do.
+///
+/// Host of every URL is resolved before fetch, if any of its addresses is
+/// reserved, URL is refused. Internal services Crabo is supposed to talk
+/// to could be allowed explicitly.
+///
+/// Checking before fetch leaves window for DNS rebinding, host could
+/// resolve to something else when client connects. [GuardResolver] closes
+/// it for [crate::page_client::PageClient] by connecting to addresses
+/// that were checked.
+#[derive(Clone, Debug, Default)]
+pub struct UrlGuard {
+    /// Hosts that are allowed regardless of what they resolve to.
+    allowed_hosts: Vec<String>,
+
+    /// Networks that are allowed even if reserved.
+    allowed_networks: Vec<IpNetwork>,
+
+    /// Resolves hosts instead of system resolver if set.
+    dns_cache: Option<DnsCache>,
+}
+
+impl UrlGuard {
+    /// Constructs new instance of [UrlGuard] with `allowlist` of hosts
+    /// and networks separated by comma, e.g.
+    /// `renderer.internal,10.1.0.0/16`.
+    pub fn new(allowlist: &str) -> Self {
+        let mut guard = Self::default();
+
+        let entries = allowlist.split(',')
+            .map(|entry| entry.trim())
+            .filter(|entry| !entry.is_empty());
+
+        for entry in entries {
+            match IpNetwork::parse(entry) {
+                Some(network) => guard.allowed_networks.push(network),
+                None => guard.allow_host(entry),
+            }
+        }
+
+        guard
+    }
+
+    /// Allows requests to `host` regardless of what it resolves to.
+    pub fn allow_host(&mut self, host: &str) {
+        self.allowed_hosts.push(normalize_host(host));
+    }
+
+    /// Makes [UrlGuard] resolve hosts with `dns_cache` rather than
+    /// asking system resolver every time.
+    pub fn use_dns_cache(&mut self, dns_cache: DnsCache) {
+        self.dns_cache = Some(dns_cache);
+    }
+
+    /// Helper method to resolve `host` to addresses to connect to on `port`.
+    async fn lookup(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<Vec<SocketAddr>, GuardError> {
+        let addresses = match &self.dns_cache {
+            Some(dns_cache) => dns_cache.lookup(host, port).await,
+
+            None => tokio::net::lookup_host((host, port))
+                .await
+                .map(|addresses| addresses.collect())
+                .map_err(|err| err.to_string()),
+        };
+
+        addresses.map_err(|err| GuardError::Unresolvable(format!("{host}: {err}")))
+    }
+
+    /// Helper method to check resolved address `ip`.
+    fn check_address(&self, ip: IpAddr) -> Result<(), GuardError> {
+        let is_allowed = self.allowed_networks.iter()
+            .any(|network| network.contains(ip));
+
+        match is_reserved(ip) && !is_allowed {
+            true => Err(GuardError::Reserved(ip)),
+            false => Ok(()),
+        }
+    }
+
+    /// Helper method to validate `addresses` `host` resolved to.
+    fn check_addresses(
+        &self,
+        host: &str,
+        addresses: &[SocketAddr],
+    ) -> Result<(), GuardError> {
+        if self.allowed_hosts.contains(&normalize_host(host)) {
+            return Ok(());
+        }
+
+        if addresses.is_empty() {
+            return Err(GuardError::Unresolvable(host.to_string()));
+        }
+
+        // all addresses are checked, as any of them could be connected to
+        for address in addresses {
+            self.check_address(address.ip())?;
+        }
+
+        Ok(())
+    }
+
+    /// This method resolves `host` and validates its addresses.
+    /// Returns validated addresses to connect to on `port`, or error if
+    /// host must not be connected to.
+    pub async fn resolve(
+        &self,
+        host: &str,
+        port: u16,
+    ) -> Result<Vec<SocketAddr>, GuardError> {
+        let addresses: Vec<SocketAddr> = match host.trim_matches(['[', ']']).parse() {
+            Ok(ip) => vec![SocketAddr::new(ip, port)],
+
+            Err(_) => self.lookup(host, port).await?,
+        };
+
+        self.check_addresses(host, &addresses)?;
+
+        Ok(addresses)
+    }
+
+    /// This method resolves host of `url` and validates its addresses.
+    /// Returns validated addresses to connect to, or error if `url`
+    /// must not be fetched.
+    pub async fn check(
+        &self,
+        url: &Url,
+    ) -> Result<Vec<SocketAddr>, GuardError> {
+        let port = url.port_or_known_default()
+            .ok_or(GuardError::NoHost)?;
+
+        let host = url.host_str()
+            .ok_or(GuardError::NoHost)?;
+
+        self.resolve(host, port).await
+    }
+
+    /// This method validates host of `url` if it is IP address.
+    /// Domains are not resolved, [GuardResolver] validates them when
+    /// connection is made.
+    pub fn check_ip_host(&self, url: &Url) -> Result<(), GuardError> {
+        let ip = match url.host() {
+            None => return Err(GuardError::NoHost),
+            Some(Host::Domain(_)) => return Ok(()),
+            Some(Host::Ipv4(ip)) => IpAddr::V4(ip),
+            Some(Host::Ipv6(ip)) => IpAddr::V6(ip),
+        };
+
+        let port = url.port_or_known_default()
+            .ok_or(GuardError::NoHost)?;
+
+        self.check_addresses(
+            url.host_str().unwrap_or_default(),
+            &[SocketAddr::new(ip, port)],
+        )
+    }
+}
+
+/// Resolver of connections made by [awc::Client] that validates
+/// addresses with [UrlGuard].
+///
+/// Connection is made to exactly the addresses that were validated,
+/// so DNS rebinding between validation and connect is not possible.
+/// This applies to every redirect hop as well.
+pub struct GuardResolver {
+    url_guard: UrlGuard,
+}
+
+impl GuardResolver {
+    /// Constructs new instance of [GuardResolver] that uses `url_guard`.
+    pub fn new(url_guard: UrlGuard) -> Self {
+        Self {
+            url_guard,
+        }
+    }
+}
+
+impl Resolve for GuardResolver {
+    fn lookup<'a>(
+        &'a self,
+        host: &'a str,
+        port: u16,
+    ) -> LocalBoxFuture<'a, Result<Vec<SocketAddr>, Box<dyn Error>>> {
+        Box::pin(async move {
+            self.url_guard.resolve(host, port)
+                .await
+                .map_err(|err| Box::new(err) as Box<dyn Error>)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::url_guard::{is_reserved, IpNetwork, UrlGuard};
+
+    #[test]
+    fn test_reserved_addresses() {
+        let reserved = |ip: &str| is_reserved(ip.parse().unwrap());
+
+        assert!(reserved("169.254.169.254"));
+        assert!(reserved("127.0.0.1"));
+        assert!(reserved("10.1.2.3"));
+        assert!(reserved("100.64.0.1"));
+        assert!(reserved("0.0.0.0"));
+        assert!(reserved("::1"));
+        assert!(reserved("fd00::1"));
+        assert!(reserved("fe80::1"));
+        assert!(reserved("::ffff:192.168.0.1"));
+        assert!(reserved("64:ff9b::a9fe:a9fe"));
+        assert!(!reserved("93.184.216.34"));
+        assert!(!reserved("2606:2800:220:1:248:1893:25c8:1946"));
+    }
+
+    #[test]
+    fn test_networks() {
+        let network = IpNetwork::parse("10.1.0.0/16").unwrap();
+
+        assert!(network.contains("10.1.2.3".parse().unwrap()));
+        assert!(!network.contains("10.2.0.1".parse().unwrap()));
+        assert!(IpNetwork::parse("0.0.0.0/0").unwrap().contains("1.2.3.4".parse().unwrap()));
+        assert!(IpNetwork::parse("10.0.0.0/33").is_none());
+        assert!(IpNetwork::parse("renderer.internal").is_none());
+    }
+
+    #[actix_rt::test]
+    async fn test_url_guard() {
+        let guard = UrlGuard::default();
+
+        let check = |url: &str| {
+            let url = Url::parse(url).unwrap();
+            let guard = guard.clone();
+            async move { guard.check(&url).await }
+        };
+
+        assert!(check("http://169.254.169.254/latest/meta-data/").await.is_err());
+        assert!(check("http://[::1]:8080/").await.is_err());
+        assert!(check("http://93.184.216.34/").await.is_ok());
+
+        let guard = UrlGuard::new("localhost, 10.1.0.0/16");
+        let url = Url::parse("http://10.1.2.3:8050/render").unwrap();
+        assert!(guard.check(&url).await.is_ok());
+
+        let url = Url::parse("http://LOCALHOST:8050/render").unwrap();
+        assert!(guard.check(&url).await.is_ok());
+    }
+
+    #[actix_rt::test]
+    async fn test_resolve() {
+        let guard = UrlGuard::default();
+
+        assert!(guard.resolve("127.0.0.1", 80).await.is_err());
+        assert!(guard.resolve("[::1]", 80).await.is_err());
+
+        assert_eq!(
+            guard.resolve("93.184.216.34", 443).await.unwrap(),
+            vec!["93.184.216.34:443".parse().unwrap()]
+        );
+
+        let url = Url::parse("http://10.0.0.1/").unwrap();
+        assert!(guard.check_ip_host(&url).is_err());
+
+        // domains are left to resolver
+        let url = Url::parse("http://internal.example/").unwrap();
+        assert!(guard.check_ip_host(&url).is_ok());
+    }
+}
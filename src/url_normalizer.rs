@@ -0,0 +1,170 @@
+use log::debug;
+use url::Url;
+use crate::idn::normalize_url_host;
+
+/// Query parameters dropped unless configured otherwise. These track
+/// campaigns and clicks, page is the same with or without them.
+/// Parameter ending with `*` matches any parameter it is prefix of.
+pub const DEFAULT_DROPPED_PARAMETERS: &str = "utm*,amp;utm*,amp;amp;utm*,\
+    fbclid,gclid,dclid,msclkid,yclid,igshid,mc_cid,mc_eid,_hsenc,_hsmi,\
+    mkt_tok,smid,via,ref,si";
+
+/// This struct brings URLs that differ only in ways servers do not care
+/// about to the same form, so they share cache entries and a page is not
+/// fetched again only because it was shared from another app.
+///
+/// URL is normalized this way:
+/// - trailing dot is dropped from host;
+/// - configured tracking parameters are dropped from query;
+/// - query parameters are sorted by name, empty query is dropped;
+/// - fragment is dropped, unless it looks like route of JavaScript
+///   application, e.g. `#!/page` or `#/page`, or carries text fragment
+///   directive, e.g. `#:~:text=passage`, which is kept alone;
+/// - trailing slash is dropped from path, except root one.
+///
+/// Default port is dropped by [Url::parse] already.
+#[derive(Clone, Debug)]
+pub struct UrlNormalizer {
+    /// Names of parameters dropped from query, see
+    /// [DEFAULT_DROPPED_PARAMETERS].
+    dropped_parameters: Vec<String>,
+}
+
+impl Default for UrlNormalizer {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl UrlNormalizer {
+    /// Constructs new instance of [UrlNormalizer] with
+    /// `dropped_parameters` separated by comma, e.g. `utm*,fbclid`.
+    /// [DEFAULT_DROPPED_PARAMETERS] are used if list is not set.
+    pub fn new(dropped_parameters: Option<&str>) -> Self {
+        let dropped_parameters = dropped_parameters
+            .unwrap_or(DEFAULT_DROPPED_PARAMETERS)
+            .split(',')
+            .map(|parameter| parameter.trim())
+            .filter(|parameter| !parameter.is_empty())
+            .map(|parameter| parameter.to_string())
+            .collect();
+
+        Self {
+            dropped_parameters,
+        }
+    }
+
+    /// Returns true if query `parameter` is to be dropped.
+    fn is_dropped(&self, parameter: &str) -> bool {
+        self.dropped_parameters.iter().any(|dropped| {
+            match dropped.strip_suffix('*') {
+                Some(prefix) => parameter.starts_with(prefix),
+                None => parameter == dropped,
+            }
+        })
+    }
+
+    /// Returns normalized copy of `url`.
+    pub fn normalize(&self, url: &Url) -> Url {
+        let mut normalized = url.clone();
+
+        normalize_url_host(&mut normalized);
+
+        if normalized.query().is_some() {
+            let mut params: Vec<_> = normalized.query_pairs()
+                .filter(|(param, _)| !self.is_dropped(param))
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+
+            // stable, so repeated parameters keep their order
+            params.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            match params.is_empty() {
+                true => normalized.set_query(None),
+
+                false => {
+                    normalized.query_pairs_mut()
+                        .clear()
+                        .extend_pairs(params)
+                        .finish();
+                }
+            }
+        }
+
+        let is_route = normalized.fragment()
+            .is_some_and(|fragment| fragment.starts_with('!') || fragment.starts_with('/'));
+
+        if !is_route {
+            // passage link points to is what snapshot describes
+            let directive = normalized.fragment()
+                .and_then(|fragment| fragment.find(":~:").map(|index| fragment[index..].to_string()));
+
+            normalized.set_fragment(directive.as_deref());
+        }
+
+        let path = normalized.path();
+
+        if path.len() > 1 && path.ends_with('/') {
+            let path = path.trim_end_matches('/').to_string();
+
+            match path.is_empty() {
+                true => normalized.set_path("/"),
+                false => normalized.set_path(&path),
+            }
+        }
+
+        if normalized != *url {
+            debug!("Normalized '{url}' to '{normalized}'");
+        }
+
+        normalized
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::url_normalizer::UrlNormalizer;
+
+    #[test]
+    fn test_normalize() {
+        let normalizer = UrlNormalizer::default();
+
+        let normalize = |url: &str| normalizer
+            .normalize(&Url::parse(url).unwrap())
+            .to_string();
+
+        assert_eq!(
+            normalize("https://a.example.:443/b/?utm_source=x&z=1&fbclid=y&a=2#top"),
+            "https://a.example/b?a=2&z=1"
+        );
+
+        assert_eq!(normalize("https://a.example/?si=x&ref=y"), "https://a.example/");
+        assert_eq!(normalize("https://a.example/?"), "https://a.example/");
+        assert_eq!(normalize("https://a.example//"), "https://a.example/");
+        assert_eq!(normalize("https://a.example/app#!/page"), "https://a.example/app#!/page");
+
+        assert_eq!(
+            normalize("https://a.example/post#intro:~:text=hello%20world"),
+            "https://a.example/post#:~:text=hello%20world"
+        );
+
+        // repeated parameters keep their order
+        assert_eq!(
+            normalize("https://a.example/?b=2&a=1&b=1"),
+            "https://a.example/?a=1&b=2&b=1"
+        );
+    }
+
+    #[test]
+    fn test_configured_parameters() {
+        let normalizer = UrlNormalizer::new(Some("session*, ref"));
+
+        assert_eq!(
+            normalizer
+                .normalize(&Url::parse("https://a.example/?sessionid=1&ref=x&utm_source=y").unwrap())
+                .to_string(),
+            "https://a.example/?utm_source=y"
+        );
+    }
+}
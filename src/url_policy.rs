@@ -0,0 +1,205 @@
+use std::fmt::{Display, Formatter};
+use log::warn;
+use url::Url;
+use crabo_model::SnapError;
+use crate::domain_list::DomainList;
+use crate::idn::is_homograph;
+
+/// Ports URLs are allowed to point to without explicit configuration.
+const STANDARD_PORTS: [u16; 2] = [80, 443];
+
+/// Reasons [UrlPolicy] rejects URL before it is routed to any snapper.
+#[derive(Debug, PartialEq)]
+pub enum RejectReason {
+    /// URL has no host, e.g. `data:` or `mailto:`.
+    NoHost,
+
+    /// Scheme is neither http nor https, e.g. `file:` or `gopher:`.
+    UnsupportedScheme(String),
+
+    /// Port is not standard one and is not allowed explicitly.
+    UnsupportedPort(u16),
+
+    /// Site is known to provide useless data or errors.
+    Ignored,
+
+    /// Host is in denylist.
+    Denied,
+
+    /// Allowlist is configured and host is not in it.
+    NotAllowed,
+
+    /// Host mixes letters of scripts that look alike.
+    Homograph,
+
+    /// Snapper of provider is turned off by operator.
+    Disabled(String),
+}
+
+impl RejectReason {
+    /// Returns class of snapping failure this reason leads to, so
+    /// callers could tell ignored sites from URLs they must not send.
+    pub fn snap_error(&self) -> SnapError {
+        match self {
+            Self::Ignored => SnapError::Ignored,
+
+            Self::NoHost |
+            Self::UnsupportedScheme(_) |
+            Self::UnsupportedPort(_) |
+            Self::Denied |
+            Self::NotAllowed |
+            Self::Homograph |
+            Self::Disabled(_) => SnapError::Rejected,
+        }
+    }
+}
+
+impl Display for RejectReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::NoHost => write!(f, "URL has no host"),
+            Self::UnsupportedScheme(scheme) => write!(f, "scheme {scheme} is not supported"),
+            Self::UnsupportedPort(port) => write!(f, "port {port} is not allowed"),
+            Self::Ignored => write!(f, "site is ignored"),
+            Self::Denied => write!(f, "host is in denylist"),
+            Self::NotAllowed => write!(f, "host is not in allowlist"),
+            Self::Homograph => write!(f, "host mixes look-alike scripts"),
+            Self::Disabled(provider) => write!(f, "snapper {provider} is disabled"),
+        }
+    }
+}
+
+/// This struct decides which URLs Crabo is willing to fetch at all:
+/// only http and https ones on standard ports, plus ports operator
+/// allowed explicitly, and only on hosts operator did not deny.
+#[derive(Clone, Debug, Default)]
+pub struct UrlPolicy {
+    /// Non-standard ports that are allowed.
+    extra_ports: Vec<u16>,
+
+    /// Hosts that are never fetched.
+    denylist: DomainList,
+
+    /// If set, only these hosts are fetched.
+    allowlist: Option<DomainList>,
+
+    /// If set, hosts that look like homograph attack are refused,
+    /// otherwise they are only reported.
+    reject_homographs: bool,
+}
+
+impl UrlPolicy {
+    /// Constructs new instance of [UrlPolicy] with `extra_ports`
+    /// separated by comma, e.g. `8080,8443`, hosts in `denylist` are
+    /// refused, and if `allowlist` is set, hosts not in it are refused.
+    /// Hosts mixing look-alike scripts are refused if `reject_homographs`
+    /// is set.
+    pub fn new(
+        extra_ports: &str,
+        denylist: DomainList,
+        allowlist: Option<DomainList>,
+        reject_homographs: bool,
+    ) -> Self {
+        let extra_ports = extra_ports.split(',')
+            .map(|port| port.trim())
+            .filter(|port| !port.is_empty())
+            .filter_map(|port| match port.parse() {
+                Ok(port) => Some(port),
+
+                Err(err) => {
+                    warn!("Ignoring invalid port '{port}': {err:?}");
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            extra_ports,
+            denylist,
+            allowlist,
+            reject_homographs,
+        }
+    }
+
+    /// This method checks whether `url` could be fetched.
+    pub fn check(&self, url: &Url) -> Result<(), RejectReason> {
+        match url.scheme() {
+            "http" | "https" => {}
+            scheme => return Err(RejectReason::UnsupportedScheme(scheme.to_string())),
+        }
+
+        let host = url.host_str()
+            .ok_or(RejectReason::NoHost)?;
+
+        let port = url.port_or_known_default()
+            .ok_or(RejectReason::NoHost)?;
+
+        if !STANDARD_PORTS.contains(&port) && !self.extra_ports.contains(&port) {
+            return Err(RejectReason::UnsupportedPort(port));
+        }
+
+        if is_homograph(host) {
+            match self.reject_homographs {
+                true => return Err(RejectReason::Homograph),
+                false => warn!("{url} looks like homograph"),
+            }
+        }
+
+        if self.denylist.matches(host) {
+            return Err(RejectReason::Denied);
+        }
+
+        match &self.allowlist {
+            Some(allowlist) if !allowlist.matches(host) => Err(RejectReason::NotAllowed),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::domain_list::DomainList;
+    use crate::url_policy::{RejectReason, UrlPolicy};
+
+    #[test]
+    fn test_url_policy() {
+        let policy = UrlPolicy::new("8080, x", DomainList::default(), None, false);
+
+        let check = |url: &str| policy.check(&Url::parse(url).unwrap());
+
+        assert!(check("https://a.example/").is_ok());
+        assert!(check("http://a.example:443/").is_ok());
+        assert!(check("http://a.example:8080/").is_ok());
+
+        assert_eq!(
+            check("file:///etc/passwd"),
+            Err(RejectReason::UnsupportedScheme("file".to_string()))
+        );
+
+        assert_eq!(
+            check("gopher://a.example/"),
+            Err(RejectReason::UnsupportedScheme("gopher".to_string()))
+        );
+
+        assert_eq!(check("https://a.example:6379/"), Err(RejectReason::UnsupportedPort(6379)));
+        assert!(UrlPolicy::default().check(&Url::parse("http://a.example:8080/").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_domain_lists() {
+        let policy = UrlPolicy::new(
+            "",
+            DomainList::parse("blocked.example.com"),
+            Some(DomainList::parse("example.com")),
+            true,
+        );
+
+        let check = |url: &str| policy.check(&Url::parse(url).unwrap());
+
+        assert!(check("https://www.example.com/").is_ok());
+        assert_eq!(check("https://a.blocked.example.com/"), Err(RejectReason::Denied));
+        assert_eq!(check("https://example.org/"), Err(RejectReason::NotAllowed));
+        assert_eq!(check("https://аpple.example.com/"), Err(RejectReason::Homograph));
+    }
+}
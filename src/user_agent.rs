@@ -0,0 +1,80 @@
+use url::Url;
+use crate::util::CRABO_VERSION;
+
+/// Name Crabo identifies itself with by default.
+pub const DEFAULT_CRAWLER_NAME: &str = "fedineko-crabo";
+
+/// This struct tells how Crabo identifies itself to servers.
+/// The same name is sent in User-Agent header and looked up in robots.txt
+/// and robots meta-tags, so site operators could address Crabo by what
+/// they see in their logs.
+#[derive(Clone, Debug)]
+pub struct UserAgent {
+    /// Name of crawler, e.g. `fedineko-crabo`.
+    name: String,
+
+    /// Page that describes crawler and how to reach its operator.
+    contact_url: Option<Url>,
+}
+
+impl Default for UserAgent {
+    fn default() -> Self {
+        Self::new(DEFAULT_CRAWLER_NAME, None)
+    }
+}
+
+impl UserAgent {
+    /// Constructs new instance of [UserAgent] with crawler `name` and
+    /// `contact_url`. Blank name is replaced with [DEFAULT_CRAWLER_NAME].
+    pub fn new(name: &str, contact_url: Option<Url>) -> Self {
+        let name = match name.trim() {
+            "" => DEFAULT_CRAWLER_NAME,
+            name => name,
+        };
+
+        Self {
+            name: name.to_string(),
+            contact_url,
+        }
+    }
+
+    /// Returns name of crawler robots.txt groups and robots meta-tags
+    /// are matched against.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns value of User-Agent header, e.g.
+    /// `fedineko-crabo/0.3.1 (+https://fedineko.org/)`. Contact URL is
+    /// appended per crawler etiquette, if there is one.
+    pub fn header(&self) -> String {
+        match &self.contact_url {
+            Some(contact_url) => format!("{}/{CRABO_VERSION} (+{contact_url})", self.name),
+            None => format!("{}/{CRABO_VERSION}", self.name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::user_agent::UserAgent;
+    use crate::util::CRABO_VERSION;
+
+    #[test]
+    fn test_header() {
+        let user_agent = UserAgent::new(
+            "example-crabo",
+            Url::parse("https://crabo.example/about").ok(),
+        );
+
+        assert_eq!(
+            user_agent.header(),
+            format!("example-crabo/{CRABO_VERSION} (+https://crabo.example/about)")
+        );
+
+        let user_agent = UserAgent::new(" ", None);
+        assert_eq!(user_agent.name(), "fedineko-crabo");
+        assert_eq!(user_agent.header(), format!("fedineko-crabo/{CRABO_VERSION}"));
+    }
+}
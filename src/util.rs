@@ -1,15 +1,382 @@
+use std::collections::HashMap;
+use actix_web::http::StatusCode;
+use chrono::{DateTime, Duration, NaiveDate, NaiveDateTime, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
 use url::Url;
-use fedineko_http_client::GenericClient;
+use proxydon_cache::typed_cache::TypedCache;
+use crate::page_client::FetchError;
+use crate::snapper::Clients;
 
-pub(crate) const CRABO_VERSION: &str = "0.3.1";
+pub const CRABO_VERSION: &str = "0.3.1";
+
+/// Range of bytes requested when server does not allow HEAD requests.
+/// It is enough for Content-Type and for magic bytes of common formats.
+const PROBE_RANGE: &str = "bytes=0-63";
+
+/// This function tells content type by magic bytes `data` starts with.
+/// Only formats that are likely to be previews are recognized.
+pub fn sniff_mime_type(data: &[u8]) -> Option<&'static str> {
+    let is_ftyp = |brands: &[&[u8; 4]]| data.len() >= 12 &&
+        data[4..8] == *b"ftyp" &&
+        brands.iter().any(|brand| data[8..12] == **brand);
+
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(b"\xff\xd8\xff") {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(&b"WEBP"[..]) {
+        Some("image/webp")
+    } else if is_ftyp(&[b"avif", b"avis"]) {
+        Some("image/avif")
+    } else if is_ftyp(&[b"isom", b"iso2", b"mp41", b"mp42", b"avc1"]) {
+        Some("video/mp4")
+    } else if data.starts_with(b"\x00\x00\x01\x00") {
+        Some("image/x-icon")
+    } else if data.starts_with(b"%PDF-") {
+        Some("application/pdf")
+    } else {
+        None
+    }
+}
+
+/// Helper function to tell content type from Content-Type `header`,
+/// generic binary type tells nothing.
+fn essence_of_content_type(header: Option<&str>) -> Option<String> {
+    header?.split(';')
+        .next()
+        .map(|x| x.trim().to_lowercase())
+        .filter(|x| !x.is_empty() && x != "application/octet-stream")
+}
+
+/// This function requests first bytes of resource at `url` using
+/// `clients` and tells its content type by Content-Type or, if there is
+/// none, by magic bytes.
+async fn probe_mime_type_with_range(url: &Url, clients: &Clients) -> Option<String> {
+    let headers = [("Range".to_string(), PROBE_RANGE.to_string())];
+
+    let mut response = match clients.page_client.get(url, &headers).await {
+        Ok(response) => response,
+
+        Err(err) => {
+            warn!("Failed to request first bytes of {url}: {err:?}");
+            return None;
+        }
+    };
+
+    if let Some(mime_type) = essence_of_content_type(response.content_type.as_deref()) {
+        return Some(mime_type);
+    }
+
+    // servers ignoring Range send everything, first chunk is enough
+    let chunk = response.next_chunk().await?;
+
+    sniff_mime_type(&chunk).map(|mime_type| mime_type.to_string())
+}
+
+/// This function tells content type of resource at `url` by HEAD request
+/// made with `clients`. Many CDNs refuse HEAD, so first bytes of resource
+/// are requested instead in that case.
+async fn probe_mime_type(url: &Url, clients: &Clients) -> Option<String> {
+    // response holds request slot, so it is not kept while probing further
+    let head_result = clients.page_client.head(url)
+        .await
+        .map(|response| response.content_type);
+
+    match head_result {
+        Ok(content_type) => {
+            match essence_of_content_type(content_type.as_deref()) {
+                Some(mime_type) => Some(mime_type),
+                None => probe_mime_type_with_range(url, clients).await,
+            }
+        }
+
+        Err(FetchError::UnexpectedStatusCode(
+            StatusCode::METHOD_NOT_ALLOWED | StatusCode::FORBIDDEN
+        )) => {
+            debug!("{url} does not allow HEAD requests, requesting first bytes");
+            probe_mime_type_with_range(url, clients).await
+        }
+
+        Err(err) => {
+            warn!("Failed to request content type of {url}: {err:?}");
+            None
+        }
+    }
+}
+
+/// Content type of resource found by request to it.
+/// Failed guesses are cached too, so failing requests are not repeated.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MimeGuess {
+    mime_type: Option<String>,
+}
+
+/// Constructs new cache of content types found by requests, so images
+/// shared by many pages, e.g. on CDN, are not requested again and again.
+pub fn new_mime_cache() -> TypedCache<MimeGuess> {
+    TypedCache::new(
+        "mime_guesses",
+        Some(1024),
+        // will keep it in remote cache for one week
+        Duration::try_weeks(1),
+        // will keep in local cache for a few hours
+        Duration::try_hours(6),
+    )
+}
 
 /// Guesses content type for resource identified by `url`.
 /// If guessing by file extension fails, request to resources
-/// is performed with given `client`.
-pub(crate) async fn guess_mime_from_url(
+/// is performed with `clients`, unless URL guard refuses it.
+/// Results of requests are cached.
+pub async fn guess_mime_from_url(
     url: Option<&Url>,
-    client: &GenericClient
+    clients: &Clients,
 ) -> Option<String> {
-    url?;
-    fedineko_url_utils::guess_mime_type_from_url(url.unwrap(), client).await
-}
\ No newline at end of file
+    let url = url?;
+
+    let by_extension = mime_guess::from_path(url.path())
+        .first()
+        .map(|mime_type| mime_type.to_string());
+
+    if by_extension.is_some() {
+        return by_extension;
+    }
+
+    let key = url.to_string();
+
+    let cached = clients.mime_cache
+        .get(vec![key.clone()], &clients.proxydon_client)
+        .await
+        .remove(&key)
+        .flatten();
+
+    if let Some(guess) = cached {
+        debug!("Got cached content type of {url}");
+        return guess.mime_type;
+    }
+
+    if let Err(err) = clients.url_guard.check(url).await {
+        warn!("Refusing to request {url} for content type: {err:?}");
+        return None;
+    }
+
+    let mime_type = probe_mime_type(url, clients).await;
+
+    clients.mime_cache.put(
+        HashMap::from([(key, MimeGuess { mime_type: mime_type.clone() })]),
+        &clients.proxydon_client,
+    ).await;
+
+    mime_type
+}
+
+/// This function makes `url` extracted from page or API response safe to
+/// pass to frontends: only http(s) URLs are accepted, so `data:` or
+/// `javascript:` never make it into snapshot, and embedded credentials
+/// are dropped. Returns None if `url` is not acceptable.
+pub fn sanitize_url(mut url: Url) -> Option<Url> {
+    if !matches!(url.scheme(), "http" | "https") || url.host().is_none() {
+        warn!("Dropping URL with unsupported scheme: {}", url.scheme());
+        return None;
+    }
+
+    if !url.username().is_empty() || url.password().is_some() {
+        // http(s) URLs with host always accept these
+        let _ = url.set_username("");
+        let _ = url.set_password(None);
+    }
+
+    Some(url)
+}
+
+/// Returns true if `host` is `domain` or its subdomain.
+pub fn is_host_of(host: &str, domain: &str) -> bool {
+    host == domain || host.ends_with(&format!(".{domain}"))
+}
+
+/// Parses date and time in `text` as found in meta tags and API responses.
+/// RFC 3339 timestamps are expected, however dates without time and
+/// timestamps without timezone are accepted too and treated as UTC.
+pub fn parse_datetime(text: &str) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+        return Some(datetime.with_timezone(&Utc));
+    }
+
+    if let Ok(datetime) = NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S%.f") {
+        return Some(datetime.and_utc());
+    }
+
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|datetime| datetime.and_utc())
+}
+
+/// Parses duration in `text` into number of seconds.
+/// Both plain number of seconds as in `og:video:duration` and
+/// ISO 8601 durations as in schema.org, e.g. `PT1H2M3S`, are accepted.
+/// Fractions of seconds are dropped, years and months are not supported
+/// as their length is ambiguous.
+pub fn parse_duration_seconds(text: &str) -> Option<u64> {
+    let text = text.trim();
+
+    if let Ok(seconds) = text.parse::<u64>() {
+        return Some(seconds);
+    }
+
+    let designators = text.strip_prefix(['P', 'p'])?;
+    let mut is_time = false;
+    let mut number = String::new();
+    let mut seconds: u64 = 0;
+    let mut has_components = false;
+
+    for c in designators.chars() {
+        if c.is_ascii_digit() || c == '.' || c == ',' {
+            number.push(c);
+            continue;
+        }
+
+        if c.eq_ignore_ascii_case(&'T') && number.is_empty() {
+            is_time = true;
+            continue;
+        }
+
+        let multiplier = match (c.to_ascii_uppercase(), is_time) {
+            ('W', false) => 7 * 24 * 3600,
+            ('D', false) => 24 * 3600,
+            ('H', true) => 3600,
+            ('M', true) => 60,
+            ('S', true) => 1,
+            _ => return None,
+        };
+
+        let value: f64 = number.replace(',', ".").parse().ok()?;
+        seconds = seconds.checked_add((value * multiplier as f64) as u64)?;
+        has_components = true;
+        number.clear();
+    }
+
+    match has_components && number.is_empty() {
+        true => Some(seconds),
+        false => None,
+    }
+}
+
+/// Normalizes language tag in `text` to BCP 47 form, e.g. `en_us`
+/// as found in `og:locale` becomes `en-US`. Returns None if `text` is
+/// not a well-formed tag or language is undetermined (`und`).
+pub fn normalize_language_tag(text: &str) -> Option<String> {
+    let subtags: Vec<_> = text.trim().split(['-', '_']).collect();
+
+    let is_well_formed = subtags.iter()
+        .all(|subtag| (1..=8).contains(&subtag.len()) &&
+            subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        );
+
+    let language = subtags[0];
+
+    if !is_well_formed ||
+        !(2..=3).contains(&language.len()) ||
+        !language.chars().all(|c| c.is_ascii_alphabetic()) ||
+        language.eq_ignore_ascii_case("und") {
+        return None;
+    }
+
+    let normalized = subtags.iter()
+        .enumerate()
+        .map(|(index, subtag)| match (index, subtag.len()) {
+            (0, _) => subtag.to_ascii_lowercase(),
+
+            // script, e.g. `Hant`
+            (_, 4) if subtag.chars().all(|c| c.is_ascii_alphabetic()) => {
+                subtag[..1].to_ascii_uppercase() + &subtag[1..].to_ascii_lowercase()
+            }
+
+            // region, e.g. `US`
+            (_, 2) => subtag.to_ascii_uppercase(),
+
+            _ => subtag.to_ascii_lowercase(),
+        })
+        .collect::<Vec<_>>()
+        .join("-");
+
+    Some(normalized)
+}
+
+/// Returns `count` shortened the way video sites show counts of views
+/// and subscribers, e.g. `1.2M`. Count is rounded down, so it never
+/// looks larger than it is.
+pub fn format_compact_count(count: u64) -> String {
+    let (tenths, suffix) = match count {
+        1_000_000_000.. => (count / 100_000_000, "B"),
+        1_000_000.. => (count / 100_000, "M"),
+        1_000.. => (count / 100, "K"),
+        _ => return count.to_string(),
+    };
+
+    match tenths % 10 {
+        0 => format!("{}{suffix}", tenths / 10),
+        fraction => format!("{}.{fraction}{suffix}", tenths / 10),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::{
+        essence_of_content_type,
+        format_compact_count,
+        is_host_of,
+        normalize_language_tag,
+        sniff_mime_type,
+    };
+
+    #[test]
+    fn test_is_host_of() {
+        assert!(is_host_of("redd.it", "redd.it"));
+        assert!(is_host_of("www.bilibili.com", "bilibili.com"));
+        assert!(!is_host_of("notbilibili.com", "bilibili.com"));
+        assert!(!is_host_of("bilibili.com.example", "bilibili.com"));
+    }
+
+    #[test]
+    fn test_format_compact_count() {
+        assert_eq!(format_compact_count(999), "999");
+        assert_eq!(format_compact_count(12_000), "12K");
+        assert_eq!(format_compact_count(1_299_999), "1.2M");
+        assert_eq!(format_compact_count(3_050_000_000), "3B");
+    }
+
+    #[test]
+    fn test_mime_sniffing() {
+        assert_eq!(sniff_mime_type(b"\x89PNG\r\n\x1a\n\0\0"), Some("image/png"));
+        assert_eq!(sniff_mime_type(b"\xff\xd8\xff\xe0"), Some("image/jpeg"));
+        assert_eq!(sniff_mime_type(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff_mime_type(b"\0\0\0\x1cftypavif\0\0"), Some("image/avif"));
+        assert_eq!(sniff_mime_type(b"<html>"), None);
+        assert_eq!(sniff_mime_type(b""), None);
+
+        assert_eq!(
+            essence_of_content_type(Some("Image/PNG; charset=binary")).as_deref(),
+            Some("image/png")
+        );
+
+        assert_eq!(essence_of_content_type(Some("application/octet-stream")), None);
+    }
+
+    #[test]
+    fn test_language_tag_normalization() {
+        assert_eq!(normalize_language_tag("en_us").as_deref(), Some("en-US"));
+        assert_eq!(normalize_language_tag(" ZH-hant-tw ").as_deref(), Some("zh-Hant-TW"));
+        assert_eq!(normalize_language_tag("ja").as_deref(), Some("ja"));
+        assert_eq!(normalize_language_tag("es-419").as_deref(), Some("es-419"));
+        assert_eq!(normalize_language_tag("und"), None);
+        assert_eq!(normalize_language_tag("english"), None);
+        assert_eq!(normalize_language_tag("en--US"), None);
+        assert_eq!(normalize_language_tag(""), None);
+    }
+}
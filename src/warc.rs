@@ -0,0 +1,244 @@
+use actix_web::http::header::{CONTENT_ENCODING, CONTENT_LENGTH, HeaderMap, TRANSFER_ENCODING};
+use actix_web::http::StatusCode;
+use actix_web::web::Bytes;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{debug, warn};
+use sha2::{Digest, Sha256};
+use url::Url;
+use crate::image_store::{ImageStore, storage_client};
+
+/// Exchange with server as it is written to WARC file, request and
+/// response to it.
+pub struct WarcCapture {
+    /// Address request was sent to.
+    pub url: Url,
+
+    /// Headers sent with request, except defaults of client.
+    pub request_headers: Vec<(String, String)>,
+
+    /// Status code of response.
+    pub status: StatusCode,
+
+    /// Headers of response.
+    pub response_headers: HeaderMap,
+
+    /// Response body as far as it was read.
+    pub body: Bytes,
+
+    /// True if response body was not read completely, e.g. because
+    /// everything needed was found in `<head>`.
+    pub is_truncated: bool,
+
+    /// When response was received.
+    pub fetched_at: DateTime<Utc>,
+}
+
+/// Returns record ID of `kind` record of `capture`, which looks like
+/// random UUID, but is derived from capture, so IDs of request and
+/// response could refer to each other.
+fn record_id(kind: &str, capture: &WarcCapture) -> String {
+    let mut hash = Sha256::new();
+    hash.update(kind.as_bytes());
+    hash.update(capture.url.as_str().as_bytes());
+    hash.update(capture.fetched_at.timestamp_nanos_opt().unwrap_or_default().to_be_bytes());
+
+    let mut bytes = hash.finalize()[..16].to_vec();
+
+    // version 4 and RFC 4122 variant
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = hex::encode(bytes);
+
+    format!(
+        "<urn:uuid:{}-{}-{}-{}-{}>",
+        &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..],
+    )
+}
+
+/// Returns WARC record with `headers` and `block`.
+fn record(headers: &[(&str, String)], block: &[u8]) -> Vec<u8> {
+    let mut record = b"WARC/1.1\r\n".to_vec();
+
+    let headers = headers.iter()
+        .cloned()
+        .chain([
+            ("WARC-Block-Digest", format!("sha256:{}", hex::encode(Sha256::digest(block)))),
+            ("Content-Length", block.len().to_string()),
+        ]);
+
+    for (name, value) in headers {
+        record.extend_from_slice(format!("{name}: {value}\r\n").as_bytes());
+    }
+
+    record.extend_from_slice(b"\r\n");
+    record.extend_from_slice(block);
+    record.extend_from_slice(b"\r\n\r\n");
+
+    record
+}
+
+/// This function returns WARC file with request and response records of
+/// `capture`. Body is decoded by client already, so encoding headers
+/// are dropped from recorded response and its length is set to length
+/// of body.
+pub fn to_warc(capture: &WarcCapture) -> Vec<u8> {
+    let date = capture.fetched_at.to_rfc3339_opts(SecondsFormat::Secs, true);
+    let request_id = record_id("request", capture);
+    let response_id = record_id("response", capture);
+
+    let target = match capture.url.query() {
+        Some(query) => format!("{}?{query}", capture.url.path()),
+        None => capture.url.path().to_string(),
+    };
+
+    let host = match capture.url.port() {
+        Some(port) => format!("{}:{port}", capture.url.host_str().unwrap_or_default()),
+        None => capture.url.host_str().unwrap_or_default().to_string(),
+    };
+
+    let mut request = format!("GET {target} HTTP/1.1\r\nHost: {host}\r\n");
+
+    for (name, value) in &capture.request_headers {
+        request.push_str(&format!("{name}: {value}\r\n"));
+    }
+
+    request.push_str("\r\n");
+
+    let mut response = format!("HTTP/1.1 {}\r\n", capture.status).into_bytes();
+
+    let response_headers = capture.response_headers.iter()
+        .filter(|(name, _)| ![CONTENT_ENCODING, CONTENT_LENGTH, TRANSFER_ENCODING].contains(name));
+
+    for (name, value) in response_headers {
+        response.extend_from_slice(name.as_str().as_bytes());
+        response.extend_from_slice(b": ");
+        response.extend_from_slice(value.as_bytes());
+        response.extend_from_slice(b"\r\n");
+    }
+
+    response.extend_from_slice(format!("Content-Length: {}\r\n\r\n", capture.body.len()).as_bytes());
+    response.extend_from_slice(&capture.body);
+
+    let mut response_headers = vec![
+        ("WARC-Type", "response".to_string()),
+        ("WARC-Record-ID", response_id.clone()),
+        ("WARC-Date", date.clone()),
+        ("WARC-Target-URI", capture.url.to_string()),
+        ("Content-Type", "application/http;msgtype=response".to_string()),
+    ];
+
+    if capture.is_truncated {
+        response_headers.push(("WARC-Truncated", "length".to_string()));
+    }
+
+    let mut warc = record(&response_headers, &response);
+
+    warc.extend(record(
+        &[
+            ("WARC-Type", "request".to_string()),
+            ("WARC-Record-ID", request_id),
+            ("WARC-Date", date),
+            ("WARC-Target-URI", capture.url.to_string()),
+            ("WARC-Concurrent-To", response_id),
+            ("Content-Type", "application/http;msgtype=request".to_string()),
+        ],
+        request.as_bytes(),
+    ));
+
+    warc
+}
+
+/// This struct keeps responses snapshots are made of as WARC files, so
+/// deployments that need to prove what was snapshotted could retain the
+/// evidence. Every exchange is written to its own file, e.g.
+/// `warc/2024/03/01/20240301120000-1f2e3d4c5b6a7980.warc`, which suits
+/// object stores that have no appends.
+///
+/// One instance per worker is expected, as its client could not be
+/// shared across threads.
+#[derive(Clone)]
+pub struct WarcArchive {
+    store: ImageStore,
+    client: awc::Client,
+}
+
+impl WarcArchive {
+    /// Constructs new instance of [WarcArchive] that writes to `store`.
+    pub fn new(store: ImageStore) -> Self {
+        Self {
+            store,
+            client: storage_client(),
+        }
+    }
+
+    /// Returns key of WARC file of `capture`.
+    fn key(capture: &WarcCapture) -> String {
+        let hash = hex::encode(Sha256::digest(capture.url.as_str().as_bytes()));
+
+        format!(
+            "warc/{}/{}-{}.warc",
+            capture.fetched_at.format("%Y/%m/%d"),
+            capture.fetched_at.format("%Y%m%d%H%M%S"),
+            &hash[..16],
+        )
+    }
+
+    /// Writes `capture` to WARC file. Failures are logged only, snapshot
+    /// is made regardless of archive.
+    pub async fn put(&self, capture: WarcCapture) {
+        let key = Self::key(&capture);
+
+        match self.store.put(&self.client, &key, "application/warc", to_warc(&capture)).await {
+            Ok(()) => debug!("{}: archived as {key}", capture.url),
+            Err(err) => warn!("{}: failed to archive response: {err}", capture.url),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::header::{CONTENT_ENCODING, CONTENT_TYPE, HeaderMap, HeaderValue};
+    use actix_web::http::StatusCode;
+    use actix_web::web::Bytes;
+    use chrono::{TimeZone, Utc};
+    use url::Url;
+    use crate::warc::{to_warc, WarcArchive, WarcCapture};
+
+    #[test]
+    fn test_to_warc() {
+        let mut response_headers = HeaderMap::new();
+        response_headers.insert(CONTENT_TYPE, HeaderValue::from_static("text/html"));
+        response_headers.insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+
+        let capture = WarcCapture {
+            url: Url::parse("https://example.com/a?b=1").unwrap(),
+            request_headers: vec![("Accept-Language".to_string(), "en".to_string())],
+            status: StatusCode::OK,
+            response_headers,
+            body: Bytes::from_static(b"<html></html>"),
+            is_truncated: true,
+            fetched_at: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+        };
+
+        let warc = String::from_utf8(to_warc(&capture)).unwrap();
+        let (response, request) = warc.split_once("\r\n\r\nWARC/1.1").unwrap();
+
+        assert!(response.starts_with("WARC/1.1\r\nWARC-Type: response\r\n"));
+        assert!(response.contains("WARC-Date: 2024-03-01T12:00:00Z\r\n"));
+        assert!(response.contains("WARC-Target-URI: https://example.com/a?b=1\r\n"));
+        assert!(response.contains("WARC-Truncated: length\r\n"));
+        assert!(response.ends_with("content-type: text/html\r\nContent-Length: 13\r\n\r\n<html></html>"));
+        assert!(!response.contains("gzip"));
+
+        assert!(request.contains("WARC-Type: request\r\n"));
+        assert!(request.contains("GET /a?b=1 HTTP/1.1\r\nHost: example.com\r\nAccept-Language: en\r\n\r\n"));
+
+        let response_id = response.split("WARC-Record-ID: ").nth(1).unwrap().split("\r\n").next().unwrap();
+        assert!(request.contains(&format!("WARC-Concurrent-To: {response_id}\r\n")));
+
+        assert!(
+            WarcArchive::key(&capture).starts_with("warc/2024/03/01/20240301120000-")
+        );
+    }
+}
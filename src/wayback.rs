@@ -0,0 +1,165 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use log::{debug, warn};
+use serde::Deserialize;
+use url::Url;
+use crate::page_client::PageClient;
+
+/// Wayback Machine availability API endpoint,
+/// see <https://archive.org/help/wayback_api.php>.
+const AVAILABILITY_API: &str = "https://archive.org/wayback/available";
+
+/// Closest archived snapshot as reported by availability API.
+#[derive(Deserialize)]
+struct ClosestSnapshot {
+    available: bool,
+    status: Option<String>,
+
+    /// Capture time as `YYYYMMDDhhmmss`.
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct ArchivedSnapshots {
+    closest: Option<ClosestSnapshot>,
+}
+
+/// Example response
+/// ```json
+/// {
+///   "url": "example.com",
+///   "archived_snapshots": {
+///     "closest": {
+///       "status": "200",
+///       "available": true,
+///       "url": "http://web.archive.org/web/20240101000000/https://example.com/",
+///       "timestamp": "20240101000000"
+///     }
+///   }
+/// }
+/// ```
+#[derive(Deserialize)]
+struct AvailabilityResponse {
+    archived_snapshots: ArchivedSnapshots,
+}
+
+/// Copy of page archived by Wayback Machine.
+pub struct ArchivedCopy {
+    /// Capture time as `YYYYMMDDhhmmss`, as Wayback Machine addresses it.
+    timestamp: String,
+
+    /// When page was captured.
+    pub captured_at: DateTime<Utc>,
+}
+
+impl ArchivedCopy {
+    /// Helper method to build address of archived resource `url`.
+    /// `modifier` selects how Wayback Machine serves it, e.g. `id_`
+    /// returns resource as it was captured, without Wayback toolbar.
+    fn archive_url(&self, url: &Url, modifier: &str) -> Option<Url> {
+        Url::parse(
+            &format!(
+                "https://web.archive.org/web/{}{modifier}/{url}",
+                self.timestamp,
+            )
+        ).ok()
+    }
+
+    /// Returns address of original archived page `url`.
+    pub fn page_url(&self, url: &Url) -> Option<Url> {
+        self.archive_url(url, "id_")
+    }
+
+    /// Returns address of archived image `url`, so preview does not
+    /// point to server that is down.
+    pub fn image_url(&self, url: &Url) -> Option<Url> {
+        if url.host_str() == Some("web.archive.org") {
+            return Some(url.clone());
+        }
+
+        self.archive_url(url, "im_")
+    }
+}
+
+/// Parses Wayback Machine `timestamp` such as `20240101000000`.
+fn parse_timestamp(timestamp: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(timestamp, "%Y%m%d%H%M%S")
+        .ok()
+        .map(|datetime| datetime.and_utc())
+}
+
+/// This function asks Wayback Machine for the most recent archived copy
+/// of page `url` using `client`. Returns None if there is no copy.
+pub async fn find_archived_copy(
+    url: &Url,
+    client: &PageClient,
+) -> Option<ArchivedCopy> {
+    let api_url = Url::parse_with_params(
+        AVAILABILITY_API,
+        &[("url", url.as_str())],
+    ).ok()?;
+
+    let response = match client.get_json::<AvailabilityResponse>(
+        &api_url,
+        &[],
+    ).await {
+        Ok(response) => response,
+
+        Err(err) => {
+            warn!("Failed to query Wayback Machine for {url}: {err:?}");
+            return None;
+        }
+    };
+
+    let closest = response.archived_snapshots.closest
+        .filter(|closest| closest.available)
+        .filter(|closest| closest.status.as_deref().unwrap_or("200") == "200");
+
+    match closest {
+        Some(closest) => Some(
+            ArchivedCopy {
+                captured_at: parse_timestamp(&closest.timestamp)?,
+                timestamp: closest.timestamp,
+            }
+        ),
+
+        None => {
+            debug!("Wayback Machine has no copy of {url}");
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use url::Url;
+    use crate::wayback::{ArchivedCopy, parse_timestamp};
+
+    #[test]
+    fn test_archive_urls() {
+        let copy = ArchivedCopy {
+            timestamp: "20240102030405".to_string(),
+            captured_at: parse_timestamp("20240102030405").unwrap(),
+        };
+
+        assert_eq!(copy.captured_at.to_rfc3339(), "2024-01-02T03:04:05+00:00");
+
+        let url = Url::parse("https://a.example/post?id=1").unwrap();
+
+        assert_eq!(
+            copy.page_url(&url).unwrap().as_str(),
+            "https://web.archive.org/web/20240102030405id_/https://a.example/post?id=1"
+        );
+
+        let image_url = copy.image_url(
+            &Url::parse("https://a.example/cover.png").unwrap()
+        ).unwrap();
+
+        assert_eq!(
+            image_url.as_str(),
+            "https://web.archive.org/web/20240102030405im_/https://a.example/cover.png"
+        );
+
+        assert_eq!(copy.image_url(&image_url), Some(image_url));
+        assert!(parse_timestamp("2024").is_none());
+    }
+}
@@ -0,0 +1,257 @@
+/// Elements that have no content in HTML, so `<br/>` means the same
+/// for HTML parser as it does for XML one.
+const VOID_ELEMENTS: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img",
+    "input", "link", "meta", "param", "source", "track", "wbr",
+];
+
+/// Elements which content HTML parser treats as raw text.
+const RAW_TEXT_ELEMENTS: [&str; 2] = ["script", "style"];
+
+/// Incomplete markup longer than this is not markup but garbage,
+/// it is passed as is instead of being buffered forever.
+const MAX_PENDING_BYTES: usize = 64 * 1024;
+
+/// Returns true if document that is served with `content_type` and
+/// starts with `text` is XML, e.g. XHTML page.
+fn is_xml_document(content_type: Option<&str>, text: &str) -> bool {
+    let is_xml_type = content_type
+        .and_then(|x| x.split(';').next())
+        .map(|x| x.trim().to_lowercase())
+        .is_some_and(|x| x.ends_with("/xml") || x.ends_with("+xml"));
+
+    is_xml_type || text.trim_start_matches('\u{feff}')
+        .trim_start()
+        .starts_with("<?xml")
+}
+
+/// Returns length of markup `text` starts with, including closing `>`,
+/// or None if markup is not complete yet.
+fn markup_len(text: &str) -> Option<usize> {
+    let terminated_by = |prefix: &str, end: &str| text.strip_prefix(prefix)
+        .map(|rest| rest.find(end)
+            .map(|position| prefix.len() + position + end.len())
+        );
+
+    if let Some(len) = terminated_by("<!--", "-->")
+        .or_else(|| terminated_by("<![CDATA[", "]]>"))
+        .or_else(|| terminated_by("<?", "?>")) {
+        return len;
+    }
+
+    // '>' could be a part of attribute value
+    let mut quote = None;
+
+    for (index, c) in text.char_indices() {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (None, '>') => return Some(index + 1),
+            _ => { /* still inside of tag */ }
+        }
+    }
+
+    None
+}
+
+/// Escapes `text` so it could be put into HTML text node as is.
+fn escape_text(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Streaming normalizer of XHTML documents to something HTML parser
+/// understands the same way XML parser would.
+///
+/// HTML parser ignores self-closing syntax, so `<script src="x"/>` or
+/// `<title/>` swallows the rest of document. This normalizer expands
+/// such elements into start and end tags, drops XML declaration and
+/// unwraps CDATA sections. HTML documents are passed as is.
+pub struct XhtmlNormalizer {
+    /// Content-Type header value document was served with.
+    content_type: Option<String>,
+
+    /// Set once it is known whether document is XML.
+    is_xml: Option<bool>,
+
+    /// Markup which end has not arrived yet.
+    pending: String,
+
+    /// True while inside of `<script>` or `<style>` element.
+    in_raw_text: bool,
+}
+
+impl XhtmlNormalizer {
+    /// Constructs new instance of [XhtmlNormalizer] for document served
+    /// with `content_type`.
+    pub fn new(content_type: Option<&str>) -> Self {
+        Self {
+            content_type: content_type.map(|x| x.to_string()),
+            is_xml: None,
+            pending: String::new(),
+            in_raw_text: false,
+        }
+    }
+
+    /// Helper method to write normalized `markup` into `output`.
+    fn normalize_markup(&mut self, markup: &str, output: &mut String) {
+        if markup.starts_with("<?") {
+            // XML declaration and processing instructions mean nothing
+            // for HTML parser
+            return;
+        }
+
+        if let Some(data) = markup.strip_prefix("<![CDATA[") {
+            let data = data.strip_suffix("]]>").unwrap_or(data);
+
+            match self.in_raw_text {
+                true => output.push_str(data),
+                false => output.push_str(&escape_text(data)),
+            }
+
+            return;
+        }
+
+        if markup.starts_with("<!") {
+            output.push_str(markup);
+            return;
+        }
+
+        let is_end_tag = markup.starts_with("</");
+
+        let name = markup.trim_start_matches(['<', '/'])
+            .split(|c: char| c.is_whitespace() || c == '/' || c == '>')
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+
+        let is_raw_text = RAW_TEXT_ELEMENTS.contains(&name.as_str());
+
+        if is_end_tag {
+            self.in_raw_text &= !is_raw_text;
+            output.push_str(markup);
+            return;
+        }
+
+        let start_tag = markup.trim_end_matches('>')
+            .trim_end();
+
+        match start_tag.strip_suffix('/') {
+            Some(start_tag) if !VOID_ELEMENTS.contains(&name.as_str()) => {
+                output.push_str(start_tag.trim_end());
+                output.push_str("></");
+                output.push_str(&name);
+                output.push('>');
+            }
+
+            Some(_) => output.push_str(markup),
+
+            None => {
+                self.in_raw_text |= is_raw_text;
+                output.push_str(markup);
+            }
+        }
+    }
+
+    /// Normalizes next `text` chunk of document.
+    /// Returns text ready for parsing, incomplete markup at the end of
+    /// chunk is kept until the rest of it arrives.
+    pub fn push(&mut self, text: &str) -> String {
+        // transcoder returns nothing while it buffers beginning of document
+        if text.is_empty() {
+            return String::new();
+        }
+
+        let is_xml = *self.is_xml.get_or_insert_with(
+            || is_xml_document(self.content_type.as_deref(), text)
+        );
+
+        if !is_xml {
+            return text.to_string();
+        }
+
+        let mut input = std::mem::take(&mut self.pending);
+        input.push_str(text);
+
+        let mut output = String::with_capacity(input.len());
+        let mut rest = input.as_str();
+
+        while let Some(start) = rest.find('<') {
+            output.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            match markup_len(rest) {
+                Some(len) => {
+                    self.normalize_markup(&rest[..len], &mut output);
+                    rest = &rest[len..];
+                }
+
+                None if rest.len() > MAX_PENDING_BYTES => {
+                    output.push_str(rest);
+                    rest = "";
+                }
+
+                None => {
+                    self.pending = rest.to_string();
+                    rest = "";
+                }
+            }
+        }
+
+        output.push_str(rest);
+        output
+    }
+
+    /// Returns whatever is left after document has ended.
+    pub fn finish(&mut self) -> String {
+        std::mem::take(&mut self.pending)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::xhtml::XhtmlNormalizer;
+
+    fn normalize(content_type: Option<&str>, chunks: &[&str]) -> String {
+        let mut normalizer = XhtmlNormalizer::new(content_type);
+
+        let mut output: String = chunks.iter()
+            .map(|chunk| normalizer.push(chunk))
+            .collect();
+
+        output.push_str(&normalizer.finish());
+        output
+    }
+
+    #[test]
+    fn test_xhtml_normalization() {
+        assert_eq!(
+            normalize(
+                None,
+                &[
+                    "<?xml version=\"1.0\"?><html><head><title/>",
+                    "<script src=\"a.js\" /><meta name=\"x\" content=\"a>b\"/",
+                    "><script type=\"application/ld+json\">",
+                    "<![CDATA[{\"a\": \"<b>\"}]]></script></head>",
+                    "<body><p><![CDATA[1 < 2]]></p></body></html>",
+                ]
+            ),
+            "<html><head><title></title>\
+            <script src=\"a.js\"></script><meta name=\"x\" content=\"a>b\"/>\
+            <script type=\"application/ld+json\">{\"a\": \"<b>\"}</script>\
+            </head><body><p>1 &lt; 2</p></body></html>"
+        );
+
+        // HTML is left alone
+        assert_eq!(
+            normalize(Some("text/html"), &["<html><title/>"]),
+            "<html><title/>"
+        );
+
+        assert_eq!(
+            normalize(Some("application/xhtml+xml; charset=utf-8"), &["<div/>"]),
+            "<div></div>"
+        );
+    }
+}
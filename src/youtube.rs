@@ -1,14 +1,55 @@
 use std::collections::HashMap;
-use log::{debug, warn};
+use actix_web::http::StatusCode;
+use chrono::{DateTime, Utc};
+use futures::future::{join_all, LocalBoxFuture};
+use itertools::Itertools;
+use log::{debug, info, warn};
+use percent_encoding::percent_decode_str;
 use serde::Deserialize;
 use url::Url;
-use crabo_model::Snapshot;
-use crate::snapper::{CacheHints, Clients, Snapper, SnapshotAndHints};
+use crabo_model::{SnapError, Snapshot};
+use crate::inflight::set_stage;
+use crate::page_client::FetchError;
+use crate::provider_headers::RequestHeaders;
+use crate::quality::{DataSource, with_quality};
+use crate::snapper::{
+    bare_snapshot,
+    CacheHints,
+    Clients,
+    Snapper,
+    SnapshotAndHints,
+};
+use crate::util::{
+    format_compact_count,
+    is_host_of,
+    normalize_language_tag,
+    parse_duration_seconds,
+};
+use crate::youtube_quota::YoutubeQuota;
+
+/// Maximum number of video IDs YouTube API accepts in a single request.
+const MAX_IDS_PER_REQUEST: usize = 50;
+
+/// Quota cost of list call, regardless of number of IDs in it.
+const QUOTA_UNITS_PER_CALL: u64 = 1;
+
+/// Icon of YouTube shown next to site name.
+const YOUTUBE_ICON_URL: &str = "https://www.youtube.com/favicon.ico";
+
+/// Endpoint that describes videos and playlists without API key.
+const YOUTUBE_OEMBED_URL: &str = "https://www.youtube.com/oembed";
+
 
 /// This snapper uses YouTube official API to get video details.
-pub(crate) struct YoutubeSnapper {
-    /// API key to access YouTube API v3
-    api_key: String,
+pub struct YoutubeSnapper {
+    /// API key to access YouTube API v3, only oEmbed is used without it.
+    api_key: Option<String>,
+
+    /// Headers sent to API along with requests.
+    request_headers: RequestHeaders,
+
+    /// Budget of API quota, oEmbed is used once it is spent.
+    quota: YoutubeQuota,
 }
 
 /// Thumbnail image details.
@@ -17,6 +58,12 @@ pub(crate) struct YoutubeSnapper {
 struct Thumbnail {
     /// Actual image data url.
     url: Option<Url>,
+
+    /// Width of image in pixels.
+    width: Option<u32>,
+
+    /// Height of image in pixels.
+    height: Option<u32>,
 }
 
 /// Keeps basic details about video.
@@ -34,58 +81,356 @@ struct Snippet {
 
     /// Video tags.
     tags: Option<Vec<String>>,
+
+    /// When video was published.
+    #[serde(rename = "publishedAt")]
+    published_at: Option<DateTime<Utc>>,
+
+    /// Name of channel that published video.
+    #[serde(rename = "channelTitle")]
+    channel_title: Option<String>,
+
+    /// Language spoken in video.
+    #[serde(rename = "defaultAudioLanguage")]
+    default_audio_language: Option<String>,
+
+    /// Language of title and description.
+    #[serde(rename = "defaultLanguage")]
+    default_language: Option<String>,
+
+    /// Title and description in language requested, if channel
+    /// translated them.
+    localized: Option<Localized>,
 }
 
-/// Wrapper Video object.
+/// Title and description of video translated by its channel.
+#[derive(Deserialize)]
+#[derive(Clone)]
+struct Localized {
+    title: Option<String>,
+    description: Option<String>,
+}
+
+/// Wrapper Video object. Playlists and channels are described the same
+/// way, except they have no content details, and channels have
+/// statistics instead.
 #[derive(Deserialize)]
 #[derive(Clone)]
 struct Video {
+    /// Video ID.
+    id: String,
+
     /// Video details snippet.
     snippet: Snippet,
+
+    /// Video content details.
+    #[serde(rename = "contentDetails")]
+    content_details: Option<ContentDetails>,
+
+    /// Channel statistics.
+    statistics: Option<Statistics>,
+}
+
+/// Keeps public numbers of channel.
+#[derive(Deserialize)]
+#[derive(Clone)]
+struct Statistics {
+    /// Number of subscribers rounded by YouTube, as decimal string.
+    #[serde(rename = "subscriberCount")]
+    subscriber_count: Option<String>,
+
+    /// True if channel hides number of subscribers.
+    #[serde(default, rename = "hiddenSubscriberCount")]
+    hidden_subscriber_count: bool,
+}
+
+/// Keeps details about video content.
+#[derive(Deserialize)]
+#[derive(Clone)]
+struct ContentDetails {
+    /// ISO 8601 duration of video, e.g. `PT4M13S`, live streams have
+    /// `P0D`.
+    duration: Option<String>,
 }
 
 /// Response expected for meta-data request.
 #[derive(Deserialize)]
 struct VideoListResponse {
-    #[serde(alias = "items")]
+    // there are no items at all if nothing is found
+    #[serde(default, alias = "items")]
     videos: Vec<Video>,
 }
 
+/// Returns true if `id` looks like ID of YouTube video or playlist, so
+/// it could be passed to API as is.
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Returns true if `host` belongs to YouTube proper, e.g. `m.youtube.com`,
+/// `music.youtube.com` or `www.youtube-nocookie.com` of embeds.
+fn is_youtube_host(host: &str) -> bool {
+    is_host_of(host, "youtube.com") || is_host_of(host, "youtube-nocookie.com")
+}
+
 /// This function extract video ID from `url` to pass it later in API request.
-/// It supports both shorter youtu.be and full size youtube.com formats.
+/// It supports shorter youtu.be links, `watch?v=` pages of youtube.com
+/// and its subdomains such as music.youtube.com, as well as Shorts, live
+/// stream and embed pages, e.g. `/shorts/{id}`.
 fn extract_video_id(url: &Url) -> Option<String> {
-    let host = url.host()?.to_string();
+    let host = url.host_str()?;
 
-    if host.ends_with("youtu.be") {
-        let path = url.path();
+    let id = if is_host_of(host, "youtu.be") {
+        url.path_segments()
+            .and_then(|mut segments| segments.next())
+            .map(|id| id.to_string())
+    } else if is_youtube_host(host) {
+        let mut segments = url.path_segments().into_iter().flatten();
 
-        return if path.is_empty() {
-            None
-        } else {
-            Some(path[1..].to_string())
-        };
+        match (segments.next(), segments.next()) {
+            // embedded playlist is not a video
+            (Some("embed"), Some("videoseries")) => None,
+
+            (Some("shorts" | "live" | "embed" | "v"), Some(id)) => Some(id.to_string()),
+
+            _ => url.query_pairs()
+                .find(|(k, _)| k == "v")
+                .map(|(_, v)| v.to_string()),
+        }
+    } else {
+        None
+    };
+
+    let id = id.filter(|id| is_valid_id(id));
+
+    if id.is_none() {
+        debug!("Could not extract YouTube video ID from URL {url}");
+    }
+
+    id
+}
+
+/// This function extracts playlist ID from `url` of playlist page, e.g.
+/// `https://www.youtube.com/playlist?list=PL123`, or of embedded playlist.
+/// Videos watched as part of playlist are videos still, so `watch` pages
+/// are not playlists.
+fn extract_playlist_id(url: &Url) -> Option<String> {
+    if !is_youtube_host(url.host_str()?) {
+        return None;
+    }
+
+    if !["/playlist", "/embed/videoseries"].contains(&url.path().trim_end_matches('/')) {
+        return None;
+    }
+
+    url.query_pairs()
+        .find(|(k, _)| k == "list")
+        .map(|(_, v)| v.to_string())
+        .filter(|id| is_valid_id(id))
+}
+
+/// This function extracts channel from `url` of channel page, e.g.
+/// `https://www.youtube.com/@handle` or
+/// `https://www.youtube.com/channel/UC123/videos`. Returns cache hints ID
+/// of channel, which is either handle or channel ID with its prefix.
+fn extract_channel(url: &Url) -> Option<String> {
+    if !is_youtube_host(url.host_str()?) {
+        return None;
     }
 
-    if host.ends_with("youtube.com") {
-        return url.query_pairs()
-            .find(|(k, _)| k == "v")
-            .map(|(_, v)| v.to_string());
+    let mut segments = url.path_segments()?;
+
+    match (segments.next(), segments.next()) {
+        (Some("channel"), Some(id)) => Some(id)
+            .filter(|id| is_valid_id(id))
+            .map(|id| Collection::Channels.hints_id(id)),
+
+        (Some(segment), _) if segment.starts_with('@') => {
+            let handle = percent_decode_str(&segment[1..]).decode_utf8().ok()?;
+
+            // handles are letters, digits, underscores, hyphens and periods
+            let is_valid = !handle.is_empty() && handle.chars()
+                .all(|c| c.is_alphanumeric() || ['_', '-', '.'].contains(&c));
+
+            // handles are case-insensitive, so they share cache entry
+            match is_valid {
+                true => Some(Collection::Handles.hints_id(&handle.to_lowercase())),
+                false => None,
+            }
+        }
+
+        _ => None,
     }
+}
 
+/// Collection of YouTube API resources are requested from.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Collection {
+    Videos,
+    Playlists,
 
-    debug!("Could not extract YouTube video ID from URL {url}");
+    /// Channels by their IDs, e.g. `UC123`.
+    Channels,
 
-    None
+    /// Channels by their handles, which API looks up one at a time.
+    Handles,
+}
+
+impl Collection {
+    /// Returns prefix of cache hints IDs of resources from this
+    /// collection, as all of them are snapped along with videos.
+    fn prefix(self) -> &'static str {
+        match self {
+            Self::Videos => "",
+            Self::Playlists => "list:",
+            Self::Channels => "channel:",
+            Self::Handles => "@",
+        }
+    }
+
+    /// Returns collection resource with cache hints `id` belongs to and
+    /// ID of resource itself.
+    fn of_hints_id(id: &str) -> (Self, &str) {
+        [Self::Playlists, Self::Channels, Self::Handles].into_iter()
+            .find_map(|collection| id.strip_prefix(collection.prefix())
+                .map(|id| (collection, id))
+            )
+            .unwrap_or((Self::Videos, id))
+    }
+
+    /// Returns cache hints ID of resource with `id` from this collection.
+    fn hints_id(self, id: &str) -> String {
+        format!("{}{id}", self.prefix())
+    }
+
+    /// Returns address of API endpoint that lists resources by ID.
+    fn endpoint(self) -> &'static str {
+        match self {
+            Self::Videos => "https://www.googleapis.com/youtube/v3/videos",
+            Self::Playlists => "https://www.googleapis.com/youtube/v3/playlists",
+            Self::Channels | Self::Handles => "https://www.googleapis.com/youtube/v3/channels",
+        }
+    }
+
+    /// Returns name of query parameter resources are looked up by.
+    fn id_parameter(self) -> &'static str {
+        match self {
+            Self::Handles => "forHandle",
+            _ => "id",
+        }
+    }
+
+    /// Returns maximum number of resources requested at once.
+    fn max_ids_per_request(self) -> usize {
+        match self {
+            Self::Handles => 1,
+            _ => MAX_IDS_PER_REQUEST,
+        }
+    }
+
+    /// Returns parts of resources requested from API.
+    fn parts(self) -> &'static str {
+        match self {
+            Self::Videos | Self::Playlists => "snippet,contentDetails",
+            Self::Channels | Self::Handles => "snippet,statistics",
+        }
+    }
+
+    /// Returns fields of resources requested from API.
+    fn fields(self) -> &'static str {
+        match self {
+            Self::Videos => "items(id,snippet,contentDetails(duration))",
+            Self::Playlists => "items(id,snippet)",
+            Self::Channels | Self::Handles => "items(id,snippet,statistics)",
+        }
+    }
+}
+
+/// Why API did not describe resources.
+#[derive(Clone, Copy, Debug)]
+enum ApiFailure {
+    /// Key is rejected or its quota is exhausted, so API fails every
+    /// request until quota resets or key is replaced. Also used once
+    /// budget of [YoutubeQuota] is spent.
+    Unavailable,
+
+    /// Request failed for any other reason.
+    Failed(SnapError),
+}
+
+impl ApiFailure {
+    /// Returns failure `err` of API call means.
+    fn of(err: &FetchError) -> Self {
+        match err {
+            FetchError::UnexpectedStatusCode(
+                StatusCode::BAD_REQUEST |
+                StatusCode::FORBIDDEN |
+                StatusCode::TOO_MANY_REQUESTS
+            ) => Self::Unavailable,
+
+            _ => Self::Failed(err.snap_error()),
+        }
+    }
+}
+
+/// Response of oEmbed endpoint, only fields snapshot is made of.
+#[derive(Deserialize)]
+struct OEmbed {
+    title: Option<String>,
+    author_name: Option<String>,
+    thumbnail_url: Option<Url>,
+    thumbnail_width: Option<u32>,
+    thumbnail_height: Option<u32>,
+}
+
+/// This function converts `oembed` of `url` into degraded [Snapshot],
+/// which has only title, channel and thumbnail. Lower quality score of
+/// [DataSource::Embed] tells it apart from snapshot made of API data.
+fn oembed_to_snapshot(url: Url, canonical_url: Url, oembed: OEmbed) -> Snapshot {
+    let preview_mime_type = oembed.thumbnail_url.as_ref()
+        .and_then(|x| mime_guess::from_path(x.path()).first())
+        .map(|m| m.to_string());
+
+    let snapshot = Snapshot {
+        preview_url: oembed.thumbnail_url,
+        preview_width: oembed.thumbnail_width,
+        preview_height: oembed.thumbnail_height,
+        preview_mime_type,
+        title: oembed.title,
+        author: oembed.author_name,
+        source: Option::from("YouTube".to_string()),
+        site_name: Option::from("YouTube".to_string()),
+        icon_url: Url::parse(YOUTUBE_ICON_URL).ok(),
+        canonical_url: Some(canonical_url),
+        ..bare_snapshot(url)
+    };
+
+    with_quality(snapshot, DataSource::Embed)
+}
+
+/// Returns `count` of subscribers shortened the way YouTube shows it,
+/// e.g. `1.2M subscribers`.
+fn format_subscribers(count: u64) -> String {
+    format!("{} subscribers", format_compact_count(count))
 }
 
 impl YoutubeSnapper {
-    /// Constructs new instance of [YoutubeSnapper].
-    pub(crate) fn new(api_key: String) -> Self {
+    /// Constructs new instance of [YoutubeSnapper] that sends
+    /// `request_headers` along with API requests. Without `api_key` every
+    /// snapshot is made of oEmbed.
+    pub fn new(api_key: Option<String>, request_headers: RequestHeaders) -> Self {
         Self {
-            api_key
+            api_key,
+            request_headers,
+            quota: YoutubeQuota::default(),
         }
     }
 
+    /// Sets `quota` API calls are counted against.
+    pub fn with_quota(self, quota: YoutubeQuota) -> Self {
+        Self { quota, ..self }
+    }
+
     /// Produces Crabo's [Snapshot] from YouTube's `video` and `thumbnail`,
     /// acquired for the processed `url`.
     fn thumbnail_to_snapshot(
@@ -104,120 +449,610 @@ impl YoutubeSnapper {
                     .and_then(|m| m.first())
                     .map(|m| m.to_string());
 
-                Some(Snapshot {
-                    url,
+                // translation is returned only if language was requested
+                let (localized_title, localized_description) = match video.snippet.localized {
+                    Some(localized) => (localized.title, localized.description),
+                    None => (None, None),
+                };
+
+                let snapshot = Snapshot {
                     preview_url,
-                    title: video.snippet.title,
-                    description: video.snippet.description,
+                    title: localized_title.or(video.snippet.title),
+                    description: localized_description.or(video.snippet.description),
                     source: Option::from("YouTube".to_string()),
+                    site_name: Option::from("YouTube".to_string()),
+                    icon_url: Url::parse(YOUTUBE_ICON_URL).ok(),
 
                     tags: video.snippet.tags.into_iter()
                         .flatten()
                         .map(|tag| format!("#{tag}"))
                         .collect(),
 
+                    published_at: video.snippet.published_at,
+                    author: video.snippet.channel_title,
+
+                    language: video.snippet.default_audio_language.as_deref()
+                        .or(video.snippet.default_language.as_deref())
+                        .and_then(normalize_language_tag),
+
+                    duration_seconds: video.content_details
+                        .and_then(|details| details.duration)
+                        .and_then(|duration| parse_duration_seconds(&duration))
+                        .filter(|seconds| *seconds > 0),
+
+                    canonical_url: Url::parse_with_params(
+                        "https://www.youtube.com/watch",
+                        &[("v", &video.id)],
+                    ).ok(),
+
+                    preview_width: thumbnail.width,
+                    preview_height: thumbnail.height,
                     preview_mime_type,
-                    application_name: None,
-                })
+                    ..bare_snapshot(url)
+                };
+
+                Some(with_quality(snapshot, DataSource::Api))
             }
             None => None,
         }
     }
+
+    /// Produces Crabo's [Snapshot] of `url` from YouTube's `video`,
+    /// picking the best thumbnail available.
+    fn video_to_snapshot(&self, url: Url, video: &Video) -> Option<Snapshot> {
+        // Types of thumbnail according to
+        // https://developers.google.com/youtube/v3/docs/videos#snippet.thumbnails
+        // -----------------------------------------------------------------------
+        //   default  –  120px x 90px
+        //   medium   –  320px x 180px
+        //   high     –  480px x 360px
+        //   standard –  640px x 480px (available for some videos)
+        //   maxres   – 1280px x 720px (available for some videos).
+
+        let thumbnail = [
+            "high",
+            "standard",
+            "maxres",
+            "medium",
+            "default"
+        ].into_iter()
+            .filter_map(
+                |key| video.snippet.thumbnails.get(key)
+            )
+            .next();
+
+        self.thumbnail_to_snapshot(
+            url,
+            video.clone(),
+            thumbnail.cloned()
+        )
+    }
+
+    /// Produces Crabo's [Snapshot] of `url` from YouTube's `playlist`,
+    /// which is described the same way video is.
+    fn playlist_to_snapshot(&self, url: Url, playlist: &Video) -> Option<Snapshot> {
+        self.video_to_snapshot(url, playlist)
+            .map(|snapshot| Snapshot {
+                canonical_url: Url::parse_with_params(
+                    "https://www.youtube.com/playlist",
+                    &[("list", &playlist.id)],
+                ).ok(),
+
+                duration_seconds: None,
+                ..snapshot
+            })
+    }
+
+    /// Produces Crabo's [Snapshot] of `url` from YouTube's `channel`,
+    /// with avatar as preview and number of subscribers put before
+    /// description, unless channel hides it.
+    fn channel_to_snapshot(&self, url: Url, channel: &Video) -> Option<Snapshot> {
+        let subscribers = channel.statistics.as_ref()
+            .filter(|statistics| !statistics.hidden_subscriber_count)
+            .and_then(|statistics| statistics.subscriber_count.as_deref())
+            .and_then(|count| count.parse().ok())
+            .map(format_subscribers);
+
+        self.video_to_snapshot(url, channel)
+            .map(|snapshot| Snapshot {
+                description: match (subscribers, snapshot.description) {
+                    (Some(subscribers), Some(description)) if !description.trim().is_empty() => {
+                        Some(format!("{subscribers}\n\n{description}"))
+                    }
+
+                    (Some(subscribers), _) => Some(subscribers),
+                    (None, description) => description,
+                },
+
+                canonical_url: Url::parse("https://www.youtube.com/channel/")
+                    .and_then(|base| base.join(&channel.id))
+                    .ok(),
+
+                duration_seconds: None,
+                ..snapshot
+            })
+    }
+
+    /// This method requests details of resources of `collection` with
+    /// `ids`, which must not be more than [Collection::max_ids_per_request], in a
+    /// single API call. Titles and descriptions are translated to
+    /// `language` if it is set and channel provided translation.
+    /// Returns resources by ID, resources API does not know are missing.
+    /// API is not called once daily budget is spent.
+    async fn get_resources(
+        &self,
+        collection: Collection,
+        ids: &[&str],
+        language: Option<&str>,
+        clients: &Clients,
+    ) -> Result<HashMap<String, Video>, ApiFailure> {
+        let ids = ids.join(",");
+
+        let Some(api_key) = self.api_key.as_deref() else {
+            return Err(ApiFailure::Unavailable);
+        };
+
+        if !self.quota.try_spend(QUOTA_UNITS_PER_CALL) {
+            debug!("YouTube API budget is spent, '{ids}' is not requested");
+            return Err(ApiFailure::Unavailable);
+        }
+
+        let mut query_url = Url::parse_with_params(
+            collection.endpoint(),
+            &[
+                (collection.id_parameter(), ids.as_str()),
+                ("key", api_key),
+                ("part", collection.parts()),
+                ("fields", collection.fields()),
+            ],
+        ).unwrap();
+
+        if let Some(language) = language {
+            query_url.query_pairs_mut().append_pair("hl", language);
+        }
+
+        set_stage("calling YouTube API");
+
+        match clients.page_client.get_json::<VideoListResponse>(
+            &query_url,
+            &self.request_headers.merged_with(&[]),
+        ).await {
+            // channel found by handle is known by ID, not by handle
+            Ok(response) if collection == Collection::Handles => Ok(
+                response.videos.into_iter()
+                    .take(1)
+                    .map(|channel| (ids.clone(), channel))
+                    .collect()
+            ),
+
+            Ok(response) => Ok(
+                response.videos.into_iter()
+                    .map(|video| (video.id.clone(), video))
+                    .collect()
+            ),
+
+            Err(err) => {
+                warn!(
+                    "Failed to get details for YouTube '{ids}', \
+                    API call result is: {err:?}"
+                );
+
+                clients.provider_metrics.record_api_error("youtube");
+
+                Err(ApiFailure::of(&err))
+            }
+        }
+    }
+
+    /// This method makes degraded snapshot of `url` from oEmbed of
+    /// resource with `id` from `collection`, for when API is unavailable.
+    /// oEmbed needs no key, but describes videos and playlists only.
+    async fn snap_oembed(
+        &self,
+        url: Url,
+        collection: Collection,
+        id: &str,
+        clients: &Clients,
+    ) -> Result<Snapshot, SnapError> {
+        let canonical_url = match collection {
+            Collection::Videos => Url::parse_with_params(
+                "https://www.youtube.com/watch",
+                &[("v", id)],
+            ),
+
+            Collection::Playlists => Url::parse_with_params(
+                "https://www.youtube.com/playlist",
+                &[("list", id)],
+            ),
+
+            Collection::Channels | Collection::Handles => {
+                return Err(SnapError::ProviderError);
+            }
+        }.map_err(|_| SnapError::ParseFailed)?;
+
+        let oembed_url = Url::parse_with_params(
+            YOUTUBE_OEMBED_URL,
+            &[("url", canonical_url.as_str()), ("format", "json")],
+        ).map_err(|_| SnapError::ParseFailed)?;
+
+        set_stage("calling YouTube oEmbed");
+
+        match clients.page_client.get_json::<OEmbed>(
+            &oembed_url,
+            &self.request_headers.merged_with(&[]),
+        ).await {
+            Ok(oembed) => {
+                info!("{url}: YouTube API is unavailable, snapshot is made of oEmbed");
+                Ok(oembed_to_snapshot(url, canonical_url, oembed))
+            }
+
+            Err(err) => {
+                warn!("Failed to get oEmbed of YouTube '{id}': {err:?}");
+                Err(err.snap_error())
+            }
+        }
+    }
 }
 
 impl Snapper for YoutubeSnapper {
+    fn provider(&self) -> String {
+        "youtube".into()
+    }
+
+    /// Titles and descriptions are translated as `hl` tells.
+    fn is_localized(&self, _url: &Url) -> bool {
+        true
+    }
+
     fn cache_hints(&self, video_url: &Url) -> Option<CacheHints> {
         extract_video_id(video_url)
+            .or_else(|| extract_playlist_id(video_url)
+                .map(|id| Collection::Playlists.hints_id(&id))
+            )
+            .or_else(|| extract_channel(video_url))
             .map(|id| CacheHints {
-                provider: "youtube".into(),
+                provider: self.provider(),
                 id,
+                language: None,
             })
     }
 
-    async fn snap(
-        &self,
+    fn snap<'a>(
+        &'a self,
         url: Url,
         cache_hints: CacheHints,
-        clients: &Clients
-    ) -> SnapshotAndHints {
-        let video_id = &cache_hints.id;
-        let api_key = &self.api_key;
-
-        let query_url_str = format!(
-            "https://www.googleapis.com/youtube/v3/videos?\
-            id={video_id}&\
-            key={api_key}&\
-            part=snippet&\
-            fields=items(id,snippet)"
-        );
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, SnapshotAndHints> {
+        Box::pin(async move {
+            self.snap_many(vec![(url, cache_hints)], clients)
+                .await
+                .pop()
+                .unwrap()
+        })
+    }
 
-        let query_url = Url::parse(&query_url_str).unwrap();
+    /// This method produces snapshots for all `videos`, which are URLs
+    /// with hints this snapper returned. API accepts many IDs at once,
+    /// so videos are requested in batches of [MAX_IDS_PER_REQUEST],
+    /// saving quota and time.
+    /// Snapshots are returned in the same order as `videos`.
+    fn snap_many<'a>(
+        &'a self,
+        videos: Vec<(Url, CacheHints)>,
+        clients: &'a Clients,
+    ) -> LocalBoxFuture<'a, Vec<SnapshotAndHints>> {
+        Box::pin(async move {
+            // language is set per request, so videos are requested per
+            // language, and playlists come from their own collection
+            let resource_ids = videos.iter()
+                .map(|(_, cache_hints)| {
+                    let (collection, id) = Collection::of_hints_id(&cache_hints.id);
+                    ((cache_hints.language.as_deref(), collection), id)
+                })
+                .unique()
+                .into_group_map();
 
-        match clients.generic_client.get_json::<VideoListResponse>(
-            &query_url,
-            None
-        ).await {
-            Ok(response) => {
-                let snapshot = response.videos.into_iter()
-                    .next()
-                    .and_then(|video| {
-                        // Types of thumbnail according to
-                        // https://developers.google.com/youtube/v3/docs/videos#snippet.thumbnails
-                        // -----------------------------------------------------------------------
-                        //   default  –  120px x 90px
-                        //   medium   –  320px x 180px
-                        //   high     –  480px x 360px
-                        //   standard –  640px x 480px (available for some videos)
-                        //   maxres   – 1280px x 720px (available for some videos).
-
-                        let thumbnail = [
-                            "high",
-                            "standard",
-                            "maxres",
-                            "medium",
-                            "default"
-                        ].into_iter()
-                            .filter_map(
-                                |key| video.snippet.thumbnails.get(key)
-                            )
-                            .next();
-
-                        self.thumbnail_to_snapshot(
-                            url,
-                            video.clone(),
-                            thumbnail.cloned()
-                        )
-                    });
-
-                SnapshotAndHints {
-                    snapshot,
-                    hints: cache_hints,
-                }
-            }
+            let chunks: Vec<_> = resource_ids.iter()
+                .flat_map(|(key, ids)| ids
+                    .chunks(key.1.max_ids_per_request())
+                    .map(|chunk| (*key, chunk))
+                )
+                .collect();
 
-            Err(err) => {
-                warn!(
-                    "Failed to get details for YouTube '{video_id}', \
-                    API call result is: {err:?}"
-                );
+            let responses = join_all(
+                chunks.iter().map(|((language, collection), chunk)| self.get_resources(
+                    *collection,
+                    chunk,
+                    *language,
+                    clients,
+                ))
+            ).await;
 
-                SnapshotAndHints {
-                    snapshot: None,
-                    hints: cache_hints,
+            // failed API call fails every resource of its chunk
+            let mut found: HashMap<_, _> = HashMap::new();
+
+            for (((language, collection), chunk), response) in chunks.into_iter().zip(responses) {
+                let language = language.map(|language| language.to_string());
+
+                match response {
+                    Ok(resources) => found.extend(resources.into_iter().map(
+                        |(id, video)| ((language.clone(), collection.hints_id(&id)), Ok(video))
+                    )),
+
+                    Err(err) => found.extend(chunk.iter().map(
+                        |id| ((language.clone(), collection.hints_id(id)), Err(err))
+                    )),
                 }
             }
-        }
+
+            let lookups: Vec<_> = videos.into_iter()
+                .map(|(url, cache_hints)| {
+                    let result = found.get(&(
+                        cache_hints.language.clone(),
+                        cache_hints.id.clone(),
+                    )).cloned();
+
+                    (url, cache_hints, result)
+                })
+                .collect();
+
+            join_all(
+                lookups.into_iter().map(|(url, cache_hints, result)| async move {
+                    let (collection, id) = Collection::of_hints_id(&cache_hints.id);
+
+                    let snapshot = match result {
+                        Some(Ok(video)) => match collection {
+                            Collection::Videos => self.video_to_snapshot(url, &video),
+                            Collection::Playlists => self.playlist_to_snapshot(url, &video),
+
+                            Collection::Channels | Collection::Handles => {
+                                self.channel_to_snapshot(url, &video)
+                            }
+                        }
+                            .ok_or(SnapError::ParseFailed),
+
+                        // something is better than nothing until quota resets
+                        Some(Err(ApiFailure::Unavailable)) => {
+                            self.snap_oembed(url, collection, id, clients).await
+                        }
+
+                        Some(Err(ApiFailure::Failed(err))) => Err(err),
+                        None => Err(SnapError::NotFound),
+                    };
+
+                    SnapshotAndHints {
+                        snapshot,
+                        hints: cache_hints,
+                    }
+                })
+            ).await
+        })
     }
 }
 
 #[cfg(test)]
 mod test {
     use url::Url;
-    use crate::youtube::extract_video_id;
+    use crate::provider_headers::RequestHeaders;
+    use actix_web::http::StatusCode;
+    use crabo_model::SnapError;
+    use crate::page_client::FetchError;
+    use crate::snapper::Snapper;
+    use crate::youtube::{
+        ApiFailure,
+        extract_channel,
+        extract_playlist_id,
+        extract_video_id,
+        format_subscribers,
+        oembed_to_snapshot,
+        VideoListResponse,
+        YoutubeSnapper,
+    };
 
     #[test]
     fn test_youtu_be() {
         let url = Url::parse("https://youtu.be/x8?si=HxxxJ").unwrap();
         assert_eq!(extract_video_id(&url), Some("x8".to_string()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_video_paths() {
+        let extract = |url: &str| extract_video_id(&Url::parse(url).unwrap());
+
+        for url in [
+            "https://www.youtube.com/watch?v=a1_-B",
+            "https://m.youtube.com/shorts/a1_-B?feature=share",
+            "https://www.youtube.com/live/a1_-B",
+            "https://www.youtube-nocookie.com/embed/a1_-B?start=10",
+            "https://music.youtube.com/watch?v=a1_-B&list=RD1",
+        ] {
+            assert_eq!(extract(url).as_deref(), Some("a1_-B"), "{url}");
+        }
+
+        assert_eq!(extract("https://www.youtube.com/embed/videoseries?list=PL1"), None);
+        assert_eq!(extract("https://www.youtube.com/@channel/live"), None);
+        assert_eq!(extract("https://www.youtube.com/watch?v=a1%26key%3D"), None);
+        assert_eq!(extract("https://notyoutube.com/watch?v=a1"), None);
+    }
+
+    #[test]
+    fn test_canonical_cache_hints() {
+        let snapper = YoutubeSnapper::new(Some("key".to_string()), RequestHeaders::default());
+        let id = |url: &str| snapper.cache_hints(&Url::parse(url).unwrap()).unwrap().id;
+
+        for url in [
+            "https://m.youtube.com/watch?v=a1_-B&t=10",
+            "https://youtu.be/a1_-B?si=HxxxJ",
+            "https://www.youtube-nocookie.com/embed/a1_-B",
+            "https://youtube.com/shorts/a1_-B",
+        ] {
+            assert_eq!(id(url), "a1_-B", "{url}");
+        }
+
+        assert_eq!(
+            id("https://www.youtube.com/embed/videoseries?list=PL1"),
+            id("https://m.youtube.com/playlist?list=PL1"),
+        );
+
+        assert_eq!(id("https://www.youtube.com/@Some.Handle"), id("https://m.youtube.com/@some.handle"));
+    }
+
+    #[test]
+    fn test_playlist_id() {
+        let extract = |url: &str| extract_playlist_id(&Url::parse(url).unwrap());
+
+        assert_eq!(extract("https://www.youtube.com/playlist?list=PL1").as_deref(), Some("PL1"));
+        assert_eq!(extract("https://music.youtube.com/playlist?list=OL2").as_deref(), Some("OL2"));
+        assert_eq!(extract("https://www.youtube.com/embed/videoseries?list=PL1").as_deref(), Some("PL1"));
+        assert_eq!(extract("https://www.youtube.com/watch?v=a1&list=PL1"), None);
+    }
+
+    #[test]
+    fn test_channel() {
+        let extract = |url: &str| extract_channel(&Url::parse(url).unwrap());
+
+        assert_eq!(extract("https://www.youtube.com/@some.Handle").as_deref(), Some("@some.handle"));
+        assert_eq!(extract("https://m.youtube.com/@%E3%81%AD%E3%81%93/videos").as_deref(), Some("@ねこ"));
+        assert_eq!(extract("https://www.youtube.com/channel/UC1a_-/about").as_deref(), Some("channel:UC1a_-"));
+        assert_eq!(extract("https://www.youtube.com/@"), None);
+        assert_eq!(extract("https://www.youtube.com/feed/trending"), None);
+
+        let response: VideoListResponse = serde_json::from_str(r#"{
+            "items": [{"id": "UC1", "snippet": {
+                "title": "Channel",
+                "description": "About channel",
+                "thumbnails": {"high": {"url": "https://yt3.ggpht.com/a.jpg", "width": 800, "height": 800}}
+            }, "statistics": {"subscriberCount": "1234567", "hiddenSubscriberCount": false}}]
+        }"#).unwrap();
+
+        let snapper = YoutubeSnapper::new(Some("key".to_string()), RequestHeaders::default());
+
+        let snapshot = snapper.channel_to_snapshot(
+            Url::parse("https://www.youtube.com/@channel").unwrap(),
+            &response.videos[0],
+        ).unwrap();
+
+        assert_eq!(snapshot.title.as_deref(), Some("Channel"));
+        assert_eq!(snapshot.description.as_deref(), Some("1.2M subscribers\n\nAbout channel"));
+        assert_eq!(snapshot.preview_url.unwrap().as_str(), "https://yt3.ggpht.com/a.jpg");
+        assert_eq!(snapshot.canonical_url.unwrap().as_str(), "https://www.youtube.com/channel/UC1");
+
+        assert_eq!(format_subscribers(999), "999 subscribers");
+        assert_eq!(format_subscribers(12_000), "12K subscribers");
+        assert!(serde_json::from_str::<VideoListResponse>("{}").unwrap().videos.is_empty());
+    }
+
+    #[test]
+    fn test_oembed_fallback() {
+        let of_status = |status| ApiFailure::of(&FetchError::UnexpectedStatusCode(status));
+
+        assert!(matches!(of_status(StatusCode::FORBIDDEN), ApiFailure::Unavailable));
+
+        assert!(matches!(
+            of_status(StatusCode::NOT_FOUND),
+            ApiFailure::Failed(SnapError::NotFound)
+        ));
+
+        let oembed = serde_json::from_str(r#"{
+            "title": "A",
+            "author_name": "Channel",
+            "thumbnail_url": "https://i.ytimg.com/vi/a1/hqdefault.jpg",
+            "thumbnail_width": 480,
+            "thumbnail_height": 360
+        }"#).unwrap();
+
+        let snapshot = oembed_to_snapshot(
+            Url::parse("https://youtu.be/a1").unwrap(),
+            Url::parse("https://www.youtube.com/watch?v=a1").unwrap(),
+            oembed,
+        );
+
+        assert_eq!(snapshot.title.as_deref(), Some("A"));
+        assert_eq!(snapshot.author.as_deref(), Some("Channel"));
+        assert_eq!(snapshot.preview_mime_type.as_deref(), Some("image/jpeg"));
+        assert!(snapshot.quality.unwrap() < 100);
+    }
+
+    #[test]
+    fn test_snippet_attribution() {
+        let response: VideoListResponse = serde_json::from_str(r#"{
+            "items": [{"id": "a1", "snippet": {
+                "title": "A",
+                "thumbnails": {},
+                "publishedAt": "2024-03-01T12:00:00Z",
+                "channelTitle": "Channel"
+            }}]
+        }"#).unwrap();
+
+        let snippet = &response.videos[0].snippet;
+
+        assert_eq!(
+            snippet.published_at.map(|x| x.to_rfc3339()),
+            Some("2024-03-01T12:00:00+00:00".to_string())
+        );
+
+        assert_eq!(snippet.channel_title.as_deref(), Some("Channel"));
+    }
+
+    #[test]
+    fn test_video_dimensions() {
+        let response: VideoListResponse = serde_json::from_str(r#"{
+            "items": [{
+                "id": "a1",
+                "snippet": {"title": "A", "thumbnails": {
+                    "high": {"url": "https://i.ytimg.com/vi/a1/hq.jpg",
+                             "width": 480, "height": 360}
+                }},
+                "contentDetails": {"duration": "PT4M13S"}
+            }]
+        }"#).unwrap();
+
+        let video = &response.videos[0];
+        let thumbnail = &video.snippet.thumbnails["high"];
+
+        assert_eq!((thumbnail.width, thumbnail.height), (Some(480), Some(360)));
+
+        assert_eq!(
+            video.content_details.as_ref().and_then(|x| x.duration.as_deref()),
+            Some("PT4M13S")
+        );
+    }
+
+    #[test]
+    fn test_localized_snippet() {
+        let response: VideoListResponse = serde_json::from_str(r#"{
+            "items": [{"id": "a1", "snippet": {
+                "title": "Titel",
+                "description": "Beschreibung",
+                "thumbnails": {"high": {"url": "https://i.ytimg.com/vi/a1/hq.jpg"}},
+                "localized": {"title": "Title", "description": "Description"}
+            }}]
+        }"#).unwrap();
+
+        let snapper = YoutubeSnapper::new(Some("key".to_string()), RequestHeaders::default());
+
+        let snapshot = snapper.video_to_snapshot(
+            Url::parse("https://youtu.be/a1").unwrap(),
+            &response.videos[0],
+        ).unwrap();
+
+        assert_eq!(snapshot.title.as_deref(), Some("Title"));
+        assert_eq!(snapshot.description.as_deref(), Some("Description"));
+    }
+
+    #[test]
+    fn test_video_list_response() {
+        let response: VideoListResponse = serde_json::from_str(r#"{
+            "items": [
+                {"id": "a1", "snippet": {"title": "A", "thumbnails": {}}},
+                {"id": "b2", "snippet": {"title": "B", "thumbnails": {}}}
+            ]
+        }"#).unwrap();
+
+        let ids: Vec<_> = response.videos.iter()
+            .map(|video| video.id.as_str())
+            .collect();
+
+        assert_eq!(ids, vec!["a1", "b2"]);
+    }
+}
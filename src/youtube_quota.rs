@@ -0,0 +1,162 @@
+use std::sync::{Arc, Mutex};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use crate::metrics::MetricsWriter;
+
+/// Default daily quota of YouTube Data API project, in units.
+pub const DEFAULT_DAILY_BUDGET: u64 = 10_000;
+
+/// Share of daily budget, in percent, kept unused for calls Crabo does
+/// not count, e.g. self-check or other services that use the same key.
+const RESERVE_PERCENT: u64 = 5;
+
+/// Quota resets at midnight Pacific Time. Daylight saving time is
+/// ignored, so in summer budget resets an hour after quota does, which
+/// errs on the safe side.
+const RESET_OFFSET_HOURS: i64 = 8;
+
+/// Usage of quota during a single quota day.
+#[derive(Debug)]
+struct QuotaUsage {
+    /// Day units are counted for.
+    day: NaiveDate,
+
+    /// Units spent during `day`.
+    used: u64,
+
+    /// Number of calls not made due to budget, never reset.
+    denied: u64,
+}
+
+/// This struct keeps track of YouTube Data API quota spent by Crabo.
+///
+/// Once quota is exhausted, API fails every call until midnight Pacific
+/// Time, and YouTube previews silently degrade for the rest of the day.
+/// Crabo stops calling API a bit earlier, when the budget nears
+/// exhaustion, so snapper switches to its fallback right away instead
+/// of wasting calls on errors.
+///
+/// Clones share the same budget.
+#[derive(Clone, Debug)]
+pub struct YoutubeQuota {
+    daily_budget: u64,
+    usage: Arc<Mutex<QuotaUsage>>,
+}
+
+impl Default for YoutubeQuota {
+    fn default() -> Self {
+        Self::new(DEFAULT_DAILY_BUDGET)
+    }
+}
+
+/// Returns quota day `now` belongs to.
+fn quota_day(now: DateTime<Utc>) -> NaiveDate {
+    (now - Duration::hours(RESET_OFFSET_HOURS)).date_naive()
+}
+
+impl YoutubeQuota {
+    /// Constructs new instance of [YoutubeQuota] that allows to spend up
+    /// to `daily_budget` units a day, minus reserve.
+    pub fn new(daily_budget: u64) -> Self {
+        Self {
+            daily_budget,
+            usage: Arc::new(Mutex::new(QuotaUsage {
+                day: quota_day(Utc::now()),
+                used: 0,
+                denied: 0,
+            })),
+        }
+    }
+
+    /// Returns number of units Crabo may spend a day.
+    fn usable_budget(&self) -> u64 {
+        self.daily_budget - self.daily_budget * RESERVE_PERCENT / 100
+    }
+
+    /// Helper method to apply `action` to usage of the day `now` belongs
+    /// to, usage of previous day is forgotten.
+    fn with_usage<T>(&self, now: DateTime<Utc>, action: impl FnOnce(&mut QuotaUsage) -> T) -> T {
+        let mut usage = self.usage.lock().unwrap();
+        let day = quota_day(now);
+
+        if usage.day != day {
+            usage.day = day;
+            usage.used = 0;
+        }
+
+        action(&mut usage)
+    }
+
+    /// Spends `units` of budget if they are still available.
+    /// Returns false if API should not be called.
+    pub fn try_spend(&self, units: u64) -> bool {
+        self.try_spend_at(units, Utc::now())
+    }
+
+    /// Spends `units` of budget of the day `now` belongs to, if they are
+    /// still available.
+    fn try_spend_at(&self, units: u64, now: DateTime<Utc>) -> bool {
+        let usable_budget = self.usable_budget();
+
+        self.with_usage(now, |usage| match usage.used + units <= usable_budget {
+            true => {
+                usage.used += units;
+                true
+            }
+
+            false => {
+                usage.denied += 1;
+                false
+            }
+        })
+    }
+
+    /// Returns number of units of daily budget left today.
+    pub fn remaining(&self) -> u64 {
+        self.with_usage(Utc::now(), |usage| self.daily_budget.saturating_sub(usage.used))
+    }
+
+    /// This method writes budget metrics with `writer`.
+    pub fn write_metrics(&self, writer: &mut MetricsWriter) {
+        writer.gauge(
+            "crabo_youtube_quota_budget_units",
+            "Daily budget of YouTube API quota.",
+            self.daily_budget,
+        );
+
+        writer.gauge(
+            "crabo_youtube_quota_remaining_units",
+            "Units of YouTube API quota left today.",
+            self.remaining(),
+        );
+
+        writer.counter(
+            "crabo_youtube_quota_denied_calls_total",
+            "Number of YouTube API calls replaced with fallback due to budget.",
+            self.usage.lock().unwrap().denied,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, Duration, Utc};
+    use crate::youtube_quota::YoutubeQuota;
+
+    #[test]
+    fn test_budget() {
+        let quota = YoutubeQuota::new(100);
+        let now: DateTime<Utc> = "2024-05-01T12:00:00Z".parse().unwrap();
+
+        // 5 units are reserved
+        assert!(quota.try_spend_at(90, now));
+        assert!(quota.try_spend_at(5, now));
+        assert!(!quota.try_spend_at(1, now));
+
+        // quota resets at 08:00 UTC
+        let before_reset: DateTime<Utc> = "2024-05-02T07:59:00Z".parse().unwrap();
+        assert!(!quota.try_spend_at(1, before_reset));
+        assert!(quota.try_spend_at(1, before_reset + Duration::minutes(1)));
+
+        assert_eq!(quota.usage.lock().unwrap().denied, 2);
+    }
+}